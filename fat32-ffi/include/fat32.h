@@ -0,0 +1,71 @@
+/*
+ * A C interface to the fat32 crate. Link with libfat32_ffi.a or libfat32_ffi.so,
+ * built by `cargo build` in fat32-ffi.
+ *
+ * Functions return 0, or for fat32_read() and fat32_readdir() a count, on
+ * success and a negated errno value on failure. Handles stay valid until
+ * closed, even after their volume is unmounted. Access is read-only.
+ */
+
+#ifndef FAT32_H
+#define FAT32_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+struct fat32_volume;
+struct fat32_file;
+struct fat32_dir;
+
+/*
+ * A block device: read_sector() reads sector `sector`, of `sector_size`
+ * bytes, into `buf` and returns 0, or a negated errno value; a
+ * positive value is taken as that errno. It may be called from any thread
+ * that uses the volume or its handles.
+ */
+struct fat32_device {
+    void *ctx;
+    uint64_t sector_size;
+    int (*read_sector)(void *ctx, uint64_t sector, uint8_t *buf);
+};
+
+/*
+ * An entry of a directory. Times are seconds since the Unix epoch, taking
+ * the volume's times to be UTC.
+ */
+struct fat32_stat {
+    char name[255 * 3 + 1];
+    uint64_t size;
+    uint8_t is_dir;
+    uint8_t attributes;
+    int64_t ctime;
+    int64_t atime;
+    int64_t mtime;
+};
+
+int fat32_mount(const struct fat32_device *device, struct fat32_volume **out);
+int fat32_mount_image(const char *path, struct fat32_volume **out);
+void fat32_unmount(struct fat32_volume *vol);
+
+int fat32_stat(const struct fat32_volume *vol, const char *path,
+               struct fat32_stat *out);
+
+int fat32_open(const struct fat32_volume *vol, const char *path,
+               struct fat32_file **out);
+int64_t fat32_read(struct fat32_file *file, void *buf, size_t len);
+void fat32_close(struct fat32_file *file);
+
+int fat32_opendir(const struct fat32_volume *vol, const char *path,
+                  struct fat32_dir **out);
+int fat32_readdir(struct fat32_dir *dir, struct fat32_stat *out);
+void fat32_closedir(struct fat32_dir *dir);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
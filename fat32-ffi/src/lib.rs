@@ -0,0 +1,307 @@
+//! A C interface to `fat32`, so that C code, or code in any language that
+//! can call C, can read FAT32 volumes with this implementation.
+//!
+//! Builds `libfat32_ffi.so` and `libfat32_ffi.a`; `include/fat32.h` declares the
+//! interface. Volumes, files, and directories are opaque handles. Functions
+//! return 0, or for `fat32_read()` and `fat32_readdir()` a count, on success
+//! and a negated `errno` value on failure:
+//!
+//! ```c
+//! struct fat32_volume *vol;
+//! struct fat32_file *file;
+//! char buf[512];
+//!
+//! if (fat32_mount_image("sd.img", &vol) == 0) {
+//!     if (fat32_open(vol, "/config.txt", &file) == 0) {
+//!         int64_t n = fat32_read(file, buf, sizeof buf);
+//!         fat32_close(file);
+//!     }
+//!     fat32_unmount(vol);
+//! }
+//! ```
+//!
+//! A volume can be mounted from a C read callback with `fat32_mount()`.
+//! Handles stay valid until closed, even after their volume is unmounted.
+//! Access is read-only.
+
+// The types are named as they are in C.
+#![allow(non_camel_case_types)]
+
+extern crate fat32;
+
+use std::ffi::CStr;
+use std::io::{self, Read};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use fat32::device::ImageFile;
+use fat32::traits::{self, BlockDevice, Dir, FileSystem};
+use fat32::vfat::{self, Metadata, Shared, VFat};
+
+const ENOENT: c_int = 2;
+const EIO: c_int = 5;
+const EACCES: c_int = 13;
+const ENOTDIR: c_int = 20;
+const EISDIR: c_int = 21;
+const EINVAL: c_int = 22;
+const EROFS: c_int = 30;
+
+/// The longest name, in bytes of UTF-8 and without its terminating NUL,
+/// `fat32_stat` holds: 255 UTF-16 code units, each up to 3 bytes.
+const NAME_LEN: usize = 255 * 3;
+
+/// Maps an I/O error to its `errno`, or for errors with none, the closest
+/// one to its kind. The result is always positive.
+fn errno(error: &io::Error) -> c_int {
+    match error.raw_os_error() {
+        Some(code) if code > 0 => return code,
+        Some(_) => return EIO,
+        None => {}
+    }
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+/// Runs `f`, returning its result, or `-errno` if it fails. A panic must not
+/// unwind into C, so it is reported as `-EIO`.
+fn guard<F: FnOnce() -> io::Result<i64>>(f: F) -> i64 {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(n)) => n,
+        Ok(Err(e)) => -errno(&e) as i64,
+        Err(_) => -EIO as i64,
+    }
+}
+
+/// Returns the string `s` points to, which must be NUL-terminated UTF-8.
+unsafe fn path<'a>(s: *const c_char) -> io::Result<&'a str> {
+    if s.is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is null"));
+    }
+    CStr::from_ptr(s).to_str()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path is not UTF-8"))
+}
+
+/// Stores `value` in a new handle at `*out`.
+unsafe fn put<T>(out: *mut *mut T, value: T) -> io::Result<i64> {
+    if out.is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "output is null"));
+    }
+    *out = Box::into_raw(Box::new(value));
+    Ok(0)
+}
+
+/// A block device provided by C: `read_sector` reads sector `sector` of
+/// `sector_size` bytes into `buf` and returns 0, or a negated `errno`. A
+/// positive return is taken as that `errno`.
+#[repr(C)]
+pub struct fat32_device {
+    pub ctx: *mut c_void,
+    pub sector_size: u64,
+    pub read_sector: extern "C" fn(ctx: *mut c_void, sector: u64, buf: *mut u8) -> c_int,
+}
+
+struct CDevice(fat32_device);
+
+// The volume, and so the device, may be used from any thread; `fat32.h`
+// requires `read_sector` to allow that.
+unsafe impl Send for CDevice {}
+
+impl BlockDevice for CDevice {
+    fn sector_size(&self) -> u64 {
+        self.0.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.0.sector_size as usize;
+        if buf.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "buffer is smaller than the sector"));
+        }
+        // Any other return is an error, whatever its sign, so that it can't
+        // pass through `guard()` as a count.
+        match (self.0.read_sector)(self.0.ctx, n, buf.as_mut_ptr()) {
+            0 => Ok(len),
+            e => Err(io::Error::from_raw_os_error(e.checked_abs().unwrap_or(EIO))),
+        }
+    }
+
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(EROFS))
+    }
+}
+
+/// What `fat32_stat()` and `fat32_readdir()` report about an entry. Times
+/// are seconds since the Unix epoch, taking the volume's times to be UTC.
+#[repr(C)]
+pub struct fat32_stat {
+    pub name: [c_char; NAME_LEN + 1],
+    pub size: u64,
+    pub is_dir: u8,
+    /// The FAT attribute byte.
+    pub attributes: u8,
+    pub ctime: i64,
+    pub atime: i64,
+    pub mtime: i64,
+}
+
+impl fat32_stat {
+    fn fill(&mut self, name: &str, is_dir: bool, size: u64, metadata: &Metadata) {
+        let name = name.as_bytes();
+        let len = name.len().min(NAME_LEN);
+        for (dst, &src) in self.name.iter_mut().zip(name[..len].iter()) {
+            *dst = src as c_char;
+        }
+        self.name[len] = 0;
+        self.size = size;
+        self.is_dir = is_dir as u8;
+        self.attributes = metadata.attr.0;
+        self.ctime = metadata.to_unix_seconds(metadata.ctime);
+        self.atime = metadata.to_unix_seconds(metadata.atime);
+        self.mtime = metadata.to_unix_seconds(metadata.mtime);
+    }
+
+    fn fill_entry(&mut self, entry: &vfat::Entry) {
+        use fat32::traits::Entry;
+        self.fill(entry.name(), entry.is_dir(), entry.len(), entry.metadata());
+    }
+}
+
+pub struct fat32_volume(Shared<VFat>);
+pub struct fat32_file(vfat::File);
+pub struct fat32_dir(<vfat::Dir as traits::Dir>::Iter);
+
+/// Mounts the first FAT32 partition of the device `device` describes,
+/// storing the volume in `*out`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_mount(device: *const fat32_device,
+                                     out: *mut *mut fat32_volume) -> c_int {
+    guard(|| {
+        let device = device.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "device is null"))?;
+        let device = CDevice(fat32_device {
+            ctx: device.ctx,
+            sector_size: device.sector_size,
+            read_sector: device.read_sector,
+        });
+        put(out, fat32_volume(VFat::from(device)?))
+    }) as c_int
+}
+
+/// Mounts the first FAT32 partition of the disk image at `path`, storing the
+/// volume in `*out`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_mount_image(path: *const c_char,
+                                           out: *mut *mut fat32_volume) -> c_int {
+    guard(|| {
+        let image = ImageFile::open_read_only(self::path(path)?)?;
+        put(out, fat32_volume(VFat::from(image)?))
+    }) as c_int
+}
+
+/// Releases `vol`. Files and directories opened from it stay usable.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_unmount(vol: *mut fat32_volume) {
+    if !vol.is_null() {
+        drop(Box::from_raw(vol));
+    }
+}
+
+/// Stores what is known about the entry at the absolute path `path` in
+/// `*out`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_stat(vol: *const fat32_volume, path: *const c_char,
+                                    out: *mut fat32_stat) -> c_int {
+    guard(|| {
+        let (vol, out) = match (vol.as_ref(), out.as_mut()) {
+            (Some(vol), Some(out)) => (vol, out),
+            _ => return Err(io::Error::from_raw_os_error(EINVAL)),
+        };
+        out.fill_entry(&(&vol.0).open(self::path(path)?)?);
+        Ok(0)
+    }) as c_int
+}
+
+/// Opens the file at the absolute path `path`, storing it in `*out`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_open(vol: *const fat32_volume, path: *const c_char,
+                                    out: *mut *mut fat32_file) -> c_int {
+    guard(|| {
+        let vol = vol.as_ref().ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        match (&vol.0).open(self::path(path)?)? {
+            vfat::Entry::File(file) => put(out, fat32_file(file)),
+            vfat::Entry::Dir(_) => Err(io::Error::from_raw_os_error(EISDIR)),
+        }
+    }) as c_int
+}
+
+/// Reads up to `len` bytes from `file` into `buf`, returning the number of
+/// bytes read, 0 at the end of the file.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_read(file: *mut fat32_file, buf: *mut c_void,
+                                    len: usize) -> i64 {
+    guard(|| {
+        let file = file.as_mut().ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        if buf.is_null() {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+        Ok(file.0.read(buf)? as i64)
+    })
+}
+
+/// Releases `file`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_close(file: *mut fat32_file) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+/// Opens the directory at the absolute path `path` for listing with
+/// `fat32_readdir()`, storing it in `*out`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_opendir(vol: *const fat32_volume, path: *const c_char,
+                                       out: *mut *mut fat32_dir) -> c_int {
+    guard(|| {
+        let vol = vol.as_ref().ok_or_else(|| io::Error::from_raw_os_error(EINVAL))?;
+        match (&vol.0).open(self::path(path)?)? {
+            vfat::Entry::Dir(dir) => put(out, fat32_dir(dir.entries()?)),
+            vfat::Entry::File(_) => Err(io::Error::from_raw_os_error(ENOTDIR)),
+        }
+    }) as c_int
+}
+
+/// Stores the next entry of `dir` in `*out` and returns 1, or returns 0 once
+/// every entry has been listed.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_readdir(dir: *mut fat32_dir, out: *mut fat32_stat) -> c_int {
+    guard(|| {
+        let (dir, out) = match (dir.as_mut(), out.as_mut()) {
+            (Some(dir), Some(out)) => (dir, out),
+            _ => return Err(io::Error::from_raw_os_error(EINVAL)),
+        };
+        match dir.0.next() {
+            Some(entry) => {
+                out.fill_entry(&entry);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }) as c_int
+}
+
+/// Releases `dir`.
+#[no_mangle]
+pub unsafe extern "C" fn fat32_closedir(dir: *mut fat32_dir) {
+    if !dir.is_null() {
+        drop(Box::from_raw(dir));
+    }
+}
+
+#[cfg(test)]
+mod tests;
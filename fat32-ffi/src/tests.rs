@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+use fat32::testing::Fixture;
+
+use super::*;
+
+/// A disk image read through `fat32_device`, failing every read with
+/// `error` while it is non-zero.
+struct Disk {
+    image: Vec<u8>,
+    error: Cell<c_int>,
+}
+
+extern "C" fn read_disk(ctx: *mut c_void, sector: u64, buf: *mut u8) -> c_int {
+    let disk = unsafe { &*(ctx as *const Disk) };
+    if disk.error.get() != 0 {
+        return disk.error.get();
+    }
+    let start = sector as usize * 512;
+    match disk.image.get(start..start + 512) {
+        Some(data) => {
+            unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buf, 512) };
+            0
+        }
+        None => -EIO,
+    }
+}
+
+fn mini_disk() -> Disk {
+    let mut fixture = Fixture::mini();
+    for (i, byte) in fixture.data_mut(4, 1000).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    Disk { image: fixture.into_image(), error: Cell::new(0) }
+}
+
+fn device(disk: &Disk) -> fat32_device {
+    fat32_device {
+        ctx: disk as *const Disk as *mut c_void,
+        sector_size: 512,
+        read_sector: read_disk,
+    }
+}
+
+fn name(stat: &fat32_stat) -> &str {
+    unsafe { CStr::from_ptr(stat.name.as_ptr()) }.to_str().unwrap()
+}
+
+fn empty_stat() -> fat32_stat {
+    fat32_stat {
+        name: [0; NAME_LEN + 1],
+        size: 0,
+        is_dir: 0,
+        attributes: 0,
+        ctime: 0,
+        atime: 0,
+        mtime: 0,
+    }
+}
+
+fn c(s: &str) -> Vec<c_char> {
+    s.bytes().chain(Some(0)).map(|b| b as c_char).collect()
+}
+
+#[test]
+fn test_callback_device() {
+    let disk = mini_disk();
+    let device = device(&disk);
+    let mut vol = ptr::null_mut();
+    unsafe {
+        assert_eq!(fat32_mount(&device, &mut vol), 0);
+
+        let mut stat = empty_stat();
+        assert_eq!(fat32_stat(vol, c("/long.txt").as_ptr(), &mut stat), 0);
+        assert_eq!((name(&stat), stat.size, stat.is_dir), ("long.txt", 1000, 0));
+        assert_eq!(fat32_stat(vol, c("/missing").as_ptr(), &mut stat), -ENOENT);
+
+        let mut file = ptr::null_mut();
+        assert_eq!(fat32_open(vol, c("/long.txt").as_ptr(), &mut file), 0);
+        let mut buf = [0u8; 600];
+        assert_eq!(fat32_read(file, buf.as_mut_ptr() as *mut c_void, 600), 600);
+        assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+        assert_eq!(fat32_read(file, buf.as_mut_ptr() as *mut c_void, 600), 400);
+        assert_eq!(buf[0], (600 % 256) as u8);
+        assert_eq!(fat32_read(file, buf.as_mut_ptr() as *mut c_void, 600), 0);
+        fat32_close(file);
+        assert_eq!(fat32_open(vol, c("/SUB").as_ptr(), &mut file), -EISDIR);
+
+        let mut dir = ptr::null_mut();
+        assert_eq!(fat32_opendir(vol, c("/SUB").as_ptr(), &mut dir), 0);
+        let mut names = vec![];
+        while fat32_readdir(dir, &mut stat) == 1 {
+            names.push(name(&stat).to_string());
+        }
+        assert_eq!(names, [".", "..", "A.BIN"]);
+        fat32_closedir(dir);
+        assert_eq!(fat32_opendir(vol, c("/long.txt").as_ptr(), &mut dir), -ENOTDIR);
+
+        fat32_unmount(vol);
+    }
+}
+
+#[test]
+fn test_callback_errors() {
+    let disk = mini_disk();
+    let device = device(&disk);
+    let mut vol = ptr::null_mut();
+    unsafe {
+        // Errors are negated whatever their sign in the callback.
+        for &(error, expected) in &[(-EACCES, -EACCES), (EACCES, -EACCES), (c_int::MIN, -EIO)] {
+            disk.error.set(error);
+            assert_eq!(fat32_mount(&device, &mut vol), expected);
+        }
+
+        disk.error.set(0);
+        assert_eq!(fat32_mount(&device, &mut vol), 0);
+        let mut file = ptr::null_mut();
+        assert_eq!(fat32_open(vol, c("/long.txt").as_ptr(), &mut file), 0);
+        // A positive error must not come back as a count of bytes read. The
+        // error gains the failing sector as context on the way, and so is
+        // reported as `EIO`.
+        disk.error.set(7);
+        let mut buf = [0u8; 16];
+        assert_eq!(fat32_read(file, buf.as_mut_ptr() as *mut c_void, 16), -EIO as i64);
+        fat32_close(file);
+        fat32_unmount(vol);
+    }
+}
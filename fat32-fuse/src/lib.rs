@@ -0,0 +1,284 @@
+//! Mounting a `fat32` file system on the host with FUSE.
+//!
+//! `FuseFs` serves a `Shared<VFat>` read-only through the `fuser` crate, so
+//! that an image can be explored and checked with ordinary tools such as
+//! `diff` and `rsync`:
+//!
+//! ```no_run
+//! extern crate fat32;
+//! extern crate fat32_fuse;
+//!
+//! use fat32::device::ImageFile;
+//! use fat32::vfat::VFat;
+//! use fat32_fuse::FuseFs;
+//!
+//! # fn main() {
+//!
+//! let vfat = VFat::from(ImageFile::open_read_only("sd.img").unwrap()).unwrap();
+//! // Blocks until the file system is unmounted with `fusermount -u mnt`.
+//! FuseFs::new(vfat).mount("mnt").unwrap();
+//! # }
+//! ```
+
+extern crate fat32;
+extern crate fuser;
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+            ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, Request};
+
+use fat32::traits::{self, BlockDevice, Dir, FileSystem, RawLock};
+use fat32::vfat::{self, DefaultLock, Entry, Metadata, Shared, VFat};
+
+/// The inode number of the root directory.
+const ROOT_INO: u64 = 1;
+
+/// How long the kernel may cache attributes and lookups. The file system is
+/// only ever changed through the adapter, which doesn't write, so they
+/// never go stale.
+const TTL: Duration = Duration::from_secs(60);
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const EBADF: i32 = 9;
+const EACCES: i32 = 13;
+const ENOTDIR: i32 = 20;
+const EISDIR: i32 = 21;
+const EINVAL: i32 = 22;
+
+/// Maps the kind of an I/O error to the closest `errno`.
+fn errno(error: &io::Error) -> i32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+/// An entry of a directory listing: its inode number, type and name.
+type Listing = (u64, FileType, String);
+
+/// A read-only FUSE file system backed by a mounted `VFat`.
+///
+/// FAT has no inode numbers, so each path is assigned one the first time the
+/// kernel looks it up, starting at 1 for the root directory. Each file the
+/// kernel opens is kept open under its file handle until it is released, so
+/// that reads continue from the file's cursor instead of walking its path and
+/// cluster chain again.
+pub struct FuseFs<D: BlockDevice, L: RawLock = DefaultLock> {
+    vfat: Shared<VFat<D, L>, L>,
+    paths: Vec<PathBuf>,
+    inodes: HashMap<PathBuf, u64>,
+    files: HashMap<u64, vfat::File<D, L>>,
+    next_fh: u64,
+}
+
+impl<D: BlockDevice, L: RawLock> FuseFs<D, L> {
+    /// Returns a FUSE file system serving `vfat`.
+    pub fn new(vfat: Shared<VFat<D, L>, L>) -> FuseFs<D, L> {
+        let root = PathBuf::from("/");
+        let mut inodes = HashMap::new();
+        inodes.insert(root.clone(), ROOT_INO);
+
+        FuseFs {
+            vfat: vfat,
+            paths: vec![root],
+            inodes: inodes,
+            files: HashMap::new(),
+            next_fh: 1,
+        }
+    }
+
+    /// Mounts the file system read-only at `mountpoint`, serving requests
+    /// until it is unmounted.
+    pub fn mount<P: AsRef<Path>>(self, mountpoint: P) -> io::Result<()> {
+        let options = [MountOption::RO, MountOption::FSName("fat32".to_string())];
+        fuser::mount2(self, mountpoint, &options)
+    }
+
+    /// Returns the path of inode `ino`.
+    fn path(&self, ino: u64) -> Option<&Path> {
+        ino.checked_sub(1)
+           .and_then(|i| self.paths.get(i as usize))
+           .map(|path| path.as_path())
+    }
+
+    /// Returns the inode number of `path`, assigning one if it has none.
+    fn inode(&mut self, path: PathBuf) -> u64 {
+        if let Some(&ino) = self.inodes.get(&path) {
+            return ino;
+        }
+
+        self.paths.push(path.clone());
+        let ino = self.paths.len() as u64;
+        self.inodes.insert(path, ino);
+        ino
+    }
+
+    /// Returns the entry at inode `ino`.
+    fn entry(&self, ino: u64) -> io::Result<Entry<D, L>> {
+        match self.path(ino) {
+            Some(path) => (&self.vfat).open(path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "unknown inode")),
+        }
+    }
+
+    /// Looks up `name` in the directory with inode `parent` and returns its
+    /// attributes.
+    fn lookup_attr(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, i32> {
+        let path = self.path(parent).ok_or(ENOENT)?.join(name);
+        let entry = (&self.vfat).open(&path).map_err(|e| errno(&e))?;
+        let ino = self.inode(path);
+        Ok(attr(ino, &entry))
+    }
+
+    /// Returns the attributes of inode `ino`.
+    fn getattr_ino(&self, ino: u64) -> Result<FileAttr, i32> {
+        self.entry(ino).map(|entry| attr(ino, &entry)).map_err(|e| errno(&e))
+    }
+
+    /// Returns the entries of the directory with inode `ino`, including `.`
+    /// and `..`.
+    fn list(&mut self, ino: u64) -> Result<Vec<Listing>, i32> {
+        use fat32::traits::Entry;
+
+        let path = self.path(ino).ok_or(ENOENT)?.to_path_buf();
+        let entries = match (&self.vfat).open(&path) {
+            Ok(vfat::Entry::Dir(dir)) => dir.entries().map_err(|e| errno(&e))?,
+            Ok(_) => return Err(ENOTDIR),
+            Err(e) => return Err(errno(&e)),
+        };
+
+        // Only subdirectories have `.` and `..` entries on disk, so they are
+        // generated for every directory instead.
+        let parent = path.parent().map(|p| p.to_path_buf()).unwrap_or_else(|| path.clone());
+        let mut listing = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (self.inode(parent), FileType::Directory, "..".to_string()),
+        ];
+        for entry in entries.filter(|e| e.name() != "." && e.name() != "..") {
+            let kind = if entry.is_dir() { FileType::Directory } else { FileType::RegularFile };
+            let ino = self.inode(path.join(entry.name()));
+            listing.push((ino, kind, entry.name().to_string()));
+        }
+        Ok(listing)
+    }
+
+    /// Opens the file with inode `ino` and returns its new file handle.
+    fn open_ino(&mut self, ino: u64) -> Result<u64, i32> {
+        let file = match self.entry(ino) {
+            Ok(vfat::Entry::File(file)) => file,
+            Ok(_) => return Err(EISDIR),
+            Err(e) => return Err(errno(&e)),
+        };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.files.insert(fh, file);
+        Ok(fh)
+    }
+
+    /// Reads up to `size` bytes at `offset` from the file open as `fh`.
+    fn read_fh(&mut self, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let file = self.files.get_mut(&fh).ok_or(EBADF)?;
+        let mut data = Vec::with_capacity(size as usize);
+        // Sequential reads are already positioned, and so keep the cursor.
+        if file.stream_position().map_err(|e| errno(&e))? != offset as u64 {
+            file.seek(SeekFrom::Start(offset as u64)).map_err(|e| errno(&e))?;
+        }
+        file.take(size as u64).read_to_end(&mut data).map_err(|e| errno(&e))?;
+        Ok(data)
+    }
+}
+
+/// Returns the FUSE attributes of the entry `entry` with inode `ino`.
+fn attr<D: BlockDevice, L: RawLock>(ino: u64, entry: &Entry<D, L>) -> FileAttr {
+    use fat32::traits::Entry;
+
+    let metadata: &Metadata = entry.metadata();
+    let (kind, size) = match entry.as_file() {
+        Some(file) => (FileType::RegularFile, traits::File::size(file)),
+        None => (FileType::Directory, 0),
+    };
+
+    // The root directory has no directory entry, and so no timestamps.
+    let time = |ts| if ino == ROOT_INO { UNIX_EPOCH } else { metadata.to_system_time(ts) };
+    FileAttr {
+        ino: ino,
+        size: size,
+        blocks: (size + 511) / 512,
+        atime: time(metadata.atime),
+        mtime: time(metadata.mtime),
+        ctime: time(metadata.mtime),
+        crtime: time(metadata.ctime),
+        kind: kind,
+        perm: (metadata.unix_mode() & 0o7777) as u16,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 512,
+        flags: 0,
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> Filesystem for FuseFs<D, L> {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.lookup_attr(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.getattr_ino(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64,
+               mut reply: ReplyDirectory) {
+        let listing = match self.list(ino) {
+            Ok(listing) => listing,
+            Err(e) => return reply.error(e),
+        };
+
+        // `offset` is that of the last entry the kernel has already received.
+        for (i, (ino, kind, name)) in listing.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok()
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self.open_ino(ino) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn read(&mut self, _req: &Request, _ino: u64, fh: u64, offset: i64, size: u32,
+            _flags: i32, _lock_owner: Option<u64>, reply: ReplyData) {
+        match self.read_fh(fh, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn release(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: i32,
+               _lock_owner: Option<u64>, _flush: bool, reply: ReplyEmpty) {
+        self.files.remove(&fh);
+        reply.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,35 @@
+//! Mounts a FAT32 disk image read-only on the host.
+//!
+//! ```text
+//! usage: fat32-fuse IMAGE MOUNTPOINT
+//! ```
+//!
+//! Serves requests until the file system is unmounted, e.g. with
+//! `fusermount -u MOUNTPOINT`.
+
+extern crate fat32;
+extern crate fat32_fuse;
+
+use std::env;
+use std::io;
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::vfat::VFat;
+use fat32_fuse::FuseFs;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: fat32-fuse IMAGE MOUNTPOINT");
+        process::exit(2);
+    }
+
+    let result = ImageFile::open_read_only(&args[0])
+        .and_then(|image| VFat::from(image).map_err(io::Error::from))
+        .and_then(|vfat| FuseFs::new(vfat).mount(&args[1]));
+    if let Err(e) = result {
+        eprintln!("fat32-fuse: {}: {}", args[0], e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,78 @@
+use std::ffi::OsStr;
+use std::io::Cursor;
+
+use fat32::testing::Fixture;
+use fat32::traits::DynBlockDevice;
+use fat32::vfat::VFat;
+use fuser::FileType;
+
+use super::{FuseFs, EBADF, EISDIR, ENOENT, ENOTDIR, ROOT_INO};
+
+fn mini_fs() -> FuseFs<DynBlockDevice> {
+    let mut fixture = Fixture::mini();
+    for (i, byte) in fixture.data_mut(4, 1000).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    FuseFs::new(VFat::from(Cursor::new(fixture.into_image())).expect("valid image"))
+}
+
+#[test]
+fn test_lookup_and_getattr() {
+    let mut fs = mini_fs();
+    let long = fs.lookup_attr(ROOT_INO, OsStr::new("long.txt")).expect("long.txt exists");
+    assert_eq!(long.kind, FileType::RegularFile);
+    assert_eq!(long.size, 1000);
+    assert_eq!(fs.getattr_ino(long.ino).expect("inode is known").size, 1000);
+
+    let sub = fs.lookup_attr(ROOT_INO, OsStr::new("SUB")).expect("SUB exists");
+    assert_eq!(sub.kind, FileType::Directory);
+    assert_eq!(fs.lookup_attr(sub.ino, OsStr::new("A.BIN")).expect("A.BIN exists").size, 10);
+    // Looking a path up again returns the same inode.
+    assert_eq!(fs.lookup_attr(ROOT_INO, OsStr::new("SUB")).unwrap().ino, sub.ino);
+
+    assert_eq!(fs.lookup_attr(ROOT_INO, OsStr::new("missing")).unwrap_err(), ENOENT);
+    assert_eq!(fs.getattr_ino(1000).unwrap_err(), ENOENT);
+}
+
+#[test]
+fn test_readdir() {
+    let mut fs = mini_fs();
+    let listing = fs.list(ROOT_INO).expect("root is listed");
+    let names: Vec<&str> = listing.iter().map(|(_, _, name)| name.as_str()).collect();
+    assert_eq!(names, [".", "..", "long.txt", "SUB"]);
+    assert_eq!(listing[0].0, ROOT_INO);
+    assert_eq!(listing[1].0, ROOT_INO);
+    assert_eq!(listing[3].1, FileType::Directory);
+
+    let sub = listing[3].0;
+    let listing = fs.list(sub).expect("SUB is listed");
+    let names: Vec<&str> = listing.iter().map(|(_, _, name)| name.as_str()).collect();
+    assert_eq!(names, [".", "..", "A.BIN"]);
+    assert_eq!(listing[1].0, ROOT_INO);
+    assert_eq!(fs.list(listing[2].0).unwrap_err(), ENOTDIR);
+}
+
+#[test]
+fn test_open_read_release() {
+    let mut fs = mini_fs();
+    let ino = fs.lookup_attr(ROOT_INO, OsStr::new("long.txt")).unwrap().ino;
+    let fh = fs.open_ino(ino).expect("long.txt opens");
+    let other = fs.open_ino(ino).expect("long.txt opens twice");
+    assert!(fh != other);
+
+    // Sequential reads, then a read further back and one past the end.
+    let expected: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let mut data = Vec::new();
+    for offset in (0..1000).step_by(300) {
+        data.extend(fs.read_fh(fh, offset, 300).expect("file is read"));
+    }
+    assert_eq!(data, expected);
+    assert_eq!(fs.read_fh(fh, 10, 5).unwrap(), &expected[10..15]);
+    assert!(fs.read_fh(fh, 1000, 16).unwrap().is_empty());
+    assert_eq!(fs.read_fh(other, 600, 10).unwrap(), &expected[600..610]);
+
+    fs.files.remove(&fh);
+    assert_eq!(fs.read_fh(fh, 0, 16).unwrap_err(), EBADF);
+    assert_eq!(fs.read_fh(other, 0, 4).unwrap(), &expected[..4]);
+    assert_eq!(fs.open_ino(ROOT_INO).unwrap_err(), EISDIR);
+}
@@ -0,0 +1,90 @@
+//! Reading FAT32 images in the browser.
+//!
+//! Built for `wasm32-unknown-unknown` with `wasm-pack build --target web`,
+//! this exposes a `Volume` class to JavaScript that mounts an image held in
+//! memory, such as an uploaded SD card image, and lists and reads its files:
+//!
+//! ```js
+//! import init, { Volume } from "./pkg/fat32_wasm.js";
+//!
+//! await init();
+//! const image = new Uint8Array(await file.arrayBuffer());
+//! const volume = new Volume(image);
+//! for (const entry of volume.list("/")) {
+//!     console.log(entry.name, entry.is_dir, entry.size);
+//! }
+//! const config = volume.read("/config.txt");
+//! ```
+//!
+//! The image is copied into the module's memory, and never written to.
+
+extern crate fat32;
+extern crate wasm_bindgen;
+
+use std::io::Read;
+
+use wasm_bindgen::prelude::*;
+
+use fat32::device::MemoryDevice;
+use fat32::traits::{Dir, Entry, FileSystem};
+use fat32::vfat::{self, Shared, VFat};
+
+/// Converts any error to a JavaScript `Error` with its message.
+fn js_error<E: ToString>(e: E) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// An entry of a directory, as returned by `Volume.list()`. Times are
+/// milliseconds since the Unix epoch, like JavaScript's `Date`, taking the
+/// volume's times to be UTC.
+#[wasm_bindgen(getter_with_clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: f64,
+    pub created: f64,
+    pub modified: f64,
+}
+
+/// A mounted FAT32 volume.
+#[wasm_bindgen]
+pub struct Volume {
+    vfat: Shared<VFat>,
+}
+
+#[wasm_bindgen]
+impl Volume {
+    /// Mounts the first FAT32 partition of the disk image `image`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(image: Vec<u8>) -> Result<Volume, JsError> {
+        let vfat = VFat::from(MemoryDevice::from_vec(image)).map_err(js_error)?;
+        Ok(Volume { vfat: vfat })
+    }
+
+    /// Returns the entries of the directory at the absolute path `path`.
+    pub fn list(&self, path: &str) -> Result<Vec<DirEntry>, JsError> {
+        let dir = (&self.vfat).open_dir(path).map_err(js_error)?;
+        let entries = dir.entries().map_err(js_error)?;
+        Ok(entries.map(|entry: vfat::Entry| {
+            let metadata = entry.metadata();
+            DirEntry {
+                name: entry.name().to_string(),
+                is_dir: entry.is_dir(),
+                size: entry.len() as f64,
+                created: metadata.to_unix_seconds(metadata.ctime) as f64 * 1000.0,
+                modified: metadata.to_unix_seconds(metadata.mtime) as f64 * 1000.0,
+            }
+        }).collect())
+    }
+
+    /// Returns the contents of the file at the absolute path `path`.
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, JsError> {
+        let mut file = (&self.vfat).open_file(path).map_err(js_error)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(js_error)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests;
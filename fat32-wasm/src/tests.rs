@@ -0,0 +1,34 @@
+use fat32::testing::Fixture;
+
+use super::Volume;
+
+fn mini_volume() -> Volume {
+    let mut fixture = Fixture::mini();
+    for (i, byte) in fixture.data_mut(4, 1000).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    Volume::new(fixture.into_image()).unwrap_or_else(|_| panic!("valid image"))
+}
+
+#[test]
+fn test_list() {
+    let volume = mini_volume();
+    let root: Vec<_> = volume.list("/").unwrap_or_else(|_| panic!("root is listed")).into_iter()
+        .map(|entry| (entry.name, entry.is_dir, entry.size))
+        .collect();
+    assert_eq!(root, [("long.txt".to_string(), false, 1000.0), ("SUB".to_string(), true, 0.0)]);
+
+    let sub: Vec<_> = volume.list("/SUB").unwrap_or_else(|_| panic!("SUB is listed")).into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(sub, [".", "..", "A.BIN"]);
+}
+
+#[test]
+fn test_read() {
+    let volume = mini_volume();
+    let data = volume.read("/long.txt").unwrap_or_else(|_| panic!("long.txt is read"));
+    assert_eq!(data.len(), 1000);
+    assert!(data.iter().enumerate().all(|(i, &b)| b == i as u8));
+    assert_eq!(volume.read("/SUB/A.BIN").unwrap_or_else(|_| panic!("A.BIN is read")), &data[..10]);
+}
@@ -0,0 +1,120 @@
+//! Benchmarks for reading from a mounted image.
+//!
+//! The fixture image is generated with `tools::ImageBuilder` from a temporary
+//! directory holding one large file and one directory with many entries, so
+//! no resources need to be fetched first. Run with `cargo bench`.
+
+#[macro_use]
+extern crate criterion;
+extern crate fat32;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use criterion::{BatchSize, Criterion, Throughput};
+
+use fat32::device::MemoryDevice;
+use fat32::tools::ImageBuilder;
+use fat32::traits::{Dir, FileSystem};
+use fat32::vfat::VFat;
+
+const IMAGE_SIZE: u64 = 64 << 20;
+const BIG_FILE: &str = "/big.bin";
+const BIG_FILE_SIZE: usize = 16 << 20;
+const DIR_ENTRIES: usize = 2000;
+/// The last entry of the large directory, so a lookup scans all of it.
+const LAST_ENTRY: &str = "/many/file1999.txt";
+const READ_SIZE: usize = 4096;
+
+/// Builds the fixture image in memory.
+fn fixture() -> MemoryDevice {
+    let source = ::std::env::temp_dir().join(format!("fat32-bench-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(source.join("many")).expect("fixture directory is created");
+    let data: Vec<u8> = (0..BIG_FILE_SIZE).map(|i| (i * 7) as u8).collect();
+    fs::write(source.join(&BIG_FILE[1..]), &data).expect("large file is written");
+    for i in 0..DIR_ENTRIES {
+        let path: PathBuf = source.join(format!("many/file{:04}.txt", i));
+        fs::write(path, b"").expect("directory entry is written");
+    }
+
+    let mut device = MemoryDevice::new(IMAGE_SIZE as usize);
+    ImageBuilder::new(IMAGE_SIZE).build(&source, &mut device).expect("fixture image is built");
+    fs::remove_dir_all(&source).expect("fixture directory is removed");
+    device
+}
+
+/// Offsets for random reads, from a fixed linear congruential sequence so
+/// that runs are comparable.
+fn random_offsets(count: usize) -> Vec<u64> {
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    (0..count).map(|_| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) % (BIG_FILE_SIZE - READ_SIZE) as u64
+    }).collect()
+}
+
+fn benches(c: &mut Criterion) {
+    let device = fixture();
+    let vfat = VFat::from(device.clone()).expect("fixture mounts");
+
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Bytes(BIG_FILE_SIZE as u64));
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        let mut buf = vec![0; 64 << 10];
+        b.iter(|| {
+            let mut file = vfat.open_file(BIG_FILE).unwrap();
+            while file.read(&mut buf).unwrap() != 0 { }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("seek");
+    let offsets = random_offsets(64);
+    group.throughput(Throughput::Elements(offsets.len() as u64));
+    group.bench_function("random_4k", |b| {
+        let mut file = vfat.open_file(BIG_FILE).unwrap();
+        let mut buf = [0; READ_SIZE];
+        b.iter(|| {
+            for &offset in offsets.iter() {
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.read_exact(&mut buf).unwrap();
+            }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("lookup");
+    group.bench_function("last_of_2000", |b| {
+        b.iter(|| vfat.open_file(LAST_ENTRY).unwrap())
+    });
+    group.bench_function("list_2000", |b| {
+        b.iter(|| vfat.open_dir("/many").unwrap().entries().unwrap().count())
+    });
+    group.finish();
+
+    // A cold mount reads the BPB and every sector anew; a warm one reuses
+    // the sectors cached by the file system already mounted.
+    let mut group = c.benchmark_group("mount");
+    group.bench_function("cold", |b| {
+        b.iter_batched(|| device.clone(), |device| {
+            let vfat = VFat::from(device).unwrap();
+            let mut buf = [0; READ_SIZE];
+            vfat.open_file(LAST_ENTRY).unwrap();
+            vfat.open_file(BIG_FILE).unwrap().read_exact(&mut buf).unwrap();
+        }, BatchSize::LargeInput)
+    });
+    group.bench_function("warm", |b| {
+        let mut buf = [0; READ_SIZE];
+        b.iter(|| {
+            vfat.open_file(LAST_ENTRY).unwrap();
+            vfat.open_file(BIG_FILE).unwrap().read_exact(&mut buf).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(fat32_benches, benches);
+criterion_main!(fat32_benches);
@@ -0,0 +1,13 @@
+//! Overwrites the directory clusters of a small valid image with arbitrary
+//! bytes, then walks and reads the resulting directory tree.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fat32_fuzz;
+
+use fat32_fuzz::{image_with, exercise, DIRS};
+
+fuzz_target!(|data: &[u8]| {
+    exercise(image_with(DIRS, data));
+});
@@ -0,0 +1,19 @@
+//! Feeds arbitrary bytes to `BiosParameterBlock::from()` and validates the
+//! result against the length of the input.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fat32;
+
+use fat32::device::MemoryDevice;
+use fat32::vfat::BiosParameterBlock;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bpb) = BiosParameterBlock::from(MemoryDevice::from_bytes(data), 0) {
+        let _ = format!("{:?}", bpb);
+        let _ = bpb.sectors_per_fat();
+        let _ = bpb.total_logical_sectors();
+        let _ = bpb.validate(data.len() as u64);
+    }
+});
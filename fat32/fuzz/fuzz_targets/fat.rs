@@ -0,0 +1,13 @@
+//! Overwrites the FAT of a small valid image with arbitrary bytes, then
+//! mounts the image and reads everything reachable through it.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fat32_fuzz;
+
+use fat32_fuzz::{image_with, exercise, FAT};
+
+fuzz_target!(|data: &[u8]| {
+    exercise(image_with(FAT, data));
+});
@@ -0,0 +1,19 @@
+//! Feeds arbitrary bytes to `MasterBootRecord::from()` as sector 0.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fat32;
+
+use fat32::MasterBootRecord;
+use fat32::device::MemoryDevice;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mbr) = MasterBootRecord::from(MemoryDevice::from_bytes(data)) {
+        let _ = format!("{:?}", mbr);
+        if let Some(partition) = mbr.first_fat32() {
+            let _ = partition.relative_sector.checked_add(partition.total_sectors);
+        }
+        assert_eq!(MasterBootRecord::decode(&mbr.encode()).encode()[..], mbr.encode()[..]);
+    }
+});
@@ -0,0 +1,99 @@
+//! Shared setup for the fuzz targets, run with `cargo fuzz run <target>` from
+//! the `fat32` directory. Targets:
+//!
+//!   * `mbr`: arbitrary bytes as the master boot record.
+//!   * `ebpb`: arbitrary bytes as the extended BIOS parameter block.
+//!   * `fat`: a valid image whose FAT is replaced by arbitrary bytes.
+//!   * `dir`: a valid image whose directory clusters are replaced by
+//!     arbitrary bytes.
+//!
+//! The last two keep the rest of the image valid so that inputs reach the
+//! cluster chain and directory entry parsers instead of failing to mount.
+
+extern crate fat32;
+
+use std::io::Read;
+use std::ops::Range;
+
+use fat32::device::MemoryDevice;
+use fat32::testing::{self, Fixture, ROOT, SECTOR_SIZE, SUB};
+use fat32::traits::{Dir, Entry, FileSystem};
+use fat32::vfat::{self, MountOptions, VFat};
+
+/// The bytes of the base image holding its single-sector FAT.
+pub const FAT: Range<usize> = testing::FAT..testing::FAT + SECTOR_SIZE;
+/// The bytes of the base image holding the root directory and the
+/// subdirectory, which follows it on disk.
+pub const DIRS: Range<usize> = Fixture::cluster_offset(ROOT)
+    ..Fixture::cluster_offset(SUB) + SECTOR_SIZE;
+
+/// The most entries visited per input, so that looping directories end.
+const MAX_ENTRIES: usize = 256;
+const MAX_DEPTH: usize = 8;
+
+/// Returns the image the tests start from, `Fixture::mini()`: a long-named
+/// file and a subdirectory in the root, and a second file in the
+/// subdirectory.
+pub fn base_image() -> Vec<u8> {
+    Fixture::mini().into_image()
+}
+
+/// Returns the base image with the start of `region` replaced by `data`.
+/// Input beyond the end of the region is ignored.
+pub fn image_with(region: Range<usize>, data: &[u8]) -> Vec<u8> {
+    let mut image = base_image();
+    let len = ::std::cmp::min(data.len(), region.end - region.start);
+    image[region.start..region.start + len].copy_from_slice(&data[..len]);
+    image
+}
+
+/// Mounts `image`, strictly and leniently, and reads every reachable file
+/// and directory. Errors are expected; only panics are failures.
+pub fn exercise(image: Vec<u8>) {
+    let lenient = MountOptions { lenient: true, ..MountOptions::default() };
+    for &options in [MountOptions::default(), lenient].iter() {
+        let vfat = match VFat::from_with_options(MemoryDevice::from_vec(image.clone()), options) {
+            Ok(vfat) => vfat,
+            Err(_) => continue,
+        };
+        let _ = vfat.borrow_mut().usage();
+        if let Ok(root) = vfat.open_dir("/") {
+            walk(&root, 0, &mut MAX_ENTRIES.clone());
+        }
+    }
+}
+
+fn walk(dir: &vfat::Dir, depth: usize, budget: &mut usize) {
+    let entries = match dir.entries() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries {
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
+
+        let _ = entry.metadata().to_string();
+        match entry {
+            vfat::Entry::File(mut file) => {
+                let mut buf = [0u8; 512];
+                loop {
+                    match file.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => continue,
+                    }
+                }
+            }
+            vfat::Entry::Dir(ref subdir) if depth < MAX_DEPTH && !is_dot(subdir.name()) => {
+                walk(subdir, depth + 1, budget)
+            }
+            vfat::Entry::Dir(_) => {}
+        }
+    }
+}
+
+fn is_dot(name: &str) -> bool {
+    name == "." || name == ".."
+}
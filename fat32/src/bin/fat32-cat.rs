@@ -0,0 +1,104 @@
+//! Copies a file out of a FAT32 disk image.
+//!
+//! ```text
+//! usage: fat32-cat [-o OUTPUT] [--offset BYTES] [--length BYTES] IMAGE PATH
+//! ```
+//!
+//! The file is written to standard output, or to the host file `OUTPUT`.
+//! `--offset` skips that many bytes of the file and `--length` stops after
+//! copying that many.
+
+extern crate fat32;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::traits::FileSystem;
+use fat32::vfat::VFat;
+
+const USAGE: &str = "usage: fat32-cat [-o OUTPUT] [--offset BYTES] [--length BYTES] IMAGE PATH";
+
+struct Options {
+    image: String,
+    path: String,
+    output: Option<String>,
+    offset: u64,
+    length: Option<u64>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("fat32-cat: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut output = None;
+    let mut offset = 0;
+    let mut length = None;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().unwrap_or_else(|| usage_error(&format!("{} requires a value", name)))
+        };
+        let mut number = |name: &str| {
+            value(name).parse::<u64>()
+                       .unwrap_or_else(|_| usage_error(&format!("{} requires a number", name)))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value(&arg)),
+            "--offset" => offset = number(&arg),
+            "--length" => length = Some(number(&arg)),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        usage_error("expected an image and a path");
+    }
+    let path = positional.pop().unwrap();
+    let image = positional.pop().unwrap();
+    Options {
+        image: image,
+        path: path,
+        output: output,
+        offset: offset,
+        length: length,
+    }
+}
+
+fn copy(options: &Options) -> io::Result<u64> {
+    let vfat = VFat::from(ImageFile::open_read_only(&options.image)?)?;
+    let mut file = vfat.open_file(&options.path)?;
+    file.seek(SeekFrom::Start(options.offset))?;
+    let mut input = file.take(options.length.unwrap_or(u64::max_value()));
+
+    match options.output {
+        Some(ref output) => io::copy(&mut input, &mut fs::File::create(output)?),
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            let copied = io::copy(&mut input, &mut stdout)?;
+            stdout.flush()?;
+            Ok(copied)
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    if let Err(e) = copy(&options) {
+        eprintln!("fat32-cat: {}: {}", options.image, e);
+        process::exit(1);
+    }
+}
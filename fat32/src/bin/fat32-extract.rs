@@ -0,0 +1,78 @@
+//! Copies a directory tree out of a FAT32 disk image to the host.
+//!
+//! ```text
+//! usage: fat32-extract [-v] [-j THREADS] IMAGE [PATH] DEST
+//! ```
+//!
+//! `PATH`, the root directory by default, is copied to `DEST`, preserving
+//! timestamps and the read-only attribute. With `-v`, every file is printed as
+//! it is copied. With `-j`, large files are read on `THREADS` threads.
+
+extern crate fat32;
+
+use std::env;
+use std::io;
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::tools;
+use fat32::vfat::{MountOptions, VFat};
+
+const USAGE: &str = "usage: fat32-extract [-v] [-j THREADS] IMAGE [PATH] DEST";
+
+fn main() {
+    let mut verbose = false;
+    let mut options = MountOptions::default();
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-v" => verbose = true,
+            "-j" => match argv.next().and_then(|n| n.parse().ok()) {
+                Some(threads) if threads > 0 => options.read_threads = threads,
+                _ => {
+                    eprintln!("fat32-extract: -j needs a number of threads\n{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("fat32-extract: unknown option {}\n{}", arg, USAGE);
+                process::exit(2);
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    let (image, path, dest) = match args.len() {
+        2 => (&args[0], "/", &args[1]),
+        3 => (&args[0], args[1].as_str(), &args[2]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = ImageFile::open_read_only(image)
+        .and_then(|image| VFat::from_with_options(image, options).map_err(io::Error::from))
+        .and_then(|vfat| tools::extract(&vfat, path, dest, |path, _| {
+            if verbose {
+                println!("{}", path.display());
+            }
+        }));
+    match result {
+        Ok(totals) => {
+            if verbose {
+                println!("{} files, {} directories, {} bytes",
+                         totals.files, totals.dirs, totals.bytes);
+            }
+        }
+        Err(e) => {
+            eprintln!("fat32-extract: {}: {}", image, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Checks the consistency of a FAT32 disk image.
+//!
+//! ```text
+//! usage: fat32-fsck [--json] IMAGE
+//! ```
+//!
+//! Every finding is printed on a line of its own, or with `--json`, the whole
+//! report is printed as a JSON object. Nothing is written to the image. The
+//! exit status is 0 if the image is clean, 1 if anything worse than
+//! information was found, and 2 if the image couldn't be checked.
+
+extern crate fat32;
+
+use std::env;
+use std::io;
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::tools::fsck;
+use fat32::vfat::VFat;
+
+const USAGE: &str = "usage: fat32-fsck [--json] IMAGE";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("fat32-fsck: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut json = false;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+            _ => args.push(arg),
+        }
+    }
+    if args.len() != 1 {
+        usage_error("expected one image");
+    }
+
+    let image = &args[0];
+    let result = ImageFile::open_read_only(image)
+        .and_then(|image| Ok(VFat::from(image)?))
+        .and_then(|vfat| fsck::check(&vfat));
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("fat32-fsck: {}: {}", image, e);
+            process::exit(2);
+        }
+    };
+
+    if json {
+        let stdout = io::stdout();
+        if let Err(e) = report.write_json(stdout.lock()) {
+            eprintln!("fat32-fsck: {}", e);
+            process::exit(2);
+        }
+    } else {
+        for finding in report.findings.iter() {
+            println!("{}", finding);
+        }
+        println!("{}: {} files, {} directories, {} findings", image, report.files, report.dirs,
+                 report.findings.len());
+    }
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
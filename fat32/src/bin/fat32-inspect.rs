@@ -0,0 +1,98 @@
+//! Prints the on-disk structures of a FAT32 disk image.
+//!
+//! ```text
+//! usage: fat32-inspect [--mbr] [--bpb] [--fsinfo] [--fat SECTORS] [--dir] IMAGE [PATH]
+//! ```
+//!
+//! Each structure is printed with the raw bytes of its fields beside their
+//! decoded values. Without options, every structure is printed, with the
+//! first sector of the FAT and the directory entries of `PATH`, the root
+//! directory by default.
+
+extern crate fat32;
+
+use std::env;
+use std::io;
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::tools::Inspector;
+
+const USAGE: &str =
+    "usage: fat32-inspect [--mbr] [--bpb] [--fsinfo] [--fat SECTORS] [--dir] IMAGE [PATH]";
+
+#[derive(Default)]
+struct Sections {
+    mbr: bool,
+    bpb: bool,
+    fsinfo: bool,
+    fat: Option<u32>,
+    dir: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("fat32-inspect: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut sections = Sections::default();
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--mbr" => sections.mbr = true,
+            "--bpb" => sections.bpb = true,
+            "--fsinfo" => sections.fsinfo = true,
+            "--dir" => sections.dir = true,
+            "--fat" => match argv.next().and_then(|n| n.parse().ok()) {
+                Some(n) => sections.fat = Some(n),
+                None => usage_error("--fat needs a number of sectors"),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+            _ => args.push(arg),
+        }
+    }
+
+    let (image, path) = match args.len() {
+        1 => (&args[0], "/"),
+        2 => (&args[0], args[1].as_str()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if !(sections.mbr || sections.bpb || sections.fsinfo || sections.fat.is_some()
+         || sections.dir) {
+        sections = Sections { mbr: true, bpb: true, fsinfo: true, fat: Some(1), dir: true };
+    }
+
+    let result = ImageFile::open_read_only(image).and_then(|image| {
+        let stdout = io::stdout();
+        let mut inspector = Inspector::new(image, stdout.lock());
+        if sections.mbr {
+            inspector.mbr()?;
+        }
+        if sections.bpb {
+            inspector.bpb()?;
+        }
+        if sections.fsinfo {
+            inspector.fsinfo()?;
+        }
+        if let Some(sectors) = sections.fat {
+            inspector.fat(sectors)?;
+        }
+        if sections.dir {
+            inspector.dir(path)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("fat32-inspect: {}: {}", image, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Lists the contents of a directory in a FAT32 disk image.
+//!
+//! ```text
+//! usage: fat32-ls [-R] IMAGE [PATH]
+//! ```
+//!
+//! Each entry is printed with its attributes, modification time, size, short
+//! 8.3 name, and long name.
+
+extern crate fat32;
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use fat32::device::ImageFile;
+use fat32::traits::{Dir, Entry, FileSystem};
+use fat32::vfat::{Shared, VFat};
+
+const USAGE: &str = "usage: fat32-ls [-R] IMAGE [PATH]";
+
+fn list(vfat: &Shared<VFat>, path: &Path, recursive: bool) -> io::Result<()> {
+    let mut subdirs = vec![];
+    for entry in vfat.open_dir(path)?.entries()? {
+        println!("{} {:<12} {}", entry.metadata(), entry.short_name(), entry.name());
+        if recursive && entry.is_dir() && entry.name() != "." && entry.name() != ".." {
+            subdirs.push(path.join(entry.name()));
+        }
+    }
+
+    for subdir in subdirs {
+        println!("\n{}:", subdir.display());
+        list(vfat, &subdir, recursive)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut recursive = false;
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-R" => recursive = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("fat32-ls: unknown option {}\n{}", arg, USAGE);
+                process::exit(2);
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    let (image, path) = match args.len() {
+        1 => (&args[0], PathBuf::from("/")),
+        2 => (&args[0], PathBuf::from(&args[1])),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if recursive {
+        println!("{}:", path.display());
+    }
+    let result = ImageFile::open_read_only(image)
+        .and_then(|image| VFat::from(image).map_err(io::Error::from))
+        .and_then(|vfat| list(&vfat, &path, recursive));
+    if let Err(e) = result {
+        eprintln!("fat32-ls: {}: {}", image, e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,75 @@
+//! Builds a partitioned FAT32 disk image from a directory on the host.
+//!
+//! ```text
+//! usage: fat32-mkimage -s SIZE [-L LABEL] SRC_DIR IMAGE
+//! ```
+//!
+//! `SIZE` is in bytes and may carry a `K`, `M`, or `G` suffix. The resulting
+//! image can be written directly to an SD card.
+
+extern crate fat32;
+
+use std::env;
+use std::process;
+
+use fat32::tools::ImageBuilder;
+
+const USAGE: &str = "usage: fat32-mkimage -s SIZE [-L LABEL] SRC_DIR IMAGE";
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("fat32-mkimage: {}\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_size(arg: &str) -> Option<u64> {
+    let (digits, shift) = match arg.chars().last()? {
+        'k' | 'K' => (&arg[..arg.len() - 1], 10),
+        'm' | 'M' => (&arg[..arg.len() - 1], 20),
+        'g' | 'G' => (&arg[..arg.len() - 1], 30),
+        _ => (arg, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+fn main() {
+    let mut size = None;
+    let mut label = None;
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "-s" => match argv.next().as_ref().and_then(|s| parse_size(s)) {
+                Some(s) => size = Some(s),
+                None => usage_error("-s needs a size"),
+            },
+            "-L" => match argv.next() {
+                Some(l) => label = Some(l),
+                None => usage_error("-L needs a label"),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option {}", arg)),
+            _ => args.push(arg),
+        }
+    }
+
+    let size = match size {
+        Some(size) => size,
+        None => usage_error("the image size is required"),
+    };
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let mut builder = ImageBuilder::new(size);
+    if let Some(ref label) = label {
+        builder = builder.label(label);
+    }
+    if let Err(e) = builder.build_file(&args[0], &args[1]) {
+        eprintln!("fat32-mkimage: {}: {}", args[1], e);
+        process::exit(1);
+    }
+}
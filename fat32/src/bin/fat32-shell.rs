@@ -0,0 +1,174 @@
+//! An interactive shell for browsing a FAT32 disk image.
+//!
+//! ```text
+//! usage: fat32-shell IMAGE
+//! ```
+//!
+//! Commands are read from standard input, one per line:
+//!
+//! ```text
+//! pwd            print the working directory
+//! cd [DIR]       change the working directory, to / by default
+//! ls [-a] [DIR]  list a directory; -a includes hidden entries
+//! cat FILE...    print the contents of files
+//! stat PATH...   print the metadata of files and directories
+//! exit           leave the shell
+//! ```
+
+extern crate fat32;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+
+use fat32::traits::{Dir, Entry, FileSystem, Metadata as MetadataTrait};
+use fat32::device::ImageFile;
+use fat32::vfat::{self, Shared, VFat};
+
+const USAGE: &str = "usage: fat32-shell IMAGE";
+const HELP: &str = "\
+pwd            print the working directory
+cd [DIR]       change the working directory, to / by default
+ls [-a] [DIR]  list a directory; -a includes hidden entries
+cat FILE...    print the contents of files
+stat PATH...   print the metadata of files and directories
+exit           leave the shell";
+
+/// Names the attributes set in `attr`, as in `archive read-only`.
+fn attributes(attr: &vfat::Attributes) -> String {
+    let names = [
+        (attr.directory(), "directory"),
+        (attr.archive(), "archive"),
+        (attr.read_only(), "read-only"),
+        (attr.hidden(), "hidden"),
+        (attr.system(), "system"),
+        (attr.volume_id(), "volume-id"),
+    ];
+    let set: Vec<&str> = names.iter().filter(|&&(set, _)| set).map(|&(_, name)| name).collect();
+    if set.is_empty() { "none".to_string() } else { set.join(" ") }
+}
+
+struct Shell {
+    vfat: Shared<VFat>,
+    cwd: PathBuf,
+}
+
+impl Shell {
+    /// Resolves `path` against the working directory. The file system
+    /// looks each component up with `Dir::find()`, following `..` entries.
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.cwd.join(path)
+    }
+
+    fn cd(&mut self, args: &[&str]) -> io::Result<()> {
+        let dir = self.vfat.open_dir(self.resolve(args.first().cloned().unwrap_or("/")))?;
+        // The directory's path has `.` and `..` resolved and the names as
+        // stored, whatever their case in `args`.
+        self.cwd = dir.path().to_path_buf();
+        Ok(())
+    }
+
+    fn ls(&self, args: &[&str]) -> io::Result<()> {
+        let all = args.contains(&"-a");
+        let dir = args.iter().find(|a| !a.starts_with('-')).cloned().unwrap_or(".");
+        for entry in self.vfat.open_dir(self.resolve(dir))?.entries()? {
+            if all || !entry.metadata().hidden() {
+                println!("{} {}", entry.metadata(), entry.name());
+            }
+        }
+        Ok(())
+    }
+
+    fn cat(&self, args: &[&str]) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        for path in args {
+            let mut file = self.vfat.open_file(self.resolve(path))?;
+            io::copy(&mut file, &mut stdout)?;
+        }
+        stdout.flush()
+    }
+
+    fn stat(&self, args: &[&str]) -> io::Result<()> {
+        for path in args {
+            let entry = self.vfat.open(self.resolve(path))?;
+            let metadata = entry.metadata();
+            let (short_name, path) = match entry {
+                vfat::Entry::File(ref f) => (f.short_name(), f.path()),
+                vfat::Entry::Dir(ref d) => (d.short_name(), d.path()),
+            };
+            println!("    Path: {}", path.display());
+            println!("    Type: {}", if entry.is_dir() { "directory" } else { "file" });
+            println!("   Short: {}", short_name);
+            println!("    Size: {}", metadata.size());
+            println!("   Attrs: {}", attributes(&metadata.attr));
+            println!(" Created: {}", metadata.created());
+            println!("Accessed: {}", metadata.accessed());
+            println!("Modified: {}", metadata.modified());
+        }
+        Ok(())
+    }
+
+    /// Runs the command on `line`. Returns `false` once the shell should exit.
+    fn run(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        let result = match command {
+            "pwd" => { println!("{}", self.cwd.display()); Ok(()) }
+            "cd" => self.cd(args),
+            "ls" => self.ls(args),
+            "cat" if !args.is_empty() => self.cat(args),
+            "stat" if !args.is_empty() => self.stat(args),
+            "cat" | "stat" => { eprintln!("{}: missing operand", command); Ok(()) }
+            "help" => { println!("{}", HELP); Ok(()) }
+            "exit" | "quit" => return false,
+            _ => { eprintln!("{}: unknown command; try help", command); Ok(()) }
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {}", command, e);
+        }
+        true
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 1 || args[0].starts_with('-') {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let vfat = match ImageFile::open_read_only(&args[0])
+        .and_then(|image| VFat::from(image).map_err(io::Error::from))
+    {
+        Ok(vfat) => vfat,
+        Err(e) => {
+            eprintln!("fat32-shell: {}: {}", args[0], e);
+            process::exit(1);
+        }
+    };
+
+    let mut shell = Shell { vfat: vfat, cwd: PathBuf::from("/") };
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}> ", shell.cwd.display());
+        io::stdout().flush().expect("stdout is writable");
+        match lines.next() {
+            Some(Ok(line)) => if !shell.run(&line) { break },
+            Some(Err(e)) => {
+                eprintln!("fat32-shell: {}", e);
+                process::exit(1);
+            }
+            None => {
+                println!();
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use traits::BlockDevice;
+use util;
+
+/// The compression format of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// An independently compressed part of an image: a gzip member or a zstd
+/// frame.
+#[derive(Debug)]
+struct Member {
+    /// The offset of the member in the compressed file.
+    offset: u64,
+    /// The offset of the member's first byte in the decompressed image.
+    start: u64,
+    /// The number of bytes the member decompresses to.
+    len: u64,
+}
+
+/// A read-only disk image compressed with gzip (the `gzip` feature) or zstd
+/// (the `zstd` feature).
+///
+/// When the image is opened, it is decompressed once to build an index of
+/// its members: gzip members or zstd frames, each of which can be
+/// decompressed on its own. Reading a sector then only decompresses the
+/// member holding it. The most recently used member is kept in memory.
+///
+/// Since a member is the unit of random access, images should be compressed
+/// as many small members, as `bgzip` and `pzstd` do, or by concatenating
+/// separately compressed chunks of the image. An image compressed as a single
+/// member is held in memory in its entirety.
+///
+/// Writing a sector fails with an error of `PermissionDenied`.
+#[derive(Debug)]
+pub struct CompressedImage {
+    file: BufReader<fs::File>,
+    format: Format,
+    members: Vec<Member>,
+    len: u64,
+    sector_size: u64,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl CompressedImage {
+    /// Opens the compressed image at `path` with 512 byte sectors, detecting
+    /// its format from its magic number.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the image isn't in a supported
+    /// format or fails to decompress.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CompressedImage> {
+        let mut file = BufReader::new(fs::File::open(path)?);
+        let format = detect(file.fill_buf()?)?;
+
+        let mut members = vec![];
+        let mut len = 0;
+        let mut offset = 0;
+        while !file.fill_buf()?.is_empty() {
+            let member_len = decompress(format, &mut file, &mut io::sink())?;
+            if member_len != 0 {
+                members.push(Member { offset: offset, start: len, len: member_len });
+                len += member_len;
+            }
+            offset = file.seek(SeekFrom::Current(0))?;
+        }
+
+        Ok(CompressedImage {
+            file: file,
+            format: format,
+            members: members,
+            len: len,
+            sector_size: 512,
+            cached: None,
+        })
+    }
+
+    /// Sets the size of the image's sectors to `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of 512.
+    pub fn with_sector_size(mut self, sector_size: u64) -> CompressedImage {
+        assert!(sector_size != 0 && sector_size % 512 == 0,
+                "sector size must be a non-zero multiple of 512");
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Returns the size of the decompressed image in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the number of independently compressed members in the image.
+    pub fn num_members(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns the decompressed contents of member `i`, decompressing it if
+    /// it isn't the cached member.
+    fn member(&mut self, i: usize) -> io::Result<&[u8]> {
+        if self.cached.as_ref().map(|&(cached, _)| cached) != Some(i) {
+            self.cached = None;
+            let member = &self.members[i];
+            let mut data = Vec::with_capacity(member.len as usize);
+            self.file.seek(SeekFrom::Start(member.offset))?;
+            decompress(self.format, &mut self.file, &mut data)?;
+            if data.len() as u64 != member.len {
+                return Err(util::corrupt("compressed image changed since it was opened"));
+            }
+            self.cached = Some((i, data));
+        }
+
+        match self.cached {
+            Some((_, ref data)) => Ok(data),
+            None => unreachable!("member was just cached"),
+        }
+    }
+}
+
+/// Returns the format of the compressed data beginning with `magic`.
+fn detect(magic: &[u8]) -> io::Result<Format> {
+    #[cfg(feature = "gzip")]
+    {
+        if magic.starts_with(&[0x1F, 0x8B]) {
+            return Ok(Format::Gzip);
+        }
+    }
+    #[cfg(feature = "zstd")]
+    {
+        if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            return Ok(Format::Zstd);
+        }
+    }
+    Err(util::corrupt("unrecognized or unsupported compression format"))
+}
+
+/// Decompresses the member at the current position of `reader` into `out`,
+/// leaving `reader` positioned at the start of the next member. Returns the
+/// number of decompressed bytes.
+fn decompress<W: Write>(format: Format, reader: &mut BufReader<fs::File>,
+                        out: &mut W) -> io::Result<u64> {
+    let result = match format {
+        #[cfg(feature = "gzip")]
+        Format::Gzip => io::copy(&mut ::flate2::bufread::GzDecoder::new(reader), out),
+        #[cfg(feature = "zstd")]
+        Format::Zstd => {
+            let mut decoder = ::ruzstd::StreamingDecoder::new(reader)
+                .map_err(|e| util::corrupt(format!("invalid zstd frame: {}", e)))?;
+            io::copy(&mut decoder, out)
+        }
+    };
+
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::Other => util::corrupt(e.to_string()),
+        _ => e,
+    })
+}
+
+impl BlockDevice for CompressedImage {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = n.checked_mul(self.sector_size).filter(|&start| start < self.len)
+            .ok_or_else(|| util::eof(format!("sector {} is past the end of the image", n)))?;
+        let end = ::std::cmp::min(start + self.sector_size, self.len);
+        let to_read = ::std::cmp::min((end - start) as usize, buf.len());
+
+        // A sector may span several members.
+        let mut read = 0;
+        while read < to_read {
+            let pos = start + read as u64;
+            let i = match self.members.binary_search_by_key(&pos, |m| m.start) {
+                Ok(i) => i,
+                Err(i) => i - 1,
+            };
+            let offset = (pos - self.members[i].start) as usize;
+            let data = self.member(i)?;
+            let count = ::std::cmp::min(data.len() - offset, to_read - read);
+            buf[read..read + count].copy_from_slice(&data[offset..offset + count]);
+            read += count;
+        }
+        Ok(read)
+    }
+
+    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
+        Err(util::permission_denied("compressed images are read-only"))
+    }
+
+    fn discard(&mut self, _n: u64, _count: u64) -> io::Result<()> {
+        Err(util::permission_denied("compressed images are read-only"))
+    }
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::collections::BTreeMap;
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::Duration;
+
+use traits::BlockDevice;
+use util;
+
+/// A fault that `FaultyDevice` injects into accesses to a sector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Reads and writes of the sector fail with an error of kind `Other`.
+    Fail,
+    /// Reads of the sector fail with an error of kind `Other` `.0` times and
+    /// then succeed.
+    FailTimes(usize),
+    /// Reads of the sector return at most `.0` bytes.
+    ShortRead(usize),
+    /// Reads of the sector return the data with bit `bit` (0 is the least
+    /// significant) of byte `byte` inverted.
+    FlipBit { byte: usize, bit: u8 },
+    /// Reads and writes of the sector take at least `.0` longer.
+    #[cfg(feature = "std")]
+    Delay(Duration),
+}
+
+/// A `BlockDevice` wrapper for resilience testing that injects scripted
+/// faults into accesses to specific sectors of `D`.
+///
+/// Faults on a sector are applied in the order they were injected. Sectors
+/// without faults are accessed normally.
+#[derive(Debug)]
+pub struct FaultyDevice<D> {
+    device: D,
+    faults: BTreeMap<u64, Vec<Fault>>,
+}
+
+impl<D: BlockDevice> FaultyDevice<D> {
+    /// Wraps `device` without any faults.
+    pub fn new(device: D) -> FaultyDevice<D> {
+        FaultyDevice {
+            device: device,
+            faults: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `fault` to accesses of sector `sector`.
+    pub fn inject(&mut self, sector: u64, fault: Fault) -> &mut FaultyDevice<D> {
+        self.faults.entry(sector).or_insert_with(Vec::new).push(fault);
+        self
+    }
+
+    /// Removes every fault from sector `sector`.
+    pub fn clear(&mut self, sector: u64) {
+        self.faults.remove(&sector);
+    }
+
+    /// Removes every fault from every sector.
+    pub fn clear_all(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+fn injected(n: u64) -> io::Error {
+    util::failed(format!("injected fault on sector {}", n))
+}
+
+impl<D: BlockDevice> BlockDevice for FaultyDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut limit = buf.len();
+        if let Some(faults) = self.faults.get_mut(&n) {
+            for fault in faults.iter_mut() {
+                match *fault {
+                    Fault::Fail => return Err(injected(n)),
+                    Fault::FailTimes(ref mut times) if *times > 0 => {
+                        *times -= 1;
+                        return Err(injected(n));
+                    }
+                    Fault::ShortRead(len) => limit = ::std::cmp::min(limit, len),
+                    #[cfg(feature = "std")]
+                    Fault::Delay(duration) => ::std::thread::sleep(duration),
+                    _ => {}
+                }
+            }
+        }
+
+        let read = self.device.read_sector(n, &mut buf[..limit])?;
+        if let Some(faults) = self.faults.get(&n) {
+            for fault in faults.iter() {
+                if let Fault::FlipBit { byte, bit } = *fault {
+                    if byte < read {
+                        buf[byte] ^= 1 << (bit % 8);
+                    }
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if let Some(faults) = self.faults.get(&n) {
+            for fault in faults.iter() {
+                match *fault {
+                    Fault::Fail => return Err(injected(n)),
+                    #[cfg(feature = "std")]
+                    Fault::Delay(duration) => ::std::thread::sleep(duration),
+                    _ => {}
+                }
+            }
+        }
+        self.device.write_sector(n, buf)
+    }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        self.device.discard(n, count)
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(not(unix))]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(not(unix))]
+use std::sync::{Arc, Mutex};
+
+use traits::BlockDevice;
+use util;
+
+/// A disk image stored in a file on the host, used as a `BlockDevice`.
+///
+/// ```rust,no_run
+/// use fat32::vfat::VFat;
+/// use fat32::device::ImageFile;
+///
+/// let image = ImageFile::open_read_only("card.img").expect("image exists");
+/// let vfat = VFat::from(image).expect("valid FAT32 image");
+/// ```
+///
+/// On Unix, sectors are read and written with positioned I/O, `pread` and
+/// `pwrite`, which leave the file's cursor alone, so readers made by
+/// `reader()` read at the same time as the image and each other. Elsewhere
+/// they share the file's cursor with the image, so a seek and the read
+/// following it are done under a lock shared by all of them.
+#[derive(Debug)]
+pub struct ImageFile {
+    file: fs::File,
+    sector_size: u64,
+    writable: bool,
+    #[cfg(not(unix))]
+    cursor: Arc<Mutex<()>>,
+}
+
+impl ImageFile {
+    /// Opens the image at `path` for reading and writing with 512 byte
+    /// sectors.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ImageFile> {
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(ImageFile::from_file(file, true))
+    }
+
+    /// Opens the image at `path` for reading only with 512 byte sectors.
+    /// Writing a sector fails with an error of `PermissionDenied`.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<ImageFile> {
+        Ok(ImageFile::from_file(fs::File::open(path)?, false))
+    }
+
+    /// Creates a new, zero-filled image of `len` bytes at `path` with 512 byte
+    /// sectors. The file is extended without writing to it, so on file systems
+    /// that support sparse files it takes no space until sectors are written.
+    pub fn create<P: AsRef<Path>>(path: P, len: u64) -> io::Result<ImageFile> {
+        let file = fs::OpenOptions::new().read(true).write(true)
+                                         .create_new(true).open(path)?;
+        file.set_len(len)?;
+        Ok(ImageFile::from_file(file, true))
+    }
+
+    /// Wraps an already open `file` with 512 byte sectors. `writable` must
+    /// only be `true` if `file` was opened for writing.
+    pub fn from_file(file: fs::File, writable: bool) -> ImageFile {
+        ImageFile {
+            file: file,
+            sector_size: 512,
+            writable: writable,
+            #[cfg(not(unix))]
+            cursor: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Sets the size of the image's sectors to `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of 512.
+    pub fn with_sector_size(mut self, sector_size: u64) -> ImageFile {
+        assert!(sector_size != 0 && sector_size % 512 == 0,
+                "sector size must be a non-zero multiple of 512");
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Returns `true` if sectors can be written to the image.
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Returns the underlying file.
+    pub fn into_inner(self) -> fs::File {
+        self.file
+    }
+
+    #[cfg(unix)]
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+
+    #[cfg(unix)]
+    fn write_all_at(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(buf, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let _cursor = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        (&self.file).seek(SeekFrom::Start(offset))?;
+        (&self.file).read_exact(buf)
+    }
+
+    #[cfg(not(unix))]
+    fn write_all_at(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let _cursor = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        (&self.file).seek(SeekFrom::Start(offset))?;
+        (&self.file).write_all(buf)
+    }
+}
+
+impl BlockDevice for ImageFile {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    /// Reads sector `n`. Reading past the end of the image, or a sector
+    /// truncated by the end of the image, fails with `UnexpectedEof`.
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let to_read = ::std::cmp::min(self.sector_size as usize, buf.len());
+        self.read_exact_at(n * self.sector_size, &mut buf[..to_read])?;
+        Ok(to_read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(util::permission_denied("image was opened read-only"));
+        }
+        if buf.len() < self.sector_size as usize {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+
+        let to_write = self.sector_size as usize;
+        self.write_all_at(n * self.sector_size, &buf[..to_write])?;
+        Ok(to_write)
+    }
+
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        Ok(Some(Box::new(ImageFile {
+            file: self.file.try_clone()?,
+            sector_size: self.sector_size,
+            writable: false,
+            #[cfg(not(unix))]
+            cursor: self.cursor.clone(),
+        })))
+    }
+}
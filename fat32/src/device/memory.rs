@@ -0,0 +1,117 @@
+use std::io;
+use std::ops::Range;
+use std::vec::Vec;
+
+use traits::BlockDevice;
+use util;
+
+/// A RAM disk: a `BlockDevice` backed by a vector of bytes.
+///
+/// Useful for exercising a file system without touching the host, including
+/// from images embedded in the binary with `include_bytes!`:
+///
+/// ```rust,ignore
+/// use fat32::vfat::VFat;
+/// use fat32::device::MemoryDevice;
+///
+/// let device = MemoryDevice::from_bytes(include_bytes!("card.img"));
+/// let vfat = VFat::from(device).expect("valid FAT32 image");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryDevice {
+    data: Vec<u8>,
+    sector_size: u64,
+}
+
+impl MemoryDevice {
+    /// Returns a zero-filled device of `len` bytes with 512 byte sectors.
+    pub fn new(len: usize) -> MemoryDevice {
+        MemoryDevice::from_vec(vec![0; len])
+    }
+
+    /// Returns a device holding `data` with 512 byte sectors.
+    pub fn from_vec(data: Vec<u8>) -> MemoryDevice {
+        MemoryDevice {
+            data: data,
+            sector_size: 512,
+        }
+    }
+
+    /// Returns a device holding a copy of `bytes` with 512 byte sectors.
+    pub fn from_bytes(bytes: &[u8]) -> MemoryDevice {
+        MemoryDevice::from_vec(bytes.to_vec())
+    }
+
+    /// Sets the size of the device's sectors to `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of 512.
+    pub fn with_sector_size(mut self, sector_size: u64) -> MemoryDevice {
+        assert!(sector_size != 0 && sector_size % 512 == 0,
+                "sector size must be a non-zero multiple of 512");
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Returns the contents of the device.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the vector backing the device.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the byte range of the first `len` bytes of sector `n`, or an
+    /// error of `UnexpectedEof` if they lie past the end of the device.
+    fn range(&self, n: u64, len: usize) -> io::Result<Range<usize>> {
+        n.checked_mul(self.sector_size)
+         .and_then(|start| start.checked_add(len as u64).map(|end| start..end))
+         .filter(|range| range.end <= self.data.len() as u64)
+         .map(|range| range.start as usize..range.end as usize)
+         .ok_or_else(|| util::eof(format!("sector {} is past the end of the device", n)))
+    }
+}
+
+impl From<Vec<u8>> for MemoryDevice {
+    fn from(data: Vec<u8>) -> MemoryDevice {
+        MemoryDevice::from_vec(data)
+    }
+}
+
+impl BlockDevice for MemoryDevice {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = ::std::cmp::min(self.sector_size as usize, buf.len());
+        let range = self.range(n, len)?;
+        buf[..len].copy_from_slice(&self.data[range]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = self.sector_size as usize;
+        if buf.len() < len {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+        let range = self.range(n, len)?;
+        self.data[range].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Zeroes the discarded sectors.
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        let len = count.checked_mul(self.sector_size)
+                       .filter(|&len| len <= self.data.len() as u64)
+                       .ok_or_else(|| util::eof("discard is larger than the device"))?;
+        let range = self.range(n, len as usize)?;
+        for byte in self.data[range].iter_mut() {
+            *byte = 0;
+        }
+        Ok(())
+    }
+}
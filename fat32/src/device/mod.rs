@@ -0,0 +1,37 @@
+mod memory;
+mod read_only;
+mod faulty;
+mod retry;
+mod partition;
+mod overlay;
+mod remap;
+mod sd;
+#[cfg(feature = "std")]
+mod trace;
+#[cfg(feature = "std")]
+mod image;
+#[cfg(feature = "std")]
+mod sparse;
+#[cfg(feature = "std")]
+mod nbd;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compressed;
+
+pub use self::memory::MemoryDevice;
+pub use self::read_only::ReadOnly;
+pub use self::faulty::{FaultyDevice, Fault};
+pub use self::retry::{RetryDevice, SectorFailures};
+pub use self::partition::PartitionSlice;
+pub use self::overlay::Overlay;
+pub use self::remap::RemapDevice;
+pub use self::sd::SdDevice;
+#[cfg(feature = "std")]
+pub use self::trace::{TracingDevice, Trace, Access, Op};
+#[cfg(feature = "std")]
+pub use self::image::ImageFile;
+#[cfg(feature = "std")]
+pub use self::sparse::SparseImage;
+#[cfg(feature = "std")]
+pub use self::nbd::NbdDevice;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use self::compressed::CompressedImage;
@@ -0,0 +1,270 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use traits::BlockDevice;
+use util;
+
+const NBD_MAGIC: u64 = 0x4E42_444D_4147_4943; // "NBDMAGIC"
+const OPTION_MAGIC: u64 = 0x4948_4156_454F_5054; // "IHAVEOPT"
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+
+const OPT_EXPORT_NAME: u32 = 1;
+
+const FLAG_READ_ONLY: u16 = 1 << 1;
+const FLAG_SEND_FLUSH: u16 = 1 << 2;
+const FLAG_SEND_TRIM: u16 = 1 << 5;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+const CMD_TRIM: u16 = 4;
+
+/// A client for an export of a Network Block Device (NBD) server, such as
+/// `qemu-nbd` or `nbdkit`, with 512 byte sectors.
+///
+/// The client speaks the fixed newstyle handshake and simple replies, which
+/// every current server supports. Requests are sent one at a time.
+///
+/// ```no_run
+/// use fat32::device::NbdDevice;
+/// use fat32::vfat::VFat;
+///
+/// // Serve the image with `qemu-nbd -x sd -t sd.img` on the host.
+/// let device = NbdDevice::connect("raspberrypi.local:10809", "sd").unwrap();
+/// let vfat = VFat::from(device).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct NbdDevice<S: Read + Write = TcpStream> {
+    stream: S,
+    size: u64,
+    flags: u16,
+    sector_size: u64,
+    handle: u64,
+}
+
+impl NbdDevice {
+    /// Connects to the NBD server at `addr` over TCP and opens the export
+    /// named `export`.
+    pub fn connect<A: ToSocketAddrs>(addr: A, export: &str) -> io::Result<NbdDevice> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        NbdDevice::handshake(stream, export)
+    }
+}
+
+impl<S: Read + Write> NbdDevice<S> {
+    /// Performs the NBD handshake over the already connected `stream`, such
+    /// as a Unix domain socket, and opens the export named `export`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the server doesn't speak the
+    /// fixed newstyle protocol, and of `NotFound` if it closes the connection
+    /// because the export doesn't exist.
+    pub fn handshake(mut stream: S, export: &str) -> io::Result<NbdDevice<S>> {
+        if read_u64(&mut stream)? != NBD_MAGIC || read_u64(&mut stream)? != OPTION_MAGIC {
+            return Err(util::corrupt("server does not speak the newstyle NBD protocol"));
+        }
+        let server_flags = read_u16(&mut stream)?;
+        if server_flags & FLAG_FIXED_NEWSTYLE == 0 {
+            return Err(util::corrupt("server does not support fixed newstyle negotiation"));
+        }
+
+        let client_flags = server_flags & (FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES);
+        let mut request = vec![];
+        put_u32(&mut request, client_flags as u32);
+        put_u64(&mut request, OPTION_MAGIC);
+        put_u32(&mut request, OPT_EXPORT_NAME);
+        put_u32(&mut request, export.len() as u32);
+        request.extend_from_slice(export.as_bytes());
+        stream.write_all(&request)?;
+
+        // The server hangs up instead of replying if the export is unknown.
+        let size = read_u64(&mut stream).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => util::not_found(format!("no export named {:?}", export)),
+            _ => e,
+        })?;
+        let flags = read_u16(&mut stream)?;
+        if client_flags & FLAG_NO_ZEROES == 0 {
+            stream.read_exact(&mut [0; 124])?;
+        }
+
+        Ok(NbdDevice {
+            stream: stream,
+            size: size,
+            flags: flags,
+            sector_size: 512,
+            handle: 0,
+        })
+    }
+
+    /// Sets the size of the device's sectors to `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of 512.
+    pub fn with_sector_size(mut self, sector_size: u64) -> NbdDevice<S> {
+        assert!(sector_size != 0 && sector_size % 512 == 0,
+                "sector size must be a non-zero multiple of 512");
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Returns the size of the export in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns `true` if the server only allows the export to be read.
+    pub fn is_read_only(&self) -> bool {
+        self.flags & FLAG_READ_ONLY != 0
+    }
+
+    /// Asks the server to commit previous writes to stable storage. Does
+    /// nothing if the server doesn't support flushing.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.flags & FLAG_SEND_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(CMD_FLUSH, 0, 0, &[])?;
+        self.reply(&mut [])
+    }
+
+    /// Tells the server that the client is done and closes the connection.
+    pub fn disconnect(mut self) -> io::Result<()> {
+        self.request(CMD_DISC, 0, 0, &[])
+    }
+
+    /// Sends a request of type `command` for `len` bytes at `offset`,
+    /// followed by `data`.
+    fn request(&mut self, command: u16, offset: u64, len: u32, data: &[u8]) -> io::Result<()> {
+        self.handle = self.handle.wrapping_add(1);
+        let mut request = Vec::with_capacity(28 + data.len());
+        put_u32(&mut request, REQUEST_MAGIC);
+        put_u16(&mut request, 0);
+        put_u16(&mut request, command);
+        put_u64(&mut request, self.handle);
+        put_u64(&mut request, offset);
+        put_u32(&mut request, len);
+        request.extend_from_slice(data);
+        self.stream.write_all(&request)?;
+        self.stream.flush()
+    }
+
+    /// Reads the reply to the last request, filling `data` with its payload
+    /// if it succeeded.
+    fn reply(&mut self, data: &mut [u8]) -> io::Result<()> {
+        if read_u32(&mut self.stream)? != SIMPLE_REPLY_MAGIC {
+            return Err(util::corrupt("invalid NBD reply"));
+        }
+        let error = read_u32(&mut self.stream)?;
+        if read_u64(&mut self.stream)? != self.handle {
+            return Err(util::corrupt("NBD reply to an unknown request"));
+        }
+
+        match error {
+            0 => self.stream.read_exact(data),
+            1 => Err(util::permission_denied("NBD server refused the request")),
+            22 => Err(util::invalid_input("NBD server rejected the request as invalid")),
+            _ => Err(util::failed(format!("NBD server returned error {}", error))),
+        }
+    }
+
+    /// Returns the byte offset of sector `n` and the number of its bytes that
+    /// lie within the export.
+    fn locate(&self, n: u64) -> io::Result<(u64, u64)> {
+        match n.checked_mul(self.sector_size) {
+            Some(offset) if offset < self.size => {
+                Ok((offset, ::std::cmp::min(self.sector_size, self.size - offset)))
+            }
+            _ => Err(util::eof(format!("sector {} is past the end of the export", n))),
+        }
+    }
+}
+
+impl<S: Read + Write + Send> BlockDevice for NbdDevice<S> {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (offset, len) = self.locate(n)?;
+        let len = ::std::cmp::min(len as usize, buf.len());
+        self.request(CMD_READ, offset, len as u32, &[])?;
+        self.reply(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if self.is_read_only() {
+            return Err(util::permission_denied("NBD export is read-only"));
+        }
+        let (offset, len) = self.locate(n)?;
+        if (buf.len() as u64) < self.sector_size {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+        self.request(CMD_WRITE, offset, len as u32, &buf[..len as usize])?;
+        self.reply(&mut [])?;
+        Ok(len as usize)
+    }
+
+    /// Sends the discard to the server as trim requests. Does nothing if the
+    /// server doesn't support trimming.
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(util::permission_denied("NBD export is read-only"));
+        }
+        if count == 0 || self.flags & FLAG_SEND_TRIM == 0 {
+            return Ok(());
+        }
+
+        let (mut offset, _) = self.locate(n)?;
+        let (last, last_len) = self.locate(n.saturating_add(count - 1))?;
+        let end = last + last_len;
+        // Requests carry a 32-bit length, so trim whole sectors at a time.
+        let max_len = (u32::max_value() as u64 / self.sector_size) * self.sector_size;
+        while offset < end {
+            let len = ::std::cmp::min(end - offset, max_len);
+            self.request(CMD_TRIM, offset, len as u32, &[])?;
+            self.reply(&mut [])?;
+            offset += len;
+        }
+        Ok(())
+    }
+}
+
+// NBD is big-endian on the wire.
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok((buf[0] as u16) << 8 | buf[1] as u16)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok((read_u16(reader)? as u32) << 16 | read_u16(reader)? as u32)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    Ok((read_u32(reader)? as u64) << 32 | read_u32(reader)? as u64)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    put_u16(buf, (value >> 16) as u16);
+    put_u16(buf, value as u16);
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    put_u32(buf, (value >> 32) as u32);
+    put_u32(buf, value as u32);
+}
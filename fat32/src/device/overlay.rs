@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use traits::BlockDevice;
+use util;
+#[cfg(feature = "std")]
+use util::{le_u32, le_u64, put_le_u32, put_le_u64};
+
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 8] = b"FAT32OVL";
+#[cfg(feature = "std")]
+const VERSION: u32 = 1;
+#[cfg(feature = "std")]
+const HEADER_SIZE: u64 = 16;
+
+/// Where an `Overlay` keeps the sectors written to it.
+#[derive(Debug)]
+enum Delta {
+    Memory(BTreeMap<u64, Vec<u8>>),
+    /// A file of records, each a sector number followed by the sector, and
+    /// the offset of each sector's record.
+    #[cfg(feature = "std")]
+    File { file: fs::File, index: BTreeMap<u64, u64> },
+}
+
+/// A copy-on-write `BlockDevice` over a base device `D`: writes are recorded
+/// in a delta, in memory or in a file, and reads return the latest write to a
+/// sector, if any, and the base device's sector otherwise. The base device is
+/// never written to until the changes are explicitly applied.
+///
+/// This allows modifying an image to see what would happen, previewing a
+/// repair, or running tests that write to one shared base image.
+///
+/// ```rust
+/// use fat32::device::{MemoryDevice, Overlay};
+/// use fat32::traits::BlockDevice;
+///
+/// let mut overlay = Overlay::new(MemoryDevice::new(4096));
+/// overlay.write_sector(1, &[0xAA; 512]).expect("sector 1 exists");
+/// assert_eq!(overlay.changed_sectors(), vec![1]);
+/// assert_eq!(overlay.base().as_bytes()[512], 0);
+/// ```
+///
+/// A delta kept in a file starts with a 16 byte header followed by a record
+/// for every sector written: its sector number and its contents.
+///
+/// | offset | size | field                |
+/// |--------|------|----------------------|
+/// | 0      | 8    | magic, `FAT32OVL`    |
+/// | 8      | 4    | version, 1           |
+/// | 12     | 4    | sector size in bytes |
+#[derive(Debug)]
+pub struct Overlay<D> {
+    base: D,
+    delta: Delta,
+}
+
+impl<D: BlockDevice> Overlay<D> {
+    /// Overlays `base` with an empty delta kept in memory.
+    pub fn new(base: D) -> Overlay<D> {
+        Overlay {
+            base: base,
+            delta: Delta::Memory(BTreeMap::new()),
+        }
+    }
+
+    /// Overlays `base` with an empty delta kept in a new file at `path`.
+    #[cfg(feature = "std")]
+    pub fn create<P: AsRef<Path>>(base: D, path: P) -> io::Result<Overlay<D>> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[..8].copy_from_slice(MAGIC);
+        put_le_u32(&mut header, 8, VERSION);
+        put_le_u32(&mut header, 12, base.sector_size() as u32);
+
+        let mut file = fs::OpenOptions::new().read(true).write(true)
+                                             .create_new(true).open(path)?;
+        file.write_all(&header)?;
+        Ok(Overlay {
+            base: base,
+            delta: Delta::File { file: file, index: BTreeMap::new() },
+        })
+    }
+
+    /// Overlays `base` with the delta in the existing file at `path`, such as
+    /// one left by an earlier `Overlay::create()`. Further writes are added
+    /// to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the file isn't a delta or its
+    /// sector size differs from `base`'s.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(base: D, path: P) -> io::Result<Overlay<D>> {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC || le_u32(&header, 8) != VERSION {
+            return Err(util::corrupt("not an overlay delta"));
+        }
+        if le_u32(&header, 12) as u64 != base.sector_size() {
+            return Err(util::corrupt("overlay delta has a different sector size"));
+        }
+
+        // A record cut short by a crash is dropped.
+        let record_size = 8 + base.sector_size();
+        let records = (file.metadata()?.len() - HEADER_SIZE) / record_size;
+        let mut index = BTreeMap::new();
+        let mut sector = [0u8; 8];
+        for i in 0..records {
+            let offset = HEADER_SIZE + i * record_size;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut sector)?;
+            index.insert(le_u64(&sector, 0), offset);
+        }
+        file.set_len(HEADER_SIZE + records * record_size)?;
+
+        Ok(Overlay {
+            base: base,
+            delta: Delta::File { file: file, index: index },
+        })
+    }
+
+    /// Returns a reference to the base device.
+    pub fn base(&self) -> &D {
+        &self.base
+    }
+
+    /// Returns the base device, dropping the delta. A delta kept in a file
+    /// stays in the file.
+    pub fn into_base(self) -> D {
+        self.base
+    }
+
+    /// Returns the sectors that have been written, in ascending order.
+    pub fn changed_sectors(&self) -> Vec<u64> {
+        match self.delta {
+            Delta::Memory(ref sectors) => sectors.keys().cloned().collect(),
+            #[cfg(feature = "std")]
+            Delta::File { ref index, .. } => index.keys().cloned().collect(),
+        }
+    }
+
+    /// Forgets every write, so that the overlay reads as the base device
+    /// again.
+    pub fn revert(&mut self) -> io::Result<()> {
+        match self.delta {
+            Delta::Memory(ref mut sectors) => sectors.clear(),
+            #[cfg(feature = "std")]
+            Delta::File { ref mut file, ref mut index } => {
+                file.set_len(HEADER_SIZE)?;
+                index.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every changed sector to the base device and then empties the
+    /// delta.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the delta or writing to the base device
+    /// fails. The delta is kept, so applying it can be retried.
+    pub fn apply(&mut self) -> io::Result<()> {
+        let mut data = vec![0; self.base.sector_size() as usize];
+        for sector in self.changed_sectors() {
+            self.read_delta(sector, &mut data)?;
+            if self.base.write_sector(sector, &data)? != data.len() {
+                return Err(util::eof(format!("short write to sector {}", sector)));
+            }
+        }
+        self.revert()
+    }
+
+    /// Returns `true` if sector `n` has been written.
+    fn changed(&self, n: u64) -> bool {
+        match self.delta {
+            Delta::Memory(ref sectors) => sectors.contains_key(&n),
+            #[cfg(feature = "std")]
+            Delta::File { ref index, .. } => index.contains_key(&n),
+        }
+    }
+
+    /// Reads sector `n` from the delta into `buf`, which is at most a
+    /// sector long. Returns `false` if `n` hasn't been written.
+    fn read_delta(&mut self, n: u64, buf: &mut [u8]) -> io::Result<bool> {
+        match self.delta {
+            Delta::Memory(ref sectors) => match sectors.get(&n) {
+                Some(data) => buf.copy_from_slice(&data[..buf.len()]),
+                None => return Ok(false),
+            },
+            #[cfg(feature = "std")]
+            Delta::File { ref mut file, ref index } => match index.get(&n) {
+                Some(&offset) => {
+                    file.seek(SeekFrom::Start(offset + 8))?;
+                    file.read_exact(buf)?;
+                }
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Overlay<D> {
+    fn sector_size(&self) -> u64 {
+        self.base.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = ::std::cmp::min(self.sector_size() as usize, buf.len());
+        if self.read_delta(n, &mut buf[..len])? {
+            return Ok(len);
+        }
+        self.base.read_sector(n, buf)
+    }
+
+    /// Records the write in the delta. Sectors past the end of the base
+    /// device can't be written.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = self.sector_size() as usize;
+        if buf.len() < len {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+        // The first write to a sector reads it from the base device to check
+        // that it exists.
+        if !self.changed(n) {
+            self.base.read_sector(n, &mut vec![0; len])?;
+        }
+
+        match self.delta {
+            Delta::Memory(ref mut sectors) => {
+                sectors.insert(n, buf[..len].to_vec());
+            }
+            #[cfg(feature = "std")]
+            Delta::File { ref mut file, ref mut index } => {
+                let offset = match index.get(&n) {
+                    Some(&offset) => offset,
+                    None => file.seek(SeekFrom::End(0))?,
+                };
+                let mut record = vec![0; 8];
+                put_le_u64(&mut record, 0, n);
+                record.extend_from_slice(&buf[..len]);
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(&record)?;
+                index.insert(n, offset);
+            }
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,171 @@
+use std::cmp::min;
+use std::io;
+use std::vec::Vec;
+
+use traits::BlockDevice;
+use util;
+use vfat::WithContext;
+
+/// A `BlockDevice` exposing only a partition of `D`: the device sectors
+/// `[start, start + len)`, addressed from 0 in sectors of the partition's own
+/// size.
+///
+/// The partition's sectors may be larger or smaller than the device's. When
+/// they are smaller, as with 512 byte logical sectors on a 4096 byte native
+/// device, reads copy out of the containing device sector and writes read,
+/// modify, and write it back.
+///
+/// Accesses past the end of the partition fail with an error of kind
+/// `UnexpectedEof` rather than reaching whatever follows the partition.
+#[derive(Debug)]
+pub struct PartitionSlice<D> {
+    device: D,
+    start: u64,
+    len: u64,
+    sector_size: u64,
+}
+
+impl<D: BlockDevice> PartitionSlice<D> {
+    /// Restricts `device` to the `len` device sectors beginning at device
+    /// sector `start`, presented as sectors of `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple or divisor of the
+    /// device's sector size.
+    pub fn new(device: D, start: u64, len: u64, sector_size: u64) -> PartitionSlice<D> {
+        let device_sector_size = device.sector_size();
+        assert!(sector_size != 0 && (sector_size % device_sector_size == 0
+                                     || device_sector_size % sector_size == 0),
+                "partition sector size must be a multiple or divisor of the device's");
+
+        PartitionSlice {
+            device: device,
+            start: start,
+            len: len,
+            sector_size: sector_size,
+        }
+    }
+
+    /// Returns the number of sectors in the partition.
+    pub fn num_sectors(&self) -> u64 {
+        self.len * self.device.sector_size() / self.sector_size
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Returns the device sector holding the start of partition sector `n`
+    /// and the byte offset of `n` within it.
+    fn physical(&self, n: u64) -> io::Result<(u64, usize)> {
+        if n >= self.num_sectors() {
+            return Err(util::eof(format!("sector {} is past the end of the partition", n)));
+        }
+
+        let offset = n * self.sector_size;
+        let device_sector_size = self.device.sector_size();
+        Ok((self.start + offset / device_sector_size, (offset % device_sector_size) as usize))
+    }
+
+    /// Reads the whole of device sector `phys`.
+    fn read_physical(&mut self, phys: u64) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; self.device.sector_size() as usize];
+        let read = self.device.read_sector(phys, &mut data).at_sector(phys)?;
+        if read != data.len() {
+            return Err(util::eof("short read from device")).at_sector(phys);
+        }
+        Ok(data)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for PartitionSlice<D> {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (first, offset) = self.physical(n)?;
+        let device_sector_size = self.device.sector_size() as usize;
+        let len = min(self.sector_size as usize, buf.len());
+
+        if (self.sector_size as usize) < device_sector_size {
+            let data = self.read_physical(first)?;
+            buf[..len].copy_from_slice(&data[offset..offset + len]);
+            return Ok(len);
+        }
+
+        let mut read = 0;
+        let mut phys = first;
+        while read < len {
+            let end = min(len, read + device_sector_size);
+            if self.device.read_sector(phys, &mut buf[read..end]).at_sector(phys)? != end - read {
+                return Err(util::eof("short read from device")).at_sector(phys);
+            }
+            read = end;
+            phys += 1;
+        }
+        Ok(read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let (first, offset) = self.physical(n)?;
+        let sector_size = self.sector_size as usize;
+        if buf.len() < sector_size {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+
+        let device_sector_size = self.device.sector_size() as usize;
+        if sector_size < device_sector_size {
+            let mut data = self.read_physical(first)?;
+            data[offset..offset + sector_size].copy_from_slice(&buf[..sector_size]);
+            self.device.write_sector(first, &data).at_sector(first)?;
+            return Ok(sector_size);
+        }
+
+        let mut written = 0;
+        let mut phys = first;
+        while written < sector_size {
+            let chunk = &buf[written..written + device_sector_size];
+            if self.device.write_sector(phys, chunk).at_sector(phys)? != chunk.len() {
+                return Err(util::eof("short write to device")).at_sector(phys);
+            }
+            written += chunk.len();
+            phys += 1;
+        }
+        Ok(written)
+    }
+
+    /// Discards the device sectors lying wholly within the partition sectors
+    /// `[n, n + count)`. Device sectors shared with sectors outside the range
+    /// are left alone.
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        match n.checked_add(count) {
+            Some(end) if end <= self.num_sectors() => {}
+            _ => return Err(util::eof(format!("sectors {}..{} are past the end of the \
+                                                partition", n, n.saturating_add(count)))),
+        }
+
+        let device_sector_size = self.device.sector_size();
+        let first = (n * self.sector_size + device_sector_size - 1) / device_sector_size;
+        let end = (n + count) * self.sector_size / device_sector_size;
+        if end > first {
+            self.device.discard(self.start + first, end - first).at_sector(self.start + first)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a reader of the same partition of a reader of the device.
+    #[cfg(feature = "std")]
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        Ok(self.device.reader()?.map(|device| {
+            Box::new(PartitionSlice {
+                device: device,
+                start: self.start,
+                len: self.len,
+                sector_size: self.sector_size,
+            }) as Box<BlockDevice>
+        }))
+    }
+}
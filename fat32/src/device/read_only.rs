@@ -0,0 +1,52 @@
+use std::io;
+
+use traits::BlockDevice;
+use util;
+
+/// A `BlockDevice` wrapper that forwards reads to `D` and refuses every write
+/// and discard with an error of `PermissionDenied`.
+///
+/// Wrapping a device guarantees that nothing layered on top of it, including
+/// a mounted file system, can modify the underlying device.
+#[derive(Debug, Clone)]
+pub struct ReadOnly<D>(D);
+
+impl<D: BlockDevice> ReadOnly<D> {
+    /// Wraps `device`, preventing writes to it.
+    pub fn new(device: D) -> ReadOnly<D> {
+        ReadOnly(device)
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &D {
+        &self.0
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for ReadOnly<D> {
+    fn sector_size(&self) -> u64 {
+        self.0.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, _buf: &[u8]) -> io::Result<usize> {
+        Err(util::permission_denied(format!("sector {} is on a read-only device", n)))
+    }
+
+    fn discard(&mut self, n: u64, _count: u64) -> io::Result<()> {
+        Err(util::permission_denied(format!("sector {} is on a read-only device", n)))
+    }
+
+    #[cfg(feature = "std")]
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        self.0.reader()
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use device::retry::retryable;
+use traits::BlockDevice;
+use util::{self, le_u32, le_u64, put_le_u32, put_le_u64};
+
+const MAGIC: &[u8; 8] = b"FAT32MAP";
+const VERSION: u32 = 1;
+/// The length of the table's header, before its entries.
+const HEADER_LEN: usize = 16;
+/// The length of an entry: a bad sector and the spare it is remapped to.
+const ENTRY_LEN: usize = 16;
+
+/// Where a `RemapDevice` keeps its remap table.
+#[derive(Debug)]
+enum Table {
+    Memory,
+    /// The first spare sector.
+    Sector(u64),
+    #[cfg(feature = "std")]
+    File(fs::File),
+}
+
+/// A `BlockDevice` wrapper that redirects reads and writes of known-bad
+/// sectors of `D` to spare sectors, so that a worn SD card with a few failed
+/// sectors stays usable.
+///
+/// The spare sectors are a range of the device set aside for the purpose,
+/// such as the sectors past the end of the last partition; accessing them
+/// directly fails with an error of `InvalidInput`. A write that fails with an
+/// error a retry could fix remaps its sector to the next free spare and is
+/// written there instead. Sectors that fail to read can't be recovered, but
+/// can be remapped with `remap()` so that they are written to a spare from
+/// then on.
+///
+/// The table of remapped sectors is kept in memory, in the first spare
+/// sector, or, with the `std` feature, in a sidecar file. The table starts
+/// with a 16 byte header followed by an entry for every remapped sector: the
+/// bad sector and its spare, both 8 bytes.
+///
+/// | offset | size | field                   |
+/// |--------|------|-------------------------|
+/// | 0      | 8    | magic, `FAT32MAP`       |
+/// | 8      | 4    | version, 1              |
+/// | 12     | 4    | number of entries, `n`  |
+///
+/// A table kept in a sector holds as many entries as fit in it.
+///
+/// ```rust
+/// use fat32::device::{MemoryDevice, RemapDevice};
+/// use fat32::traits::BlockDevice;
+///
+/// // The last 8 sectors of the device are spares.
+/// let mut device = RemapDevice::new(MemoryDevice::new(64 * 512), 56..64);
+/// let spare = device.remap(3).expect("a spare is free");
+/// device.write_sector(3, &[0xAA; 512]).expect("sector 3 is written");
+/// assert_eq!(device.get_ref().as_bytes()[spare as usize * 512], 0xAA);
+/// ```
+#[derive(Debug)]
+pub struct RemapDevice<D> {
+    device: D,
+    /// Every remapped sector and its spare.
+    map: BTreeMap<u64, u64>,
+    /// The spare sectors that may be handed out.
+    spares: Range<u64>,
+    /// The whole spare region, including the table sector, if any.
+    reserved: Range<u64>,
+    table: Table,
+}
+
+impl<D: BlockDevice> RemapDevice<D> {
+    /// Wraps `device` with `spares` set aside as spare sectors and an empty
+    /// table kept in memory.
+    pub fn new(device: D, spares: Range<u64>) -> RemapDevice<D> {
+        RemapDevice {
+            device: device,
+            map: BTreeMap::new(),
+            spares: spares.clone(),
+            reserved: spares,
+            table: Table::Memory,
+        }
+    }
+
+    /// Wraps `device` with `spares` set aside, keeping the table in the first
+    /// of them. The table already there is read; if there is none, an empty
+    /// one is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `spares` is empty, and an error
+    /// of `InvalidData` if the table is corrupt.
+    pub fn with_table_sector(mut device: D, spares: Range<u64>) -> io::Result<RemapDevice<D>> {
+        if spares.start >= spares.end {
+            return Err(util::invalid_input("no sector for the remap table"));
+        }
+        let mut buf = vec![0; device.sector_size() as usize];
+        device.read_sector(spares.start, &mut buf)?;
+        let mut remap = RemapDevice {
+            device: device,
+            map: BTreeMap::new(),
+            spares: spares.start + 1..spares.end,
+            reserved: spares.clone(),
+            table: Table::Sector(spares.start),
+        };
+        if &buf[..8] == MAGIC {
+            remap.map = parse_table(&buf)?;
+        } else {
+            remap.save()?;
+        }
+        Ok(remap)
+    }
+
+    /// Wraps `device` with `spares` set aside, keeping the table in the file
+    /// at `path`. The table in the file is read; if there is no file, it is
+    /// created with an empty table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the file isn't a remap table.
+    #[cfg(feature = "std")]
+    pub fn with_table_file<P: AsRef<Path>>(device: D, spares: Range<u64>, path: P)
+        -> io::Result<RemapDevice<D>>
+    {
+        let mut file = fs::OpenOptions::new().read(true).write(true).create(true).open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut remap = RemapDevice {
+            device: device,
+            map: BTreeMap::new(),
+            spares: spares.clone(),
+            reserved: spares,
+            table: Table::File(file),
+        };
+        if buf.is_empty() {
+            remap.save()?;
+        } else {
+            remap.map = parse_table(&buf)?;
+        }
+        Ok(remap)
+    }
+
+    /// Returns every remapped sector and the spare it is remapped to.
+    pub fn remapped(&self) -> &BTreeMap<u64, u64> {
+        &self.map
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Remaps sector `n` to a free spare and returns the spare. What can be
+    /// read of the sector is copied to the spare. A sector that is already
+    /// remapped is moved to a new spare, as when the spare itself goes bad.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `n` is a spare, and an error of
+    /// `Other` if no spare is free or the table is full.
+    pub fn remap(&mut self, n: u64) -> io::Result<u64> {
+        self.check(n)?;
+        let mut data = vec![0; self.device.sector_size() as usize];
+        let from = self.map.get(&n).cloned().unwrap_or(n);
+        let readable = self.device.read_sector(from, &mut data).is_ok();
+
+        let spare = self.allocate(n)?;
+        if readable {
+            // The spare is only a copy; failing to fill it loses nothing.
+            let _ = self.device.write_sector(spare, &data);
+        }
+        Ok(spare)
+    }
+
+    /// Records `n` as remapped to the next free spare, saves the table, and
+    /// returns the spare.
+    fn allocate(&mut self, n: u64) -> io::Result<u64> {
+        let used: Vec<u64> = self.map.values().cloned().collect();
+        let spare = match self.spares.clone().find(|spare| !used.contains(spare)) {
+            Some(spare) => spare,
+            None => return Err(util::failed("no spare sectors are left")),
+        };
+        let old = self.map.insert(n, spare);
+        if let Err(e) = self.save() {
+            match old {
+                Some(old) => self.map.insert(n, old),
+                None => self.map.remove(&n),
+            };
+            return Err(e);
+        }
+        Ok(spare)
+    }
+
+    /// Returns an error of `InvalidInput` if `n` is in the spare region.
+    fn check(&self, n: u64) -> io::Result<()> {
+        if self.reserved.start <= n && n < self.reserved.end {
+            return Err(util::invalid_input(format!("sector {} is reserved for remapping", n)));
+        }
+        Ok(())
+    }
+
+    /// Writes the table to where it is kept.
+    fn save(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; HEADER_LEN + ENTRY_LEN * self.map.len()];
+        buf[..8].copy_from_slice(MAGIC);
+        put_le_u32(&mut buf, 8, VERSION);
+        put_le_u32(&mut buf, 12, self.map.len() as u32);
+        for (i, (&bad, &spare)) in self.map.iter().enumerate() {
+            put_le_u64(&mut buf, HEADER_LEN + ENTRY_LEN * i, bad);
+            put_le_u64(&mut buf, HEADER_LEN + ENTRY_LEN * i + 8, spare);
+        }
+
+        match self.table {
+            Table::Memory => Ok(()),
+            Table::Sector(sector) => {
+                let sector_size = self.device.sector_size() as usize;
+                if buf.len() > sector_size {
+                    return Err(util::failed("the remap table is full"));
+                }
+                buf.resize(sector_size, 0);
+                if self.device.write_sector(sector, &buf)? != sector_size {
+                    return Err(util::eof("short write of the remap table"));
+                }
+                Ok(())
+            }
+            #[cfg(feature = "std")]
+            Table::File(ref mut file) => {
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&buf)?;
+                file.set_len(buf.len() as u64)?;
+                file.sync_data()
+            }
+        }
+    }
+}
+
+/// Parses a remap table.
+fn parse_table(buf: &[u8]) -> io::Result<BTreeMap<u64, u64>> {
+    if buf.len() < HEADER_LEN || &buf[..8] != MAGIC || le_u32(buf, 8) != VERSION {
+        return Err(util::corrupt("not a remap table"));
+    }
+    let len = le_u32(buf, 12) as usize;
+    if buf.len() < HEADER_LEN + ENTRY_LEN * len {
+        return Err(util::corrupt("remap table is truncated"));
+    }
+    Ok((0..len).map(|i| {
+        let entry = HEADER_LEN + ENTRY_LEN * i;
+        (le_u64(buf, entry), le_u64(buf, entry + 8))
+    }).collect())
+}
+
+impl<D: BlockDevice> BlockDevice for RemapDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.check(n)?;
+        let sector = self.map.get(&n).cloned().unwrap_or(n);
+        self.device.read_sector(sector, buf)
+    }
+
+    /// Writes to the sector's spare if it is remapped. A write that fails
+    /// with an error a retry could fix remaps the sector and is retried on
+    /// the new spare, until one takes the write or no spare is left.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.check(n)?;
+        let mut sector = self.map.get(&n).cloned().unwrap_or(n);
+        loop {
+            match self.device.write_sector(sector, buf) {
+                Err(ref e) if retryable(e) => sector = self.allocate(n)?,
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::time::Duration;
+
+use traits::BlockDevice;
+
+/// How often accesses to a sector have failed, as counted by `RetryDevice`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SectorFailures {
+    /// Failed attempts that a later retry of the same access recovered from.
+    pub transient: u32,
+    /// Accesses that still failed after every retry.
+    pub fatal: u32,
+}
+
+/// A `BlockDevice` wrapper that retries failed accesses to `D`, so that the
+/// occasional failed read of an SD card doesn't fail a file system operation.
+///
+/// A failed access is retried up to `retries` times, 3 by default. With the
+/// `std` feature, a backoff can be set with `with_backoff()`: the first retry
+/// waits that long and every later one twice as long as the one before.
+///
+/// Errors that retrying can't fix, of kind `UnexpectedEof`, `InvalidInput`,
+/// `PermissionDenied`, and `NotFound`, are returned at once. Failures are
+/// counted per sector and can be inspected with `failures()`.
+#[derive(Debug)]
+pub struct RetryDevice<D> {
+    device: D,
+    retries: u32,
+    #[cfg(feature = "std")]
+    backoff: Duration,
+    failures: BTreeMap<u64, SectorFailures>,
+}
+
+impl<D: BlockDevice> RetryDevice<D> {
+    /// Wraps `device`, retrying failed accesses 3 times without waiting.
+    pub fn new(device: D) -> RetryDevice<D> {
+        RetryDevice {
+            device: device,
+            retries: 3,
+            #[cfg(feature = "std")]
+            backoff: Duration::from_secs(0),
+            failures: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the number of times a failed access is retried.
+    pub fn with_retries(mut self, retries: u32) -> RetryDevice<D> {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the time to wait before the first retry of an access. Later
+    /// retries of the same access wait twice as long as the one before.
+    #[cfg(feature = "std")]
+    pub fn with_backoff(mut self, backoff: Duration) -> RetryDevice<D> {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Returns the sectors accesses to which have failed, with how often.
+    pub fn failures(&self) -> &BTreeMap<u64, SectorFailures> {
+        &self.failures
+    }
+
+    /// Forgets every failure counted so far.
+    pub fn clear_failures(&mut self) {
+        self.failures.clear();
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn get_ref(&self) -> &D {
+        &self.device
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Runs `access` on the device, retrying it if it fails, and counts the
+    /// failures against `sector`.
+    fn retry<T, F>(&mut self, sector: u64, mut access: F) -> io::Result<T>
+        where F: FnMut(&mut D) -> io::Result<T>
+    {
+        let mut failed = 0;
+        loop {
+            match access(&mut self.device) {
+                Ok(value) => {
+                    if failed > 0 {
+                        self.failures.entry(sector).or_insert_with(SectorFailures::default)
+                            .transient += failed;
+                    }
+                    return Ok(value);
+                }
+                Err(e) => {
+                    if !retryable(&e) || failed >= self.retries {
+                        self.failures.entry(sector).or_insert_with(SectorFailures::default)
+                            .fatal += 1;
+                        return Err(e);
+                    }
+                    #[cfg(feature = "std")]
+                    {
+                        let wait = self.backoff.checked_mul(2u32.saturating_pow(failed))
+                                               .unwrap_or(self.backoff);
+                        if wait > Duration::from_secs(0) {
+                            ::std::thread::sleep(wait);
+                        }
+                    }
+                    failed += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if retrying the access that failed with `e` could succeed.
+pub(crate) fn retryable(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidInput
+            | io::ErrorKind::PermissionDenied | io::ErrorKind::NotFound => false,
+        _ => true,
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for RetryDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.retry(n, |device| device.read_sector(n, buf))
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.retry(n, |device| device.write_sector(n, buf))
+    }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        self.retry(n, |device| device.discard(n, count))
+    }
+}
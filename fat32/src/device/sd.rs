@@ -0,0 +1,126 @@
+use std::{cmp, fmt, io};
+
+use traits::{BlockDevice, SdCard};
+use util;
+
+/// Keeps polling a busy card for as long as it takes.
+fn spin() -> bool {
+    true
+}
+
+/// Adapts an `SdCard` driver into a `BlockDevice` whose sectors are the
+/// card's blocks.
+///
+/// Before each command, the card is polled with `SdCard::is_busy()`. Each
+/// time the card reports being busy, the wait callback is called: it may
+/// sleep, yield to other tasks, or count attempts, and returns `false` to
+/// give up, failing the request with an error of `TimedOut`. By default, the
+/// card is polled until it becomes ready.
+pub struct SdDevice<C, F = fn() -> bool> {
+    card: C,
+    wait: F,
+}
+
+impl<C: SdCard> SdDevice<C> {
+    /// Wraps `card`, polling it until it is ready whenever it is busy.
+    pub fn new(card: C) -> SdDevice<C> {
+        SdDevice::with_wait(card, spin)
+    }
+}
+
+impl<C: SdCard, F: FnMut() -> bool> SdDevice<C, F> {
+    /// Wraps `card`, calling `wait` each time the card is found busy.
+    pub fn with_wait(card: C, wait: F) -> SdDevice<C, F> {
+        SdDevice {
+            card: card,
+            wait: wait,
+        }
+    }
+
+    /// Returns a reference to the card's driver.
+    pub fn get_ref(&self) -> &C {
+        &self.card
+    }
+
+    /// Returns a mutable reference to the card's driver.
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.card
+    }
+
+    /// Returns the card's driver.
+    pub fn into_inner(self) -> C {
+        self.card
+    }
+
+    /// Reads as many whole sectors as fit in `buf`, starting at sector `n`,
+    /// with a single multi-block command. Returns the number of bytes read.
+    pub fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.whole_blocks(buf.len())?;
+        self.ready()?;
+        self.card.read_blocks(n, &mut buf[..len]).map_err(card_error)?;
+        Ok(len)
+    }
+
+    /// Writes as many whole sectors as `buf` holds, starting at sector `n`,
+    /// with a single multi-block command. Returns the number of bytes
+    /// written.
+    pub fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = self.whole_blocks(buf.len())?;
+        self.ready()?;
+        self.card.write_blocks(n, &buf[..len]).map_err(card_error)?;
+        Ok(len)
+    }
+
+    /// Returns the length of the whole blocks in a buffer of `len` bytes.
+    fn whole_blocks(&self, len: usize) -> io::Result<usize> {
+        let block_size = self.card.block_size() as usize;
+        match len - len % block_size {
+            0 => Err(util::eof("buffer is smaller than a sector")),
+            len => Ok(len),
+        }
+    }
+
+    /// Waits until the card is no longer busy.
+    fn ready(&mut self) -> io::Result<()> {
+        while self.card.is_busy().map_err(card_error)? {
+            if !(self.wait)() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "SD card stayed busy"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn card_error<E: fmt::Debug>(error: E) -> io::Error {
+    util::failed(format!("SD card error: {:?}", error))
+}
+
+impl<C: SdCard + Send, F: FnMut() -> bool + Send> BlockDevice for SdDevice<C, F> {
+    fn sector_size(&self) -> u64 {
+        self.card.block_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let block_size = self.card.block_size() as usize;
+        if buf.len() >= block_size {
+            return self.read_sectors(n, &mut buf[..block_size]);
+        }
+
+        let mut block = vec![0; block_size];
+        self.read_sectors(n, &mut block)?;
+        let len = cmp::min(block_size, buf.len());
+        buf[..len].copy_from_slice(&block[..len]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let block_size = self.card.block_size() as usize;
+        self.write_sectors(n, &buf[..cmp::min(block_size, buf.len())])
+    }
+}
+
+impl<C: fmt::Debug, F> fmt::Debug for SdDevice<C, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SdDevice").field("card", &self.card).finish()
+    }
+}
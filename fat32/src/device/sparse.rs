@@ -0,0 +1,277 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use traits::BlockDevice;
+use util::{self, le_u32, le_u64, put_le_u32, put_le_u64};
+
+const MAGIC: &[u8; 8] = b"FAT32SPI";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 512;
+
+/// A sparse disk image: only the parts of the disk that have been written
+/// take up space in the file on the host.
+///
+/// The disk is divided into extents of `extent_sectors` sectors. The file
+/// begins with a 512 byte header followed by an index holding, for every
+/// extent, the offset in the file of its data, or 0 if the extent has never
+/// been written and reads as zeroes. Extents are allocated at the end of the
+/// file on their first non-zero write. Discarding a whole extent releases it:
+/// the file shrinks if it was the last one, and otherwise its space is reused
+/// by the next extent allocated.
+///
+/// | offset | size | field                   |
+/// |--------|------|-------------------------|
+/// | 0      | 8    | magic, `FAT32SPI`       |
+/// | 8      | 4    | version, 1              |
+/// | 12     | 4    | sector size in bytes    |
+/// | 16     | 8    | number of sectors       |
+/// | 24     | 4    | sectors per extent      |
+/// | 512    | 8 n  | extent offsets          |
+#[derive(Debug)]
+pub struct SparseImage {
+    file: fs::File,
+    sector_size: u64,
+    num_sectors: u64,
+    extent_sectors: u64,
+    index: Vec<u64>,
+    /// Offsets of extents in the file that the index no longer refers to.
+    free: Vec<u64>,
+    writable: bool,
+}
+
+impl SparseImage {
+    /// Creates a new, empty sparse image at `path` of `num_sectors` sectors of
+    /// `sector_size` bytes, allocated `extent_sectors` sectors at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a non-zero multiple of 512 or if
+    /// `extent_sectors` is 0.
+    pub fn create<P: AsRef<Path>>(path: P, num_sectors: u64, sector_size: u64,
+                                  extent_sectors: u32) -> io::Result<SparseImage> {
+        assert!(sector_size != 0 && sector_size % 512 == 0,
+                "sector size must be a non-zero multiple of 512");
+        assert!(extent_sectors != 0, "extents must hold at least one sector");
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        header[..8].copy_from_slice(MAGIC);
+        put_le_u32(&mut header, 8, VERSION);
+        put_le_u32(&mut header, 12, sector_size as u32);
+        put_le_u64(&mut header, 16, num_sectors);
+        put_le_u32(&mut header, 24, extent_sectors);
+
+        let mut file = fs::OpenOptions::new().read(true).write(true)
+                                             .create_new(true).open(path)?;
+        file.write_all(&header)?;
+        let extent_sectors = extent_sectors as u64;
+        let num_extents = (num_sectors + extent_sectors - 1) / extent_sectors;
+        // The index starts out as a hole of zeroes.
+        file.set_len(HEADER_SIZE + num_extents * 8)?;
+
+        Ok(SparseImage {
+            file: file,
+            sector_size: sector_size,
+            num_sectors: num_sectors,
+            extent_sectors: extent_sectors,
+            index: vec![0; num_extents as usize],
+            free: vec![],
+            writable: true,
+        })
+    }
+
+    /// Opens the sparse image at `path` for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SparseImage> {
+        let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        SparseImage::from_file(file, true)
+    }
+
+    /// Opens the sparse image at `path` for reading only. Writing a sector
+    /// fails with an error of `PermissionDenied`.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<SparseImage> {
+        SparseImage::from_file(fs::File::open(path)?, false)
+    }
+
+    fn from_file(mut file: fs::File, writable: bool) -> io::Result<SparseImage> {
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(util::corrupt("not a sparse image"));
+        }
+        if le_u32(&header, 8) != VERSION {
+            return Err(util::corrupt("unsupported sparse image version"));
+        }
+
+        let sector_size = le_u32(&header, 12) as u64;
+        let num_sectors = le_u64(&header, 16);
+        let extent_sectors = le_u32(&header, 24) as u64;
+        if sector_size == 0 || sector_size % 512 != 0 || extent_sectors == 0 {
+            return Err(util::corrupt("invalid sparse image header"));
+        }
+
+        let num_extents = (num_sectors + extent_sectors - 1) / extent_sectors;
+        let mut raw_index = vec![0; num_extents as usize * 8];
+        file.read_exact(&mut raw_index)?;
+        let index: Vec<u64> = raw_index.chunks(8).map(|entry| le_u64(entry, 0)).collect();
+
+        // Extents follow the index back to back; those it doesn't refer to
+        // were discarded and can be reused.
+        let extent_size = extent_sectors * sector_size;
+        let mut offset = HEADER_SIZE + num_extents * 8;
+        let mut free = vec![];
+        let len = file.metadata()?.len();
+        while offset + extent_size <= len {
+            if !index.contains(&offset) {
+                free.push(offset);
+            }
+            offset += extent_size;
+        }
+
+        Ok(SparseImage {
+            file: file,
+            sector_size: sector_size,
+            num_sectors: num_sectors,
+            extent_sectors: extent_sectors,
+            index: index,
+            free: free,
+            writable: writable,
+        })
+    }
+
+    /// Returns the number of sectors on the disk.
+    pub fn num_sectors(&self) -> u64 {
+        self.num_sectors
+    }
+
+    /// Returns the number of sectors backed by data in the file.
+    pub fn allocated_sectors(&self) -> u64 {
+        self.index.iter().filter(|&&offset| offset != 0).count() as u64 * self.extent_sectors
+    }
+
+    /// Returns the extent holding sector `n` and the byte offset of `n`
+    /// within it.
+    fn locate(&self, n: u64) -> io::Result<(usize, u64)> {
+        if n >= self.num_sectors {
+            return Err(util::eof(format!("sector {} is past the end of the image", n)));
+        }
+        let extent = (n / self.extent_sectors) as usize;
+        Ok((extent, (n % self.extent_sectors) * self.sector_size))
+    }
+
+    /// Allocates a zeroed extent, reusing a discarded one if there is one and
+    /// appending one to the file otherwise, records it in the index, and
+    /// returns its offset.
+    fn allocate(&mut self, extent: usize) -> io::Result<u64> {
+        let extent_size = self.extent_sectors * self.sector_size;
+        let offset = match self.free.pop() {
+            Some(offset) => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.write_all(&vec![0; extent_size as usize])?;
+                offset
+            }
+            None => {
+                let offset = self.file.seek(SeekFrom::End(0))?;
+                self.file.set_len(offset + extent_size)?;
+                offset
+            }
+        };
+
+        self.set_index(extent, offset)?;
+        Ok(offset)
+    }
+
+    /// Returns the extent at `offset` to the free space, shrinking the file
+    /// if it and any free extents before it are at its end.
+    fn release(&mut self, offset: u64) -> io::Result<()> {
+        let extent_size = self.extent_sectors * self.sector_size;
+        self.free.push(offset);
+        let mut len = self.file.metadata()?.len();
+        while let Some(i) = self.free.iter().position(|&free| free + extent_size == len) {
+            len = self.free.swap_remove(i);
+        }
+        self.file.set_len(len)
+    }
+
+    /// Points extent `extent` at `offset` in the index, on disk and in
+    /// memory.
+    fn set_index(&mut self, extent: usize, offset: u64) -> io::Result<()> {
+        let mut entry = [0u8; 8];
+        put_le_u64(&mut entry, 0, offset);
+        self.file.seek(SeekFrom::Start(HEADER_SIZE + extent as u64 * 8))?;
+        self.file.write_all(&entry)?;
+        self.index[extent] = offset;
+        Ok(())
+    }
+}
+
+impl BlockDevice for SparseImage {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (extent, offset) = self.locate(n)?;
+        let to_read = ::std::cmp::min(self.sector_size as usize, buf.len());
+        match self.index[extent] {
+            0 => {
+                for byte in buf[..to_read].iter_mut() {
+                    *byte = 0;
+                }
+            }
+            start => {
+                self.file.seek(SeekFrom::Start(start + offset))?;
+                self.file.read_exact(&mut buf[..to_read])?;
+            }
+        }
+        Ok(to_read)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(util::permission_denied("image was opened read-only"));
+        }
+        let (extent, offset) = self.locate(n)?;
+        let to_write = self.sector_size as usize;
+        if buf.len() < to_write {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+
+        let start = match self.index[extent] {
+            // Zeroes written to an unallocated extent are already there.
+            0 if buf[..to_write].iter().all(|&b| b == 0) => return Ok(to_write),
+            0 => self.allocate(extent)?,
+            start => start,
+        };
+        self.file.seek(SeekFrom::Start(start + offset))?;
+        self.file.write_all(&buf[..to_write])?;
+        Ok(to_write)
+    }
+
+    /// Releases the extents lying wholly within the discarded sectors. The
+    /// rest of the discarded sectors keep their data.
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        if !self.writable {
+            return Err(util::permission_denied("image was opened read-only"));
+        }
+        let end = match n.checked_add(count) {
+            Some(end) if end <= self.num_sectors => end,
+            _ => return Err(util::eof("discarded sectors are past the end of the image")),
+        };
+
+        let first = (n + self.extent_sectors - 1) / self.extent_sectors;
+        for extent in first as usize..self.index.len() {
+            let extent_start = extent as u64 * self.extent_sectors;
+            let extent_end = ::std::cmp::min(extent_start + self.extent_sectors,
+                                             self.num_sectors);
+            if extent_end > end {
+                break;
+            }
+            let offset = self.index[extent];
+            if offset != 0 {
+                self.set_index(extent, 0)?;
+                self.release(offset)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use traits::BlockDevice;
+
+/// The kind of access recorded by `TracingDevice`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+    Discard,
+}
+
+/// A single sector access recorded by `TracingDevice`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    /// Whether the sector was read, written, or discarded.
+    pub op: Op,
+    /// The sector that was accessed, the first of them for a discard.
+    pub sector: u64,
+    /// The number of bytes transferred, or discarded, or `None` if the access
+    /// failed.
+    pub len: Option<usize>,
+    /// How long the wrapped device took to complete the access.
+    pub elapsed: Duration,
+}
+
+/// A handle to the accesses recorded by a `TracingDevice`. The handle stays
+/// usable after the device is moved into a file system.
+#[derive(Debug, Clone, Default)]
+pub struct Trace(Arc<Mutex<Vec<Access>>>);
+
+impl Trace {
+    /// Returns every access recorded so far, oldest first.
+    pub fn accesses(&self) -> Vec<Access> {
+        self.0.lock().expect("all okay").clone()
+    }
+
+    /// Returns every access recorded so far and clears the trace.
+    pub fn take(&self) -> Vec<Access> {
+        mem::replace(&mut *self.0.lock().expect("all okay"), Vec::new())
+    }
+
+    fn push(&self, access: Access) {
+        self.0.lock().expect("all okay").push(access);
+    }
+}
+
+/// A `BlockDevice` wrapper that records every sector read, write, and discard
+/// made to `D`, to help find out what I/O the file system issues.
+///
+/// ```rust
+/// use fat32::device::{MemoryDevice, TracingDevice};
+/// use fat32::traits::BlockDevice;
+///
+/// let mut device = TracingDevice::new(MemoryDevice::new(4096));
+/// let trace = device.trace();
+/// device.read_sector(3, &mut [0; 512]).expect("sector 3 exists");
+/// assert_eq!(trace.accesses()[0].sector, 3);
+/// ```
+#[derive(Debug)]
+pub struct TracingDevice<D> {
+    device: D,
+    trace: Trace,
+}
+
+impl<D: BlockDevice> TracingDevice<D> {
+    /// Wraps `device` with an empty trace.
+    pub fn new(device: D) -> TracingDevice<D> {
+        TracingDevice {
+            device: device,
+            trace: Trace::default(),
+        }
+    }
+
+    /// Returns a handle to the device's trace.
+    pub fn trace(&self) -> Trace {
+        self.trace.clone()
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn record<F>(&mut self, op: Op, sector: u64, access: F) -> io::Result<usize>
+        where F: FnOnce(&mut D) -> io::Result<usize>
+    {
+        let start = Instant::now();
+        let result = access(&mut self.device);
+        self.trace.push(Access {
+            op: op,
+            sector: sector,
+            len: result.as_ref().ok().cloned(),
+            elapsed: start.elapsed(),
+        });
+        result
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for TracingDevice<D> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.record(Op::Read, n, |device| device.read_sector(n, buf))
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.record(Op::Write, n, |device| device.write_sector(n, buf))
+    }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        let len = (count * self.device.sector_size()) as usize;
+        self.record(Op::Discard, n, |device| device.discard(n, count).map(|_| len))
+            .map(|_| ())
+    }
+}
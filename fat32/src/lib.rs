@@ -1,16 +1,45 @@
 #![feature(decl_macro, conservative_impl_trait)]
 #![feature(entry_and_modify)]
+#![feature(associated_type_defaults)]
+#![cfg_attr(not(feature = "std"), feature(alloc))]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(safe_packed_borrows)]
 
 #[cfg(not(target_endian="little"))]
 compile_error!("only little endian platforms supported");
 
-#[cfg(test)]
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate ruzstd;
+
+#[cfg(not(feature = "std"))]
+pub mod nostd;
+#[cfg(not(feature = "std"))]
+use nostd as std;
+
+#[cfg(all(test, feature = "std"))]
 mod tests;
 mod mbr;
 mod util;
 
 pub mod vfat;
 pub mod traits;
+pub mod device;
+pub mod digest;
+pub mod path;
+#[cfg(feature = "std")]
+pub mod tools;
+#[cfg(any(test, feature = "testing"))]
+#[doc(hidden)]
+pub mod testing;
 
 pub use mbr::*;
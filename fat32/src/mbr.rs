@@ -1,6 +1,10 @@
-use std::{fmt, io, mem};
+use std::{fmt, io};
 
 use traits::BlockDevice;
+use util::{self, le_u32, put_le_u32};
+
+/// The size of the bootstrap code area at the start of an MBR, in bytes.
+pub const BOOTSTRAP_SIZE: usize = 436;
 
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, Default)]
@@ -21,10 +25,52 @@ pub struct PartitionEntry {
     pub total_sectors: u32,
 }
 
+impl CHS {
+    /// Decodes a CHS address from the 3 bytes of `buf`.
+    pub fn decode(buf: &[u8]) -> CHS {
+        CHS {
+            head: buf[0],
+            sector: buf[1],
+            cylinder: buf[2],
+        }
+    }
+
+    /// Encodes `self` into the first 3 bytes of `buf`.
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.head;
+        buf[1] = self.sector;
+        buf[2] = self.cylinder;
+    }
+}
+
+impl PartitionEntry {
+    /// Decodes a partition table entry from the first 16 bytes of `buf`.
+    pub fn decode(buf: &[u8]) -> PartitionEntry {
+        PartitionEntry {
+            boot_indicator: buf[0],
+            start_chs: CHS::decode(&buf[1..4]),
+            partition_type: buf[4],
+            end_chs: CHS::decode(&buf[5..8]),
+            relative_sector: le_u32(buf, 8),
+            total_sectors: le_u32(buf, 12),
+        }
+    }
+
+    /// Encodes `self` into the first 16 bytes of `buf`.
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.boot_indicator;
+        self.start_chs.encode(&mut buf[1..4]);
+        buf[4] = self.partition_type;
+        self.end_chs.encode(&mut buf[5..8]);
+        put_le_u32(buf, 8, self.relative_sector);
+        put_le_u32(buf, 12, self.total_sectors);
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
-    pub bootstrap: [u8; 436],
+    pub bootstrap: [u8; BOOTSTRAP_SIZE],
     pub disk_id: [u8; 10],
     pub partition_table: [PartitionEntry; 4],
     pub signature: [u8; 2],
@@ -40,6 +86,34 @@ pub enum Error {
     BadSignature,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::UnknownBootIndicator(n) => {
+                write!(f, "partition {} has an unknown boot indicator", n)
+            }
+            Error::BadSignature => write!(f, "invalid MBR signature"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for Error {
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
 impl MasterBootRecord {
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
@@ -50,9 +124,9 @@ impl MasterBootRecord {
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
     pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
-        let mut mbr_buf = [0u8; mem::size_of::<MasterBootRecord>()];
-        device.read_sector(0, &mut mbr_buf).map_err(|e|{Error::Io(e)})?;
-        let mbr : MasterBootRecord = unsafe { mem::transmute(mbr_buf) };
+        let mut mbr_buf = [0u8; 512];
+        device.read_sector(0, &mut mbr_buf)?;
+        let mbr = MasterBootRecord::decode(&mbr_buf);
 
         if mbr.signature != [0x55, 0xAA] {
             return Err(Error::BadSignature);
@@ -68,6 +142,84 @@ impl MasterBootRecord {
         Ok(mbr)
     }
 
+    /// Decodes an MBR from the bytes of a sector. No validation is performed.
+    pub fn decode(buf: &[u8; 512]) -> MasterBootRecord {
+        let mut mbr = MasterBootRecord {
+            bootstrap: [0; BOOTSTRAP_SIZE],
+            disk_id: [0; 10],
+            partition_table: [PartitionEntry::default(); 4],
+            signature: [buf[510], buf[511]],
+        };
+        mbr.bootstrap.copy_from_slice(&buf[..BOOTSTRAP_SIZE]);
+        mbr.disk_id.copy_from_slice(&buf[BOOTSTRAP_SIZE..446]);
+        for (i, entry) in mbr.partition_table.iter_mut().enumerate() {
+            *entry = PartitionEntry::decode(&buf[446 + i * 16..]);
+        }
+        mbr
+    }
+
+    /// Encodes `self` into the bytes of a sector.
+    pub fn encode(&self) -> [u8; 512] {
+        let mut buf = [0u8; 512];
+        buf[..BOOTSTRAP_SIZE].copy_from_slice(&self.bootstrap);
+        buf[BOOTSTRAP_SIZE..446].copy_from_slice(&self.disk_id);
+        for (i, entry) in self.partition_table.iter().enumerate() {
+            entry.encode(&mut buf[446 + i * 16..]);
+        }
+        buf[510..].copy_from_slice(&self.signature);
+        buf
+    }
+
+    /// Replaces the bootstrap code with `code`, padded with zeroes. The disk
+    /// ID, partition table, and signature are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `code` is longer than
+    /// `BOOTSTRAP_SIZE` bytes.
+    pub fn set_bootstrap(&mut self, code: &[u8]) -> io::Result<()> {
+        if code.len() > BOOTSTRAP_SIZE {
+            return Err(util::invalid_input(format!("bootstrap code is longer than {} bytes",
+                                                   BOOTSTRAP_SIZE)));
+        }
+        self.bootstrap = [0; BOOTSTRAP_SIZE];
+        self.bootstrap[..code.len()].copy_from_slice(code);
+        Ok(())
+    }
+
+    /// Replaces the bootstrap code in the MBR of `device` with `code`, as
+    /// `set_bootstrap()`, leaving the rest of the sector as it is.
+    pub fn install_bootstrap<T: BlockDevice>(mut device: T, code: &[u8]) -> io::Result<()> {
+        let mut mbr = MasterBootRecord::decode(&[0; 512]);
+        mbr.set_bootstrap(code)?;
+        let mut sector = vec![0; device.sector_size() as usize];
+        device.read_sector(0, &mut sector)?;
+        sector[..BOOTSTRAP_SIZE].copy_from_slice(&mbr.bootstrap);
+        device.write_sector(0, &sector)?;
+        Ok(())
+    }
+
+    /// Returns the disk signature, the 32-bit ID operating systems tell
+    /// disks apart by, stored at byte 440 of the MBR.
+    pub fn disk_signature(&self) -> u32 {
+        le_u32(&self.disk_id, 4)
+    }
+
+    /// Sets the disk signature.
+    pub fn set_disk_signature(&mut self, id: u32) {
+        put_le_u32(&mut self.disk_id, 4, id);
+    }
+
+    /// Sets the disk signature in the MBR of `device` to `id`, leaving the
+    /// rest of the sector as it is.
+    pub fn write_disk_signature<T: BlockDevice>(mut device: T, id: u32) -> io::Result<()> {
+        let mut sector = vec![0; device.sector_size() as usize];
+        device.read_sector(0, &mut sector)?;
+        put_le_u32(&mut sector, BOOTSTRAP_SIZE + 4, id);
+        device.write_sector(0, &sector)?;
+        Ok(())
+    }
+
     pub fn first_fat32(&self) -> Option<&PartitionEntry> {
         self.partition_table.iter()
             .find(|part| part.partition_type == 0xB || part.partition_type == 0xC)
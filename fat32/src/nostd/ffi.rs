@@ -0,0 +1,37 @@
+use core::mem;
+use alloc::string::String;
+
+/// A borrowed platform string. Without `std`, this is always UTF-8.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OsStr {
+    inner: str,
+}
+
+impl OsStr {
+    pub fn new<S: AsRef<str> + ?Sized>(s: &S) -> &OsStr {
+        unsafe { mem::transmute(s.as_ref()) }
+    }
+
+    /// Yields a `&str` slice. Always `Some` without `std`.
+    pub fn to_str(&self) -> Option<&str> {
+        Some(&self.inner)
+    }
+}
+
+impl AsRef<OsStr> for OsStr {
+    fn as_ref(&self) -> &OsStr {
+        self
+    }
+}
+
+impl AsRef<OsStr> for str {
+    fn as_ref(&self) -> &OsStr {
+        OsStr::new(self)
+    }
+}
+
+impl AsRef<OsStr> for String {
+    fn as_ref(&self) -> &OsStr {
+        OsStr::new(self.as_str())
+    }
+}
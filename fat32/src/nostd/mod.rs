@@ -0,0 +1,20 @@
+//! Minimal stand-ins for the parts of `std` this crate uses, built on `core`
+//! and `alloc`. Only compiled without the `std` feature, in which case the
+//! crate root aliases this module as `std` so the rest of the crate is
+//! unchanged.
+
+pub use core::*;
+pub use alloc::{borrow, boxed, rc, string, vec};
+
+pub mod io;
+pub mod path;
+pub mod ffi;
+
+pub mod collections {
+    pub use alloc::collections::*;
+}
+
+pub mod sync {
+    pub use core::sync::*;
+    pub use alloc::sync::*;
+}
@@ -0,0 +1,181 @@
+use core::{fmt, mem};
+use core::ops::Deref;
+use alloc::string::String;
+
+use nostd::ffi::OsStr;
+
+/// A single component of a `Path`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Component<'a> {
+    /// Never produced without `std`; present for parity with `std::path`.
+    Prefix(&'a OsStr),
+    RootDir,
+    CurDir,
+    ParentDir,
+    Normal(&'a OsStr),
+}
+
+/// An iterator over the `Component`s of a `Path`.
+pub struct Components<'a> {
+    path: &'a str,
+    has_root: bool,
+    at_start: bool,
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Component<'a>> {
+        if self.has_root {
+            self.has_root = false;
+            self.at_start = false;
+            return Some(Component::RootDir);
+        }
+
+        loop {
+            if self.path.is_empty() {
+                return None;
+            }
+
+            let (comp, rest) = match self.path.find('/') {
+                Some(i) => (&self.path[..i], &self.path[i + 1..]),
+                None => (self.path, ""),
+            };
+            self.path = rest;
+
+            let at_start = self.at_start;
+            self.at_start = false;
+            match comp {
+                "" => continue,
+                // Like `std`, only a leading `.` of a relative path is kept.
+                "." if at_start => return Some(Component::CurDir),
+                "." => continue,
+                ".." => return Some(Component::ParentDir),
+                name => return Some(Component::Normal(OsStr::new(name))),
+            }
+        }
+    }
+}
+
+/// A slice of a `/`-separated path.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path {
+    inner: str,
+}
+
+impl Path {
+    pub fn new<S: AsRef<str> + ?Sized>(s: &S) -> &Path {
+        unsafe { mem::transmute(s.as_ref()) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    /// Returns the path as a `str`. Always `Some`; present for parity with
+    /// `std::path`.
+    pub fn to_str(&self) -> Option<&str> {
+        Some(&self.inner)
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.inner.starts_with('/')
+    }
+
+    pub fn components(&self) -> Components {
+        Components {
+            path: self.inner.trim_left_matches('/'),
+            has_root: self.is_absolute(),
+            at_start: true,
+        }
+    }
+
+    /// Creates an owned `PathBuf` with `path` adjoined to `self`.
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut buf = self.to_path_buf();
+        buf.push(path);
+        buf
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf { inner: String::from(&self.inner) }
+    }
+
+    /// Returns an object that implements `Display`.
+    pub fn display(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl fmt::Debug for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+/// An owned, mutable path.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct PathBuf {
+    inner: String,
+}
+
+impl PathBuf {
+    pub fn new() -> PathBuf {
+        PathBuf::default()
+    }
+
+    /// Extends `self` with `path`. If `path` is absolute, it replaces `self`.
+    pub fn push<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            self.inner.clear();
+        } else if !self.inner.is_empty() && !self.inner.ends_with('/') {
+            self.inner.push('/');
+        }
+        self.inner.push_str(path.as_str());
+    }
+}
+
+impl Deref for PathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        Path::new(&self.inner)
+    }
+}
+
+impl AsRef<Path> for Path {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for PathBuf {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for str {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for String {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for OsStr {
+    fn as_ref(&self) -> &Path {
+        Path::new(self.to_str().unwrap_or(""))
+    }
+}
+
+impl<'a> From<&'a str> for PathBuf {
+    fn from(s: &'a str) -> PathBuf {
+        PathBuf { inner: String::from(s) }
+    }
+}
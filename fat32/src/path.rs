@@ -0,0 +1,85 @@
+//! Splitting and resolving `/`-separated paths on `str`s, without
+//! `std::path`, so that paths resolve the same way in `no_std` builds, such
+//! as a kernel's, as on hosts. Empty components and `.` are skipped; `..` is
+//! left for the caller to apply, as `FileSystem::open()` does while walking
+//! the path, so that `/file/..` and `/missing/..` fail as they would on a
+//! host.
+//!
+//! ```rust
+//! use fat32::path::{self, Component};
+//!
+//! let parts: Vec<_> = path::components("/boot//./config.txt").collect();
+//! assert_eq!(parts, [Component::RootDir, Component::Normal("boot"),
+//!                    Component::Normal("config.txt")]);
+//! ```
+
+/// A component of a path, as produced by `components()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Component<'a> {
+    /// The leading `/` of an absolute path.
+    RootDir,
+    /// `..`, the parent of the directory before it.
+    ParentDir,
+    /// A name, neither empty nor `.` or `..`.
+    Normal(&'a str),
+}
+
+impl<'a> Component<'a> {
+    /// Returns the equivalent `std::path::Component`.
+    #[cfg(feature = "std")]
+    pub fn to_std(self) -> ::std::path::Component<'a> {
+        use std::ffi::OsStr;
+        use std::path::Component as Std;
+
+        match self {
+            Component::RootDir => Std::RootDir,
+            Component::ParentDir => Std::ParentDir,
+            Component::Normal(name) => Std::Normal(OsStr::new(name)),
+        }
+    }
+}
+
+/// An iterator over the `Component`s of a path.
+#[derive(Debug, Clone)]
+pub struct Components<'a> {
+    rest: &'a str,
+    has_root: bool,
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Component<'a>> {
+        if self.has_root {
+            self.has_root = false;
+            return Some(Component::RootDir);
+        }
+        while !self.rest.is_empty() {
+            let (comp, rest) = match self.rest.find('/') {
+                Some(i) => (&self.rest[..i], &self.rest[i + 1..]),
+                None => (self.rest, ""),
+            };
+            self.rest = rest;
+            match comp {
+                "" | "." => continue,
+                ".." => return Some(Component::ParentDir),
+                name => return Some(Component::Normal(name)),
+            }
+        }
+        None
+    }
+}
+
+/// Returns whether `path` starts at the root directory.
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with('/')
+}
+
+/// Returns an iterator over the components of `path`, skipping empty
+/// components and `.`.
+pub fn components(path: &str) -> Components {
+    Components {
+        rest: path,
+        has_root: is_absolute(path),
+    }
+}
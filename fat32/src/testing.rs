@@ -0,0 +1,301 @@
+//! Builders for small FAT32 images used as test and fuzzing fixtures.
+//!
+//! Every image has the same geometry: 128 512-byte sectors, an MBR with one
+//! FAT32 partition starting at sector 1, one reserved sector holding the
+//! EBPB, a single one-sector FAT, and one-sector clusters, so that cluster
+//! `c` is image sector `c + 1`. The root directory is cluster 2.
+//!
+//! Fixtures are built entry by entry so that tests can say which entry or
+//! cluster they damage instead of poking bytes at fixed offsets:
+//!
+//! ```rust,ignore
+//! let mut fixture = Fixture::mini();
+//! fixture.entry(SUB, 2).set_size(0);
+//! let vfat = VFat::from(Cursor::new(fixture.into_image()))?;
+//! ```
+
+use std::vec::Vec;
+
+/// The size of the image's sectors and clusters.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The number of sectors in a fixture image.
+pub const SECTORS: usize = 128;
+
+/// The cluster of the root directory.
+pub const ROOT: u32 = 2;
+
+/// The cluster of `/SUB` in `Fixture::mini()`.
+pub const SUB: u32 = 3;
+
+/// The attributes of a directory entry for a directory.
+pub const DIRECTORY: u8 = 0x10;
+
+/// The attributes of a directory entry for a regular file.
+pub const ARCHIVE: u8 = 0x20;
+
+/// The FAT entry marking the end of a chain.
+pub const EOC: u32 = 0x0FFF_FFFF;
+
+/// The byte offset of the partition, and so of the EBPB, in the image.
+pub const PARTITION: usize = SECTOR_SIZE;
+
+/// The byte offset of the FAT in the image.
+pub const FAT: usize = 2 * SECTOR_SIZE;
+
+/// A FAT32 image under construction.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    image: Vec<u8>,
+    next_free: u32,
+}
+
+/// A view of one 32-byte directory entry of a `Fixture`.
+#[derive(Debug)]
+pub struct EntryMut<'a>(&'a mut [u8]);
+
+impl Fixture {
+    /// Returns an image holding an empty root directory.
+    pub fn new() -> Fixture {
+        let mut fixture = Fixture {
+            image: vec![0; SECTORS * SECTOR_SIZE],
+            next_free: ROOT + 1,
+        };
+
+        {
+            let mbr = fixture.mbr_mut();
+            mbr[446 + 4] = 0xC; // FAT32 (LBA)
+            put_u32(mbr, 446 + 8, 1);
+            put_u32(mbr, 446 + 12, SECTORS as u32 - 1);
+            mbr[510] = 0x55;
+            mbr[511] = 0xAA;
+        }
+
+        {
+            let bpb = fixture.bpb_mut();
+            bpb[11..13].copy_from_slice(&[0x00, 0x02]); // bytes per sector
+            bpb[13] = 1; // sectors per cluster
+            bpb[14] = 1; // reserved sectors
+            bpb[16] = 1; // number of FATs
+            bpb[21] = 0xF8; // media descriptor
+            put_u32(bpb, 32, SECTORS as u32 - 1); // total logical sectors
+            put_u32(bpb, 36, 1); // sectors per FAT
+            put_u32(bpb, 44, ROOT); // root cluster
+            bpb[510] = 0x55;
+            bpb[511] = 0xAA;
+        }
+
+        fixture.set_fat(0, 0x0FFF_FFF8);
+        fixture.set_fat(1, EOC);
+        fixture.set_fat(ROOT, EOC);
+        fixture
+    }
+
+    /// Returns the fixture most tests start from: a long-named file and a
+    /// subdirectory in the root, and a second file in the subdirectory.
+    ///
+    /// ```text
+    /// /long.txt     LONG~1.TXT, clusters 4 and 5, 1000 zero bytes
+    /// /SUB          cluster 3 (`SUB`)
+    /// /SUB/A.BIN    10 bytes, sharing cluster 4 with /long.txt
+    /// ```
+    ///
+    /// The root holds the long name in entry 0, `LONG~1.TXT` in entry 1 and
+    /// `SUB` in entry 2; `SUB` holds `.`, `..` and `A.BIN` in entries 0-2.
+    pub fn mini() -> Fixture {
+        let mut fixture = Fixture::new();
+        let sub = fixture.dir(ROOT, 2, b"SUB        ");
+        fixture.lfn(ROOT, 0, "long.txt", b"LONG~1  TXT");
+        let long = fixture.file(ROOT, 1, b"LONG~1  TXT", &[0; 1000]);
+        fixture.entry(sub, 2).set(b"A       BIN", ARCHIVE, long, 10);
+        fixture
+    }
+
+    /// Returns the byte offset of cluster `cluster` in the image.
+    pub const fn cluster_offset(cluster: u32) -> usize {
+        (cluster as usize + 1) * SECTOR_SIZE
+    }
+
+    /// Returns the MBR.
+    pub fn mbr_mut(&mut self) -> &mut [u8] {
+        &mut self.image[..SECTOR_SIZE]
+    }
+
+    /// Returns the boot sector of the partition, holding the EBPB.
+    pub fn bpb_mut(&mut self) -> &mut [u8] {
+        &mut self.image[PARTITION..PARTITION + SECTOR_SIZE]
+    }
+
+    /// Returns the FAT.
+    pub fn fat_mut(&mut self) -> &mut [u8] {
+        &mut self.image[FAT..FAT + SECTOR_SIZE]
+    }
+
+    /// Returns the contents of cluster `cluster`.
+    pub fn cluster_mut(&mut self, cluster: u32) -> &mut [u8] {
+        let start = Fixture::cluster_offset(cluster);
+        &mut self.image[start..start + SECTOR_SIZE]
+    }
+
+    /// Returns `len` bytes of the data region starting at cluster `cluster`,
+    /// spanning the clusters that follow it on disk.
+    pub fn data_mut(&mut self, cluster: u32, len: usize) -> &mut [u8] {
+        let start = Fixture::cluster_offset(cluster);
+        &mut self.image[start..start + len]
+    }
+
+    /// Sets the FAT entry of `cluster` to `value`.
+    pub fn set_fat(&mut self, cluster: u32, value: u32) -> &mut Fixture {
+        put_u32(self.fat_mut(), cluster as usize * 4, value);
+        self
+    }
+
+    /// Links `clusters` into a chain ended by `EOC`.
+    pub fn chain(&mut self, clusters: &[u32]) -> &mut Fixture {
+        for (i, &cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).cloned().unwrap_or(EOC);
+            self.set_fat(cluster, next);
+        }
+        self
+    }
+
+    /// Returns the `index`th entry of the directory starting at cluster
+    /// `dir`.
+    pub fn entry<'a>(&'a mut self, dir: u32, index: usize) -> EntryMut<'a> {
+        let start = index * 32;
+        EntryMut(&mut self.cluster_mut(dir)[start..start + 32])
+    }
+
+    /// Allocates `count` contiguous clusters after those already in use,
+    /// links them into a chain, and returns the first.
+    pub fn alloc(&mut self, count: usize) -> u32 {
+        let first = self.next_free;
+        let clusters: Vec<u32> = (first..first + count as u32).collect();
+        assert!(Fixture::cluster_offset(first + count as u32) <= self.image.len(),
+                "fixture image is full");
+        self.chain(&clusters);
+        self.next_free += count as u32;
+        first
+    }
+
+    /// Adds a file named `name` holding `data` as the `index`th entry of
+    /// `dir`, and returns its first cluster, or 0 if `data` is empty.
+    pub fn file(&mut self, dir: u32, index: usize, name: &[u8; 11], data: &[u8]) -> u32 {
+        let cluster = match data.len() {
+            0 => 0,
+            len => self.alloc((len - 1) / SECTOR_SIZE + 1),
+        };
+        self.data_mut(cluster, data.len()).copy_from_slice(data);
+        self.entry(dir, index).set(name, ARCHIVE, cluster, data.len() as u32);
+        cluster
+    }
+
+    /// Adds an empty subdirectory named `name` as the `index`th entry of
+    /// `parent`, and returns its cluster.
+    pub fn dir(&mut self, parent: u32, index: usize, name: &[u8; 11]) -> u32 {
+        let cluster = self.alloc(1);
+        let dotdot = if parent == ROOT { 0 } else { parent };
+        self.entry(parent, index).set(name, DIRECTORY, cluster, 0);
+        self.entry(cluster, 0).set(b".          ", DIRECTORY, cluster, 0);
+        self.entry(cluster, 1).set(b"..         ", DIRECTORY, dotdot, 0);
+        cluster
+    }
+
+    /// Writes a one-entry long file name, of at most 13 ASCII characters,
+    /// for the short name `short` as the `index`th entry of `dir`. The short
+    /// entry itself goes in the next entry.
+    pub fn lfn(&mut self, dir: u32, index: usize, name: &str, short: &[u8; 11]) -> &mut Fixture {
+        const OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        assert!(name.len() <= OFFSETS.len(), "long name doesn't fit one entry");
+
+        {
+            let entry = self.entry(dir, index).0;
+            entry[0] = 0x41; // the first and last entry of the name
+            entry[11] = 0x0F;
+            entry[13] = lfn_checksum(short);
+            let units = name.encode_utf16().chain(Some(0)).chain(::std::iter::repeat(0xFFFF));
+            for (&offset, unit) in OFFSETS.iter().zip(units) {
+                entry[offset] = unit as u8;
+                entry[offset + 1] = (unit >> 8) as u8;
+            }
+        }
+        self
+    }
+
+    /// Returns the finished image.
+    pub fn into_image(self) -> Vec<u8> {
+        self.image
+    }
+}
+
+impl Default for Fixture {
+    fn default() -> Fixture {
+        Fixture::new()
+    }
+}
+
+impl<'a> EntryMut<'a> {
+    /// Sets the short name, attributes, first cluster and size.
+    pub fn set(&mut self, name: &[u8; 11], attr: u8, cluster: u32, size: u32) -> &mut Self {
+        self.set_name(name).set_attributes(attr).set_cluster(cluster).set_size(size)
+    }
+
+    /// Sets the short name, `NAME    EXT`, padded with spaces.
+    pub fn set_name(&mut self, name: &[u8; 11]) -> &mut Self {
+        self.0[..11].copy_from_slice(name);
+        self
+    }
+
+    /// Sets the attributes byte.
+    pub fn set_attributes(&mut self, attr: u8) -> &mut Self {
+        self.0[11] = attr;
+        self
+    }
+
+    /// Sets the first cluster.
+    pub fn set_cluster(&mut self, cluster: u32) -> &mut Self {
+        put_u16(self.0, 20, (cluster >> 16) as u16);
+        put_u16(self.0, 26, cluster as u16);
+        self
+    }
+
+    /// Sets the size in bytes.
+    pub fn set_size(&mut self, size: u32) -> &mut Self {
+        put_u32(self.0, 28, size);
+        self
+    }
+
+    /// Sets the raw FAT date and time of last modification.
+    pub fn set_modified(&mut self, date: u16, time: u16) -> &mut Self {
+        put_u16(self.0, 22, time);
+        put_u16(self.0, 24, date);
+        self
+    }
+
+    /// Marks the entry deleted.
+    pub fn delete(&mut self) -> &mut Self {
+        self.0[0] = 0xE5;
+        self
+    }
+
+    /// Returns the raw bytes of the entry.
+    pub fn bytes(&mut self) -> &mut [u8] {
+        self.0
+    }
+}
+
+/// Returns the checksum of a short name stored in its long name entries.
+pub fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buf[offset + i] = (value >> (i * 8)) as u8;
+    }
+}
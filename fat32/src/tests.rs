@@ -1,3 +1,4 @@
+extern crate fatfs;
 extern crate rand;
 
 use std::io::prelude::*;
@@ -6,8 +7,15 @@ use std::path::Path;
 
 use vfat::{Shared, VFat, BiosParameterBlock};
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
+use testing::{Fixture, EOC, FAT, PARTITION, ROOT, SUB};
 use traits::*;
 
+/// The seeds of the randomized tests, one per test so that changing the
+/// inputs of one doesn't change those of the others. Any seed should pass.
+const CORRUPT_IMAGE_SEED: [u32; 4] = [0xc0de, 0xfa7, 0x32, 0xdead];
+const GENERATED_IMAGE_SEED: [u32; 4] = [0xf47, 0x6e4, 0xbeef, 0x5eed];
+const FATFS_IMAGE_SEED: [u32; 4] = [0xf47, 0xfa75, 0xfa7f5, 0xd1ff];
+
 macro check_size($T:ty, $size:expr) {
     assert_eq!(::std::mem::size_of::<$T>(), $size,
         "'{}' does not have the expected size of {}", stringify!($T), $size);
@@ -98,6 +106,27 @@ fn test_mbr() {
     MasterBootRecord::from(Cursor::new(&mut data[..])).expect("valid MBR");
 }
 
+#[test]
+fn test_mbr_decode_encode() {
+    let mut data = [0u8; 512];
+    data[436..446].copy_from_slice(b"disk-id-42");
+    data[446..462].copy_from_slice(&[
+        0x80, 0x01, 0x02, 0x03, 0x0C, 0xFE, 0xFF, 0xFF,
+        0x00, 0x08, 0x00, 0x00, 0x78, 0x56, 0x34, 0x12,
+    ]);
+    data[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    let mbr = MasterBootRecord::decode(&data);
+    let part = mbr.partition_table[0];
+    assert_eq!(&mbr.disk_id, b"disk-id-42");
+    assert_eq!(part.boot_indicator, 0x80);
+    assert_eq!(part.partition_type, 0x0C);
+    assert_eq!({ part.relative_sector }, 0x800);
+    assert_eq!({ part.total_sectors }, 0x12345678);
+    assert_eq!(mbr.first_fat32().map(|p| p.relative_sector), Some(0x800));
+    assert_eq!(&mbr.encode()[..], &data[..]);
+}
+
 #[test]
 fn check_ebpb_size() {
     check_size!(BiosParameterBlock, 512);
@@ -114,6 +143,39 @@ fn check_ebpb_signature() {
     BiosParameterBlock::from(Cursor::new(&mut data[..]), 0).unwrap();
 }
 
+#[test]
+fn test_ebpb_decode_encode() {
+    let mut data = [0u8; 512];
+    data[..11].copy_from_slice(b"\xEB\x58\x90mkfs.fat");
+    data[11..48].copy_from_slice(&[
+        0x00, 0x02, 0x08, 0x20, 0x00, 0x02, 0x00, 0x00,
+        0x00, 0x00, 0xF8, 0x00, 0x00, 0x20, 0x00, 0x40,
+        0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0xF1, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x00, 0x00, 0x00,
+    ]);
+    data[64..71].copy_from_slice(&[0x80, 0x00, 0x29, 0xEF, 0xBE, 0xAD, 0xDE]);
+    data[71..90].copy_from_slice(b"NO NAME    FAT32   ");
+    data[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    let bpb = BiosParameterBlock::decode(&data);
+    assert_eq!(&bpb.oem_id, b"mkfs.fat");
+    assert_eq!({ bpb.bytes_per_sector }, 512);
+    assert_eq!(bpb.sectors_per_cluster, 8);
+    assert_eq!({ bpb.num_reserved_sectors }, 32);
+    assert_eq!(bpb.num_fat, 2);
+    assert_eq!(bpb.media_desc_type, 0xF8);
+    assert_eq!({ bpb.num_hidden_sectors }, 0x800);
+    assert_eq!(bpb.total_logical_sectors(), 0x40000);
+    assert_eq!(bpb.sectors_per_fat(), 0x3F1);
+    assert_eq!({ bpb.root_cluster }, 2);
+    assert_eq!(bpb.signature, 0x29);
+    assert_eq!({ bpb.volumn_id }, 0xDEADBEEF);
+    assert_eq!(&bpb.volumn_label, b"NO NAME    ");
+    assert_eq!({ bpb.bootable_signature }, 0xAA55);
+    assert_eq!(&bpb.encode()[..], &data[..]);
+}
+
 #[test]
 fn test_ebpb() {
     let mut ebpb1 = resource!("ebpb1.img");
@@ -127,6 +189,24 @@ fn test_ebpb() {
     BiosParameterBlock::from(Cursor::new(&mut data[..]), 1).expect("valid EBPB");
 }
 
+#[test]
+fn test_checked_casts() {
+    use util::{SliceExt, VecExt, CastError};
+
+    let bytes: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+    unsafe {
+        assert_eq!(bytes[..7].cast_copy::<u32>(), Err(CastError::Size));
+        assert_eq!(bytes[1..9].cast_copy::<u32>(),
+                   Ok(vec![u32::from_le(0x05040302), u32::from_le(0x09080706)]));
+
+        let pairs = vec![[1u8, 2]; 3];
+        let pairs = pairs.cast::<[u8; 4]>().expect_err("6 bytes aren't whole quads");
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(vec![[1u8, 2]; 4].cast::<[u8; 4]>().ok(), Some(vec![[1, 2, 1, 2]; 2]));
+        assert!(vec![0u8; 8].cast::<u32>().is_err());
+    }
+}
+
 #[test]
 fn check_entry_sizes() {
     check_size!(::vfat::dir::VFatRegularDirEntry, 32);
@@ -186,7 +266,9 @@ fn hash_dir<T: Dir>(
     Ok(entries)
 }
 
-fn hash_dir_from<P: AsRef<Path>>(vfat: Shared<VFat>, path: P) -> String {
+fn hash_dir_from<D, L, P>(vfat: Shared<VFat<D, L>, L>, path: P) -> String
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
     let mut hash = String::new();
     hash_dir(&mut hash, vfat.open_dir(path).expect("directory exists")).unwrap();
     hash
@@ -349,3 +431,3437 @@ fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
     f::<Shared<VFat>>();
 }
+
+#[test]
+fn test_usage() {
+    use vfat::ClusterState;
+
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let usage = vfat.borrow_mut().usage().expect("usage");
+    assert_eq!(usage.free + usage.used + usage.bad, usage.total());
+    assert_eq!(usage.runs().iter().map(|r| r.len).sum::<u32>(), usage.total());
+
+    let root = vfat.borrow().root_dir_cluster;
+    assert_eq!(usage.state(root), Some(ClusterState::Used));
+}
+
+#[test]
+fn test_timestamp_system_time() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use vfat::Timestamp as VFatTimestamp;
+
+    // 2018-03-02 12:34:57.25 UTC
+    let time = UNIX_EPOCH + Duration::new(1519994097, 250_000_000);
+    let ts = VFatTimestamp::from(time);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2018, 3, 2));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (12, 34, 56));
+    assert_eq!(ts.hundredths(), 125);
+    assert_eq!(SystemTime::from(ts), time);
+
+    let epoch = VFatTimestamp::from(UNIX_EPOCH);
+    assert_eq!((epoch.year(), epoch.month(), epoch.day()), (1980, 1, 1));
+}
+
+#[test]
+fn test_unix_mode() {
+    use vfat::{Attributes, Metadata as VFatMetadata, S_IFDIR, S_IFREG};
+
+    let dir = VFatMetadata { attr: Attributes::from_unix_mode(S_IFDIR | 0o755), ..Default::default() };
+    assert!(dir.attr.directory() && !dir.attr.read_only());
+    assert_eq!(dir.unix_mode(), S_IFDIR | 0o755);
+
+    let file = VFatMetadata { attr: Attributes::from_unix_mode(S_IFREG | 0o444), ..Default::default() };
+    assert!(!file.attr.directory() && file.attr.read_only());
+    assert_eq!(file.unix_mode(), S_IFREG | 0o444);
+}
+
+#[test]
+fn test_metadata_display() {
+    use vfat::{Attributes, Date, Metadata as VFatMetadata, Time, Timestamp as VFatTimestamp};
+
+    let mtime = VFatTimestamp {
+        date: Date::from_ymd(2024, 1, 2),
+        time: Time::from_hms(13, 37, 10),
+        hundredths: 0,
+    };
+    let metadata = VFatMetadata {
+        attr: Attributes(0x10 | 0x01 | 0x02),
+        mtime: mtime,
+        size: 4096,
+        ..Default::default()
+    };
+    assert_eq!(metadata.to_string(), "d-rh- 2024-01-02 13:37       4096");
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let listing: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.to_string())
+        .collect();
+    assert_eq!(listing, vec![
+        "-a--- 1980-00-00 00:00       1000 long.txt".to_string(),
+        "d---- 1980-00-00 00:00          0 SUB".to_string(),
+    ]);
+}
+
+#[test]
+fn test_timestamp_ordering() {
+    use vfat::Timestamp as VFatTimestamp;
+
+    let earlier = VFatTimestamp::from_unix_seconds(1519994097);
+    let later = VFatTimestamp::from_unix_seconds(1519994097 + 2);
+    assert!(earlier < later);
+    assert_eq!(earlier.to_unix_seconds(), 1519994097);
+    assert_eq!(VFatTimestamp::from_unix_seconds(-1).year(), 1980);
+}
+
+#[test]
+fn test_timestamp_validation() {
+    use vfat::{Date, Time, Timestamp as VFatTimestamp, TimestampPolicy};
+
+    // 1980-00-00 25:62:62
+    let bad = VFatTimestamp { date: Date::default(), time: Time(0xCFDF), hundredths: 0 };
+    assert!(!bad.is_valid());
+    assert_eq!(bad.sanitize(TimestampPolicy::Unchecked), Some(bad));
+    assert_eq!(bad.sanitize(TimestampPolicy::Error), None);
+
+    let clamped = bad.sanitize(TimestampPolicy::Clamp).unwrap();
+    assert!(clamped.is_valid());
+    assert_eq!((clamped.month(), clamped.day(), clamped.hour()), (1, 1, 23));
+
+    let epoch = bad.sanitize(TimestampPolicy::EpochDefault).unwrap();
+    assert_eq!(epoch.to_unix_seconds(), 315532800);
+
+    let unset = VFatTimestamp::default();
+    assert!(unset.is_unset() && unset.is_valid());
+    for &policy in [TimestampPolicy::Error, TimestampPolicy::Clamp].iter() {
+        assert_eq!(unset.sanitize(policy), Some(unset));
+    }
+}
+
+#[test]
+fn test_timestamp_policy_on_mount() {
+    use std::io::ErrorKind;
+    use vfat::{MountOptions, TimestampPolicy};
+
+    // Every timestamp in the image is unset.
+    for &policy in [TimestampPolicy::Error, TimestampPolicy::Clamp].iter() {
+        let options = MountOptions::new().timestamp_policy(policy);
+        let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).unwrap();
+        let entries: Vec<_> = (&vfat).open_dir("/").unwrap().entries().unwrap().collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.metadata().mtime.is_unset()));
+    }
+
+    // SUB/A.BIN is modified on 1980-13-00.
+    let mut fixture = Fixture::mini();
+    fixture.entry(SUB, 2).set_modified(0x01A0, 0);
+    let options = MountOptions::new().timestamp_policy(TimestampPolicy::Error);
+    let vfat = VFat::from_with_options(Cursor::new(fixture.into_image()), options).unwrap();
+    let e = (&vfat).open_dir("/SUB").unwrap().entries().map(|_| ()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!((&vfat).open_dir("/").unwrap().entries().is_ok());
+}
+
+#[test]
+fn test_utc_offset() {
+    use std::time::{Duration, UNIX_EPOCH};
+    use vfat::{Timestamp as VFatTimestamp, UtcOffset};
+
+    // 2018-03-02 12:34:56 in UTC+01:30
+    let ts = VFatTimestamp::from_unix_seconds(1519994096);
+    let offset = UtcOffset::from_hm(1, 30);
+    assert_eq!(offset.to_system_time(ts), UNIX_EPOCH + Duration::new(1519994096 - 5400, 0));
+    assert_eq!(offset.to_timestamp(offset.to_system_time(ts)), ts);
+    assert_eq!(offset.format_iso8601(ts), "2018-03-02T12:34:56.00+01:30");
+    assert_eq!(UtcOffset::UTC.format_iso8601(ts), "2018-03-02T12:34:56.00Z");
+    assert_eq!(UtcOffset::from_hm(-5, 0).format_iso8601(ts), "2018-03-02T12:34:56.00-05:00");
+}
+
+#[test]
+fn test_utc_offset_on_mount() {
+    use std::time::{Duration, UNIX_EPOCH};
+    use vfat::{Date, MountOptions, Time, UtcOffset};
+
+    // SUB/A.BIN is modified at 2018-03-02 12:34:56 local time.
+    let mut fixture = Fixture::mini();
+    let (time, date) = (Time::from_hms(12, 34, 56).0, Date::from_ymd(2018, 3, 2).to_raw());
+    fixture.entry(SUB, 2).set_modified(date, time);
+
+    let options = MountOptions::new().utc_offset(UtcOffset::from_hm(-5, 0));
+    let vfat = VFat::from_with_options(Cursor::new(fixture.into_image()), options).unwrap();
+    let file = (&vfat).open_file("/SUB/A.BIN").unwrap();
+    let metadata = file.metadata();
+    assert_eq!(metadata.utc_offset, UtcOffset::from_hm(-5, 0));
+    assert_eq!(metadata.mtime.to_unix_seconds(), 1519994096);
+    assert_eq!(metadata.to_unix_seconds(metadata.mtime), 1519994096 + 5 * 3600);
+    assert_eq!(metadata.to_system_time(metadata.mtime),
+               UNIX_EPOCH + Duration::new(1519994096 + 5 * 3600, 0));
+
+    let root = (&vfat).open_dir("/").unwrap();
+    assert_eq!(root.metadata().utc_offset, UtcOffset::from_hm(-5, 0));
+}
+
+#[test]
+fn test_vfat_static_device() {
+    let vfat: Shared<VFat<::std::fs::File>> =
+        VFat::from_device(resource!("mock1.fat32.img"), Default::default())
+            .expect("failed to initialize VFAT from image");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 root directory", hash, hash_for!("root-entries-1"));
+}
+
+#[test]
+fn test_shared_fs_across_threads() {
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let handles: Vec<_> = (0..4).map(|_| {
+        let vfat = vfat.clone();
+        ::std::thread::spawn(move || hash_dir_from(vfat, "/"))
+    }).collect();
+
+    for handle in handles {
+        let hash = handle.join().expect("thread panicked");
+        assert_hash_eq!("mock 1 root directory", hash, hash_for!("root-entries-1"));
+    }
+}
+
+#[test]
+fn test_non_sync_device_across_threads() {
+    use std::cell::Cell;
+
+    // A device that isn't `Sync` mounts, and moves between threads, whatever
+    // features are enabled.
+    struct Counting(Cursor<Vec<u8>>, Cell<u64>);
+
+    impl BlockDevice for Counting {
+        fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.1.set(self.1.get() + 1);
+            self.0.read_sector(n, buf)
+        }
+
+        fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.write_sector(n, buf)
+        }
+    }
+
+    let device = Counting(Cursor::new(Fixture::mini().into_image()), Cell::new(0));
+    let vfat = VFat::from(device).expect("valid image");
+    let data = ::std::thread::spawn(move || (&vfat).read("/long.txt").unwrap())
+        .join().expect("thread panicked");
+    assert_eq!(data.len(), 1000);
+}
+
+#[test]
+#[cfg(feature = "rwlock")]
+fn test_concurrent_readers() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use vfat::BlockingRwLock;
+
+    let device = Box::new(Cursor::new(Fixture::mini().into_image())) as SyncBlockDevice;
+    let vfat: Shared<VFat<SyncBlockDevice, BlockingRwLock>, BlockingRwLock> =
+        VFat::from_device_with_lock(device, Default::default()).expect("valid image");
+
+    // Another thread borrows the volume while this one holds a borrow.
+    let held = vfat.borrow();
+    let (sender, receiver) = mpsc::channel();
+    let reader = {
+        let vfat = vfat.clone();
+        ::std::thread::spawn(move || sender.send(vfat.borrow().bytes_per_sector).unwrap())
+    };
+    let bytes = receiver.recv_timeout(Duration::from_secs(10)).expect("readers run concurrently");
+    assert_eq!(bytes, held.bytes_per_sector);
+    drop(held);
+    reader.join().expect("thread panicked");
+    assert_eq!((&vfat).read("/long.txt").unwrap().len(), 1000);
+}
+
+#[test]
+fn test_vfat_custom_lock() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    struct SpinLock(AtomicBool);
+
+    unsafe impl RawLock for SpinLock {
+        fn new() -> SpinLock {
+            SpinLock(AtomicBool::new(false))
+        }
+
+        fn lock(&self) {
+            while self.0.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_err() {}
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    let vfat: Shared<VFat<DynBlockDevice, SpinLock>, SpinLock> =
+        VFat::from_device_with_lock(Box::new(resource!("mock1.fat32.img")) as DynBlockDevice,
+                                    Default::default())
+            .expect("failed to initialize VFAT from image");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 root directory", hash, hash_for!("root-entries-1"));
+}
+
+#[test]
+fn test_cached_device_shared() {
+    use vfat::{CachedDevice, DefaultLock};
+
+    let device: Shared<Cursor<Vec<u8>>, DefaultLock> = Shared::with_lock(Cursor::new(vec![0; 2048]));
+    let mut first = CachedDevice::shared(device.clone());
+    let mut second = CachedDevice::shared(device.clone());
+    first.write_at(1, 0, b"first").unwrap();
+    second.write_at(2, 0, b"second").unwrap();
+    assert_eq!(&device.borrow().get_ref()[512..517], &[0; 5]);
+
+    first.flush().unwrap();
+    second.flush().unwrap();
+    let mut buf = [0; 6];
+    second.read_at(1, 0, &mut buf[..5]).unwrap();
+    assert_eq!(&buf[..5], b"first");
+    first.read_at(2, 0, &mut buf).unwrap();
+    assert_eq!(&buf, b"second");
+    assert_eq!(&device.borrow().get_ref()[1024..1030], b"second");
+}
+
+
+/// Visits entries reachable from `dir`, reading every file, until `budget`
+/// entries have been visited. Errors are ignored; only panics matter.
+fn fuzz_walk(dir: &::vfat::Dir, depth: usize, budget: &mut usize) {
+    let entries = match dir.entries() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries {
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
+
+        let _ = entry.metadata().to_string();
+        match entry {
+            ::vfat::Entry::File(mut file) => {
+                let mut buf = [0u8; 4096];
+                loop {
+                    match file.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => continue,
+                    }
+                }
+                let _ = file.seek(::std::io::SeekFrom::Current(::std::i64::MIN));
+                let _ = file.seek(::std::io::SeekFrom::End(::std::i64::MAX));
+            }
+            ::vfat::Entry::Dir(ref subdir) if depth < 4 => fuzz_walk(subdir, depth + 1, budget),
+            ::vfat::Entry::Dir(_) => {}
+        }
+    }
+}
+
+#[test]
+fn test_corrupt_images_do_not_panic() {
+    use tests::rand::{Rng, SeedableRng, XorShiftRng};
+
+    // Corrupt bytes in the partition table, the EBPB, the FAT entries in
+    // use, or the directories.
+    let regions = [(446, 512), (PARTITION + 11, PARTITION + 48), (FAT, FAT + 24),
+                   (Fixture::cluster_offset(ROOT), Fixture::cluster_offset(SUB + 1))];
+
+    let mut rng = XorShiftRng::from_seed(CORRUPT_IMAGE_SEED);
+    for round in 0..2048 {
+        let mut image = Fixture::mini().into_image();
+        let (start, end) = regions[round % regions.len()];
+        for _ in 0..rng.gen_range(1, 8) {
+            let i = rng.gen_range(start, end);
+            image[i] = rng.gen();
+        }
+
+        if let Ok(vfat) = VFat::from(Cursor::new(image)) {
+            let _ = vfat.borrow_mut().usage();
+            let _ = (&vfat).disk_usage("/", |_, _| ());
+            let _ = (&vfat).open("/SUB/./A.BIN");
+            let _ = (&vfat).open("/SUB/../long.txt");
+            let mut budget = 256;
+            fuzz_walk(&::vfat::Dir::root(vfat.clone()), 0, &mut budget);
+        }
+    }
+}
+
+#[test]
+fn test_vfat_mount_errors() {
+    let mut fixture = Fixture::mini();
+    fixture.bpb_mut()[13] = 0;
+    let e = VFat::from(Cursor::new(fixture.into_image())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb("sectors per cluster"));
+
+    let invalid_bpb = |offset: usize, bytes: &[u8], field: &str| {
+        let mut fixture = Fixture::mini();
+        fixture.bpb_mut()[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let e = VFat::from(Cursor::new(fixture.into_image())).unwrap_err();
+        expect_variant!(e, ::vfat::Error::InvalidBpb(f) if f == field);
+    };
+    invalid_bpb(11, &[0x00, 0x03], "bytes per sector");
+    invalid_bpb(13, &[3], "sectors per cluster");
+    invalid_bpb(14, &[0, 0], "reserved sectors");
+    invalid_bpb(16, &[0], "number of FATs");
+    invalid_bpb(36, &[0, 0, 0, 0], "sectors per FAT");
+    invalid_bpb(32, &[128, 0, 0, 0], "total sectors");
+    invalid_bpb(32, &[2, 0, 0, 0], "total sectors");
+
+    let mut fixture = Fixture::mini();
+    fixture.bpb_mut()[22] = 1;
+    let e = VFat::from(Cursor::new(fixture.into_image())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::UnsupportedFatType);
+    assert_eq!(e.to_string(), "FAT12 and FAT16 are not supported");
+
+    let mut fixture = Fixture::mini();
+    fixture.mbr_mut()[510] = 0;
+    let e = VFat::from(Cursor::new(fixture.into_image())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Mbr(::mbr::Error::BadSignature));
+    assert_eq!(::std::io::Error::from(e).kind(), ::std::io::ErrorKind::InvalidData);
+
+    // Cluster 4 links to cluster 5, which is marked free.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(5, 0);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let e = vfat.borrow_mut().chain_len(::vfat::Cluster::from(4)).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    assert_eq!(e.to_string(), "corrupt cluster chain at cluster 5");
+}
+
+#[test]
+fn test_chain_loop() {
+    // Cluster 5, the last of `long.txt`, links back to cluster 4.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(5, 4);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let e = vfat.borrow_mut().chain_len(::vfat::Cluster::from(4)).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    let e = vfat.borrow_mut().resolve_chain(::vfat::Cluster::from(4), true).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+    let mut buf = Vec::new();
+    let e = vfat.borrow_mut().read_chain(::vfat::Cluster::from(4), &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+    assert!(buf.is_empty());
+
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let e = file.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_dotdot_of_root_subdir() {
+    // `/SUB/..` records cluster 0, which stands for the root directory.
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let sub = (&vfat).open_dir("/SUB").expect("SUB exists");
+    let dotdot = sub.entries().expect("SUB is readable")
+        .find(|entry| entry.name() == "..")
+        .expect("SUB has '..'");
+    let parent = dotdot.as_dir().expect("'..' is a directory");
+    assert_eq!(parent.first_cluster.get_index(), 0);
+
+    let names: Vec<String> = parent.entries().expect("the root is readable")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["long.txt", "SUB"]);
+}
+
+#[test]
+fn test_bad_cluster() {
+    use vfat::MountOptions;
+
+    // Cluster 5, the second half of `long.txt`, is marked bad.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(5, 0x0FFF_FFF7);
+    for byte in fixture.data_mut(4, 1024).iter_mut() {
+        *byte = 0xAB;
+    }
+
+    let vfat = VFat::from(Cursor::new(fixture.clone().into_image())).expect("valid image");
+    let e = vfat.borrow_mut().chain_len(::vfat::Cluster::from(4)).unwrap_err();
+    assert_eq!(e.to_string(), "cluster 5 is marked bad");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let e = file.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+
+    let options = MountOptions { lenient: true, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(fixture.clone().into_image()), options)
+        .expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("lenient read");
+    assert_eq!(data.len(), 1000);
+    assert!(data[..512].iter().all(|&b| b == 0xAB));
+    assert!(data[512..].iter().all(|&b| b == 0));
+    assert_eq!(file.unreliable_ranges(), &[512..1000]);
+
+    // Reserved entries are still reported, even in lenient mode.
+    fixture.set_fat(5, 0x0FFF_FFF0);
+    let vfat = VFat::from_with_options(Cursor::new(fixture.into_image()), options)
+        .expect("valid image");
+    let e = vfat.borrow_mut().chain_len(::vfat::Cluster::from(4)).unwrap_err();
+    assert_eq!(e.to_string(), "cluster 5 is marked reserved");
+}
+
+#[test]
+fn test_lenient_read_cursor() {
+    use vfat::MountOptions;
+
+    let mut fixture = Fixture::mini();
+    for byte in fixture.cluster_mut(5).iter_mut() {
+        *byte = 0xCD;
+    }
+    let options = MountOptions { lenient: true, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(fixture.into_image()), options)
+        .expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    file.read_exact(&mut [0; 600]).expect("lenient read");
+
+    // Reads carry on from the cluster last read rather than walking the
+    // chain again, so marking cluster 4 bad now doesn't affect them.
+    let fat_start = vfat.borrow().fat_start_sector;
+    vfat.borrow_mut().device.write_at(fat_start, 16, &[0xF7, 0xFF, 0xFF, 0x0F]).unwrap();
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).expect("lenient read");
+    assert_eq!(rest.len(), 400);
+    assert!(rest.iter().all(|&b| b == 0xCD));
+    assert!(file.unreliable_ranges().is_empty());
+}
+
+#[test]
+fn test_cluster_bounds() {
+    use std::io::ErrorKind;
+
+    let mut fixture = Fixture::mini();
+    fixture.bpb_mut()[44] = 1;
+    let e = VFat::from(Cursor::new(fixture.into_image())).unwrap_err();
+    expect_variant!(e, ::vfat::Error::InvalidBpb("root cluster"));
+
+    // `A.BIN` starts past the last of the 125 data clusters.
+    let mut fixture = Fixture::mini();
+    fixture.entry(SUB, 2).set_cluster(127);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    assert_eq!(vfat.borrow().max_cluster, 126);
+    let mut file = (&vfat).open_file("/SUB/A.BIN").expect("A.BIN exists");
+    let e = file.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!(e.to_string().starts_with("cluster 127 is out of range"));
+
+    // Cluster 4 links past the data region.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(4, 200);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let e = vfat.borrow_mut().chain_len(::vfat::Cluster::from(4)).unwrap_err();
+    assert_eq!(e.to_string(), "corrupt cluster chain at cluster 4");
+}
+
+#[test]
+fn test_read_at_byte_offsets() {
+    use std::io::{Seek, SeekFrom};
+
+    // `long.txt` spans clusters 4 and 5.
+    let mut fixture = Fixture::mini();
+    let pattern: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+    fixture.data_mut(4, 1024).copy_from_slice(&pattern);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let mut buf = [0; 100];
+    let read = vfat.borrow_mut().read_cluster(::vfat::Cluster::from(4), 500, &mut buf);
+    assert_eq!(read.unwrap(), 12);
+    assert_eq!(&buf[..12], &pattern[500..512]);
+
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    file.seek(SeekFrom::Start(500)).unwrap();
+    let mut buf = [0; 30];
+    assert_eq!(file.read(&mut buf).unwrap(), 30);
+    assert_eq!(&buf[..], &pattern[500..530]);
+
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut data = Vec::new();
+    let mut chunk = [0; 7];
+    loop {
+        match file.read(&mut chunk).unwrap() {
+            0 => break,
+            n => data.extend_from_slice(&chunk[..n]),
+        }
+    }
+    assert_eq!(&data[..], &pattern[..1000]);
+}
+
+#[test]
+fn test_cached_device_partial_io() {
+    use std::io::ErrorKind;
+    use vfat::CachedDevice;
+
+    let image: Vec<u8> = (0..2048).map(|i| i as u8).collect();
+    let mut device: CachedDevice<_> = CachedDevice::new(Cursor::new(image));
+
+    let e = device.read_sector(1, &mut [0; 100]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    let e = device.write_sector(1, &[0xFF; 100]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+    let mut buf = [0; 4];
+    device.read_at(1, 508, &mut buf).unwrap();
+    assert_eq!(buf, [252, 253, 254, 255]);
+    let e = device.read_at(1, 510, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    device.write_at(1, 2, &[0xAA, 0xBB]).unwrap();
+    let mut sector = [0; 512];
+    assert_eq!(device.read_sector(1, &mut sector).unwrap(), 512);
+    assert_eq!(&sector[..5], &[0, 1, 0xAA, 0xBB, 4]);
+}
+
+#[test]
+fn test_cache_eviction() {
+    use vfat::{CachePolicy, Clock, EvictionPolicy, Lfu, MountOptions};
+
+    for &policy in [CachePolicy::Lru, CachePolicy::Lfu, CachePolicy::Clock].iter() {
+        let options = MountOptions { cache_sectors: 3, cache_policy: policy, pin_fat: true,
+                                     ..MountOptions::default() };
+        let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).unwrap();
+        let mut data = Vec::new();
+        (&vfat).open_file("/long.txt").unwrap().read_to_end(&mut data).unwrap();
+        (&vfat).open_file("/SUB/A.BIN").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 1010);
+        let stats = vfat.borrow().stats();
+        assert!(stats.cached_sectors <= 3 && stats.evicted_sectors > 0, "{:?}", policy);
+        assert!(vfat.borrow().device.is_cached(1..2), "{:?} evicted the FAT", policy);
+    }
+
+    let mut lfu = Lfu::new();
+    let mut clock = Clock::new();
+    for policy in [&mut lfu as &mut EvictionPolicy, &mut clock].iter_mut() {
+        policy.insert(1);
+        policy.insert(2);
+        policy.touch(1);
+        assert_eq!(policy.victim(&mut |_| true), Some(2));
+        assert_eq!(policy.victim(&mut |sector| sector != 2), Some(1));
+        policy.remove(1);
+        assert_eq!(policy.victim(&mut |sector| sector != 2), None);
+    }
+}
+
+#[test]
+fn test_dir_position() {
+    use traits::Entry;
+    use vfat::{Cluster, DirPosition};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let root = (&vfat).open_dir("/").unwrap();
+    let mut entries = root.entries_all(false).unwrap();
+    assert_eq!(entries.next().unwrap().name(), "long.txt");
+    let position = entries.position();
+    assert_eq!(position, DirPosition { cluster: Cluster::from(2), index: 2 });
+    assert_eq!(DirPosition::from_token(position.to_token()), position);
+
+    let mut resumed = root.entries_from(position, false).unwrap();
+    assert_eq!(resumed.next().unwrap().name(), "SUB");
+    assert!(resumed.next().is_none());
+    assert!(root.entries_from(resumed.position(), false).unwrap().next().is_none());
+    assert_eq!(root.entries_from(DirPosition::from_token(0), false).unwrap().count(), 2);
+
+    let stray = DirPosition { cluster: Cluster::from(3), index: 0 };
+    let e = root.entries_from(stray, false).err().expect("SUB's cluster isn't in the root");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_change_notifications() {
+    use std::sync::{Arc, Mutex};
+    use vfat::{ChangeEvent, Notification};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    let id = vfat.borrow_mut().subscribe(move |n: &Notification| {
+        sink.lock().unwrap().push(n.clone())
+    });
+
+    vfat.borrow_mut().notify(ChangeEvent::Modified, "/long.txt");
+    assert!(seen.lock().unwrap().is_empty(), "delivered before the flush");
+    vfat.borrow_mut().flush().unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![Notification {
+        event: ChangeEvent::Modified,
+        path: Path::new("/long.txt").to_path_buf(),
+    }]);
+
+    assert!(vfat.borrow_mut().unsubscribe(id));
+    assert!(!vfat.borrow_mut().unsubscribe(id));
+    vfat.borrow_mut().notify(ChangeEvent::Removed, "/SUB");
+    vfat.borrow_mut().flush().unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_cluster_helpers() {
+    use vfat::Cluster;
+
+    assert_eq!(Cluster::data(0), None);
+    assert_eq!(Cluster::data(1), None);
+    assert_eq!(Cluster::data(2), Some(Cluster::from(2)));
+    assert_eq!(Cluster::data(0x0FFFFFEF), Some(Cluster::from(0x0FFFFFEF)));
+    assert_eq!(Cluster::data(0x0FFFFFF7), None);
+    assert_eq!(Cluster::data(0x10000005), None);
+    assert_eq!(Cluster::from(0x10000005), Cluster::from(5));
+    assert_eq!(Cluster::data_within(10, 9), None);
+    assert!(!Cluster::from(0x0FFFFFFF).is_valid_data_cluster());
+
+    let cluster = Cluster::from(5);
+    assert_eq!(cluster.get_offset(), Some(3));
+    assert_eq!(cluster.first_sector(100, 8), Some(124));
+    assert_eq!(Cluster::from(1).first_sector(100, 8), None);
+    assert_eq!(Cluster::from_sector(124, 100, 8), Some(cluster));
+    assert_eq!(Cluster::from_sector(131, 100, 8), Some(cluster));
+    assert_eq!(Cluster::from_sector(99, 100, 8), None);
+    assert_eq!(cluster.checked_add(2), Some(Cluster::from(7)));
+    assert_eq!(Cluster::from(0x0FFFFFEF).checked_add(1), None);
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let vfat = vfat.borrow();
+    assert_eq!(vfat.cluster_sector(Cluster::from(2)).unwrap(), vfat.data_start_sector);
+    assert!(vfat.cluster_sector(Cluster::from(vfat.max_cluster + 1)).is_err());
+}
+
+#[test]
+fn test_fat_entry_status() {
+    use vfat::{Cluster, FatEntry, Status};
+
+    assert_eq!(FatEntry(0).status(), Status::Free);
+    assert_eq!(FatEntry(1).status(), Status::Reserved);
+    assert_eq!(FatEntry(2).status(), Status::Data(Cluster::from(2)));
+    assert_eq!(FatEntry(0x0FFFFFEF).status(), Status::Data(Cluster::from(0x0FFFFFEF)));
+    assert_eq!(FatEntry(0x0FFFFFF0).status(), Status::Reserved);
+    assert_eq!(FatEntry(0x0FFFFFF6).status(), Status::Reserved);
+    assert_eq!(FatEntry(0x0FFFFFF7).status(), Status::Bad);
+    assert_eq!(FatEntry(0x0FFFFFF8).status(), Status::Eoc(0x0FFFFFF8));
+    assert_eq!(FatEntry(0xFFFFFFFF).status(), Status::Eoc(0x0FFFFFFF));
+    assert_eq!(FatEntry(0xA0000000).status(), Status::Free);
+
+    let entry = FatEntry(0xA0000005);
+    assert_eq!(entry.value(), 5);
+    assert_eq!(entry.reserved_bits(), 0xA0000000);
+    assert_eq!(entry.with_value(0xFFFFFFFF).raw(), 0xAFFFFFFF);
+    assert_eq!(entry.to_string(), "0xA0000005 (next 5)");
+    assert_eq!(FatEntry(0x0FFFFFFF).to_string(), "0x0FFFFFFF (end of chain 0xFFFFFFF)");
+
+    // Rewriting an entry keeps its reserved bits.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(5, 0x3FFF_FFFF);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let mut vfat = vfat.borrow_mut();
+    vfat.truncate_chain(Cluster::from(4), 1).unwrap();
+    assert_eq!(vfat.fat_entry(Cluster::from(5)).unwrap().raw(), 0x30000000);
+}
+
+#[test]
+fn test_open_files_share_size() {
+    use std::io::SeekFrom;
+    use vfat::{Cluster, Status};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut a = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let mut b = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let location = a.location.expect("read from a directory");
+    assert_eq!(vfat.borrow().open_handles(location), 2);
+
+    b.seek(SeekFrom::Start(600)).unwrap();
+    a.set_len(100).expect("shrinks");
+    assert_eq!(b.size(), 100);
+    assert_eq!(b.read(&mut [0; 16]).unwrap(), 0);
+    b.seek(SeekFrom::Start(0)).unwrap();
+    let mut data = Vec::new();
+    b.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 100);
+
+    // The second cluster of the chain was freed.
+    assert_eq!(vfat.borrow_mut().chain_len(Cluster::from(4)).unwrap(), 1);
+    assert_eq!(vfat.borrow_mut().fat_entry(Cluster::from(5)).unwrap().status(), Status::Free);
+    let e = a.set_len(200).expect_err("can't grow");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+
+    drop(a);
+    assert_eq!(vfat.borrow().open_handles(location), 1);
+    drop(b);
+    assert_eq!(vfat.borrow().open_handles(location), 0);
+
+    // The directory entry holds the new size.
+    let file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    assert_eq!(file.size(), 100);
+    let mut empty = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    empty.set_len(0).unwrap();
+    assert_eq!(file.size(), 0);
+    assert_eq!(empty.first_cluster, Cluster::from(0));
+}
+
+#[test]
+fn test_set_len_rewrites_entry_first() {
+    use std::io::ErrorKind;
+
+    // Freeing the tail of a corrupt chain fails after the entry was shrunk.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(5, 1);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let e = file.set_len(100).expect_err("chain is corrupt");
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert_eq!(file.size(), 100);
+    assert_eq!((&vfat).open_file("/long.txt").unwrap().size(), 100);
+
+    // Files sync through the volume and refuse writes rather than panicking.
+    file.sync().expect("volume is flushed");
+    file.flush().expect("volume is flushed");
+    let e = file.write(b"data").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_handle_table() {
+    use std::io::{ErrorKind, SeekFrom};
+    use vfat::HandleTable;
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut handles = HandleTable::with_limit(vfat, 2);
+    let file = handles.open("/long.txt").unwrap();
+    let dir = handles.open("/").unwrap();
+    assert_eq!((file, dir), (0, 1));
+    let e = handles.open("/SUB").expect_err("over the limit");
+    assert_eq!(e.kind(), ErrorKind::Other);
+
+    let mut buf = [0; 1024];
+    assert_eq!(handles.read(file, &mut buf).unwrap(), 1000);
+    assert_eq!(handles.seek(file, SeekFrom::End(-10)).unwrap(), 990);
+    assert_eq!(handles.read(file, &mut buf).unwrap(), 10);
+    assert_eq!(handles.write(file, b"x").unwrap_err().kind(), ErrorKind::PermissionDenied);
+    assert_eq!(handles.read(dir, &mut buf).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    let names = |handles: &mut HandleTable<_, _>| {
+        let mut names = Vec::new();
+        while let Some(entry) = handles.read_dir(dir).unwrap() {
+            names.push(entry.name().to_string());
+        }
+        names
+    };
+    assert_eq!(names(&mut handles), vec!["long.txt", "SUB"]);
+    assert!(handles.read_dir(dir).unwrap().is_none());
+    handles.seek(dir, SeekFrom::Start(0)).unwrap();
+    assert_eq!(names(&mut handles), vec!["long.txt", "SUB"]);
+
+    // The lowest free descriptor is reused.
+    handles.close(file).unwrap();
+    assert_eq!(handles.close(file).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(handles.open("/SUB").unwrap(), 0);
+    assert_eq!(handles.len(), 2);
+}
+
+#[test]
+fn test_path_resolution() {
+    use path::{self, Component};
+
+    let parts: Vec<_> = path::components("a//./b/../").collect();
+    assert_eq!(parts, [Component::Normal("a"), Component::Normal("b"), Component::ParentDir]);
+    assert!(!path::is_absolute("a/b"));
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let entry = (&vfat).open("/SUB/.././/long.txt").expect("long.txt exists");
+    assert_eq!(entry.name(), "long.txt");
+    assert!((&vfat).open("/SUB/..").expect("the root").is_dir());
+    let e = (&vfat).open("long.txt").expect_err("relative path");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+
+    // `..` is resolved while walking the path, so it must follow an
+    // existing directory.
+    assert_eq!((&vfat).open_dir("/SUB/..").unwrap().path(), Path::new("/"));
+    assert_eq!((&vfat).open_dir("/../SUB").unwrap().path(), Path::new("/SUB"));
+    assert!((&vfat).open_file("/SUB/../SUB/./A.BIN").is_ok());
+    let kind = |path: &str| (&vfat).open(path).map(|_| ()).unwrap_err().kind();
+    assert_eq!(kind("/long.txt/../SUB"), ::std::io::ErrorKind::InvalidInput);
+    assert_eq!(kind("/missing/.."), ::std::io::ErrorKind::NotFound);
+    assert_eq!(kind("/SUB/A.BIN/.."), ::std::io::ErrorKind::InvalidInput);
+    assert_eq!(kind("/missing/../long.txt"), ::std::io::ErrorKind::NotFound);
+
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Paths that aren't UTF-8 are walked the same way.
+        let path = Path::new(OsStr::from_bytes(b"/missing/../\xFF"));
+        let e = (&vfat).open(path).map(|_| ()).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::NotFound);
+    }
+}
+
+#[test]
+fn test_find_utf16() {
+    let mut fixture = Fixture::mini();
+    // Replace the `l` of `long.txt` with an unpaired high surrogate.
+    fixture.entry(ROOT, 0).bytes()[1..3].copy_from_slice(&[0x00, 0xD8]);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let root = ::vfat::Dir::root(vfat.clone());
+
+    let mut name: Vec<u16> = "?ONG.TXT".encode_utf16().collect();
+    name[0] = 0xD800;
+    let entry = root.find_utf16(&name).expect("found by code units");
+    assert_eq!(entry.name(), "\u{FFFD}ong.txt");
+    name[0] = 0xFFFD;
+    let e = root.find_utf16(&name).expect_err("only the name as stored matches");
+    assert_eq!(e.kind(), ::std::io::ErrorKind::NotFound);
+    let sub: Vec<u16> = "sub".encode_utf16().collect();
+    assert!(root.find_utf16(&sub).expect("short names match").is_dir());
+}
+
+#[test]
+fn test_invalid_names_are_replaced() {
+    use traits::Entry;
+
+    let mut fixture = Fixture::mini();
+    // An unpaired high surrogate in place of the 'l' of `long.txt`.
+    fixture.entry(ROOT, 0).bytes()[1..3].copy_from_slice(&[0x00, 0xD8]);
+    // A non-ASCII byte in the short name of `A.BIN`.
+    fixture.entry(SUB, 2).set_name(b"A\xE9      BIN");
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let names: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["\u{FFFD}ong.txt", "SUB"]);
+
+    let names: Vec<String> = (&vfat).open_dir("/SUB").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, [".", "..", "A\u{FFFD}.BIN"]);
+}
+
+#[test]
+fn test_orphaned_lfn() {
+    use traits::Entry;
+
+    // Renaming `long.txt` with a tool unaware of long names leaves its LFN
+    // entry behind with a stale checksum.
+    let mut fixture = Fixture::mini();
+    fixture.entry(ROOT, 1).set_name(b"RENAMED TXT");
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let names: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["RENAMED.TXT", "SUB"]);
+}
+
+#[test]
+fn test_malformed_lfn_runs() {
+    use traits::Entry;
+
+    let root_names = |seq: u8| {
+        let mut fixture = Fixture::mini();
+        fixture.entry(ROOT, 0).bytes()[0] = seq;
+        let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+        let names: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+            .map(|entry| entry.name().to_string())
+            .collect();
+        names
+    };
+
+    assert_eq!(root_names(0x41), ["long.txt", "SUB"]);
+    // Longer than the 255 character limit.
+    assert_eq!(root_names(0x5F), ["LONG~1.TXT", "SUB"]);
+    // The first of two fragments is missing.
+    assert_eq!(root_names(0x42), ["LONG~1.TXT", "SUB"]);
+    // Not marked as the start of a run.
+    assert_eq!(root_names(0x01), ["LONG~1.TXT", "SUB"]);
+    assert_eq!(root_names(0x40), ["LONG~1.TXT", "SUB"]);
+}
+
+#[test]
+fn test_lfn_runs_do_not_leak() {
+    use traits::Entry;
+
+    fn put_lfn(buf: &mut [u8], seq: u8, checksum: u8, name: &[u8; 13]) {
+        buf[0] = seq;
+        buf[11] = 0x0F;
+        buf[13] = checksum;
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (&offset, &c) in offsets.iter().zip(name.iter()) {
+            buf[offset] = c;
+        }
+    }
+
+    let mut fixture = Fixture::mini();
+    // A two entry run for a short entry that has since been deleted.
+    put_lfn(fixture.entry(ROOT, 3).bytes(), 0x42, 0x12, b"nopqrstuvwxyz");
+    put_lfn(fixture.entry(ROOT, 4).bytes(), 0x01, 0x12, b"abcdefghijklm");
+    fixture.entry(ROOT, 5).delete();
+    // A one entry run that fills its entry, leaving no terminator.
+    put_lfn(fixture.entry(ROOT, 6).bytes(), 0x41, 0x32, b"ABCDEFGHIJKLM");
+    fixture.entry(ROOT, 7).set_name(b"EXACT   TXT");
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let names: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["long.txt", "SUB", "ABCDEFGHIJKLM"]);
+}
+
+#[test]
+fn test_image_file() {
+    use std::io::ErrorKind;
+    use device::ImageFile;
+
+    let path = ::std::env::temp_dir().join(format!("fat32-image-{}.img", ::std::process::id()));
+    ::std::fs::write(&path, Fixture::mini().into_image()).expect("temporary image");
+
+    let mut image = ImageFile::open_read_only(&path).expect("image opens");
+    assert!(!image.is_writable());
+    let e = image.write_sector(0, &[0; 512]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    let e = image.read_sector(128, &mut [0; 512]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+    let vfat = VFat::from(image).expect("valid image");
+    assert!((&vfat).open_file("/SUB/A.BIN").is_ok());
+
+    let mut image = ImageFile::open(&path).expect("image opens").with_sector_size(1024);
+    image.write_sector(63, &[0xAB; 1024]).expect("sector is written");
+    let mut sector = [0; 1024];
+    assert_eq!(image.read_sector(63, &mut sector).unwrap(), 1024);
+    assert!(sector.iter().all(|&b| b == 0xAB));
+
+    ::std::fs::remove_file(&path).expect("temporary image is removed");
+}
+
+#[test]
+fn test_memory_device() {
+    use std::io::ErrorKind;
+    use device::MemoryDevice;
+
+    let mut device = MemoryDevice::new(2048).with_sector_size(1024);
+    device.write_sector(1, &[0xAB; 1024]).expect("sector is written");
+    let e = device.write_sector(2, &[0; 1024]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    let e = device.write_sector(0, &[0; 512]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+    let mut buf = [0; 16];
+    assert_eq!(device.read_sector(1, &mut buf).unwrap(), 16);
+    assert_eq!(buf, [0xAB; 16]);
+    assert_eq!(&device.as_bytes()[..1024], &[0; 1024][..]);
+
+    let vfat = VFat::from(MemoryDevice::from(Fixture::mini().into_image())).expect("valid image");
+    assert!((&vfat).open_file("/long.txt").is_ok());
+}
+
+#[test]
+fn test_partition_slice() {
+    use std::io::ErrorKind;
+    use device::{MemoryDevice, PartitionSlice};
+
+    let bytes: Vec<u8> = (0..4096).map(|i| (i / 512) as u8).collect();
+    let mut slice = PartitionSlice::new(MemoryDevice::from(bytes), 2, 5, 1024);
+    assert_eq!(slice.num_sectors(), 2);
+
+    let mut sector = [0; 1024];
+    assert_eq!(slice.read_sector(1, &mut sector).unwrap(), 1024);
+    assert_eq!((sector[0], sector[1023]), (4, 5));
+    let e = slice.read_sector(2, &mut sector).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+
+    slice.write_sector(0, &[0xFF; 1024]).unwrap();
+    let bytes = slice.into_inner().into_inner();
+    assert!(bytes[1024..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!((bytes[1023], bytes[2048]), (1, 4));
+}
+
+#[test]
+fn test_4k_native_device() {
+    use device::{MemoryDevice, PartitionSlice};
+
+    // The volume's 512 byte sectors start at the second 4096 byte sector.
+    let mini = Fixture::mini().into_image();
+    let mut image = vec![0; 4096 + 16 * 4096];
+    image[..512].copy_from_slice(&mini[..512]);
+    image[446 + 12] = 16;
+    image[4096..4096 + mini.len() - 512].copy_from_slice(&mini[512..]);
+
+    let device = MemoryDevice::from(image).with_sector_size(4096);
+    let vfat = VFat::from(device).expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    let mut data = Vec::new();
+    assert_eq!(file.read_to_end(&mut data).unwrap(), 1000);
+
+    let device = MemoryDevice::new(8192).with_sector_size(4096);
+    let mut slice = PartitionSlice::new(device, 1, 1, 512);
+    assert_eq!(slice.num_sectors(), 8);
+    slice.write_sector(3, &[0xAB; 512]).unwrap();
+    let mut sector = [0; 512];
+    slice.read_sector(3, &mut sector).unwrap();
+    assert_eq!(&sector[..], &[0xAB; 512][..]);
+    let bytes = slice.into_inner().into_inner();
+    assert!(bytes[4096 + 1536..4096 + 2048].iter().all(|&b| b == 0xAB));
+    assert_eq!(bytes.iter().filter(|&&b| b != 0).count(), 512);
+}
+
+#[test]
+fn test_read_only_device() {
+    use std::io::ErrorKind;
+    use device::{MemoryDevice, ReadOnly};
+
+    let mut device = ReadOnly::new(MemoryDevice::from(Fixture::mini().into_image()));
+    let e = device.write_sector(3, &[0; 512]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(&device.get_ref().as_bytes()[1536..1538], &[0x41, b'l']);
+
+    let vfat = VFat::from(device).expect("valid image");
+    assert!((&vfat).open_file("/long.txt").is_ok());
+}
+
+#[test]
+fn test_injected_faults() {
+    use std::io::ErrorKind;
+    use traits::Entry;
+    use device::{MemoryDevice, FaultyDevice, Fault};
+    use vfat::ContextError;
+
+    let mount = |sector: u64, fault: Fault| {
+        let mut device = FaultyDevice::new(MemoryDevice::from(Fixture::mini().into_image()));
+        device.inject(sector, fault);
+        VFat::from(device).expect("valid image")
+    };
+
+    // Sector 2 holds the FAT.
+    let root_error = |vfat: &::vfat::Shared<VFat>| {
+        vfat.open_dir("/").unwrap().entries().err().expect("reading the root fails")
+    };
+
+    let vfat = mount(2, Fault::Fail);
+    let e = root_error(&vfat);
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert_eq!(ContextError::of(&e).unwrap().sector, Some(2));
+
+    // Sector 3 holds the root directory.
+    let vfat = mount(3, Fault::ShortRead(100));
+    assert_eq!(root_error(&vfat).kind(), ErrorKind::UnexpectedEof);
+
+    let vfat = mount(3, Fault::FailTimes(1));
+    assert_eq!(root_error(&vfat).kind(), ErrorKind::Other);
+    assert!((&vfat).open_dir("/").unwrap().entries().is_ok());
+
+    // Lowercasing the short name of `long.txt` orphans its long name.
+    let vfat = mount(3, Fault::FlipBit { byte: 32, bit: 5 });
+    let names: Vec<String> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["lONG~1.TXT", "SUB"]);
+}
+
+#[test]
+fn test_error_kinds() {
+    use std::io::ErrorKind;
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let kind = |result: ::std::io::Result<_>| result.map(|_: ::vfat::Entry| ()).unwrap_err().kind();
+
+    assert_eq!(kind((&vfat).open("SUB")), ErrorKind::InvalidInput);
+    assert_eq!(kind((&vfat).open("/SUB/A.BIN/B")), ErrorKind::InvalidInput);
+    assert_eq!(kind((&vfat).open("/SUB/MISSING")), ErrorKind::NotFound);
+    assert_eq!((&vfat).open_dir("/long.txt").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!((&vfat).open_file("/SUB").unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    // The long-named file claims 1000 bytes but its chain now ends early.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(4, EOC);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("file exists");
+    assert_eq!(file.read(&mut [0; 1000]).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_error_context() {
+    use std::path::PathBuf;
+    use vfat::{ContextError, ErrorContext};
+
+    // Cut the image off before the long-named file's first cluster, sector 5.
+    let mut image = Fixture::mini().into_image();
+    image.truncate(5 * 512);
+    let vfat = VFat::from(Cursor::new(image)).expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("file exists");
+    let e = file.read(&mut [0; 1000]).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(ContextError::of(&e), Some(&ErrorContext {
+        path: Some(PathBuf::from("/long.txt")),
+        cluster: Some(4),
+        sector: Some(5),
+    }));
+    assert!(e.to_string().ends_with("(path /long.txt, cluster 4, sector 5)"));
+
+    // Entries listed from a directory, and those found again through the
+    // dentry cache, carry their full path.
+    let sub = (&vfat).open_dir("/SUB").expect("SUB exists");
+    assert_eq!(sub.path(), Path::new("/SUB"));
+    let mut file = sub.entries().unwrap().last().unwrap().into_file().expect("A.BIN");
+    assert_eq!(file.path(), Path::new("/SUB/A.BIN"));
+    let e = file.read(&mut [0; 10]).unwrap_err();
+    assert_eq!(ContextError::of(&e).unwrap().path, Some(PathBuf::from("/SUB/A.BIN")));
+    for _ in 0..2 {
+        let file = (&vfat).open_file("/sub/a.bin").unwrap();
+        assert_eq!(file.path(), Path::new("/SUB/A.BIN"));
+    }
+
+    let e = (&vfat).open("/SUB/MISSING").map(|_| ()).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::NotFound);
+    assert_eq!(e.to_string(), "name not found (path /SUB/MISSING)");
+}
+
+#[test]
+fn test_sparse_image() {
+    use device::SparseImage;
+
+    let path = ::std::env::temp_dir().join(format!("fat32-sparse-{}.img", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+
+    // A 16GiB disk, of which only the sectors of the mini image are stored.
+    let mut image = SparseImage::create(&path, 1 << 25, 512, 64).expect("image is created");
+    for (i, sector) in Fixture::mini().into_image().chunks(512).enumerate() {
+        image.write_sector(i as u64, sector).expect("sector is written");
+    }
+    assert_eq!(image.allocated_sectors(), 64);
+    let mut sector = [0xFF; 512];
+    image.read_sector((1 << 25) - 1, &mut sector).expect("unwritten sector reads");
+    assert!(sector.iter().all(|&b| b == 0));
+    assert!(image.read_sector(1 << 25, &mut sector).is_err());
+    drop(image);
+
+    assert!(::std::fs::metadata(&path).unwrap().len() < 8 << 20);
+    let image = SparseImage::open_read_only(&path).expect("image opens");
+    assert_eq!(image.num_sectors(), 1 << 25);
+    let vfat = VFat::from(image).expect("valid image");
+    let mut data = vec![];
+    (&vfat).open_file("/SUB/A.BIN").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 10);
+
+    ::std::fs::remove_file(&path).expect("temporary image is removed");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_compressed_image() {
+    use std::io::ErrorKind;
+    use flate2::{write::GzEncoder, Compression};
+    use device::CompressedImage;
+
+    // Compress the image as three members, with a sector straddling the
+    // first two.
+    let image = Fixture::mini().into_image();
+    let mut compressed = vec![];
+    for chunk in [&image[..1000], &image[1000..4096], &image[4096..]].iter() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(chunk).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+    }
+
+    let path = ::std::env::temp_dir().join(format!("fat32-image-{}.img.gz", ::std::process::id()));
+    ::std::fs::write(&path, &compressed).expect("temporary image");
+
+    let mut device = CompressedImage::open(&path).expect("image opens");
+    assert_eq!(device.num_members(), 3);
+    assert_eq!(device.len(), image.len() as u64);
+    let mut sector = [0; 512];
+    assert_eq!(device.read_sector(1, &mut sector).unwrap(), 512);
+    assert_eq!(&sector[..], &image[512..1024]);
+    let e = device.write_sector(1, &sector).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    let vfat = VFat::from(device).expect("valid image");
+    let mut data = vec![];
+    (&vfat).open_file("/long.txt").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 1000);
+
+    ::std::fs::write(&path, &image).expect("temporary image");
+    let e = CompressedImage::open(&path).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+    ::std::fs::remove_file(&path).expect("temporary image is removed");
+}
+
+#[test]
+fn test_extract() {
+    use tools::{self, Extracted};
+
+    let dest = ::std::env::temp_dir().join(format!("fat32-extract-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_dir_all(&dest);
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+
+    let mut copied = vec![];
+    let totals = tools::extract(&vfat, "/", &dest, |path, _| copied.push(path.to_path_buf()))
+        .expect("tree is extracted");
+    assert_eq!(totals, Extracted { files: 2, dirs: 2, bytes: 1010 });
+    assert_eq!(copied, vec![Path::new("/long.txt"), Path::new("/SUB/A.BIN")]);
+    assert_eq!(::std::fs::metadata(dest.join("long.txt")).unwrap().len(), 1000);
+    assert_eq!(::std::fs::read(dest.join("SUB/A.BIN")).unwrap(), vec![0; 10]);
+
+    // A single file is copied to the destination path itself.
+    let totals = tools::extract(&vfat, "/SUB/A.BIN", dest.join("a"), |_, _| ())
+        .expect("file is extracted");
+    assert_eq!(totals, Extracted { files: 1, dirs: 0, bytes: 10 });
+    assert!(dest.join("a").is_file());
+
+    ::std::fs::remove_dir_all(&dest).expect("temporary tree is removed");
+}
+
+#[test]
+fn test_extract_hostile_names() {
+    use std::io::ErrorKind;
+    use tools;
+
+    let root = ::std::env::temp_dir().join(format!("fat32-hostile-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_dir_all(&root);
+    let dest = root.join("out");
+    // A NUL ends a long file name, so it can't reach the host.
+    for &name in ["../evil.txt", "a/../../b", "..", ".", "a\\b"].iter() {
+        let mut fixture = Fixture::mini();
+        fixture.lfn(ROOT, 3, name, b"EVIL    TXT");
+        fixture.file(ROOT, 4, b"EVIL    TXT", b"evil");
+        let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+        let e = tools::extract(&vfat, "/", &dest, |_, _| ()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData, "{:?}", name);
+    }
+
+    // Nothing was written outside of the destination.
+    let mut outside: Vec<_> = ::std::fs::read_dir(&root).unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    outside.sort();
+    assert_eq!(outside, ["out"]);
+    assert!(!root.join("b").exists() && !dest.join("evil.txt").exists());
+    ::std::fs::remove_dir_all(&root).expect("temporary tree is removed");
+}
+
+#[test]
+fn test_image_builder() {
+    use std::fs;
+    use std::io::ErrorKind;
+    use device::MemoryDevice;
+    use tools::ImageBuilder;
+
+    let source = ::std::env::temp_dir().join(format!("fat32-build-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(source.join("Sub/deeper")).unwrap();
+    fs::write(source.join("README.TXT"), b"read me").unwrap();
+    fs::write(source.join("Sub/README.txt"), b"read me too").unwrap();
+    fs::write(source.join("A long file name.text"), b"long").unwrap();
+    fs::write(source.join("A long file name.text2"), b"longer").unwrap();
+    fs::write(source.join("empty"), b"").unwrap();
+    let data: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    fs::write(source.join("Sub/deeper/data.bin"), &data).unwrap();
+
+    let mut device = MemoryDevice::new(40 << 20);
+    ImageBuilder::new(40 << 20).label("boot").boot_code(&[0xF4])
+        .ids_from(&mut || 0xABCD).build(&source, &mut device)
+        .expect("image is built");
+    assert_eq!(&device.as_bytes()[440..444], &[0xCD, 0xAB, 0, 0]);
+    let e = ImageBuilder::new(32 << 20).build(&source, MemoryDevice::new(32 << 20)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    let vfat = VFat::from(device).expect("built image mounts");
+    assert_eq!(vfat.borrow_mut().boot_code().unwrap()[..2], [0xF4, 0]);
+    assert_eq!(vfat.borrow_mut().volume_id().unwrap(), 0xABCD);
+    let names: Vec<(String, String)> = (&vfat).open_dir("/").unwrap().entries().unwrap()
+        .filter(|e| !e.metadata().attr.volume_id())
+        .map(|e| (e.name().to_string(), e.short_name().to_string()))
+        .collect();
+    assert_eq!(names, vec![
+        ("A long file name.text".to_string(), "ALONGF~1.TEX".to_string()),
+        ("A long file name.text2".to_string(), "ALONGF~2.TEX".to_string()),
+        ("README.TXT".to_string(), "README.TXT".to_string()),
+        ("Sub".to_string(), "SUB".to_string()),
+        ("empty".to_string(), "EMPTY".to_string()),
+    ]);
+
+    let mut read = vec![];
+    (&vfat).open_file("/Sub/deeper/data.bin").unwrap().read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    let entries = (&vfat).open_dir("/Sub/deeper").unwrap().entries().unwrap().count();
+    assert_eq!(entries, 3);
+    // Only the extension's case differs from the 8.3 name, so it takes an LFN.
+    let readme = (&vfat).open_file("/Sub/README.txt").unwrap();
+    assert_eq!((readme.name().as_str(), readme.short_name().as_str()),
+               ("README.txt", "README.TXT"));
+    assert_eq!((&vfat).open_file("/empty").unwrap().size, 0);
+
+    fs::remove_dir_all(&source).expect("temporary tree is removed");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_image_builder_host_entries() {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+    use device::MemoryDevice;
+    use tools::ImageBuilder;
+
+    let source = ::std::env::temp_dir().join(format!("fat32-host-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(source.join("tree/dir")).unwrap();
+    let outside = source.join("target.bin");
+    fs::write(&outside, vec![9; 1500]).unwrap();
+    fs::write(source.join("tree/dir/inner.txt"), b"inner").unwrap();
+    let tree = source.join("tree");
+    let build = || {
+        let mut device = MemoryDevice::new(40 << 20);
+        ImageBuilder::new(40 << 20).build(&tree, &mut device).map(|_| device)
+    };
+
+    // Links are stored as what they point to.
+    symlink(&outside, tree.join("file-link")).unwrap();
+    symlink(tree.join("dir"), tree.join("dir-link")).unwrap();
+    let vfat = VFat::from(build().expect("image is built")).expect("built image mounts");
+    assert_eq!((&vfat).read("/file-link").unwrap(), vec![9; 1500]);
+    assert_eq!((&vfat).read("/dir-link/inner.txt").unwrap(), b"inner");
+
+    // Names FAT can't tell apart are rejected.
+    fs::write(tree.join("README"), b"upper").unwrap();
+    fs::write(tree.join("readme"), b"lower").unwrap();
+    assert_eq!(build().unwrap_err().kind(), ErrorKind::InvalidInput);
+    fs::remove_file(tree.join("readme")).unwrap();
+
+    // So are entries that are neither files nor directories, rather than
+    // blocking on or misreading them.
+    let socket = UnixListener::bind(tree.join("socket")).unwrap();
+    assert_eq!(build().unwrap_err().kind(), ErrorKind::InvalidInput);
+    drop(socket);
+
+    fs::remove_dir_all(&source).expect("temporary tree is removed");
+}
+
+#[test]
+fn test_boot_code() {
+    use std::io::ErrorKind;
+    use mbr::MasterBootRecord;
+
+    let mut cursor = Cursor::new(Fixture::mini().into_image());
+    MasterBootRecord::install_bootstrap(&mut cursor, &[0xFA, 0xEB, 0xFE]).unwrap();
+    let e = MasterBootRecord::install_bootstrap(&mut cursor, &[0; 437]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let mbr = MasterBootRecord::from(&mut cursor).expect("MBR is intact");
+    assert_eq!(&mbr.bootstrap[..4], &[0xFA, 0xEB, 0xFE, 0]);
+    assert_eq!({ mbr.partition_table[0].relative_sector }, 1);
+
+    let vfat = VFat::from(cursor).expect("valid image");
+    let mut vfat = vfat.borrow_mut();
+    vfat.set_boot_code(b"boot").unwrap();
+    let code = vfat.boot_code().unwrap();
+    assert_eq!(code.len(), 420);
+    assert_eq!(&code[..5], b"boot\0");
+
+    let mut vbr = [0x90; 512];
+    vbr[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    vfat.install_vbr(&vbr).unwrap();
+    assert!(vfat.install_vbr(&vbr[..511]).is_err());
+    let bpb = vfat.boot_sector().unwrap();
+    assert_eq!(bpb.jump_short_nop, [0xEB, 0x58, 0x90]);
+    assert!(bpb.boot_code.iter().all(|&b| b == 0x90));
+    assert_eq!((bpb.bytes_per_sector, bpb.root_cluster), (512, 2));
+    assert_eq!({ bpb.bootable_signature }, 0xAA55);
+}
+
+#[test]
+fn test_volume_ids() {
+    use mbr::MasterBootRecord;
+    use tools::reidentify;
+    use traits::{Entropy, XorShift};
+
+    let (mut a, mut b) = (XorShift::new(7), XorShift::new(7));
+    let ids: Vec<u32> = (0..4).map(|_| a.next_u32()).collect();
+    assert_eq!(ids, (0..4).map(|_| b.next_u32()).collect::<Vec<_>>());
+    assert_ne!(ids[0], ids[1]);
+    assert_ne!(XorShift::new(0).next_u32(), 0);
+
+    let mut mbr = MasterBootRecord::from(Cursor::new(Fixture::mini().into_image())).expect("MBR is intact");
+    mbr.set_disk_signature(0xDEADBEEF);
+    assert_eq!(mbr.disk_signature(), 0xDEADBEEF);
+    assert_eq!(&mbr.encode()[440..444], &[0xEF, 0xBE, 0xAD, 0xDE]);
+
+    let mut cursor = Cursor::new(Fixture::mini().into_image());
+    let mut next = 0x1000;
+    let reidentified = reidentify(&mut cursor, &mut || { next += 1; next }).unwrap();
+    assert_eq!((reidentified.disk_id, reidentified.volume_id), (0x1001, 0x1002));
+    let mbr = MasterBootRecord::from(&mut cursor).expect("MBR is intact");
+    assert_eq!(mbr.disk_signature(), 0x1001);
+    assert_eq!({ mbr.partition_table[0].relative_sector }, 1);
+
+    let vfat = VFat::from(cursor).expect("valid image");
+    let mut vfat = vfat.borrow_mut();
+    assert_eq!(vfat.volume_id().unwrap(), 0x1002);
+    vfat.set_volume_id(0xCAFE).unwrap();
+    assert_eq!(vfat.volume_id().unwrap(), 0xCAFE);
+    assert_eq!({ vfat.boot_sector().unwrap().root_cluster }, 2);
+}
+
+#[test]
+fn test_inspector() {
+    use tools::Inspector;
+
+    let mut fixture = Fixture::mini();
+    let mut inspector = Inspector::new(Cursor::new(fixture.clone().into_image()), vec![]);
+    inspector.bpb().expect("BPB is printed");
+    inspector.dir("/sub/a.bin").expect("directory is printed");
+    let (_, out) = inspector.into_inner();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("bytes per sector       512"), "{}", out);
+    assert!(out.contains("> [   2] file     \"A.BIN\""), "{}", out);
+    assert!(out.contains("[   3] end of directory"), "{}", out);
+
+    // Chains are judged as the file system judges them.
+    let mut looping = fixture.clone();
+    looping.set_fat(SUB, SUB);
+    let mut inspector = Inspector::new(Cursor::new(looping.into_image()), vec![]);
+    inspector.dir("/sub").expect("directory is printed");
+    let out = String::from_utf8(inspector.into_inner().1).unwrap();
+    assert!(out.contains("Directory /sub (clusters 3)"), "{}", out);
+    assert!(out.contains("cluster chain is broken: corrupt cluster chain at cluster 3"), "{}", out);
+
+    // The inspector reads images that fail to mount.
+    fixture.bpb_mut()[510] = 0;
+    let image = fixture.into_image();
+    assert!(VFat::from(Cursor::new(image.clone())).is_err());
+    let mut inspector = Inspector::new(Cursor::new(image), vec![]);
+    inspector.bpb().expect("BPB is printed");
+    assert!(String::from_utf8(inspector.into_inner().1).unwrap()
+                .contains("boot signature         INVALID"));
+}
+
+/// What a generated image is expected to hold at one path.
+#[derive(Debug, Clone, PartialEq)]
+struct ManifestEntry {
+    path: String,
+    dir: bool,
+    data: Vec<u8>,
+    size: u64,
+    // Year, month, day, hour, minute, second.
+    modified: (usize, u8, u8, u8, u8, u8),
+    read_only: bool,
+    hidden: bool,
+}
+
+/// A file or directory to be written to a generated image.
+struct GenNode {
+    name: String,
+    short_name: [u8; 11],
+    lfn: bool,
+    attr: u8,
+    date: u16,
+    time: u16,
+    data: Vec<u8>,
+    children: Option<Vec<GenNode>>,
+    // Deleted entries written just before this node's entries.
+    deleted: Vec<[u8; 32]>,
+    clusters: Vec<u32>,
+}
+
+impl GenNode {
+    fn entries(&self) -> usize {
+        let lfn_entries = if self.lfn { (self.name.encode_utf16().count() + 12) / 13 } else { 0 };
+        self.deleted.len() + lfn_entries + 1
+    }
+}
+
+/// Parameters of a generated image, printed when the reader disagrees.
+#[derive(Debug, Clone, Copy)]
+struct GenParams {
+    bytes_per_sector: u32,
+    sectors_per_cluster: u32,
+    num_fats: u32,
+    reserved_sectors: u32,
+    partition_start: u32,
+    fragmented: bool,
+}
+
+/// Generates a random but valid image: random sector and cluster sizes, one
+/// or two FATs, contiguous or shuffled cluster allocation, long names of
+/// every length including non-ASCII and surrogate pairs, and deleted entries
+/// scattered through the directories. Returns the image, the parameters it
+/// was built with, and the manifest of what it holds.
+fn generate_image<R: self::rand::Rng>(rng: &mut R) -> (Vec<u8>, GenParams, Vec<ManifestEntry>) {
+    const NAME_CHARS: &[char] = &['a', 'B', 'c', 'X', 'y', 'z', '0', '7', ' ', '.', '-', '_',
+                                  '~', '(', '!', '\'', 'é', 'ß', 'Ω', '日', '本', '😀'];
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset] = value as u8;
+        buf[offset + 1] = (value >> 8) as u8;
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        put_u16(buf, offset, value as u16);
+        put_u16(buf, offset + 2, (value >> 16) as u16);
+    }
+
+    fn checksum(short_name: &[u8; 11]) -> u8 {
+        short_name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+    }
+
+    fn gen_name<R: self::rand::Rng>(rng: &mut R, taken: &mut Vec<String>, serial: &mut u32)
+        -> (String, [u8; 11], bool)
+    {
+        loop {
+            let (name, short_name, lfn) = if rng.gen_weighted_bool(4) {
+                // An upper-case 8.3 name stored without LFN entries.
+                let upper = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_";
+                let mut short_name = *b"           ";
+                for i in 0..rng.gen_range(1, 9) {
+                    short_name[i] = *rng.choose(upper).unwrap();
+                }
+                for i in 0..rng.gen_range(0, 4) {
+                    short_name[8 + i] = *rng.choose(upper).unwrap();
+                }
+                let base = String::from_utf8_lossy(&short_name[..8]).trim_right().to_string();
+                let ext = String::from_utf8_lossy(&short_name[8..]).trim_right().to_string();
+                let name = if ext.is_empty() { base } else { format!("{}.{}", base, ext) };
+                (name, short_name, false)
+            } else {
+                let units = match rng.gen_range(0, 10) {
+                    0 => 13 * rng.gen_range(1, 5),
+                    1 => rng.gen_range(100, 256),
+                    _ => rng.gen_range(1, 30),
+                };
+                let mut name = String::new();
+                while name.encode_utf16().count() < units {
+                    let c = *rng.choose(NAME_CHARS).unwrap();
+                    if name.encode_utf16().count() + c.len_utf16() <= units {
+                        name.push(c);
+                    }
+                }
+                *serial += 1;
+                let mut short_name = *b"           ";
+                short_name[..8].copy_from_slice(format!("N{:07}", serial).as_bytes());
+                (name, short_name, true)
+            };
+
+            let key = name.to_ascii_lowercase();
+            if name.starts_with(' ') || name.ends_with(' ') || name.ends_with('.')
+                || taken.contains(&key) {
+                continue;
+            }
+            taken.push(key);
+            return (name, short_name, lfn);
+        }
+    }
+
+    fn gen_dir<R: self::rand::Rng>(rng: &mut R, depth: usize, cluster_bytes: usize,
+                                   serial: &mut u32) -> Vec<GenNode> {
+        let mut taken = vec![];
+        let count = rng.gen_range(if depth == 0 { 1 } else { 0 }, 10);
+        (0..count).map(|_| {
+            let (name, short_name, lfn) = gen_name(rng, &mut taken, serial);
+            let dir = depth < 3 && rng.gen_weighted_bool(4);
+            let mut attr = if dir { 0x10 } else { 0x20 };
+            if rng.gen_weighted_bool(6) { attr |= 0x01; }
+            if rng.gen_weighted_bool(6) { attr |= 0x02; }
+
+            let date = ((rng.gen_range(0, 128) as u16) << 9) | (rng.gen_range(1, 13) << 5)
+                | rng.gen_range(1, 29);
+            let time = (rng.gen_range(0, 24) << 11) | (rng.gen_range(0, 60) << 5)
+                | rng.gen_range(0, 30);
+            let mut data = vec![0; match rng.gen_range(0, 4) {
+                _ if dir => 0,
+                0 => 0,
+                1 => cluster_bytes * rng.gen_range(1, 3),
+                _ => rng.gen_range(1, 20000),
+            }];
+            rng.fill_bytes(&mut data);
+
+            let deleted = (0..rng.gen_range(0, 3)).map(|_| {
+                let mut entry = [0u8; 32];
+                rng.fill_bytes(&mut entry);
+                entry[0] = 0xE5;
+                if rng.gen() { entry[11] = 0x0F; }
+                entry
+            }).collect();
+
+            let children = if dir {
+                Some(gen_dir(rng, depth + 1, cluster_bytes, serial))
+            } else {
+                None
+            };
+            GenNode {
+                name: name,
+                short_name: short_name,
+                lfn: lfn,
+                attr: attr,
+                date: date,
+                time: time,
+                data: data,
+                children: children,
+                deleted: deleted,
+                clusters: vec![],
+            }
+        }).collect()
+    }
+
+    fn clusters_needed(node: &GenNode, cluster_bytes: usize) -> usize {
+        match node.children {
+            Some(ref children) => {
+                let entries = 2 + children.iter().map(|c| c.entries()).sum::<usize>();
+                let own = (entries * 32 + cluster_bytes - 1) / cluster_bytes;
+                own + children.iter().map(|c| clusters_needed(c, cluster_bytes)).sum::<usize>()
+            }
+            None => (node.data.len() + cluster_bytes - 1) / cluster_bytes,
+        }
+    }
+
+    fn allocate(node: &mut GenNode, free: &mut Vec<u32>, cluster_bytes: usize) {
+        let len = match node.children {
+            Some(ref children) => {
+                let entries = 2 + children.iter().map(|c| c.entries()).sum::<usize>();
+                (entries * 32 + cluster_bytes - 1) / cluster_bytes
+            }
+            None => (node.data.len() + cluster_bytes - 1) / cluster_bytes,
+        };
+        node.clusters = free.drain(..len).collect();
+        for child in node.children.iter_mut().flat_map(|c| c.iter_mut()) {
+            allocate(child, free, cluster_bytes);
+        }
+    }
+
+    fn put_entry(buf: &mut [u8], node: &GenNode, short_name: &[u8; 11], cluster: u32) {
+        buf[..11].copy_from_slice(short_name);
+        buf[11] = node.attr;
+        put_u16(buf, 14, node.time);
+        put_u16(buf, 16, node.date);
+        put_u16(buf, 18, node.date);
+        put_u16(buf, 20, (cluster >> 16) as u16);
+        put_u16(buf, 22, node.time);
+        put_u16(buf, 24, node.date);
+        put_u16(buf, 26, cluster as u16);
+        put_u32(buf, 28, node.data.len() as u32);
+    }
+
+    fn dir_bytes(node: &GenNode, parent_cluster: u32, root: bool) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut entry = [0u8; 32];
+        if !root {
+            put_entry(&mut entry, node, b".          ", node.clusters[0]);
+            buf.extend_from_slice(&entry);
+            put_entry(&mut entry, node, b"..         ", parent_cluster);
+            buf.extend_from_slice(&entry);
+        }
+        for child in node.children.as_ref().unwrap() {
+            for deleted in child.deleted.iter() {
+                buf.extend_from_slice(deleted);
+            }
+            if child.lfn {
+                let mut units: Vec<u16> = child.name.encode_utf16().collect();
+                let count = (units.len() + 12) / 13;
+                if units.len() % 13 != 0 {
+                    units.push(0);
+                }
+                while units.len() < count * 13 {
+                    units.push(0xFFFF);
+                }
+                for seq in (1..count + 1).rev() {
+                    let mut lfn = [0u8; 32];
+                    lfn[0] = seq as u8 | if seq == count { 0x40 } else { 0 };
+                    lfn[11] = 0x0F;
+                    lfn[13] = checksum(&child.short_name);
+                    let chars = &units[(seq - 1) * 13..seq * 13];
+                    let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+                    for (&offset, &unit) in offsets.iter().zip(chars) {
+                        put_u16(&mut lfn, offset, unit);
+                    }
+                    buf.extend_from_slice(&lfn);
+                }
+            }
+            let cluster = child.clusters.first().cloned().unwrap_or(0);
+            put_entry(&mut entry, child, &child.short_name, cluster);
+            buf.extend_from_slice(&entry);
+        }
+        buf
+    }
+
+    fn manifest(node: &GenNode, path: &str, out: &mut Vec<ManifestEntry>) {
+        for child in node.children.as_ref().unwrap() {
+            let child_path = format!("{}/{}", path, child.name);
+            out.push(ManifestEntry {
+                path: child_path.clone(),
+                dir: child.children.is_some(),
+                data: child.data.clone(),
+                size: child.data.len() as u64,
+                modified: ((child.date >> 9) as usize + 1980, (child.date >> 5) as u8 & 0xF,
+                           child.date as u8 & 0x1F, (child.time >> 11) as u8,
+                           (child.time >> 5) as u8 & 0x3F, (child.time as u8 & 0x1F) * 2),
+                read_only: child.attr & 0x01 != 0,
+                hidden: child.attr & 0x02 != 0,
+            });
+            if child.children.is_some() {
+                manifest(child, &child_path, out);
+            }
+        }
+    }
+
+    let params = GenParams {
+        bytes_per_sector: *rng.choose(&[512, 1024, 2048, 4096]).unwrap(),
+        sectors_per_cluster: *rng.choose(&[1, 2, 4, 8]).unwrap(),
+        num_fats: rng.gen_range(1, 3),
+        reserved_sectors: rng.gen_range(2, 16),
+        partition_start: rng.gen_range(1, 64),
+        fragmented: rng.gen(),
+    };
+    let sector = params.bytes_per_sector as usize;
+    let cluster_bytes = sector * params.sectors_per_cluster as usize;
+
+    let mut serial = 0;
+    let mut root = GenNode {
+        name: String::new(),
+        short_name: *b"           ",
+        lfn: false,
+        attr: 0x10,
+        date: 0,
+        time: 0,
+        data: vec![],
+        children: Some(gen_dir(rng, 0, cluster_bytes, &mut serial)),
+        deleted: vec![],
+        clusters: vec![],
+    };
+
+    let num_clusters = clusters_needed(&root, cluster_bytes) as u32 + rng.gen_range(0, 64);
+    let mut free: Vec<u32> = (2..num_clusters + 2).collect();
+    if params.fragmented {
+        rng.shuffle(&mut free);
+    }
+    allocate(&mut root, &mut free, cluster_bytes);
+
+    let sectors_per_fat = ((num_clusters as usize + 2) * 4 + sector - 1) / sector;
+    let fat_start = params.reserved_sectors as usize * sector;
+    let data_start = fat_start + params.num_fats as usize * sectors_per_fat * sector;
+    let total_bytes = data_start + num_clusters as usize * cluster_bytes;
+    let part = params.partition_start as usize * 512;
+    let mut image = vec![0u8; part + total_bytes];
+
+    image[446 + 4] = 0x0C;
+    put_u32(&mut image, 446 + 8, params.partition_start);
+    put_u32(&mut image, 446 + 12, (total_bytes / 512) as u32);
+    image[510] = 0x55;
+    image[511] = 0xAA;
+
+    {
+        let bpb = &mut image[part..part + 512];
+        put_u16(bpb, 11, params.bytes_per_sector as u16);
+        bpb[13] = params.sectors_per_cluster as u8;
+        put_u16(bpb, 14, params.reserved_sectors as u16);
+        bpb[16] = params.num_fats as u8;
+        bpb[21] = 0xF8;
+        put_u32(bpb, 32, (total_bytes / sector) as u32);
+        put_u32(bpb, 36, sectors_per_fat as u32);
+        put_u32(bpb, 44, root.clusters[0]);
+        put_u16(bpb, 48, 1);
+        bpb[66] = 0x29;
+        bpb[82..90].copy_from_slice(b"FAT32   ");
+        bpb[510] = 0x55;
+        bpb[511] = 0xAA;
+    }
+
+    // Link every chain in each FAT and write the contents of every node.
+    let mut fat = vec![0u8; sectors_per_fat * sector];
+    put_u32(&mut fat, 0, 0x0FFFFFF8);
+    put_u32(&mut fat, 4, 0x0FFFFFFF);
+    let mut stack = vec![(&root, 0u32, true)];
+    while let Some((node, parent_cluster, is_root)) = stack.pop() {
+        for (i, &cluster) in node.clusters.iter().enumerate() {
+            let next = node.clusters.get(i + 1).cloned().unwrap_or(0x0FFFFFFF);
+            put_u32(&mut fat, cluster as usize * 4, next);
+        }
+
+        let bytes = match node.children {
+            Some(ref children) => {
+                let own = if is_root { 0 } else { node.clusters[0] };
+                for child in children {
+                    stack.push((child, own, false));
+                }
+                dir_bytes(node, parent_cluster, is_root)
+            }
+            None => node.data.clone(),
+        };
+        for (chunk, &cluster) in bytes.chunks(cluster_bytes).zip(node.clusters.iter()) {
+            let offset = part + data_start + (cluster as usize - 2) * cluster_bytes;
+            image[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+    for i in 0..params.num_fats as usize {
+        let offset = part + fat_start + i * fat.len();
+        image[offset..offset + fat.len()].copy_from_slice(&fat);
+    }
+
+    let mut expected = vec![];
+    manifest(&root, "", &mut expected);
+    expected.sort_by(|a, b| a.path.cmp(&b.path));
+    (image, params, expected)
+}
+
+/// Reads the manifest of everything reachable from `dir`.
+fn read_manifest(dir: &::vfat::Dir, path: &str, out: &mut Vec<ManifestEntry>) {
+    use traits::Timestamp as TimestampTrait;
+
+    for entry in dir.entries().expect("directory is readable") {
+        let name = entry.name().to_string();
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        let child_path = format!("{}/{}", path, name);
+        let metadata = entry.metadata().clone();
+        let modified = metadata.modified();
+        let mut data = vec![];
+        if let ::vfat::Entry::File(mut file) = entry {
+            file.read_to_end(&mut data).expect("file is readable");
+        } else if let ::vfat::Entry::Dir(ref subdir) = entry {
+            read_manifest(subdir, &child_path, out);
+        }
+        out.push(ManifestEntry {
+            path: child_path,
+            dir: metadata.attr.directory(),
+            size: Metadata::size(&metadata),
+            data: data,
+            modified: (modified.year(), modified.month(), modified.day(), modified.hour(),
+                       modified.minute(), modified.second()),
+            read_only: metadata.read_only(),
+            hidden: metadata.hidden(),
+        });
+    }
+}
+
+#[test]
+fn test_generated_images_match_manifest() {
+    use tests::rand::{SeedableRng, XorShiftRng};
+
+    let mut rng = XorShiftRng::from_seed(GENERATED_IMAGE_SEED);
+    for round in 0..48 {
+        let (image, params, expected) = generate_image(&mut rng);
+        let vfat = VFat::from(Cursor::new(image))
+            .unwrap_or_else(|e| panic!("round {} ({:?}) fails to mount: {}", round, params, e));
+        let mut actual = vec![];
+        read_manifest(&::vfat::Dir::root(vfat), "", &mut actual);
+        actual.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(actual.len(), expected.len(), "round {} ({:?})", round, params);
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert!(actual == expected, "round {} ({:?}): read {:?} ({} bytes), expected {:?}",
+                    round, params, actual.path, actual.data.len(), expected.path);
+        }
+    }
+}
+
+/// Lists every file and directory below `dir` in the format of the golden
+/// fixture expectations: `f SIZE DATE TIME HASH PATH` for files, where
+/// `HASH` is the 64-bit FNV-1a hash of the contents, and `d PATH` for
+/// directories. Lines are sorted by path. Volume labels aren't listed.
+fn golden_listing(dir: &::vfat::Dir) -> String {
+    use traits::Timestamp as TimestampTrait;
+
+    fn list(dir: &::vfat::Dir, path: &str, lines: &mut Vec<(String, String)>) {
+        for entry in dir.entries().expect("directory is readable") {
+            let metadata = entry.metadata().clone();
+            if entry.name() == "." || entry.name() == ".." || metadata.attr.volume_id() {
+                continue;
+            }
+
+            let child_path = format!("{}/{}", path, entry.name());
+            match entry {
+                ::vfat::Entry::Dir(ref subdir) => {
+                    lines.push((child_path.clone(), format!("d {}", child_path)));
+                    list(subdir, &child_path, lines);
+                }
+                ::vfat::Entry::File(mut file) => {
+                    let mut data = vec![];
+                    file.read_to_end(&mut data).expect("file is readable");
+                    let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+                        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+                    });
+                    let t = metadata.modified();
+                    lines.push((child_path.clone(), format!(
+                        "f {} {:04}-{:02}-{:02} {:02}:{:02}:{:02} {:016x} {}",
+                        data.len(), t.year(), t.month(), t.day(), t.hour(), t.minute(),
+                        t.second(), hash, child_path)));
+                }
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    list(dir, "", &mut lines);
+    lines.sort();
+    lines.into_iter().map(|(_, line)| line + "\n").collect()
+}
+
+/// Checks the images in `tests/golden`, such as ones formatted by Windows,
+/// macOS, and mkfs.vfat, against the listings recorded next to them:
+/// `NAME.img`, or `NAME.spi` as a `SparseImage`, is expected to list as
+/// `NAME.expected`. With the environment variable `FAT32_GOLDEN_RECORD` set,
+/// the listings are recorded instead.
+///
+/// `fatfs.spi` was formatted and filled by the `fatfs` crate with the tree
+/// `test_golden_mkfs()` builds, its largest file cut to 7000 bytes.
+#[test]
+fn test_golden_fixtures() {
+    use std::fs;
+    use device::SparseImage;
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let record = ::std::env::var_os("FAT32_GOLDEN_RECORD").is_some();
+    let mut checked = 0;
+    for entry in fs::read_dir(&dir).expect("fixture directory is readable") {
+        let path = entry.expect("fixture directory is readable").path();
+        let vfat = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("img") => VFat::from(fs::File::open(&path).expect("fixture opens")),
+            Some("spi") => VFat::from(SparseImage::open_read_only(&path).expect("fixture opens")),
+            _ => continue,
+        };
+        let vfat = vfat.expect("fixture mounts");
+        let listing = golden_listing(&::vfat::Dir::root(vfat));
+        let expected = path.with_extension("expected");
+        if record {
+            fs::write(&expected, &listing).expect("expectation is recorded");
+        } else {
+            let expected = fs::read_to_string(&expected).expect("fixture has an expectation");
+            assert_hash_eq!(path.display(), listing, expected);
+        }
+        checked += 1;
+    }
+    assert!(checked > 0, "no golden fixtures in {}", dir.display());
+}
+
+/// Formats a reference image with mkfs.fat and fills it with mtools, and
+/// checks that it lists as the tree it was made from. Run it with
+/// `cargo test -- --ignored` where both are installed.
+#[test]
+#[ignore = "needs mkfs.fat and mcopy"]
+fn test_golden_mkfs() {
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+    use vfat::{Date, Time, Timestamp as VFatTimestamp};
+
+    fn find_tool(names: &[&str]) -> Option<::std::path::PathBuf> {
+        let path = env::var_os("PATH")?;
+        env::split_paths(&path)
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find(|path| path.is_file())
+    }
+
+    let mkfs = find_tool(&["mkfs.fat", "mkfs.vfat"]).expect("mkfs.fat is installed");
+    let mcopy = find_tool(&["mcopy"]).expect("mcopy is installed");
+
+    let root = env::temp_dir().join(format!("fat32-golden-{}", ::std::process::id()));
+    let source = root.join("source");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(source.join("Sub Dir/deeper")).unwrap();
+
+    // Times are even seconds, as FAT stores them at 2 second resolution.
+    let files: &[(&str, usize, (usize, u8, u8), (u8, u8, u8))] = &[
+        ("README.TXT", 300, (2018, 3, 1), (12, 0, 0)),
+        ("lower.txt", 0, (1999, 12, 31), (23, 59, 58)),
+        ("A much longer name than 8.3 allows.text", 5000, (2004, 2, 29), (6, 30, 2)),
+        ("Sub Dir/ünïcödé 日本.bin", 70000, (2030, 7, 4), (0, 0, 0)),
+        ("Sub Dir/deeper/exactly-512", 512, (1980, 1, 1), (0, 0, 0)),
+    ];
+    let mut expected = vec![
+        ("/Sub Dir".to_string(), "d /Sub Dir\n".to_string()),
+        ("/Sub Dir/deeper".to_string(), "d /Sub Dir/deeper\n".to_string()),
+    ];
+    for &(name, len, (year, month, day), (hour, minute, second)) in files {
+        let data: Vec<u8> = (0..len).map(|i| (i * 31 + len) as u8).collect();
+        let path = source.join(name);
+        fs::write(&path, &data).unwrap();
+        let ts = VFatTimestamp {
+            date: Date::from_ymd(year, month, day),
+            time: Time::from_hms(hour, minute, second),
+            hundredths: 0,
+        };
+        fs::File::options().write(true).open(&path).unwrap()
+            .set_modified(ts.into()).unwrap();
+
+        let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        let line = format!("f {} {:04}-{:02}-{:02} {:02}:{:02}:{:02} {:016x} /{}\n", len, year,
+                           month, day, hour, minute, second, hash, name);
+        expected.push((format!("/{}", name), line));
+    }
+    expected.sort();
+    let expected: String = expected.into_iter().map(|(_, line)| line).collect();
+
+    // Format and fill a 40MiB partition, then put it behind an MBR at 1MiB.
+    let partition = root.join("partition.img");
+    fs::File::create(&partition).unwrap().set_len(40 << 20).unwrap();
+    let status = Command::new(&mkfs)
+        .args(&["-F", "32", "-S", "512", "-s", "1", "-n", "GOLDEN"])
+        .arg(&partition)
+        .status().expect("mkfs.fat runs");
+    assert!(status.success(), "mkfs.fat failed");
+    let mut copy = Command::new(&mcopy);
+    copy.env("TZ", "UTC").env("MTOOLS_SKIP_CHECK", "1").arg("-i").arg(&partition)
+        .args(&["-s", "-m", "-Q"]);
+    for entry in fs::read_dir(&source).unwrap() {
+        copy.arg(entry.unwrap().path());
+    }
+    assert!(copy.arg("::/").status().expect("mcopy runs").success(), "mcopy failed");
+
+    let mut image = vec![0u8; 1 << 20];
+    image[446 + 4] = 0x0C;
+    image[446 + 8..446 + 12].copy_from_slice(&2048u32.to_le_bytes());
+    image[446 + 12..446 + 16].copy_from_slice(&((40u32 << 20) / 512).to_le_bytes());
+    image[510] = 0x55;
+    image[511] = 0xAA;
+    image.extend(fs::read(&partition).unwrap());
+    fs::remove_dir_all(&root).expect("temporary files are removed");
+
+    let vfat = VFat::from(Cursor::new(image)).expect("mkfs.fat image mounts");
+    assert_hash_eq!("mkfs.fat image", golden_listing(&::vfat::Dir::root(vfat)), expected);
+}
+
+/// What one reader sees at each path of an image: a description of the
+/// entry's kind, attributes, size, and timestamps, and the file's contents.
+type ReaderView = ::std::collections::BTreeMap<String, (String, Vec<u8>)>;
+
+/// Mounts the same image with this crate and with the `fatfs` crate and
+/// returns every way in which the two disagree: entries only one of them
+/// lists, differing metadata, and differing contents. `partition` is the
+/// byte offset of the volume, which `fatfs` is given directly. Returns
+/// `None` if `fatfs` doesn't see a FAT32 volume, which this crate can't read.
+fn fatfs_divergences(image: &[u8], partition: usize) -> Option<Vec<String>> {
+    use std::collections::BTreeSet;
+    use traits::Timestamp as TimestampTrait;
+
+    fn describe(dir: bool, attr: u8, size: u64, times: [(u16, u16, u16, u16, u16, u32); 3])
+        -> String
+    {
+        let mut description = format!("{} attr={:02x} size={}", if dir { "d" } else { "f" },
+                                       attr, size);
+        for (name, t) in ["ctime", "atime", "mtime"].iter().zip(times.iter()) {
+            description += &format!(" {}={:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}", name,
+                                    t.0, t.1, t.2, t.3, t.4, t.5 / 1000, t.5 % 1000);
+        }
+        description
+    }
+
+    fn ours(dir: &::vfat::Dir, path: &str, view: &mut ReaderView) {
+        let timestamp = |t: ::vfat::Timestamp| {
+            (t.year() as u16, t.month() as u16, t.day() as u16, t.hour() as u16,
+             t.minute() as u16, t.second() as u32 * 1000 + t.hundredths as u32 * 10)
+        };
+        for entry in dir.entries().expect("directory is readable") {
+            let metadata = entry.metadata().clone();
+            if entry.name() == "." || entry.name() == ".." || metadata.attr.volume_id() {
+                continue;
+            }
+
+            let child_path = format!("{}/{}", path, entry.name());
+            let description = describe(metadata.attr.directory(), metadata.attr.0,
+                                       metadata.size as u64,
+                                       [timestamp(metadata.ctime),
+                                        timestamp(::vfat::Timestamp {
+                                            time: ::vfat::Time(0), hundredths: 0,
+                                            ..metadata.atime
+                                        }),
+                                        timestamp(metadata.mtime)]);
+            let mut data = vec![];
+            match entry {
+                ::vfat::Entry::Dir(ref subdir) => ours(subdir, &child_path, view),
+                ::vfat::Entry::File(mut file) => {
+                    file.read_to_end(&mut data).expect("file is readable");
+                }
+            }
+            view.insert(child_path, (description, data));
+        }
+    }
+
+    fn theirs<T: fatfs::ReadWriteSeek>(dir: &fatfs::Dir<T>, path: &str,
+                                         view: &mut ReaderView) {
+        let timestamp = |d: fatfs::Date, t: fatfs::Time| {
+            (d.year, d.month, d.day, t.hour, t.min, t.sec as u32 * 1000 + t.millis as u32)
+        };
+        for entry in dir.iter() {
+            let entry = entry.expect("fatfs reads the directory");
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            let child_path = format!("{}/{}", path, name);
+            let (created, modified) = (entry.created(), entry.modified());
+            let midnight = fatfs::Time { hour: 0, min: 0, sec: 0, millis: 0 };
+            let description = describe(entry.is_dir(), entry.attributes().bits(),
+                                       if entry.is_dir() { 0 } else { entry.len() },
+                                       [timestamp(created.date, created.time),
+                                        timestamp(entry.accessed(), midnight),
+                                        timestamp(modified.date, modified.time)]);
+            let mut data = vec![];
+            if entry.is_dir() {
+                theirs(&entry.to_dir(), &child_path, view);
+            } else {
+                entry.to_file().read_to_end(&mut data).expect("fatfs reads the file");
+            }
+            view.insert(child_path, (description, data));
+        }
+    }
+
+    let fs = fatfs::FileSystem::new(Cursor::new(image[partition..].to_vec()),
+                                      fatfs::FsOptions::new())
+        .expect("fatfs mounts the image");
+    if fs.fat_type() != fatfs::FatType::Fat32 {
+        return None;
+    }
+    let mut their_view = ReaderView::new();
+    theirs(&fs.root_dir(), "", &mut their_view);
+
+    let vfat = VFat::from(Cursor::new(image.to_vec())).expect("image mounts");
+    let mut our_view = ReaderView::new();
+    ours(&::vfat::Dir::root(vfat), "", &mut our_view);
+
+    let paths: BTreeSet<&String> = our_view.keys().chain(their_view.keys()).collect();
+    let mut divergences = vec![];
+    for path in paths {
+        match (our_view.get(path), their_view.get(path)) {
+            (Some(_), None) => divergences.push(format!("{}: only listed by fat32", path)),
+            (None, Some(_)) => divergences.push(format!("{}: only listed by fatfs", path)),
+            (Some(ours), Some(theirs)) if ours.0 != theirs.0 => {
+                divergences.push(format!("{}: fat32 has {}, fatfs has {}", path, ours.0,
+                                         theirs.0));
+            }
+            (Some(ours), Some(theirs)) if ours.1 != theirs.1 => {
+                let at = ours.1.iter().zip(theirs.1.iter()).take_while(|&(a, b)| a == b).count();
+                divergences.push(format!("{}: contents differ at byte {} ({} vs {} bytes)",
+                                         path, at, ours.1.len(), theirs.1.len()));
+            }
+            _ => {}
+        }
+    }
+    Some(divergences)
+}
+
+/// Formats volumes and fills them with random trees using `fatfs`, then
+/// checks that this crate reads them exactly as `fatfs` does.
+#[test]
+fn test_differential_fatfs() {
+    use tests::rand::{Rng, SeedableRng, XorShiftRng};
+
+    const PARTITION: usize = 1 << 20;
+    const NAME_CHARS: &[char] = &['a', 'B', 'c', 'X', 'y', '0', '7', ' ', '.', '-', '_', '~',
+                                  '(', '!', 'é', 'ß', 'Ω', '日', '本'];
+
+    // `set_modified` is deprecated only in favor of a custom time provider.
+    #[allow(deprecated)]
+    fn fill<T: fatfs::ReadWriteSeek, R: Rng>(rng: &mut R, dir: &fatfs::Dir<T>, depth: u32) {
+        for i in 0..rng.gen_range(0, 12) {
+            let len = rng.gen_range(1, 40);
+            let suffix: String = (0..len).map(|_| *rng.choose(NAME_CHARS).unwrap()).collect();
+            // Distinct prefixes keep names unique; no name ends in a dot or space.
+            let name = format!("{}{}{}", i, suffix.trim_right_matches(|c| c == '.' || c == ' '),
+                               if rng.gen() { "" } else { ".TXT" });
+            if depth < 3 && rng.gen_weighted_bool(4) {
+                let subdir = dir.create_dir(&name).expect("fatfs creates the directory");
+                fill(rng, &subdir, depth + 1);
+                continue;
+            }
+
+            let mut file = dir.create_file(&name).expect("fatfs creates the file");
+            let random_len = rng.gen_range(0, 100_000);
+            let len = *rng.choose(&[0, 1, 511, 512, 513, 4096, random_len]).unwrap();
+            let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            file.write_all(&data).expect("fatfs writes the file");
+            file.set_modified(fatfs::DateTime {
+                date: fatfs::Date {
+                    year: rng.gen_range(1980, 2108),
+                    month: rng.gen_range(1, 13),
+                    day: rng.gen_range(1, 29),
+                },
+                time: fatfs::Time {
+                    hour: rng.gen_range(0, 24),
+                    min: rng.gen_range(0, 60),
+                    sec: rng.gen_range(0, 30) * 2,
+                    millis: 0,
+                },
+            });
+        }
+    }
+
+    let mut rng = XorShiftRng::from_seed(FATFS_IMAGE_SEED);
+    for round in 0..6 {
+        let (sectors, fats) = (80 * 1024, rng.gen_range(1, 3));
+        let mut partition = Cursor::new(vec![0u8; sectors * 512]);
+        fatfs::format_volume(&mut partition, fatfs::FormatVolumeOptions::new()
+            .fat_type(fatfs::FatType::Fat32)
+            .bytes_per_cluster(512)
+            .fats(fats)
+            .volume_label(*b"DIFFERENTIA"))
+            .expect("fatfs formats the volume");
+        partition.set_position(0);
+        {
+            let fs = fatfs::FileSystem::new(&mut partition, fatfs::FsOptions::new())
+                .expect("fatfs mounts the volume");
+            fill(&mut rng, &fs.root_dir(), 0);
+            fs.unmount().expect("fatfs unmounts the volume");
+        }
+
+        let mut image = vec![0u8; PARTITION];
+        image[446 + 4] = 0xC;
+        image[446 + 8..446 + 12].copy_from_slice(&((PARTITION / 512) as u32).to_le_bytes());
+        image[446 + 12..446 + 16].copy_from_slice(&(sectors as u32).to_le_bytes());
+        image[510] = 0x55;
+        image[511] = 0xAA;
+        image.extend(partition.into_inner());
+
+        let divergences = fatfs_divergences(&image, PARTITION).expect("volume is FAT32");
+        assert!(divergences.is_empty(), "round {} ({} FATs) diverges from fatfs:\n{}",
+                round, fats, divergences.join("\n"));
+    }
+
+    // The assignment images, when they've been fetched.
+    for name in &["mock1.fat32.img", "mock2.fat32.img", "mock3.fat32.img", "mock4.fat32.img"] {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../files/resources").join(name);
+        if let Ok(image) = ::std::fs::read(&path) {
+            let mbr = MasterBootRecord::from(Cursor::new(image.clone())).expect("image has an MBR");
+            let start = mbr.first_fat32().expect("image has a FAT32 partition").relative_sector;
+            if let Some(divergences) = fatfs_divergences(&image, start as usize * 512) {
+                assert!(divergences.is_empty(), "{} diverges from fatfs:\n{}", name,
+                        divergences.join("\n"));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_free_chain_discards() {
+    use device::{MemoryDevice, Op, SparseImage, TracingDevice};
+    use vfat::{Cluster, MountOptions, Status};
+
+    let device = TracingDevice::new(MemoryDevice::from_vec(Fixture::mini().into_image()));
+    let trace = device.trace();
+    let options = MountOptions { discard: true, ..MountOptions::default() };
+    let vfat = VFat::from_device(device, options).expect("valid image");
+    trace.take();
+
+    // long.txt occupies clusters 4 and 5, the device sectors 5 and 6.
+    assert_eq!(vfat.borrow_mut().free_chain(Cluster::from(4)).unwrap(), 2);
+    assert_eq!(vfat.borrow_mut().fat_entry(Cluster::from(5)).unwrap().status(), Status::Free);
+    let accesses: Vec<_> = trace.take().into_iter().map(|a| (a.op, a.sector, a.len)).collect();
+    assert_eq!(accesses, vec![(Op::Read, 2, Some(512)), (Op::Write, 2, Some(512)),
+                              (Op::Discard, 5, Some(1024))]);
+    assert_eq!(vfat.borrow_mut().free_chain(Cluster::from(0)).unwrap(), 0);
+    assert!(trace.take().is_empty());
+
+    // Discarding whole extents of a sparse image gives their space back.
+    let path = ::std::env::temp_dir().join(format!("fat32-discard-{}.img", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let mut image = SparseImage::create(&path, 64, 512, 8).expect("image is created");
+    for &sector in &[0, 9, 20] {
+        image.write_sector(sector, &[0xAA; 512]).expect("sector is written");
+    }
+    let len = ::std::fs::metadata(&path).unwrap().len();
+    image.discard(4, 16).expect("sectors are discarded");
+    assert_eq!(image.allocated_sectors(), 16);
+    image.discard(16, 8).expect("sectors are discarded");
+    assert_eq!(image.allocated_sectors(), 8);
+    assert_eq!(::std::fs::metadata(&path).unwrap().len(), len - 2 * 8 * 512);
+    let mut sector = [0xFF; 512];
+    image.read_sector(9, &mut sector).unwrap();
+    assert!(sector.iter().all(|&b| b == 0));
+    ::std::fs::remove_file(&path).expect("temporary image is removed");
+}
+
+#[test]
+fn test_free_count() {
+    use std::fs;
+    use device::MemoryDevice;
+    use tools::{fsck, ImageBuilder};
+    use vfat::{FreeCountMismatch, MountOptions};
+
+    let source = ::std::env::temp_dir().join(format!("fat32-free-count-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("data.bin"), vec![7; 3000]).unwrap();
+    let mut device = MemoryDevice::new(40 << 20);
+    ImageBuilder::new(40 << 20).build(&source, &mut device).expect("image is built");
+    fs::remove_dir_all(&source).unwrap();
+
+    let checked = MountOptions { check_free_count: true, ..MountOptions::default() };
+    let free = {
+        let vfat = VFat::from_device(&mut device, checked).expect("image mounts");
+        assert_eq!(vfat.borrow().free_count_mismatch(), None);
+        let mut vfat = vfat.borrow_mut();
+        let free = vfat.fsinfo().unwrap().expect("has FSInfo").free_count.unwrap();
+        // Leave the recorded count stale.
+        vfat.device.write_at(1, 488, &[0; 4]).unwrap();
+        vfat.flush().unwrap();
+        free
+    };
+
+    let vfat = VFat::from_device(&mut device, checked).expect("image mounts");
+    let mismatch = FreeCountMismatch { recorded: Some(0), actual: free };
+    assert_eq!(vfat.borrow().free_count_mismatch(), Some(mismatch));
+    let report = fsck::check(&vfat).unwrap();
+    assert_eq!(report.findings.len(), 1);
+    assert_eq!(report.findings[0].problem, fsck::Problem::WrongFreeCount);
+
+    assert_eq!(vfat.borrow_mut().recompute_free_count().unwrap(), free);
+    assert_eq!(vfat.borrow().free_count_mismatch(), None);
+    vfat.borrow_mut().flush().unwrap();
+    drop(vfat);
+    let vfat = VFat::from_device(&mut device, checked).expect("image mounts");
+    assert_eq!(vfat.borrow().free_count_mismatch(), None);
+    assert!(fsck::check(&vfat).unwrap().findings.is_empty());
+}
+
+#[test]
+fn test_fat_signature() {
+    use tools::fsck::{self, Problem};
+    use vfat::{FatSignatureMismatch, MountOptions};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    assert_eq!(vfat.borrow_mut().check_fat_signature().unwrap(), None);
+
+    // Entry 1 keeps its volume flags cleared, as after an unclean shutdown,
+    // which is no mismatch; entry 0 names the wrong media.
+    let mut fixture = Fixture::mini();
+    fixture.set_fat(0, 0x0FFF_FFF0).set_fat(1, 0x03FF_FFFF);
+    let checked = MountOptions::new().check_fat_signature(true);
+    let vfat = VFat::from_with_options(Cursor::new(fixture.clone().into_image()), checked)
+        .unwrap();
+    let expected = FatSignatureMismatch { media: 0xF8, entry0: Some(0x0FFFFFF0), entry1: None };
+    assert_eq!(vfat.borrow().fat_signature_mismatch(), Some(expected));
+
+    fixture.set_fat(1, 0);
+    let image = fixture.into_image();
+    let vfat = VFat::from(Cursor::new(image.clone())).unwrap();
+    let report = fsck::check(&vfat).unwrap();
+    let problems: Vec<_> = report.findings.iter().map(|f| (f.problem, f.cluster)).collect();
+    assert!(problems.contains(&(Problem::BadFatSignature, Some(0))));
+    assert!(problems.contains(&(Problem::BadFatSignature, Some(1))));
+    assert!(vfat.borrow_mut().repair_fat_signature().unwrap());
+    assert!(!vfat.borrow_mut().repair_fat_signature().unwrap());
+
+    let vfat = VFat::from_with_options(Cursor::new(image), checked.repair(true)).unwrap();
+    let mut vfat = vfat.borrow_mut();
+    assert!(vfat.fat_signature_mismatch().is_some());
+    assert_eq!(vfat.check_fat_signature().unwrap(), None);
+    let fat_start = vfat.fat_start_sector;
+    let fat = vfat.device.get(fat_start).unwrap();
+    assert_eq!(&fat[..8], &[0xF8, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x03]);
+}
+
+#[test]
+fn test_fat_backup() {
+    use vfat::{Cluster, FatBackup, Status};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut vfat = vfat.borrow_mut();
+    let backup = vfat.backup_fat(4..6).unwrap();
+    assert_eq!(backup.len(), 1);
+    let mut saved = Vec::new();
+    backup.save(&mut saved).unwrap();
+    assert_eq!(FatBackup::load(&saved[..]).unwrap(), backup);
+    assert!(FatBackup::load(&saved[1..]).is_err());
+
+    vfat.free_chain(Cluster::from(4)).unwrap();
+    vfat.restore_fat(&backup).unwrap();
+    assert_eq!(vfat.chain_len(Cluster::from(4)).unwrap(), 2);
+
+    let e = vfat.with_fat_rollback(4..6, |vfat| {
+        vfat.free_chain(Cluster::from(4))?;
+        Err::<(), _>(::util::cancelled())
+    }).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+    assert_eq!(vfat.chain_len(Cluster::from(4)).unwrap(), 2);
+    vfat.with_fat_rollback(4..6, |vfat| vfat.free_chain(Cluster::from(4))).unwrap();
+    assert_eq!(vfat.fat_entry(Cluster::from(4)).unwrap().status(), Status::Free);
+}
+
+#[test]
+fn test_mount_options_builder() {
+    use std::io::ErrorKind;
+    use vfat::{Cluster, Date, MountOptions, Time, Timestamp};
+
+    fn clock() -> Timestamp {
+        Timestamp { time: Time::from_hms(12, 30, 0), date: Date::from_ymd(2020, 6, 1),
+                    hundredths: 0 }
+    }
+
+    let options = MountOptions::new().read_only(true).case_sensitive(true).dentry_cache(0);
+    assert!(options.read_only && options.case_sensitive);
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).unwrap();
+    assert!((&vfat).open("/long.txt").is_ok());
+    let e = (&vfat).open("/LONG.TXT").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert!((&vfat).open("/sub").is_err());
+    let e = vfat.borrow_mut().free_chain(Cluster::from(4)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    let e = (&vfat).open_file("/long.txt").unwrap().set_len(0).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), MountOptions::new().clock(clock))
+        .unwrap();
+    assert!((&vfat).open("/LONG.TXT").is_ok());
+    let mut file = (&vfat).open_file("/long.txt").unwrap();
+    file.set_len(10).unwrap();
+    assert_eq!(file.metadata.mtime, clock());
+    let file = (&vfat).open_file("/long.txt").unwrap();
+    assert_eq!(file.metadata.mtime, clock());
+    assert_eq!(file.metadata.atime.date, clock().date);
+}
+
+#[test]
+fn test_parse_policy() {
+    use std::io::ErrorKind;
+    use vfat::{Cluster, Diagnostic, EntryLocation, MountOptions, ParsePolicy, Violation};
+
+    let mut fixture = Fixture::mini();
+    // The long name of long.txt is padded with NULs rather than 0xFFFF.
+    for &offset in [22, 24, 28, 30].iter() {
+        fixture.entry(ROOT, 0).bytes()[offset..offset + 2].copy_from_slice(&[0, 0]);
+    }
+    fixture.entry(ROOT, 2).bytes()[12] = 0x01; // invalid case flags on SUB
+    fixture.entry(SUB, 2).set_size(0); // SUB/A.BIN is empty but keeps cluster 4
+    let image = fixture.into_image();
+    let at = |cluster, offset| EntryLocation { cluster: Cluster::from(cluster), offset: offset };
+
+    let vfat = VFat::from(Cursor::new(image.clone())).expect("valid image");
+    for _ in 0..2 {
+        assert_eq!(vfat.open_dir("/SUB").unwrap().entries().unwrap().count(), 3);
+    }
+    assert_eq!(vfat.borrow().diagnostics(), &[
+        Diagnostic { location: at(2, 0), violation: Violation::LfnPadding },
+        Diagnostic { location: at(2, 64), violation: Violation::NtFlags(0x01) },
+        Diagnostic { location: at(3, 64), violation: Violation::EmptyFileWithCluster(4) },
+    ]);
+    vfat.borrow_mut().clear_diagnostics();
+    assert!(vfat.borrow().diagnostics().is_empty());
+
+    let options = MountOptions::new().parse_policy(ParsePolicy::Strict);
+    let vfat = VFat::from_with_options(Cursor::new(image), options).expect("valid image");
+    let e = vfat.open_dir("/").unwrap().entries().err().expect("strict");
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    assert!((&vfat).open("/SUB").is_err());
+}
+
+#[test]
+fn test_journal() {
+    use std::fs;
+    use device::{MemoryDevice, PartitionSlice};
+    use tools::ImageBuilder;
+    use vfat::{Error, Journal, MountOptions, Status};
+
+    let source = ::std::env::temp_dir().join(format!("fat32-journal-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("data.bin"), vec![7; 3000]).unwrap();
+    let mut device = MemoryDevice::new(40 << 20);
+    ImageBuilder::new(40 << 20).build(&source, &mut device).expect("image is built");
+    fs::remove_dir_all(&source).unwrap();
+
+    let journaled = MountOptions { journal: true, ..MountOptions::default() };
+    let (cluster, sector) = {
+        let vfat = VFat::from_device(&mut device, journaled).expect("image mounts");
+        let cluster = vfat.open_file("/data.bin").unwrap().first_cluster;
+        let mut vfat = vfat.borrow_mut();
+        let sector = vfat.data_start_sector
+            + (cluster.get_index() - 2) as u64 * vfat.sectors_per_cluster as u64;
+        assert_eq!(vfat.free_chain(cluster).unwrap(), 6);
+        vfat.flush().expect("changes are written");
+        (cluster, sector)
+    };
+    {
+        let vfat = VFat::from_device(&mut device, MountOptions::default()).expect("image mounts");
+        assert_eq!(vfat.borrow_mut().fat_entry(cluster).unwrap().status(), Status::Free);
+    }
+
+    // A flush changing more sectors than the journal holds fails whole,
+    // rather than being split over several transactions.
+    {
+        let vfat = VFat::from_device(&mut device, journaled).expect("image mounts");
+        let mut vfat = vfat.borrow_mut();
+        let capacity = vfat.journal.expect("journal is used").capacity();
+        for n in sector..sector + capacity as u64 + 1 {
+            vfat.device.write_at(n, 0, &[0xA5; 512]).unwrap();
+        }
+        let err = vfat.flush().expect_err("flush doesn't fit the journal");
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidInput);
+        assert_eq!(vfat.device.dirty_len(), capacity + 1);
+    }
+    {
+        let vfat = VFat::from_device(&mut device, MountOptions::default()).expect("image mounts");
+        let mut data = [0; 512];
+        vfat.borrow_mut().read_cluster(cluster, 0, &mut data).unwrap();
+        assert!(data.iter().all(|&b| b == 7), "nothing was written in place");
+    }
+
+    // A crash after the transaction was committed, but before it was written
+    // in place, leaves it in the journal.
+    let bpb = BiosParameterBlock::from(&mut device, 2048).unwrap();
+    let journal = Journal::locate(&bpb).expect("reserved sectors have room");
+    let commit = |device: &mut MemoryDevice| {
+        let mut slice = PartitionSlice::new(device, 2048, (40 << 20) / 512 - 2048, 512);
+        journal.commit(&mut slice, &[(sector, &[0x5A; 512][..])]).expect("commit is written");
+    };
+    commit(&mut device);
+    match VFat::from_device(&mut device, MountOptions::default()) {
+        Err(Error::Journal(_)) => {}
+        other => panic!("mounted a volume with a pending journal: {:?}", other.map(|_| ())),
+    }
+    let mut data = [0; 512];
+    {
+        let vfat = VFat::from_device(&mut device, journaled).expect("journal is replayed");
+        vfat.borrow_mut().read_cluster(cluster, 0, &mut data).unwrap();
+        assert!(data.iter().all(|&b| b == 0x5A));
+    }
+    VFat::from_device(&mut device, MountOptions::default()).expect("journal is clear");
+
+    // A transaction torn while being committed is ignored.
+    commit(&mut device);
+    let copy_offset = ((2048 + 14) * 512) as usize;
+    let mut bytes = device.into_inner();
+    bytes[copy_offset] ^= 1;
+    let mut device = MemoryDevice::from_vec(bytes);
+    VFat::from_device(&mut device, MountOptions::default()).expect("torn journal is ignored");
+}
+
+#[test]
+fn test_overlay() {
+    use device::{MemoryDevice, Overlay};
+    use vfat::{Cluster, MountOptions, Status};
+
+    let path = ::std::env::temp_dir().join(format!("fat32-overlay-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let mut base = MemoryDevice::from_vec(Fixture::mini().into_image());
+    {
+        let overlay = Overlay::create(&mut base, &path).expect("delta is created");
+        let vfat = VFat::from_device(overlay, MountOptions::default()).expect("image mounts");
+        vfat.borrow_mut().free_chain(Cluster::from(4)).unwrap();
+        vfat.borrow_mut().flush().unwrap();
+    }
+    assert_eq!(base.as_bytes(), &Fixture::mini().into_image()[..]);
+
+    let mut overlay = Overlay::open(&mut base, &path).expect("delta opens");
+    assert_eq!(overlay.changed_sectors(), vec![2]);
+    {
+        let vfat = VFat::from_device(&mut overlay, MountOptions::default()).unwrap();
+        assert_eq!(vfat.borrow_mut().fat_entry(Cluster::from(4)).unwrap().status(), Status::Free);
+    }
+    overlay.apply().expect("delta is applied");
+    assert!(overlay.changed_sectors().is_empty());
+    assert_eq!(overlay.base().as_bytes()[1024 + 16..1024 + 24], [0; 8]);
+    ::std::fs::remove_file(&path).expect("delta is removed");
+}
+
+#[test]
+fn test_dentry_cache() {
+    use vfat::{Cluster, MountOptions};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let file = (&vfat).open_file("/SUB/A.BIN").expect("file exists");
+    assert!(vfat.borrow_mut().dentries.get("/sub").is_some());
+    let cached = (&vfat).open_file("/sub/a.bin").expect("lookup is cached");
+    assert_eq!((cached.name(), cached.size), (file.name(), 10));
+    assert!((&vfat).open_file("/SUB/A.BIN/x").is_err());
+
+    vfat.borrow_mut().free_chain(Cluster::from(4)).unwrap();
+    assert!(vfat.borrow_mut().dentries.get("/sub/a.bin").is_none());
+
+    let options = MountOptions { dentry_cache: 0, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).unwrap();
+    (&vfat).open_file("/SUB/A.BIN").expect("file exists");
+    assert!(vfat.borrow_mut().dentries.get("/sub").is_none());
+}
+
+#[test]
+fn test_parallel_read() {
+    use std::fs;
+    use device::ImageFile;
+    use tools::{self, ImageBuilder};
+    use vfat::MountOptions;
+
+    let dir = ::std::env::temp_dir().join(format!("fat32-parallel-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("source")).unwrap();
+    let data: Vec<u8> = (0..700_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(dir.join("source/big.bin"), &data).unwrap();
+    ImageBuilder::new(40 << 20).sectors_per_cluster(1)
+        .build_file(dir.join("source"), dir.join("image"))
+        .expect("image is built");
+
+    let options = MountOptions { read_threads: 4, ..MountOptions::default() };
+    let image = ImageFile::open_read_only(dir.join("image")).unwrap();
+    let vfat = VFat::from_with_options(image, options).expect("image mounts");
+    let cluster = vfat.open_file("/big.bin").unwrap().first_cluster;
+
+    // A change still in the sector cache, to the file's 100th cluster, is
+    // read from the cache.
+    {
+        let mut vfat = vfat.borrow_mut();
+        let sector = vfat.data_start_sector + (cluster.get_index() - 2 + 100) as u64;
+        vfat.device.write_at(sector, 0, &[0xEE]).unwrap();
+    }
+    let mut expected = data.clone();
+    expected[100 * 512] = 0xEE;
+
+    let mut buf = vec![];
+    vfat.borrow_mut().read_chain(cluster, &mut buf).expect("chain is read");
+    assert_eq!(&buf[..data.len()], &expected[..]);
+
+    tools::extract(&vfat, "/big.bin", dir.join("big.bin"), |_, _| ()).expect("file is extracted");
+    assert_eq!(fs::read(dir.join("big.bin")).unwrap(), expected);
+    fs::remove_dir_all(&dir).expect("temporary files are removed");
+}
+
+#[test]
+fn test_max_chain_bytes() {
+    use std::io::ErrorKind;
+    use traits::Dir;
+    use vfat::MountOptions;
+
+    // `long.txt` spans two 512 byte clusters.
+    let options = MountOptions { max_chain_bytes: 512, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).expect("valid image");
+    let mut buf = Vec::new();
+    let e = vfat.borrow_mut().read_chain(::vfat::Cluster::from(4), &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    assert!(buf.is_empty());
+    assert_eq!(vfat.borrow_mut().read_chain(::vfat::Cluster::from(3), &mut buf).unwrap(), 512);
+
+    // Files are streamed, so the limit doesn't apply to them.
+    let mut data = Vec::new();
+    (&vfat).open_file("/long.txt").unwrap().read_to_end(&mut data).expect("file is read");
+    assert_eq!(data.len(), 1000);
+    assert!((&vfat).open_dir("/SUB").unwrap().entries().is_ok());
+
+    let options = MountOptions { max_chain_bytes: 0, ..options };
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).expect("valid image");
+    let e = (&vfat).open("/SUB").map(|_| ()).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_cancel() {
+    use tools;
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let token = vfat.borrow().cancel_token();
+    token.cancel();
+    assert!(vfat.borrow_mut().usage().is_err());
+    assert!((&vfat).disk_usage("/", |_, _| ()).is_err());
+    let dest = ::std::env::temp_dir().join(format!("fat32-cancel-{}", ::std::process::id()));
+    let e = tools::extract(&vfat, "/", &dest, |_, _| ()).unwrap_err();
+    assert_eq!(e.to_string(), "operation was cancelled");
+    assert!(!dest.exists());
+
+    // Files can still be read, and everything runs again once reset.
+    let mut data = Vec::new();
+    (&vfat).open_file("/long.txt").unwrap().read_to_end(&mut data).expect("file is read");
+    token.reset();
+    assert_eq!((&vfat).disk_usage("/", |_, _| ()).unwrap().files, 2);
+}
+
+#[test]
+fn test_default_disk_usage() {
+    use vfat;
+
+    // Forwards everything but `disk_usage()`, which is left to the default.
+    struct Walk<'a>(&'a Shared<VFat>);
+
+    impl<'a> FileSystem for Walk<'a> {
+        type File = vfat::File;
+        type Dir = vfat::Dir;
+        type Entry = vfat::Entry;
+
+        fn open<P: AsRef<Path>>(self, path: P) -> ::std::io::Result<vfat::Entry> {
+            self.0.open(path)
+        }
+        fn create_file<P: AsRef<Path>>(self, path: P) -> ::std::io::Result<vfat::File> {
+            self.0.create_file(path)
+        }
+        fn create_dir<P: AsRef<Path>>(self, path: P, parents: bool)
+            -> ::std::io::Result<vfat::Dir>
+        {
+            self.0.create_dir(path, parents)
+        }
+        fn rename<P, Q>(self, from: P, to: Q) -> ::std::io::Result<()>
+            where P: AsRef<Path>, Q: AsRef<Path>
+        {
+            self.0.rename(from, to)
+        }
+        fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> ::std::io::Result<()> {
+            self.0.remove(path, children)
+        }
+    }
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut dirs = Vec::new();
+    let usage = Walk(&vfat).disk_usage("/", |path, usage| {
+        dirs.push((path.to_path_buf(), usage.apparent));
+    }).expect("tree is walked");
+    assert_eq!(usage, DiskUsage { allocated: 1010, apparent: 1010, files: 2, dirs: 2 });
+    assert_eq!(dirs, vec![(Path::new("/SUB").to_path_buf(), 10),
+                          (Path::new("/").to_path_buf(), 1010)]);
+
+    let usage = Walk(&vfat).disk_usage("/long.txt", |_, _| panic!("not a directory"));
+    assert_eq!(usage.expect("file is found"),
+               DiskUsage { allocated: 1000, apparent: 1000, files: 1, dirs: 0 });
+    assert!(Walk(&vfat).disk_usage("/missing", |_, _| ()).is_err());
+}
+
+#[test]
+fn test_retry_device() {
+    use device::{Fault, FaultyDevice, MemoryDevice, RetryDevice, SectorFailures};
+
+    let mut faulty = FaultyDevice::new(MemoryDevice::new(4096));
+    faulty.inject(1, Fault::FailTimes(2)).inject(2, Fault::Fail);
+    let mut device = RetryDevice::new(faulty);
+    let mut buf = [0; 512];
+    assert_eq!(device.read_sector(1, &mut buf).expect("read is retried"), 512);
+    assert!(device.read_sector(2, &mut buf).is_err());
+    assert!(device.read_sector(8, &mut buf).is_err());
+
+    let failures: Vec<_> = device.failures().iter().map(|(&n, &f)| (n, f)).collect();
+    assert_eq!(failures, vec![
+        (1, SectorFailures { transient: 2, fatal: 0 }),
+        (2, SectorFailures { transient: 0, fatal: 1 }),
+        // Reading past the end isn't retried.
+        (8, SectorFailures { transient: 0, fatal: 1 }),
+    ]);
+}
+
+#[test]
+fn test_remap_device() {
+    use device::{Fault, FaultyDevice, MemoryDevice, RemapDevice};
+
+    let mut faulty = FaultyDevice::new(MemoryDevice::new(16 * 512));
+    faulty.inject(2, Fault::Fail);
+    let mut device = RemapDevice::with_table_sector(faulty, 12..16).expect("table is written");
+    device.write_sector(2, &[0xAB; 512]).expect("write is remapped");
+    assert_eq!(device.remapped().get(&2), Some(&13));
+    let mut buf = [0; 512];
+    device.read_sector(2, &mut buf).expect("spare is read");
+    assert_eq!(buf[..], [0xAB; 512][..]);
+    assert!(device.read_sector(13, &mut buf).is_err());
+
+    // The table survives in the first spare sector.
+    let memory = device.into_inner().into_inner();
+    let mut device = RemapDevice::with_table_sector(memory, 12..16).expect("table is read");
+    assert_eq!(device.remapped().get(&2), Some(&13));
+    device.read_sector(2, &mut buf).expect("spare is read");
+    assert_eq!(buf[0], 0xAB);
+    assert_eq!(device.remap(5).unwrap(), 14);
+    assert_eq!(device.remap(6).unwrap(), 15);
+    assert!(device.remap(7).is_err());
+}
+
+#[test]
+fn test_nbd_device() {
+    use std::collections::VecDeque;
+    use std::io::{self, ErrorKind};
+    use device::NbdDevice;
+
+    /// One end of an in-memory connection to an NBD server exporting
+    /// `export` under the name "sd". Reads of the last sector fail with EIO.
+    #[derive(Debug)]
+    struct Server {
+        export: Vec<u8>,
+        outbox: VecDeque<u8>,
+        inbox: Vec<u8>,
+        negotiated: bool,
+    }
+
+    impl Server {
+        fn new(export: Vec<u8>) -> Server {
+            let mut outbox = VecDeque::new();
+            outbox.extend(b"NBDMAGICIHAVEOPT");
+            outbox.extend(&[0, 3]); // fixed newstyle, no zeroes
+            Server { export, outbox, inbox: Vec::new(), negotiated: false }
+        }
+
+        fn take(&mut self, len: usize) -> Option<Vec<u8>> {
+            if self.inbox.len() < len {
+                return None;
+            }
+            Some(self.inbox.drain(..len).collect())
+        }
+
+        fn reply(&mut self, error: u32, handle: &[u8], data: &[u8]) {
+            self.outbox.extend(&0x6744_6698u32.to_be_bytes());
+            self.outbox.extend(&error.to_be_bytes());
+            self.outbox.extend(handle);
+            self.outbox.extend(data);
+        }
+
+        fn serve(&mut self) {
+            let be = |bytes: &[u8]| bytes.iter().fold(0u64, |n, &b| n << 8 | b as u64);
+            if !self.negotiated {
+                if self.inbox.len() < 20 {
+                    return;
+                }
+                let name_len = be(&self.inbox[16..20]) as usize;
+                let option = match self.take(20 + name_len) {
+                    Some(option) => option,
+                    None => return,
+                };
+                assert_eq!(&option[..16], b"\0\0\0\x03IHAVEOPT\0\0\0\x01");
+                self.negotiated = true;
+                if &option[20..] == b"sd" {
+                    let size = self.export.len() as u64;
+                    self.outbox.extend(&size.to_be_bytes());
+                    self.outbox.extend(&[0, 1 | 1 << 2]); // has flags, flush
+                }
+            }
+
+            while self.inbox.len() >= 28 {
+                let command = be(&self.inbox[6..8]) as u16;
+                let offset = be(&self.inbox[16..24]) as usize;
+                let len = be(&self.inbox[24..28]) as usize;
+                let data_len = if command == 1 { len } else { 0 };
+                let request = match self.take(28 + data_len) {
+                    Some(request) => request,
+                    None => return,
+                };
+                assert_eq!(be(&request[..4]), 0x2560_9513);
+                let handle = request[8..16].to_vec();
+                match command {
+                    0 if offset + len == self.export.len() => self.reply(5, &handle, &[]),
+                    0 => {
+                        let data = self.export[offset..offset + len].to_vec();
+                        self.reply(0, &handle, &data);
+                    }
+                    1 => {
+                        self.export[offset..offset + len].copy_from_slice(&request[28..]);
+                        self.reply(0, &handle, &[]);
+                    }
+                    3 => self.reply(0, &handle, &[]),
+                    _ => panic!("unexpected NBD command {}", command),
+                }
+            }
+        }
+    }
+
+    impl Read for Server {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = ::std::cmp::min(buf.len(), self.outbox.len());
+            for (byte, value) in buf.iter_mut().zip(self.outbox.drain(..len)) {
+                *byte = value;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for Server {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inbox.extend_from_slice(buf);
+            self.serve();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let export: Vec<u8> = (0..4 * 512).map(|i| (i / 512) as u8).collect();
+    let e = NbdDevice::handshake(Server::new(export.clone()), "missing").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    let mut device = NbdDevice::handshake(Server::new(export), "sd").expect("handshake");
+    assert_eq!(device.size(), 4 * 512);
+    assert!(!device.is_read_only());
+
+    let mut buf = [0; 512];
+    assert_eq!(device.read_sector(2, &mut buf).expect("sector is read"), 512);
+    assert!(buf.iter().all(|&b| b == 2));
+
+    assert_eq!(device.write_sector(1, &[0xAB; 512]).expect("sector is written"), 512);
+    device.flush().expect("flush");
+    device.read_sector(1, &mut buf).expect("sector is read");
+    assert_eq!(buf[..], [0xAB; 512][..]);
+
+    let e = device.read_sector(3, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert_eq!(e.to_string(), "NBD server returned error 5");
+    let e = device.read_sector(4, &mut buf).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_geometry() {
+    use vfat::{Geometry, Stats};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let geometry = vfat.borrow().geometry();
+    assert_eq!(geometry, Geometry {
+        bytes_per_sector: 512,
+        sectors_per_cluster: 1,
+        bytes_per_cluster: 512,
+        total_sectors: 127,
+        num_clusters: 125,
+        sectors_per_fat: 1,
+        num_fats: 1,
+        fat_start_sector: 1,
+        data_start_sector: 2,
+        root_cluster: 2,
+    });
+    assert_eq!(geometry.data_bytes(), 125 * 512);
+
+    let mut buf = [0; 16];
+    vfat.borrow_mut().read_cluster(::vfat::Cluster::from(4), 0, &mut buf).unwrap();
+    let stats = vfat.borrow().stats();
+    assert_eq!(stats.dirty_sectors, 0);
+    assert!(stats.cached_sectors > 0);
+    vfat.borrow_mut().device.write_at(4, 0, &[1]).unwrap();
+    let stats = Stats { dirty_sectors: 1, ..stats };
+    assert_eq!(vfat.borrow().stats(), stats);
+}
+
+#[test]
+fn test_entry_accessors() {
+    use vfat::Entry;
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let file = (&vfat).open("/long.txt").expect("long.txt exists");
+    assert!(file.is_file() && !file.is_dir());
+    assert_eq!(file.len(), 1000);
+    assert_eq!(file.created(), file.info().metadata.ctime);
+    assert_eq!(file.modified(), file.info().metadata.mtime);
+
+    let dir = (&vfat).open("/SUB").expect("SUB exists");
+    assert!(dir.is_dir() && !dir.is_file());
+    assert_eq!(dir.len(), 0);
+
+    let file = Entry::from(file.into_file().unwrap());
+    assert!(file.is_file());
+    let dir = Entry::from(dir.into_dir().unwrap());
+    assert!(dir.is_dir());
+}
+
+#[test]
+fn test_raw_entries() {
+    use vfat::RawEntryKind;
+
+    let mut fixture = Fixture::mini();
+    fixture.entry(SUB, 2).delete();
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let root: Vec<_> = ::vfat::Dir::root(vfat.clone()).raw_entries().unwrap().collect();
+    assert_eq!(root.len(), 16);
+    let kinds: Vec<_> = root.iter().take(4).map(|e| e.kind).collect();
+    assert_eq!(kinds, [RawEntryKind::Lfn, RawEntryKind::Regular, RawEntryKind::Regular,
+                       RawEntryKind::End]);
+    assert_eq!((root[2].cluster, root[2].offset, root[2].index), (2, 64, 2));
+    assert_eq!(&root[2].bytes[..11], b"SUB        ");
+    assert!(root[2].attributes().directory());
+
+    let sub = (&vfat).open_dir("/SUB").unwrap();
+    let deleted = sub.raw_entries().unwrap().nth(2).unwrap();
+    assert_eq!(deleted.kind, RawEntryKind::Deleted);
+    assert_eq!((deleted.cluster, deleted.offset), (3, 64));
+}
+
+#[test]
+fn test_entries_all() {
+    let mut fixture = Fixture::mini();
+    // Delete long.txt, with its LFN entry, from the root and A.BIN from SUB.
+    fixture.entry(ROOT, 0).delete();
+    fixture.entry(ROOT, 1).delete();
+    fixture.entry(SUB, 2).delete();
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let root = ::vfat::Dir::root(vfat.clone());
+    let names: Vec<_> = root.entries().unwrap().map(|e| String::from(e.name())).collect();
+    assert_eq!(names, ["SUB"]);
+
+    let entries: Vec<_> = root.entries_all(true).unwrap().collect();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].is_deleted() && !entries[1].is_deleted());
+    assert_eq!(entries[0].name(), "long.txt");
+    assert_eq!(entries[0].short_name(), "LONG~1.TXT");
+    assert_eq!(entries[0].len(), 1000);
+    assert_eq!(entries[0].as_file().unwrap().first_cluster.get_index(), 4);
+
+    let sub = (&vfat).open_dir("/SUB").unwrap();
+    let deleted = sub.entries_all(true).unwrap().find(|e| e.is_deleted()).unwrap();
+    assert_eq!(deleted.name(), "?.BIN");
+}
+
+#[test]
+fn test_seek_policy() {
+    use std::io::{Seek, SeekFrom};
+    use vfat::{MountOptions, SeekPolicy};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut file = (&vfat).open_file("/long.txt").expect("long.txt exists");
+    assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), 1000);
+    let e = file.seek(SeekFrom::Current(1)).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    assert!(file.seek(SeekFrom::Current(-1001)).is_err());
+
+    file.set_seek_policy(SeekPolicy::Sparse);
+    assert_eq!(file.seek(SeekFrom::End(24)).unwrap(), 1024);
+    assert_eq!(file.read(&mut [0; 16]).unwrap(), 0);
+    assert!(file.seek(SeekFrom::Start(1 << 32)).is_err());
+    assert_eq!(file.seek(SeekFrom::Start(990)).unwrap(), 990);
+    assert_eq!(file.read(&mut [0; 16]).unwrap(), 10);
+
+    let options = MountOptions { seek_policy: SeekPolicy::Sparse, ..Default::default() };
+    let vfat = VFat::from_with_options(Cursor::new(Fixture::mini().into_image()), options).unwrap();
+    let mut file = (&vfat).open_file("/SUB/A.BIN").expect("A.BIN exists");
+    assert_eq!(file.seek(SeekFrom::Start(4096)).unwrap(), 4096);
+}
+
+#[test]
+fn test_large_directory() {
+    use std::fs;
+    use tools::ImageBuilder;
+
+    // 2000 long names take three entries each: a directory of 192000 bytes.
+    let dir = ::std::env::temp_dir().join(format!("fat32-large-dir-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("source")).unwrap();
+    for i in 0..2000 {
+        fs::write(dir.join(format!("source/a long file name {:04}", i)), format!("{}", i)).unwrap();
+    }
+    ImageBuilder::new(40 << 20).sectors_per_cluster(1)
+        .build_file(dir.join("source"), dir.join("image"))
+        .expect("image is built");
+
+    let vfat = VFat::from(::device::ImageFile::open_read_only(dir.join("image")).unwrap())
+        .expect("image mounts");
+    let root = ::vfat::Dir::root(vfat.clone());
+    // The files, and the volume label.
+    assert_eq!(root.entries().unwrap().count(), 2001);
+    assert!(root.raw_entries().unwrap().count() >= 6000);
+
+    let mut contents = String::new();
+    vfat.open_file("/a long file name 1999").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "1999");
+    fs::remove_dir_all(&dir).expect("temporary files are removed");
+}
+
+#[test]
+fn test_partition_reader() {
+    use std::io::{Seek, SeekFrom};
+    use vfat::PartitionReader;
+
+    let image = Fixture::mini().into_image();
+    let vfat = VFat::from(Cursor::new(image.clone())).expect("valid image");
+    vfat.borrow_mut().device.write_at(5, 3, &[0xEE]).unwrap();
+    let cached = vfat.borrow().stats().cached_sectors;
+
+    let mut reader = PartitionReader::new(vfat.clone());
+    assert_eq!(reader.len(), 127 * 512);
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    let mut expected = image[512..].to_vec();
+    expected[5 * 512 + 3] = 0xEE;
+    assert!(data == expected);
+    assert_eq!(vfat.borrow().stats().cached_sectors, cached);
+
+    assert_eq!(reader.seek(SeekFrom::End(-510)).unwrap(), 126 * 512 + 2);
+    assert_eq!(reader.read(&mut [0; 1024]).unwrap(), 510);
+    assert_eq!(reader.read(&mut [0; 1024]).unwrap(), 0);
+    assert!(reader.seek(SeekFrom::Current(-(128 * 512))).is_err());
+}
+
+#[test]
+fn test_fs_facade() {
+    use vfat::fs;
+
+    let mut fixture = Fixture::mini();
+    fixture.cluster_mut(4)[..5].copy_from_slice(b"hello");
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    let paths: Vec<_> = fs::read_dir(&vfat, "/SUB").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(paths, [::std::path::PathBuf::from("/SUB/A.BIN")]);
+
+    assert_eq!(fs::read(&vfat, "/SUB/A.BIN").unwrap().len(), 10);
+    assert_eq!(fs::read_to_string(&vfat, "/long.txt").unwrap().len(), 1000);
+    assert!(fs::metadata(&vfat, "/SUB").unwrap().is_dir());
+    assert_eq!(fs::metadata(&vfat, "/long.txt").unwrap().len(), 1000);
+    assert_eq!(fs::read(&vfat, "/nope").unwrap_err().kind(), ::std::io::ErrorKind::NotFound);
+
+    let out = ::std::env::temp_dir().join(format!("fat32-fs-copy-{}", ::std::process::id()));
+    assert_eq!(fs::copy(&vfat, "/SUB/A.BIN", &out).unwrap(), 10);
+    assert_eq!(&::std::fs::read(&out).unwrap()[..5], b"hello");
+    ::std::fs::remove_file(&out).unwrap();
+}
+
+#[test]
+fn test_filesystem_read() {
+    let mut fixture = Fixture::mini();
+    // Claim long.txt is larger than FileSystem::read() allows.
+    fixture.entry(ROOT, 1).set_size(0x0200_0000);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    assert_eq!((&vfat).read("/SUB/A.BIN").unwrap().len(), 10);
+    assert_eq!((&vfat).read_to_string("/SUB/A.BIN").unwrap(), "\0".repeat(10));
+    let e = (&vfat).read("/long.txt").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    let e = (&vfat).read("/SUB").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_filesystem_read_only() {
+    use std::io::ErrorKind::PermissionDenied;
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    assert_eq!((&vfat).create_file("/new.txt").unwrap_err().kind(), PermissionDenied);
+    assert_eq!((&vfat).create_dir("/NEW", false).unwrap_err().kind(), PermissionDenied);
+    assert_eq!((&vfat).rename("/long.txt", "/short.txt").unwrap_err().kind(), PermissionDenied);
+    assert_eq!((&vfat).remove("/long.txt", false).unwrap_err().kind(), PermissionDenied);
+    assert_eq!((&vfat).read("/long.txt").unwrap().len(), 1000);
+}
+
+#[test]
+fn test_file_hash() {
+    use digest::{Crc32, Digest, Sha256};
+
+    let mut sha = Sha256::new();
+    sha.update(&[b'a'; 1000]);
+    assert_eq!(&sha.finish()[..4], &[0x41, 0xed, 0xec, 0xe4]);
+
+    // Fill long.txt, which spans two clusters.
+    let mut fixture = Fixture::mini();
+    for (i, byte) in fixture.data_mut(4, 1024).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let data = (&vfat).read("/long.txt").unwrap();
+
+    let mut file = (&vfat).open_file("/long.txt").unwrap();
+    let mut crc = Crc32::new();
+    crc.update(&data);
+    assert_eq!(file.hash(Crc32::new()).unwrap(), crc.finish());
+    let mut sha = Sha256::new();
+    sha.update(&data);
+    assert_eq!(file.hash(Sha256::new()).unwrap(), sha.finish());
+}
+
+#[test]
+fn test_entry_location() {
+    use vfat::{Cluster, EntryLocation};
+
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let location = EntryLocation { cluster: Cluster::from(2), offset: 32 };
+    assert_eq!((&vfat).open("/long.txt").unwrap().location(), Some(location));
+    // Cached lookups keep the location.
+    assert_eq!((&vfat).open("/long.txt").unwrap().location(), Some(location));
+    let a = (&vfat).open("/SUB/A.BIN").unwrap().location().unwrap();
+    assert_eq!(a, EntryLocation { cluster: Cluster::from(3), offset: 64 });
+    assert_eq!((&vfat).open("/").unwrap().location(), None);
+
+    let mut entry = vfat.borrow_mut().read_dir_entry(location).unwrap();
+    assert_eq!(&entry[..8], b"LONG~1  ");
+    entry[28..32].copy_from_slice(&[0xF4, 0x01, 0, 0]);
+    vfat.borrow_mut().write_dir_entry(location, &entry).unwrap();
+    assert_eq!((&vfat).open("/long.txt").unwrap().len(), 500);
+
+    let bad = EntryLocation { cluster: Cluster::from(2), offset: 500 };
+    let e = vfat.borrow_mut().write_dir_entry(bad, &entry).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_fsck_report() {
+    use tools::fsck::{self, Problem, Severity};
+
+    // A.BIN shares cluster 4 with long.txt.
+    let vfat = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let report = fsck::check(&vfat).unwrap();
+    assert_eq!((report.files, report.dirs), (2, 2));
+    assert_eq!(report.findings.len(), 1);
+    assert_eq!(report.findings[0].problem, Problem::CrossLinked);
+    assert_eq!(report.findings[0].path, Some(String::from("/SUB/A.BIN")));
+    assert_eq!(report.findings[0].cluster, Some(4));
+    assert!(!report.is_clean());
+
+    // Give A.BIN a cluster of its own, shrink long.txt to one cluster, and
+    // leave cluster 7 allocated to nothing.
+    let mut fixture = Fixture::mini();
+    fixture.entry(SUB, 2).set_cluster(6);
+    fixture.entry(ROOT, 1).set_size(200);
+    fixture.chain(&[6]).chain(&[7]);
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let report = fsck::check(&vfat).unwrap();
+    let problems: Vec<_> = report.findings.iter().map(|f| (f.severity, f.problem)).collect();
+    assert_eq!(problems, vec![(Severity::Warning, Problem::ChainExceedsSize),
+                              (Severity::Warning, Problem::LostClusters)]);
+    assert_eq!(report.findings[1].cluster, Some(7));
+    assert_eq!(report.max_severity(), Some(Severity::Warning));
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("{\"clean\":false,\"files\":2,\"dirs\":2,\"findings\":[{"));
+    assert!(json.contains("\"problem\":\"lost-clusters\",\"path\":null,\"cluster\":7,"));
+}
+
+#[test]
+fn test_diff() {
+    use tools::{self, Change, ChangeKind};
+
+    let old = VFat::from(Cursor::new(Fixture::mini().into_image())).expect("valid image");
+    let mut fixture = Fixture::mini();
+    // Change bytes 600 and 601 of long.txt and rename A.BIN to B.BIN.
+    fixture.cluster_mut(5)[88..90].copy_from_slice(b"xy");
+    fixture.entry(SUB, 2).set_name(b"B       BIN");
+    let new = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+
+    assert_eq!(tools::diff(&old, &old).unwrap(), vec![]);
+    assert_eq!(tools::diff(&old, &new).unwrap(), vec![
+        Change { path: "/SUB/A.BIN".into(), is_dir: false, kind: ChangeKind::Removed },
+        Change { path: "/SUB/B.BIN".into(), is_dir: false, kind: ChangeKind::Added },
+        Change { path: "/long.txt".into(), is_dir: false, kind: ChangeKind::Modified(vec![600..602]) },
+    ]);
+
+    let host = ::std::env::temp_dir().join(format!("fat32-diff-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_dir_all(&host);
+    ::std::fs::create_dir_all(host.join("sub")).unwrap();
+    ::std::fs::write(host.join("long.txt"), vec![0; 1001]).unwrap();
+    ::std::fs::write(host.join("sub/a.bin"), vec![0; 10]).unwrap();
+    let changes = tools::diff_host(&old, &host).unwrap();
+    ::std::fs::remove_dir_all(&host).unwrap();
+    assert_eq!(changes, vec![
+        Change { path: "/long.txt".into(), is_dir: false, kind: ChangeKind::Modified(vec![1000..1001]) },
+    ]);
+}
+
+#[test]
+fn test_wipe_deleted() {
+    use tools;
+    use vfat::PartitionReader;
+
+    let mut fixture = Fixture::mini();
+    fixture.entry(ROOT, 3).set_name(b"\xE5ELETED TXT").set_size(42);
+    // Stale entries past the end of the directory.
+    fixture.entry(ROOT, 4).bytes()[1] = 1;
+    fixture.entry(ROOT, 5).set_name(b"STALE   TXT");
+    // Data left in free cluster 10.
+    for byte in fixture.cluster_mut(10).iter_mut() {
+        *byte = 0xAB;
+    }
+    let image = fixture.into_image();
+    let original = image.clone();
+    let vfat = VFat::from(Cursor::new(image)).expect("valid image");
+    let names = |vfat: &Shared<VFat>| -> Vec<String> {
+        vfat.open_dir("/").unwrap().entries().unwrap().map(|e| e.name().to_string()).collect()
+    };
+    let before = names(&vfat);
+
+    let wiped = tools::wipe_deleted(&vfat, 2).unwrap();
+    assert_eq!(wiped.entries, 3);
+    assert_eq!(wiped.clusters, vfat.borrow_mut().usage().unwrap().free);
+    assert_eq!(names(&vfat), before);
+
+    let mut data = Vec::new();
+    PartitionReader::new(vfat.clone()).read_to_end(&mut data).unwrap();
+    assert_eq!(&data[2 * 512..2 * 512 + 96], &original[3 * 512..3 * 512 + 96]);
+    assert_eq!(data[2 * 512 + 96], 0xE5);
+    assert!(data[2 * 512 + 97..3 * 512].iter().all(|&b| b == 0));
+    assert!(data[10 * 512..11 * 512].iter().all(|&b| b == 0));
+}
+
+
+#[test]
+#[cfg(feature = "async")]
+fn test_async_volume() {
+    use std::io::{ErrorKind, SeekFrom};
+    use traits::async_traits::{block_on, Job, ThreadSpawner};
+    use vfat::async_fs::AsyncVolume;
+
+    let mut fixture = Fixture::mini();
+    for (i, byte) in fixture.data_mut(4, 1000).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let vfat = VFat::from(Cursor::new(fixture.into_image())).expect("valid image");
+    let volume = AsyncVolume::new(vfat.clone(), ThreadSpawner);
+
+    let data = block_on(volume.read("/long.txt")).expect("long.txt is read");
+    assert!(data.len() == 1000 && data.iter().enumerate().all(|(i, &b)| b == i as u8));
+    let names: Vec<_> = block_on(volume.read_dir("/")).expect("root is listed").iter()
+        .map(|entry| entry.file_name().to_string())
+        .collect();
+    assert_eq!(names, ["long.txt", "SUB"]);
+    assert!(block_on(volume.metadata("/SUB")).expect("SUB exists").is_dir());
+    let e = block_on(volume.read("/missing")).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    let file = block_on(volume.open_file("/long.txt")).expect("long.txt opens");
+    assert_eq!(file.size(), 1000);
+    assert_eq!(block_on(file.seek(SeekFrom::Start(990))).unwrap(), 990);
+    assert_eq!(block_on(file.read(100)).unwrap(), &data[990..]);
+    assert!(block_on(file.read(100)).unwrap().is_empty());
+
+    // A job the spawner drops, as a runtime shutting down may, fails its
+    // task instead of leaving it pending forever.
+    let volume = AsyncVolume::new(vfat, |job: Job| drop(job));
+    assert_eq!(block_on(volume.read("/long.txt")).unwrap_err().kind(), ErrorKind::Other);
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn test_async_block_device() {
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use device::MemoryDevice;
+    use traits::async_traits::{block_on, AsyncBlockDevice, BlockOn, BoxFuture, ThreadSpawner};
+    use vfat::async_fs::AsyncVolume;
+
+    /// Is pending once, and woken from another thread, before running `op`.
+    struct Later<F> {
+        op: Option<F>,
+        yielded: bool,
+    }
+
+    impl<T, F: FnOnce() -> T + Unpin> Future for Later<F> {
+        type Output = T;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+            if !self.yielded {
+                self.yielded = true;
+                let waker = cx.waker().clone();
+                thread::spawn(move || waker.wake());
+                return Poll::Pending;
+            }
+            Poll::Ready((self.op.take().expect("polled after completion"))())
+        }
+    }
+
+    struct Remote(MemoryDevice);
+
+    impl AsyncBlockDevice for Remote {
+        fn read_sector<'a>(&'a mut self, n: u64, buf: &'a mut [u8])
+            -> BoxFuture<'a, io::Result<usize>>
+        {
+            let device = &mut self.0;
+            Box::pin(Later { op: Some(move || device.read_sector(n, buf)), yielded: false })
+        }
+
+        fn write_sector<'a>(&'a mut self, n: u64, buf: &'a [u8])
+            -> BoxFuture<'a, io::Result<usize>>
+        {
+            let device = &mut self.0;
+            Box::pin(Later { op: Some(move || device.write_sector(n, buf)), yielded: false })
+        }
+    }
+
+    let device = BlockOn(Remote(MemoryDevice::from_vec(Fixture::mini().into_image())));
+    let vfat = VFat::from(device).expect("mounts over the async device");
+    let volume = AsyncVolume::new(vfat, ThreadSpawner);
+    let sub: Vec<_> = block_on(volume.read_dir("/SUB")).expect("SUB is listed").iter()
+        .map(|entry| entry.file_name().to_string())
+        .collect();
+    assert_eq!(sub, ["A.BIN"]);
+    assert_eq!(block_on(volume.read("/SUB/A.BIN")).expect("A.BIN is read"), [0; 10]);
+}
@@ -0,0 +1,660 @@
+use std::cmp::min;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use device::ImageFile;
+use mbr::{CHS, MasterBootRecord, PartitionEntry, BOOTSTRAP_SIZE};
+use traits::{BlockDevice, Entropy, XorShift};
+use util::{self, put_le_u16, put_le_u32};
+use vfat::{Attributes, BiosParameterBlock, Metadata, Timestamp, BOOT_CODE_SIZE};
+
+const SECTOR_SIZE: u64 = 512;
+/// The first sector of the partition, aligning it to 1MiB.
+const PARTITION_START: u32 = 2048;
+const RESERVED_SECTORS: u16 = 32;
+const NUM_FATS: u32 = 2;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOT_CLUSTER: u32 = 2;
+/// Fewer clusters than this make a FAT16 volume, whatever the BPB says.
+const MIN_CLUSTERS: u32 = 65525;
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const ENTRY_SIZE: usize = 32;
+const LFN_CHARS: usize = 13;
+
+/// Builds a partitioned disk image holding a FAT32 file system with the
+/// contents of a directory on the host, ready to be written to an SD card.
+///
+/// The image has a master boot record with a single FAT32 (LBA) partition
+/// starting at 1MiB and spanning the rest of the image. Files are laid out
+/// contiguously, and modification times and the read-only attribute are
+/// carried over from the host.
+///
+/// ```no_run
+/// use fat32::tools::ImageBuilder;
+///
+/// ImageBuilder::new(64 << 20).label("BOOT").build_file("boot/", "sd.img").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ImageBuilder {
+    size: u64,
+    label: [u8; 11],
+    sectors_per_cluster: Option<u8>,
+    volume_id: u32,
+    disk_id: u32,
+    bootstrap: Vec<u8>,
+    boot_code: Vec<u8>,
+}
+
+/// A file or directory to be written to the image.
+#[derive(Debug)]
+struct Node {
+    host_path: PathBuf,
+    name: String,
+    short_name: [u8; 11],
+    // Whether the name needs LFN entries.
+    long: bool,
+    metadata: Metadata,
+    children: Vec<Node>,
+    first_cluster: u32,
+    num_clusters: u32,
+}
+
+/// The layout of the file system within the partition.
+#[derive(Debug, Copy, Clone)]
+struct Geometry {
+    partition_sectors: u32,
+    sectors_per_cluster: u8,
+    sectors_per_fat: u32,
+    num_clusters: u32,
+}
+
+impl Geometry {
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE as usize
+    }
+
+    /// The first sector of cluster `cluster`, relative to the partition.
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        RESERVED_SECTORS as u64 + NUM_FATS as u64 * self.sectors_per_fat as u64
+            + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+}
+
+impl ImageBuilder {
+    /// Returns a builder for an image of `size` bytes. The image must be
+    /// large enough for a FAT32 volume: about 33MiB with the default cluster
+    /// size.
+    pub fn new(size: u64) -> ImageBuilder {
+        let mut entropy = XorShift::from_clock();
+        ImageBuilder {
+            size: size,
+            label: *b"NO NAME    ",
+            sectors_per_cluster: None,
+            volume_id: entropy.next_u32(),
+            disk_id: entropy.next_u32(),
+            bootstrap: Vec::new(),
+            boot_code: Vec::new(),
+        }
+    }
+
+    /// Sets the volume label. Labels are upper-cased and truncated to 11
+    /// bytes.
+    pub fn label(mut self, label: &str) -> ImageBuilder {
+        self.label = *b"           ";
+        for (byte, c) in self.label.iter_mut().zip(label.bytes()) {
+            *byte = c.to_ascii_uppercase();
+        }
+        self
+    }
+
+    /// Sets the number of sectors per cluster, which must be a power of two
+    /// no greater than 128. By default, it is chosen from the size of the
+    /// image as Windows does.
+    pub fn sectors_per_cluster(mut self, sectors: u8) -> ImageBuilder {
+        self.sectors_per_cluster = Some(sectors);
+        self
+    }
+
+    /// Sets the volume serial number. By default, it is random, seeded from
+    /// the current time.
+    pub fn volume_id(mut self, id: u32) -> ImageBuilder {
+        self.volume_id = id;
+        self
+    }
+
+    /// Sets the disk signature in the MBR. By default, it is random, seeded
+    /// from the current time.
+    pub fn disk_id(mut self, id: u32) -> ImageBuilder {
+        self.disk_id = id;
+        self
+    }
+
+    /// Draws the volume serial number and disk signature from `entropy`.
+    pub fn ids_from<E: Entropy>(mut self, entropy: &mut E) -> ImageBuilder {
+        self.volume_id = entropy.next_u32();
+        self.disk_id = entropy.next_u32();
+        self
+    }
+
+    /// Sets the code in the bootstrap area of the MBR, at most
+    /// `mbr::BOOTSTRAP_SIZE` bytes. By default, the area is zeroed.
+    pub fn bootstrap(mut self, code: &[u8]) -> ImageBuilder {
+        self.bootstrap = code.to_vec();
+        self
+    }
+
+    /// Sets the boot code of the volume's boot sector, at most
+    /// `vfat::BOOT_CODE_SIZE` bytes, run by the jump at the start of the
+    /// sector. By default, the boot code is zeroed.
+    pub fn boot_code(mut self, code: &[u8]) -> ImageBuilder {
+        self.boot_code = code.to_vec();
+        self
+    }
+
+    /// Creates a new image file at `image` containing the files in the host
+    /// directory `source`. Only sectors holding data are written, so on file
+    /// systems that support sparse files the image takes little space.
+    pub fn build_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, source: P, image: Q)
+        -> io::Result<()>
+    {
+        let device = ImageFile::create(image.as_ref(), self.size)?;
+        self.build(source, device)
+    }
+
+    /// Writes an image containing the files in the host directory `source` to
+    /// `device`, which must have 512 byte sectors and hold at least the size
+    /// of the image. Data clusters not used by the files are not written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the image is too small or too
+    /// large for FAT32, if the cluster size is invalid, if the contents of
+    /// `source` don't fit in the image, if a name in `source` is not valid
+    /// UTF-8 or is longer than 255 characters, if two names in a directory
+    /// differ only in case, if an entry in `source` is neither a regular
+    /// file nor a directory once symbolic links are followed, or if the
+    /// bootstrap or boot code is too long.
+    pub fn build<P: AsRef<Path>, D: BlockDevice>(&self, source: P, mut device: D)
+        -> io::Result<()>
+    {
+        if device.sector_size() != SECTOR_SIZE {
+            return Err(util::invalid_input("device must have 512 byte sectors"));
+        }
+        if self.bootstrap.len() > BOOTSTRAP_SIZE || self.boot_code.len() > BOOT_CODE_SIZE {
+            return Err(util::invalid_input("boot code is too long"));
+        }
+        let geometry = self.geometry()?;
+
+        let mut root = Node {
+            host_path: source.as_ref().to_path_buf(),
+            name: String::new(),
+            short_name: [0; 11],
+            long: false,
+            metadata: Metadata::default(),
+            children: scan_dir(source.as_ref())?,
+            first_cluster: 0,
+            num_clusters: 0,
+        };
+        let mut next_cluster = ROOT_CLUSTER;
+        allocate(&mut root, true, geometry.cluster_bytes(), &mut next_cluster);
+        if next_cluster - 2 > geometry.num_clusters {
+            return Err(util::invalid_input("the files don't fit in the image"));
+        }
+
+        let mut writer = Writer { device: &mut device, geometry: geometry };
+        writer.write_mbr(self.disk_id, &self.bootstrap)?;
+        writer.write_boot_sectors(self, next_cluster)?;
+        writer.write_fats(&root)?;
+        writer.write_dir(&root, 0, Some(&self.label))
+    }
+
+    fn geometry(&self) -> io::Result<Geometry> {
+        let total_sectors = self.size / SECTOR_SIZE;
+        if total_sectors > u32::max_value() as u64 {
+            return Err(util::invalid_input("image is too large for an MBR partition"));
+        }
+        let partition_sectors = (total_sectors as u32).saturating_sub(PARTITION_START);
+
+        let sectors_per_cluster = match self.sectors_per_cluster {
+            Some(n) if n.is_power_of_two() && n <= 128 => n,
+            Some(_) => return Err(util::invalid_input("invalid number of sectors per cluster")),
+            None => match self.size {
+                size if size <= 260 << 20 => 1,
+                size if size <= 8 << 30 => 8,
+                size if size <= 16 << 30 => 16,
+                size if size <= 32 << 30 => 32,
+                _ => 64,
+            },
+        };
+
+        // The FAT's size depends on the number of clusters, which depends on
+        // the FAT's size. Growing the FAT until it covers every cluster left
+        // settles within a few iterations.
+        let available = partition_sectors.saturating_sub(RESERVED_SECTORS as u32) as u64;
+        let mut sectors_per_fat = 1;
+        let num_clusters = loop {
+            let data_sectors = available.saturating_sub(NUM_FATS as u64 * sectors_per_fat);
+            let num_clusters = data_sectors / sectors_per_cluster as u64;
+            let needed = ((num_clusters + 2) * 4 + SECTOR_SIZE - 1) / SECTOR_SIZE;
+            if needed <= sectors_per_fat {
+                break num_clusters;
+            }
+            sectors_per_fat = needed;
+        };
+
+        if num_clusters < MIN_CLUSTERS as u64 || num_clusters > 0x0FFF_FFF5 {
+            return Err(util::invalid_input("image size is out of range for FAT32"));
+        }
+        Ok(Geometry {
+            partition_sectors: partition_sectors,
+            sectors_per_cluster: sectors_per_cluster,
+            sectors_per_fat: sectors_per_fat as u32,
+            num_clusters: num_clusters as u32,
+        })
+    }
+}
+
+/// Reads the entries of the directory at `path` on the host, and everything
+/// below them. Symbolic links are followed.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` for an entry that is neither a regular
+/// file nor a directory, such as a FIFO or a socket, and for names that are
+/// equal ignoring case, which FAT can't tell apart.
+fn scan_dir(path: &Path) -> io::Result<Vec<Node>> {
+    let mut children = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string()
+            .map_err(|_| util::invalid_input("file name is not valid UTF-8"))?;
+        if name.encode_utf16().count() > 255 {
+            return Err(util::invalid_input(format!("file name {} is too long", name)));
+        }
+
+        let host = fs::metadata(entry.path())?;
+        let is_dir = host.is_dir();
+        if !is_dir && !host.is_file() {
+            return Err(util::invalid_input(format!(
+                "{} is neither a regular file nor a directory", entry.path().display())));
+        }
+        if !is_dir && host.len() > u32::max_value() as u64 {
+            return Err(util::invalid_input(format!("file {} is too large for FAT32", name)));
+        }
+
+        let mtime = host.modified().map(Timestamp::from).unwrap_or_default();
+        children.push(Node {
+            host_path: entry.path(),
+            name: name,
+            short_name: [0; 11],
+            long: false,
+            metadata: Metadata {
+                attr: Attributes::default().with_directory(is_dir)
+                                           .with_archive(!is_dir)
+                                           .with_read_only(host.permissions().readonly()),
+                ctime: host.created().map(Timestamp::from).unwrap_or(mtime),
+                atime: host.accessed().map(Timestamp::from).unwrap_or(mtime),
+                mtime: mtime,
+                size: if is_dir { 0 } else { host.len() as u32 },
+                ..Metadata::default()
+            },
+            children: if is_dir { scan_dir(&entry.path())? } else { vec![] },
+            first_cluster: 0,
+            num_clusters: 0,
+        });
+    }
+
+    let mut folded: Vec<(String, &str)> = children.iter()
+        .map(|child| (child.name.to_uppercase(), child.name.as_str()))
+        .collect();
+    folded.sort();
+    for pair in folded.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(util::invalid_input(format!(
+                "{} and {} in {} differ only in case", pair[0].1, pair[1].1, path.display())));
+        }
+    }
+
+    // Sort for reproducible images, then give every entry a unique 8.3 name.
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut taken: Vec<[u8; 11]> = vec![];
+    for child in children.iter_mut() {
+        let (short_name, long) = short_name(&child.name, &taken);
+        child.short_name = short_name;
+        child.long = long;
+        taken.push(short_name);
+    }
+    Ok(children)
+}
+
+/// Returns whether `c` may appear in a short name.
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// Returns an 8.3 name for `name` that isn't in `taken`, and whether `name`
+/// needs LFN entries because it isn't that 8.3 name.
+fn short_name(name: &str, taken: &[[u8; 11]]) -> ([u8; 11], bool) {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+
+    // Converting to upper case loses nothing, as the LFN keeps the case;
+    // dropping or replacing characters does.
+    let mut lossy = false;
+    let mut clean = |s: &str| -> Vec<u8> {
+        let mut out = vec![];
+        for c in s.chars() {
+            match c.to_ascii_uppercase() {
+                ' ' | '.' => lossy = true,
+                c if is_short_char(c) => out.push(c as u8),
+                _ => {
+                    lossy = true;
+                    out.push(b'_');
+                }
+            }
+        }
+        out
+    };
+    let base = clean(base);
+    let ext = clean(ext);
+    lossy |= base.is_empty() || base.len() > 8 || ext.len() > 3 || name.ends_with('.');
+
+    let mut short = *b"           ";
+    short[8..8 + min(ext.len(), 3)].copy_from_slice(&ext[..min(ext.len(), 3)]);
+    if !lossy {
+        short[..base.len()].copy_from_slice(&base);
+        if !taken.contains(&short) {
+            // The whole name, extension included, must match for the case to
+            // survive without an LFN.
+            let mut exact = base.clone();
+            if !ext.is_empty() {
+                exact.push(b'.');
+                exact.extend_from_slice(&ext);
+            }
+            return (short, exact != name.as_bytes());
+        }
+    }
+
+    // Number the basis name, as in `LONGNA~1.TXT`.
+    for n in 1.. {
+        let suffix = format!("~{}", n);
+        let keep = min(base.len(), 8 - suffix.len());
+        short[..8].copy_from_slice(b"        ");
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + suffix.len()].copy_from_slice(suffix.as_bytes());
+        if !taken.contains(&short) {
+            break;
+        }
+    }
+    (short, true)
+}
+
+/// Returns the number of directory entries `node` takes up in its parent.
+fn num_entries(node: &Node) -> usize {
+    if node.long {
+        1 + (node.name.encode_utf16().count() + LFN_CHARS - 1) / LFN_CHARS
+    } else {
+        1
+    }
+}
+
+/// Assigns contiguous clusters to `dir` and everything below it, starting
+/// at `next`.
+fn allocate(dir: &mut Node, is_root: bool, cluster_bytes: usize, next: &mut u32) {
+    // The root holds the volume label; other directories `.` and `..`.
+    let extra = if is_root { 1 } else { 2 };
+    let entries = extra + dir.children.iter().map(num_entries).sum::<usize>();
+    let clusters = |bytes: usize| ((bytes + cluster_bytes - 1) / cluster_bytes) as u32;
+
+    dir.first_cluster = *next;
+    dir.num_clusters = ::std::cmp::max(clusters(entries * ENTRY_SIZE), 1);
+    *next += dir.num_clusters;
+
+    for child in dir.children.iter_mut().filter(|c| !c.metadata.attr.directory()) {
+        child.num_clusters = clusters(child.metadata.size as usize);
+        if child.num_clusters != 0 {
+            child.first_cluster = *next;
+            *next += child.num_clusters;
+        }
+    }
+    for child in dir.children.iter_mut().filter(|c| c.metadata.attr.directory()) {
+        allocate(child, false, cluster_bytes, next);
+    }
+}
+
+/// Encodes a short directory entry for `name` into `buf`.
+fn encode_entry(buf: &mut [u8], name: &[u8; 11], metadata: &Metadata, cluster: u32) {
+    fn date(ts: &Timestamp) -> u16 {
+        let date = ts.date;
+        ((date.year().saturating_sub(1980) as u16) << 9)
+            | (date.month() as u16) << 5 | date.day() as u16
+    }
+
+    buf[..11].copy_from_slice(name);
+    buf[11] = metadata.attr.0;
+    buf[13] = metadata.ctime.hundredths;
+    put_le_u16(buf, 14, metadata.ctime.time.0);
+    put_le_u16(buf, 16, date(&metadata.ctime));
+    put_le_u16(buf, 18, date(&metadata.atime));
+    put_le_u16(buf, 20, (cluster >> 16) as u16);
+    put_le_u16(buf, 22, metadata.mtime.time.0);
+    put_le_u16(buf, 24, date(&metadata.mtime));
+    put_le_u16(buf, 26, cluster as u16);
+    put_le_u32(buf, 28, metadata.size);
+}
+
+/// Encodes the LFN entries for `name`, belonging to the short entry named
+/// `short_name`, into `buf`.
+fn encode_lfn(buf: &mut [u8], name: &str, short_name: &[u8; 11]) {
+    let checksum = short_name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = (chars.len() + LFN_CHARS - 1) / LFN_CHARS;
+    // The name is terminated by a NUL if it doesn't fill the last entry,
+    // which is then padded with 0xFFFF.
+    if chars.len() % LFN_CHARS != 0 {
+        chars.push(0);
+    }
+    chars.resize(count * LFN_CHARS, 0xFFFF);
+
+    for (i, entry) in buf.chunks_mut(ENTRY_SIZE).take(count).enumerate() {
+        let seq = count - i;
+        let part = &chars[(seq - 1) * LFN_CHARS..seq * LFN_CHARS];
+        entry[0] = seq as u8 | if i == 0 { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        let offsets = (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2));
+        for (offset, &c) in offsets.zip(part.iter()) {
+            put_le_u16(entry, offset, c);
+        }
+    }
+}
+
+struct Writer<'a, D: BlockDevice + 'a> {
+    device: &'a mut D,
+    geometry: Geometry,
+}
+
+impl<'a, D: BlockDevice> Writer<'a, D> {
+    /// Writes `data`, padded with zeroes to a whole number of sectors, to the
+    /// partition starting at sector `sector`.
+    fn write(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        let mut buf = [0u8; SECTOR_SIZE as usize];
+        for (i, chunk) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+            buf[..chunk.len()].copy_from_slice(chunk);
+            for byte in buf[chunk.len()..].iter_mut() {
+                *byte = 0;
+            }
+            self.device.write_sector(PARTITION_START as u64 + sector + i as u64, &buf)?;
+        }
+        Ok(())
+    }
+
+    fn write_mbr(&mut self, disk_id: u32, bootstrap: &[u8]) -> io::Result<()> {
+        // CHS addresses are meaningless on disks this size; LBA is used.
+        let lba_only = CHS::decode(&[0xFE, 0xFF, 0xFF]);
+        let mut mbr = MasterBootRecord::decode(&[0; 512]);
+        mbr.partition_table[0] = PartitionEntry {
+            boot_indicator: 0x80,
+            start_chs: lba_only,
+            partition_type: 0x0C,
+            end_chs: lba_only,
+            relative_sector: PARTITION_START,
+            total_sectors: self.geometry.partition_sectors,
+        };
+        mbr.set_disk_signature(disk_id);
+        mbr.set_bootstrap(bootstrap)?;
+        mbr.signature = [0x55, 0xAA];
+        self.device.write_sector(0, &mbr.encode())?;
+        Ok(())
+    }
+
+    fn write_boot_sectors(&mut self, builder: &ImageBuilder, next_cluster: u32) -> io::Result<()> {
+        let geometry = self.geometry;
+        let mut bpb = BiosParameterBlock::decode(&[0; 512]);
+        bpb.jump_short_nop = [0xEB, 0x58, 0x90];
+        bpb.oem_id = *b"MSWIN4.1";
+        bpb.bytes_per_sector = SECTOR_SIZE as u16;
+        bpb.sectors_per_cluster = geometry.sectors_per_cluster;
+        bpb.num_reserved_sectors = RESERVED_SECTORS;
+        bpb.num_fat = NUM_FATS as u8;
+        bpb.media_desc_type = 0xF8;
+        bpb.sectors_per_track = 63;
+        bpb.num_heads = 255;
+        bpb.num_hidden_sectors = PARTITION_START;
+        bpb.total_logical_sectors_32 = geometry.partition_sectors;
+        bpb.sectors_per_fat_32 = geometry.sectors_per_fat;
+        bpb.root_cluster = ROOT_CLUSTER;
+        bpb.fsinfo_sector = FSINFO_SECTOR;
+        bpb.backup_boot_sector = BACKUP_BOOT_SECTOR;
+        bpb.drive_num = 0x80;
+        bpb.signature = 0x29;
+        bpb.volumn_id = builder.volume_id;
+        bpb.volumn_label = builder.label;
+        bpb.sys_id_str = *b"FAT32   ";
+        bpb.set_boot_code(&builder.boot_code)?;
+        bpb.bootable_signature = 0xAA55;
+        let boot_sector = bpb.encode();
+
+        let mut fsinfo = [0u8; 512];
+        put_le_u32(&mut fsinfo, 0, 0x4161_5252);
+        put_le_u32(&mut fsinfo, 484, 0x6141_7272);
+        put_le_u32(&mut fsinfo, 488, geometry.num_clusters - (next_cluster - 2));
+        put_le_u32(&mut fsinfo, 492, next_cluster);
+        put_le_u32(&mut fsinfo, 508, 0xAA55_0000);
+
+        for &start in [0, BACKUP_BOOT_SECTOR as u64].iter() {
+            self.write(start, &boot_sector)?;
+            self.write(start + FSINFO_SECTOR as u64, &fsinfo)?;
+        }
+        Ok(())
+    }
+
+    fn write_fats(&mut self, root: &Node) -> io::Result<()> {
+        // Every chain is contiguous, so only where each one ends is needed.
+        fn chain_ends(node: &Node, ends: &mut Vec<u32>) {
+            if node.num_clusters != 0 {
+                ends.push(node.first_cluster + node.num_clusters - 1);
+            }
+            for child in node.children.iter() {
+                chain_ends(child, ends);
+            }
+        }
+        let mut ends = vec![];
+        chain_ends(root, &mut ends);
+        ends.sort();
+        let last = *ends.last().expect("the root directory is allocated");
+
+        let entries_per_sector = SECTOR_SIZE as u32 / 4;
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        for i in 0..self.geometry.sectors_per_fat {
+            for j in 0..entries_per_sector {
+                let cluster = i * entries_per_sector + j;
+                let entry = match cluster {
+                    0 => 0x0FFF_FFF8,
+                    1 => END_OF_CHAIN,
+                    c if c > last => 0,
+                    c if ends.binary_search(&c).is_ok() => END_OF_CHAIN,
+                    c => c + 1,
+                };
+                put_le_u32(&mut sector, j as usize * 4, entry);
+            }
+            for fat in 0..NUM_FATS {
+                let start = RESERVED_SECTORS as u64 + (fat * self.geometry.sectors_per_fat) as u64;
+                self.write(start + i as u64, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the directory `dir`, whose parent starts at `parent_cluster`,
+    /// and everything below it. Only the root directory has a `label`.
+    fn write_dir(&mut self, dir: &Node, parent_cluster: u32, label: Option<&[u8; 11]>)
+        -> io::Result<()>
+    {
+        let mut buf = vec![0u8; dir.num_clusters as usize * self.geometry.cluster_bytes()];
+        let mut entries = buf.chunks_mut(ENTRY_SIZE);
+        let dir_metadata = Metadata {
+            attr: Attributes::default().with_directory(true),
+            ..dir.metadata.clone()
+        };
+        match label {
+            Some(label) => {
+                let metadata = Metadata { attr: Attributes(0x08), ..Metadata::default() };
+                encode_entry(entries.next().unwrap(), label, &metadata, 0);
+            }
+            None => {
+                encode_entry(entries.next().unwrap(), b".          ", &dir_metadata,
+                             dir.first_cluster);
+                encode_entry(entries.next().unwrap(), b"..         ", &dir_metadata,
+                             parent_cluster);
+            }
+        }
+        for child in dir.children.iter() {
+            if child.long {
+                let lfn_entries = num_entries(child) - 1;
+                let mut lfn = vec![0u8; lfn_entries * ENTRY_SIZE];
+                encode_lfn(&mut lfn, &child.name, &child.short_name);
+                for chunk in lfn.chunks(ENTRY_SIZE) {
+                    entries.next().unwrap().copy_from_slice(chunk);
+                }
+            }
+            encode_entry(entries.next().unwrap(), &child.short_name, &child.metadata,
+                         child.first_cluster);
+        }
+        let sector = self.geometry.cluster_sector(dir.first_cluster);
+        self.write(sector, &buf)?;
+
+        for child in dir.children.iter() {
+            if child.metadata.attr.directory() {
+                // `..` entries refer to the root directory as cluster 0.
+                let parent = if label.is_some() { 0 } else { dir.first_cluster };
+                self.write_dir(child, parent, None)?;
+            } else if child.num_clusters != 0 {
+                self.write_file(child)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_file(&mut self, file: &Node) -> io::Result<()> {
+        let mut host_file = fs::File::open(&file.host_path)?;
+        let mut sector = self.geometry.cluster_sector(file.first_cluster);
+        let mut remaining = file.metadata.size as usize;
+        let mut buf = vec![0u8; self.geometry.cluster_bytes()];
+        while remaining > 0 {
+            let len = min(remaining, buf.len());
+            host_file.read_exact(&mut buf[..len]).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => util::eof("file shrank while being copied"),
+                _ => e,
+            })?;
+            self.write(sector, &buf[..len])?;
+            sector += self.geometry.sectors_per_cluster as u64;
+            remaining -= len;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use traits::{self, BlockDevice, FileSystem, RawLock};
+use vfat::{Entry, File, Shared, VFat};
+
+/// How many bytes of each file are compared at a time.
+const CHUNK_BYTES: usize = 64 << 10;
+
+/// A difference between two trees, as returned by `diff()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The absolute path of the file or directory, as named in the new tree
+    /// unless it was removed.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub kind: ChangeKind,
+}
+
+/// What changed about a file or directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Only in the new tree.
+    Added,
+    /// Only in the old tree.
+    Removed,
+    /// A file in both trees whose contents differ in the given byte ranges,
+    /// in ascending order. A file that grew or shrank differs from the end
+    /// of the shorter version on.
+    Modified(Vec<Range<u64>>),
+}
+
+/// Compares the tree of `old` with that of `new` and returns every file and
+/// directory added, removed, or modified, in order of path. Names are
+/// compared case-insensitively, as FAT does.
+///
+/// An added or removed directory is reported once, without its contents. A
+/// file replaced by a directory of the same name, or the other way around, is
+/// reported as removed and added.
+///
+/// Metadata such as timestamps and attributes isn't compared, so rebuilding
+/// an image from the same files reports no changes.
+///
+/// ```rust,no_run
+/// # fn main() -> std::io::Result<()> {
+/// use fat32::device::ImageFile;
+/// use fat32::tools::{diff, ChangeKind};
+/// use fat32::vfat::VFat;
+///
+/// let old = VFat::from(ImageFile::open_read_only("old.img")?)?;
+/// let new = VFat::from(ImageFile::open_read_only("new.img")?)?;
+/// for change in diff(&old, &new)? {
+///     match change.kind {
+///         ChangeKind::Added => println!("+ {}", change.path.display()),
+///         ChangeKind::Removed => println!("- {}", change.path.display()),
+///         ChangeKind::Modified(ranges) => {
+///             println!("M {} {:?}", change.path.display(), ranges)
+///         }
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Errors reading either image are returned immediately.
+pub fn diff<D, L, E, M>(old: &Shared<VFat<D, L>, L>, new: &Shared<VFat<E, M>, M>)
+    -> io::Result<Vec<Change>>
+    where D: BlockDevice, L: RawLock, E: BlockDevice, M: RawLock
+{
+    let mut changes = Vec::new();
+    diff_dir(&old, &new, Path::new("/"), &mut changes)?;
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Compares the tree of `vfat`, as the old tree, with the host directory
+/// `host`, as the new one, like `diff()`. This checks that an image holds
+/// exactly the files it was built from.
+///
+/// Host entries that are neither files nor directories are ignored, and
+/// host names that aren't valid Unicode are compared in their lossy form.
+///
+/// # Errors
+///
+/// Errors reading the image or the host directory are returned immediately.
+pub fn diff_host<D, L, P>(vfat: &Shared<VFat<D, L>, L>, host: P) -> io::Result<Vec<Change>>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
+    let mut changes = Vec::new();
+    diff_dir(&vfat, &Host(host.as_ref()), Path::new("/"), &mut changes)?;
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// A tree of files and directories to compare.
+trait Tree {
+    type File: Read;
+
+    /// Returns the name of every entry of the directory at `dir`, and whether
+    /// it is a directory, keyed by its lower-cased name.
+    fn list(&self, dir: &Path) -> io::Result<BTreeMap<String, (String, bool)>>;
+
+    fn open(&self, file: &Path) -> io::Result<Self::File>;
+}
+
+impl<'a, D: BlockDevice, L: RawLock> Tree for &'a Shared<VFat<D, L>, L> {
+    type File = File<D, L>;
+
+    fn list(&self, dir: &Path) -> io::Result<BTreeMap<String, (String, bool)>> {
+        let mut entries = BTreeMap::new();
+        for entry in traits::Dir::entries(&self.open_dir(dir)?)? {
+            let name = traits::Entry::name(&entry).to_string();
+            if name == "." || name == ".." || traits::Entry::metadata(&entry).attr.volume_id() {
+                continue;
+            }
+            let is_dir = match entry {
+                Entry::Dir(_) => true,
+                Entry::File(_) => false,
+            };
+            entries.insert(name.to_lowercase(), (name, is_dir));
+        }
+        Ok(entries)
+    }
+
+    fn open(&self, file: &Path) -> io::Result<File<D, L>> {
+        self.open_file(file)
+    }
+}
+
+/// A directory on the host, whose contents are named as if it were the root
+/// of an image.
+struct Host<'a>(&'a Path);
+
+impl<'a> Host<'a> {
+    fn host_path(&self, path: &Path) -> PathBuf {
+        self.0.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+impl<'a> Tree for Host<'a> {
+    type File = fs::File;
+
+    fn list(&self, dir: &Path) -> io::Result<BTreeMap<String, (String, bool)>> {
+        let mut entries = BTreeMap::new();
+        for entry in fs::read_dir(self.host_path(dir))? {
+            let entry = entry?;
+            let file_type = fs::metadata(entry.path())?.file_type();
+            if !file_type.is_file() && !file_type.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.insert(name.to_lowercase(), (name, file_type.is_dir()));
+        }
+        Ok(entries)
+    }
+
+    fn open(&self, file: &Path) -> io::Result<fs::File> {
+        fs::File::open(self.host_path(file))
+    }
+}
+
+fn diff_dir<A: Tree, B: Tree>(old: &A, new: &B, dir: &Path, changes: &mut Vec<Change>)
+    -> io::Result<()>
+{
+    let old_entries = old.list(dir)?;
+    let mut new_entries = new.list(dir)?;
+    for (key, (old_name, old_is_dir)) in old_entries {
+        let old_path = dir.join(&old_name);
+        let (new_name, new_is_dir) = match new_entries.remove(&key) {
+            Some(entry) => entry,
+            None => {
+                changes.push(Change { path: old_path, is_dir: old_is_dir,
+                                      kind: ChangeKind::Removed });
+                continue;
+            }
+        };
+
+        let path = dir.join(&new_name);
+        if old_is_dir != new_is_dir {
+            changes.push(Change { path: old_path, is_dir: old_is_dir,
+                                  kind: ChangeKind::Removed });
+            changes.push(Change { path: path, is_dir: new_is_dir, kind: ChangeKind::Added });
+        } else if new_is_dir {
+            diff_dir(old, new, &path, changes)?;
+        } else {
+            let ranges = diff_files(old.open(&old_path)?, new.open(&path)?)?;
+            if !ranges.is_empty() {
+                changes.push(Change { path: path, is_dir: false,
+                                      kind: ChangeKind::Modified(ranges) });
+            }
+        }
+    }
+    for (_, (name, is_dir)) in new_entries {
+        changes.push(Change { path: dir.join(&name), is_dir: is_dir, kind: ChangeKind::Added });
+    }
+    Ok(())
+}
+
+/// Returns the byte ranges in which the contents of `old` and `new` differ.
+fn diff_files<A: Read, B: Read>(mut old: A, mut new: B) -> io::Result<Vec<Range<u64>>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    let (mut old_buf, mut new_buf) = (vec![0; CHUNK_BYTES], vec![0; CHUNK_BYTES]);
+    let mut pos = 0u64;
+    loop {
+        let old_len = read_full(&mut old, &mut old_buf)?;
+        let new_len = read_full(&mut new, &mut new_buf)?;
+        let len = old_len.max(new_len);
+        if len == 0 {
+            return Ok(ranges);
+        }
+
+        for i in 0..len {
+            let same = i < old_len && i < new_len && old_buf[i] == new_buf[i];
+            if same {
+                continue;
+            }
+            let offset = pos + i as u64;
+            match ranges.last_mut() {
+                Some(range) if range.end == offset => range.end += 1,
+                _ => ranges.push(offset..offset + 1),
+            }
+        }
+        pos += len as u64;
+    }
+}
+
+/// Reads from `reader` until `buf` is full or the end is reached, and returns
+/// the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
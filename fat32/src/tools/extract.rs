@@ -0,0 +1,239 @@
+use std::cmp::{max, min};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+
+use traits::{self, BlockDevice, FileSystem, RawLock};
+use util;
+use vfat::{Cluster, Dir, Entry, File, Metadata, Shared, Timestamp, VFat, WithContext};
+
+/// The most bytes of a file read at once when reading on several threads.
+const WINDOW_BYTES: usize = 8 << 20;
+/// The most bytes of a file read at once otherwise.
+const COPY_BYTES: usize = 64 << 10;
+
+/// Totals of an extraction so far.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Extracted {
+    /// Number of regular files copied.
+    pub files: u64,
+    /// Number of directories created, including the starting directory.
+    pub dirs: u64,
+    /// Number of bytes of file data copied.
+    pub bytes: u64,
+}
+
+/// Copies the file or directory tree at `from` in `vfat` to the host path
+/// `to`. `from` must be absolute. Directories are created as needed; existing
+/// files are overwritten.
+///
+/// Modification and access times are preserved, and read-only files are made
+/// read-only on the host. On Windows, hidden entries are also made hidden.
+/// Timestamps that are invalid on disk are left at the time of extraction.
+///
+/// With the `read_threads` mount option, each file is read several clusters
+/// at a time on that many threads.
+///
+/// `progress` is invoked after every file is copied with the file's path in
+/// the image and the totals so far.
+///
+/// Cancelling the volume's `CancelToken` stops the extraction before the
+/// next file or, within a large file, the next window of clusters, leaving the
+/// file being copied incomplete.
+///
+/// # Errors
+///
+/// Returns the same errors as `FileSystem::open()` for `from`. Errors reading
+/// the image or writing to the host are returned immediately, leaving the
+/// files extracted so far in place.
+pub fn extract<D, L, P, Q, F>(vfat: &Shared<VFat<D, L>, L>, from: P, to: Q, mut progress: F)
+    -> io::Result<Extracted>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>, Q: AsRef<Path>, F: FnMut(&Path, &Extracted)
+{
+    let mut totals = Extracted::default();
+    match vfat.open(from.as_ref())? {
+        Entry::File(mut file) => {
+            extract_file(&mut file, to.as_ref(), &mut totals)?;
+            progress(from.as_ref(), &totals);
+        }
+        Entry::Dir(dir) => {
+            extract_dir(&dir, from.as_ref(), to.as_ref(), &mut Vec::new(), &mut totals,
+                        &mut progress)?;
+        }
+    }
+    Ok(totals)
+}
+
+fn extract_dir<D, L, F>(dir: &Dir<D, L>, from: &Path, to: &Path, ancestors: &mut Vec<Cluster>,
+                        totals: &mut Extracted, progress: &mut F) -> io::Result<()>
+    where D: BlockDevice, L: RawLock, F: FnMut(&Path, &Extracted)
+{
+    if ancestors.contains(&dir.first_cluster) {
+        return Err(util::corrupt("directory tree contains a cycle"));
+    }
+    ancestors.push(dir.first_cluster);
+    dir.vfat.borrow().cancel.check()?;
+
+    fs::create_dir_all(to)?;
+    totals.dirs += 1;
+    for entry in traits::Dir::entries(dir)? {
+        match entry {
+            Entry::File(mut file) => {
+                let name = host_name(file.name()).at_path(&file.path)?;
+                let (from, to) = (from.join(name), to.join(name));
+                extract_file(&mut file, &to, totals)?;
+                progress(&from, totals);
+            }
+            Entry::Dir(ref subdir) => {
+                if subdir.name() != "." && subdir.name() != ".." {
+                    let name = host_name(subdir.name()).at_path(&subdir.path)?;
+                    extract_dir(subdir, &from.join(name), &to.join(name),
+                                ancestors, totals, progress)?;
+                }
+            }
+        }
+    }
+
+    ancestors.pop();
+    // Creating the children updated the directory's times, so they are set
+    // last. Not every platform can set a directory's times; that's fine.
+    if let Ok(host_dir) = fs::File::open(to) {
+        let _ = set_times(&host_dir, &dir.metadata);
+    }
+    Ok(())
+}
+
+/// Returns `name` if it names a single entry of a host directory, so that
+/// joining it to the destination can't reach outside of it. Long file names
+/// on a damaged or hostile image may hold separators, NULs or `..`.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidData` for any other name.
+fn host_name(name: &str) -> io::Result<&str> {
+    let mut components = Path::new(name).components();
+    let single = match (components.next(), components.next()) {
+        (Some(Component::Normal(normal)), None) => normal == name,
+        _ => false,
+    };
+    if !single || name.contains(&['/', '\\', '\0'][..]) {
+        return Err(util::corrupt(format!("unsafe file name {:?}", name)));
+    }
+    Ok(name)
+}
+
+fn extract_file<D, L>(file: &mut File<D, L>, to: &Path, totals: &mut Extracted)
+    -> io::Result<()> where D: BlockDevice, L: RawLock
+{
+    file.vfat.borrow().cancel.check()?;
+    // A read-only file left by an earlier extraction can't be overwritten.
+    if let Ok(metadata) = fs::symlink_metadata(to) {
+        if metadata.permissions().readonly() {
+            fs::remove_file(to)?;
+        }
+    }
+
+    let mut host_file = create(to, &file.metadata)?;
+    totals.bytes += copy(file, &mut host_file)?;
+    totals.files += 1;
+
+    set_times(&host_file, &file.metadata)?;
+    if file.metadata.attr.read_only() {
+        let mut permissions = host_file.metadata()?.permissions();
+        permissions.set_readonly(true);
+        host_file.set_permissions(permissions)?;
+    }
+    Ok(())
+}
+
+/// Copies the contents of `file` to `host_file`, a window of clusters at a
+/// time when several threads may read it, checking for cancellation between
+/// reads.
+fn copy<D, L>(file: &mut File<D, L>, host_file: &mut fs::File) -> io::Result<u64>
+    where D: BlockDevice, L: RawLock
+{
+    file.refresh();
+    let threads = {
+        let vfat = file.vfat.borrow();
+        if vfat.options.lenient { 1 } else { vfat.options.read_threads }
+    };
+    if threads < 2 || file.size == 0 {
+        let mut buf = vec![0; COPY_BYTES];
+        let mut copied = 0;
+        loop {
+            file.vfat.borrow().cancel.check()?;
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                return Ok(copied);
+            }
+            host_file.write_all(&buf[..n])?;
+            copied += n as u64;
+        }
+    }
+
+    let mut vfat = file.vfat.borrow_mut();
+    let chain_len = vfat.chain_len(file.first_cluster).at_path(&file.name)?;
+    let bytes_per_cluster = vfat.bytes_per_cluster();
+    if (file.size as usize - 1) / bytes_per_cluster >= chain_len as usize {
+        return Err(util::corrupt("file size exceeds its cluster chain")).at_path(&file.name);
+    }
+
+    // Only a window of the chain is held at once, so that memory use doesn't
+    // grow with the size of the file.
+    let window_len = max(1, min(WINDOW_BYTES, vfat.options.max_chain_bytes) / bytes_per_cluster);
+    let mut left = file.size as usize;
+    let mut next = Some(file.first_cluster);
+    let mut window = Vec::with_capacity(window_len);
+    let mut buf = Vec::new();
+    while left > 0 {
+        vfat.cancel.check()?;
+        window.clear();
+        while window.len() * bytes_per_cluster < left && window.len() < window_len {
+            let cluster = next.ok_or_else(|| util::corrupt("file size exceeds its cluster chain"))
+                              .at_path(&file.name)?;
+            window.push(cluster);
+            next = vfat.next_cluster(cluster, false).at_path(&file.name)?;
+        }
+        buf.clear();
+        vfat.read_clusters(&window, &mut buf).at_path(&file.name)?;
+        let len = min(left, buf.len());
+        host_file.write_all(&buf[..len])?;
+        left -= len;
+    }
+    Ok(file.size as u64)
+}
+
+#[cfg(not(windows))]
+fn create(path: &Path, _metadata: &Metadata) -> io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(windows)]
+fn create(path: &Path, metadata: &Metadata) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    let attributes = if metadata.attr.hidden() { FILE_ATTRIBUTE_HIDDEN } else { 0 };
+    fs::OpenOptions::new().write(true).create(true).truncate(true)
+                          .attributes(attributes)
+                          .open(path)
+}
+
+/// Sets the modification and access times of `file` to those in `metadata`
+/// that are valid.
+fn set_times(file: &fs::File, metadata: &Metadata) -> io::Result<()> {
+    let valid = |ts: Timestamp| if ts.is_valid() && !ts.is_unset() {
+        Some(metadata.to_system_time(ts))
+    } else {
+        None
+    };
+
+    let mut times = fs::FileTimes::new();
+    if let Some(mtime) = valid(metadata.mtime) {
+        times = times.set_modified(mtime);
+    }
+    if let Some(atime) = valid(metadata.atime) {
+        times = times.set_accessed(atime);
+    }
+    file.set_times(times)
+}
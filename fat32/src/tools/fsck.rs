@@ -0,0 +1,491 @@
+//! A consistency checker for mounted volumes, reporting what it finds in a
+//! structured `Report` that tools and CI can act on.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use traits::{self, BlockDevice, RawLock};
+use vfat::{Cluster, Dir, Entry, Shared, Status, VFat};
+
+/// How serious a `Finding` is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Worth knowing, but the volume is consistent.
+    Info,
+    /// Space is wasted or unaccounted for, but no data is at risk.
+    Warning,
+    /// Data is missing, shared, or unreachable.
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What is wrong, for each `Finding`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Problem {
+    /// An entry's chain runs into a free, reserved, or out of range cluster,
+    /// or loops back on itself.
+    BrokenChain,
+    /// An entry's chain runs into a cluster marked bad.
+    BadCluster,
+    /// A file's size needs more clusters than its chain has.
+    SizeExceedsChain,
+    /// A file's chain has more clusters than its size needs.
+    ChainExceedsSize,
+    /// A cluster belongs to the chains of two entries.
+    CrossLinked,
+    /// Clusters are allocated in the FAT but belong to no entry.
+    LostClusters,
+    /// A directory couldn't be read or listed.
+    UnreadableDirectory,
+    /// The free-cluster count in the FSInfo sector disagrees with the FAT.
+    WrongFreeCount,
+    /// FAT entry 0 doesn't repeat the media descriptor, or entry 1 doesn't
+    /// mark an end of chain.
+    BadFatSignature,
+}
+
+impl Problem {
+    /// Returns a stable, kebab-case name for the problem.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Problem::BrokenChain => "broken-chain",
+            Problem::BadCluster => "bad-cluster",
+            Problem::SizeExceedsChain => "size-exceeds-chain",
+            Problem::ChainExceedsSize => "chain-exceeds-size",
+            Problem::CrossLinked => "cross-linked",
+            Problem::LostClusters => "lost-clusters",
+            Problem::UnreadableDirectory => "unreadable-directory",
+            Problem::WrongFreeCount => "wrong-free-count",
+            Problem::BadFatSignature => "bad-fat-signature",
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single inconsistency found by `check()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub problem: Problem,
+    /// The path of the affected file or directory, if any.
+    pub path: Option<String>,
+    /// The affected cluster, if any.
+    pub cluster: Option<u32>,
+    /// A description of the problem.
+    pub message: String,
+    /// A description of the repair that would fix the problem.
+    pub fix: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.problem)?;
+        if let Some(ref path) = self.path {
+            write!(f, " at {}", path)?;
+        }
+        if let Some(cluster) = self.cluster {
+            write!(f, " (cluster {})", cluster)?;
+        }
+        write!(f, ": {}; fix: {}", self.message, self.fix)
+    }
+}
+
+/// The results of `check()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Every problem found, in the order found.
+    pub findings: Vec<Finding>,
+    /// The number of files checked.
+    pub files: u64,
+    /// The number of directories checked, including the root directory.
+    pub dirs: u64,
+}
+
+impl Report {
+    /// Returns `true` if nothing worse than `Severity::Info` was found.
+    pub fn is_clean(&self) -> bool {
+        self.findings.iter().all(|finding| finding.severity == Severity::Info)
+    }
+
+    /// Returns the severity of the most serious finding, if there are any.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+
+    /// Writes the report to `out` as a JSON object, with the fields of
+    /// `Report` and of each `Finding`. Severities and problems are written as
+    /// their `as_str()` names, and missing paths and clusters as `null`.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "{{\"clean\":{},\"files\":{},\"dirs\":{},\"findings\":[",
+               self.is_clean(), self.files, self.dirs)?;
+        for (i, finding) in self.findings.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write!(out, "{{\"severity\":\"{}\",\"problem\":\"{}\",\"path\":",
+                   finding.severity, finding.problem)?;
+            match finding.path {
+                Some(ref path) => json_str(&mut out, path)?,
+                None => write!(out, "null")?,
+            }
+            match finding.cluster {
+                Some(cluster) => write!(out, ",\"cluster\":{}", cluster)?,
+                None => write!(out, ",\"cluster\":null")?,
+            }
+            write!(out, ",\"message\":")?;
+            json_str(&mut out, &finding.message)?;
+            write!(out, ",\"fix\":")?;
+            json_str(&mut out, &finding.fix)?;
+            write!(out, "}}")?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+fn json_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// Checks the consistency of the directory tree and the FAT of `vfat`, and
+/// returns what it found. Nothing is written to the volume.
+///
+/// Every entry's cluster chain is followed, and the size of every file
+/// compared with its chain. Clusters claimed by two entries, and clusters
+/// allocated in the FAT that no entry claims, are reported as well, as is a
+/// free-cluster count in the FSInfo sector that disagrees with the FAT.
+///
+/// ```rust,no_run
+/// # fn main() -> std::io::Result<()> {
+/// use fat32::device::ImageFile;
+/// use fat32::tools::fsck;
+/// use fat32::vfat::VFat;
+///
+/// let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+/// let report = fsck::check(&vfat)?;
+/// for finding in report.findings.iter() {
+///     println!("{}", finding);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Problems with the volume are findings, not errors. An error is returned
+/// only if the FAT can't be read or the volume's `CancelToken` is cancelled.
+pub fn check<D: BlockDevice, L: RawLock>(vfat: &Shared<VFat<D, L>, L>) -> io::Result<Report> {
+    let (max_cluster, bytes_per_cluster) = {
+        let vfat = vfat.borrow();
+        (vfat.max_cluster, vfat.bytes_per_cluster() as u64)
+    };
+    let mut checker = Checker {
+        vfat: vfat,
+        report: Report::default(),
+        owners: vec![0; max_cluster as usize + 1],
+        paths: Vec::new(),
+        bytes_per_cluster: bytes_per_cluster,
+    };
+
+    let root = Dir::root(vfat.clone());
+    if checker.claim_chain(root.first_cluster, "/")?.is_some() {
+        checker.check_dir(&root, "/")?;
+    }
+    checker.find_lost_clusters()?;
+    checker.check_free_count()?;
+    checker.check_fat_signature()?;
+    Ok(checker.report)
+}
+
+struct Checker<'a, D: 'a, L: RawLock + 'a> {
+    vfat: &'a Shared<VFat<D, L>, L>,
+    report: Report,
+    /// For every cluster, 1 more than the index into `paths` of the entry
+    /// whose chain it belongs to, or 0 if it belongs to none yet.
+    owners: Vec<u32>,
+    paths: Vec<String>,
+    bytes_per_cluster: u64,
+}
+
+impl<'a, D: BlockDevice, L: RawLock> Checker<'a, D, L> {
+    fn report(&mut self, severity: Severity, problem: Problem, path: Option<&str>,
+              cluster: Option<u32>, message: String, fix: String) {
+        self.report.findings.push(Finding {
+            severity: severity,
+            problem: problem,
+            path: path.map(String::from),
+            cluster: cluster,
+            message: message,
+            fix: fix,
+        });
+    }
+
+    fn check_dir(&mut self, dir: &Dir<D, L>, path: &str) -> io::Result<()> {
+        self.vfat.borrow().cancel.check()?;
+        self.report.dirs += 1;
+        let entries = match traits::Dir::entries(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.report(Severity::Error, Problem::UnreadableDirectory, Some(path), None,
+                            format!("directory can't be listed: {}", e),
+                            String::from("remove the directory"));
+                return Ok(());
+            }
+        };
+
+        for entry in entries {
+            let name = traits::Entry::name(&entry).to_string();
+            if name == "." || name == ".." || traits::Entry::metadata(&entry).attr.volume_id() {
+                continue;
+            }
+            let child = if path == "/" {
+                format!("/{}", name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+
+            match entry {
+                Entry::File(ref file) => {
+                    self.report.files += 1;
+                    if let Some(len) = self.claim_chain(file.first_cluster, &child)? {
+                        self.check_size(&child, file.first_cluster, file.size as u64, len);
+                    }
+                }
+                Entry::Dir(ref subdir) => {
+                    // A directory whose chain is broken, or was already
+                    // claimed, such as by a directory containing itself, isn't
+                    // descended into.
+                    match self.claim_chain(subdir.first_cluster, &child)? {
+                        Some(0) | None => {}
+                        Some(_) => self.check_dir(subdir, &child)?,
+                    }
+                    if subdir.first_cluster.get_index() == 0 {
+                        self.report(Severity::Error, Problem::BrokenChain, Some(&child), None,
+                                    String::from("directory has no clusters"),
+                                    String::from("remove the directory"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the size of the file at `path` with the length of its chain,
+    /// `clusters`.
+    fn check_size(&mut self, path: &str, first: Cluster, size: u64, clusters: u64) {
+        let needed = (size + self.bytes_per_cluster - 1) / self.bytes_per_cluster;
+        let cluster = Some(first.get_index()).filter(|&c| c != 0);
+        if needed > clusters {
+            let held = clusters * self.bytes_per_cluster;
+            self.report(Severity::Error, Problem::SizeExceedsChain, Some(path), cluster,
+                        format!("file is {} bytes but its chain holds {}", size, held),
+                        format!("truncate the file to {} bytes", held));
+        } else if clusters > needed {
+            self.report(Severity::Warning, Problem::ChainExceedsSize, Some(path), cluster,
+                        format!("file of {} bytes has {} clusters, {} more than needed",
+                                size, clusters, clusters - needed),
+                        format!("free the last {} clusters of the chain", clusters - needed));
+        }
+    }
+
+    /// Follows the chain starting at `start` on behalf of the entry at
+    /// `path`, claiming its clusters, and returns its length. A chain that
+    /// runs into a cluster that is out of range, not allocated, or already
+    /// claimed is reported and `None` returned.
+    fn claim_chain(&mut self, start: Cluster, path: &str) -> io::Result<Option<u64>> {
+        if start.get_index() == 0 {
+            return Ok(Some(0));
+        }
+        self.paths.push(String::from(path));
+        let owner = self.paths.len() as u32;
+
+        let mut claimed = 0;
+        let mut cluster = start.get_index();
+        loop {
+            if cluster < 2 || cluster as usize >= self.owners.len() {
+                self.report(Severity::Error, Problem::BrokenChain, Some(path), Some(cluster),
+                            String::from("chain runs out of the data region"),
+                            String::from("end the chain at the previous cluster"));
+                return Ok(None);
+            }
+            match self.owners[cluster as usize] {
+                0 => {}
+                other if other == owner => {
+                    self.report(Severity::Error, Problem::BrokenChain, Some(path),
+                                Some(cluster), String::from("chain loops back on itself"),
+                                String::from("end the chain before the loop"));
+                    return Ok(None);
+                }
+                other => {
+                    let other = self.paths[other as usize - 1].clone();
+                    self.report(Severity::Error, Problem::CrossLinked, Some(path),
+                                Some(cluster), format!("cluster also belongs to {}", other),
+                                String::from("copy the shared clusters to a new chain"));
+                    return Ok(None);
+                }
+            }
+
+            let status = self.vfat.borrow_mut().fat_entry(Cluster::from(cluster))?.status();
+            match status {
+                Status::Data(next) => {
+                    self.owners[cluster as usize] = owner;
+                    claimed += 1;
+                    cluster = next.get_index();
+                }
+                Status::Eoc(_) => {
+                    self.owners[cluster as usize] = owner;
+                    return Ok(Some(claimed + 1));
+                }
+                Status::Bad => {
+                    self.report(Severity::Error, Problem::BadCluster, Some(path),
+                                Some(cluster), String::from("chain runs into a bad cluster"),
+                                String::from("end the chain at the previous cluster"));
+                    return Ok(None);
+                }
+                Status::Free | Status::Reserved => {
+                    self.report(Severity::Error, Problem::BrokenChain, Some(path),
+                                Some(cluster), String::from("chain runs into a free cluster"),
+                                String::from("end the chain at the previous cluster"));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Reports clusters allocated in the FAT that no entry claimed.
+    fn find_lost_clusters(&mut self) -> io::Result<()> {
+        let mut lost = 0u32;
+        let mut first = None;
+        for cluster in 2..self.owners.len() as u32 {
+            if cluster % 4096 == 0 {
+                self.vfat.borrow().cancel.check()?;
+            }
+            if self.owners[cluster as usize] != 0 {
+                continue;
+            }
+            match self.vfat.borrow_mut().fat_entry(Cluster::from(cluster))?.status() {
+                Status::Data(_) | Status::Eoc(_) => {
+                    lost += 1;
+                    first = first.or(Some(cluster));
+                }
+                _ => {}
+            }
+        }
+
+        if lost > 0 {
+            self.report(Severity::Warning, Problem::LostClusters, None, first,
+                        format!("{} allocated clusters belong to no file or directory", lost),
+                        format!("free the {} lost clusters", lost));
+        }
+        Ok(())
+    }
+
+    /// Reports an FSInfo free-cluster count that disagrees with the FAT.
+    fn check_free_count(&mut self) -> io::Result<()> {
+        let mismatch = self.vfat.borrow_mut().check_free_count()?;
+        if let Some(mismatch) = mismatch {
+            let recorded = match mismatch.recorded {
+                Some(count) => format!("{}", count),
+                None => String::from("unknown"),
+            };
+            self.report(Severity::Warning, Problem::WrongFreeCount, None, None,
+                        format!("FSInfo records {} free clusters, but {} are free",
+                                recorded, mismatch.actual),
+                        String::from("recompute the free-cluster count"));
+        }
+        Ok(())
+    }
+
+    /// Reports FAT entries 0 and 1 not holding their signature.
+    fn check_fat_signature(&mut self) -> io::Result<()> {
+        let mismatch = self.vfat.borrow_mut().check_fat_signature()?;
+        if let Some(mismatch) = mismatch {
+            if let Some(entry0) = mismatch.entry0 {
+                self.report(Severity::Warning, Problem::BadFatSignature, None, Some(0),
+                            format!("FAT entry 0 is {:#010X}, but the media descriptor is \
+                                     {:#04X}", entry0, mismatch.media),
+                            String::from("rewrite FAT entry 0 from the media descriptor"));
+            }
+            if let Some(entry1) = mismatch.entry1 {
+                self.report(Severity::Warning, Problem::BadFatSignature, None, Some(1),
+                            format!("FAT entry 1 is {:#010X}, not an end of chain", entry1),
+                            String::from("rewrite FAT entry 1 as an end of chain"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialize {
+    use serde::ser::{Serialize, Serializer, SerializeStruct};
+
+    use super::{Finding, Problem, Report, Severity};
+
+    /// Serializes as `as_str()`.
+    impl Serialize for Severity {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    /// Serializes as `as_str()`.
+    impl Serialize for Problem {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl Serialize for Finding {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("Finding", 6)?;
+            s.serialize_field("severity", &self.severity)?;
+            s.serialize_field("problem", &self.problem)?;
+            s.serialize_field("path", &self.path)?;
+            s.serialize_field("cluster", &self.cluster)?;
+            s.serialize_field("message", &self.message)?;
+            s.serialize_field("fix", &self.fix)?;
+            s.end()
+        }
+    }
+
+    impl Serialize for Report {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("Report", 4)?;
+            s.serialize_field("clean", &self.is_clean())?;
+            s.serialize_field("files", &self.files)?;
+            s.serialize_field("dirs", &self.dirs)?;
+            s.serialize_field("findings", &self.findings)?;
+            s.end()
+        }
+    }
+}
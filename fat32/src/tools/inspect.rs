@@ -0,0 +1,552 @@
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+
+use mbr::MasterBootRecord;
+use traits::BlockDevice;
+use util::{self, le_u16, le_u32};
+use vfat::{BiosParameterBlock, ChainWalk, Cluster, FatEntry, Status};
+
+const DIR_ENTRY_SIZE: usize = 32;
+/// Fields longer than this many bytes have their hex elided.
+const FIELD_HEX_BYTES: usize = 8;
+const FAT_ENTRIES_PER_LINE: usize = 4;
+
+/// Prints the on-disk structures of a FAT32 image, the raw bytes of each
+/// field beside its decoded value, for diagnosing corrupt or nonstandard
+/// images.
+///
+/// Nothing is validated beyond what is needed to find a structure, so images
+/// that `VFat` refuses to mount can still be inspected. Each method prints one
+/// structure to the writer.
+///
+/// ```no_run
+/// use std::io;
+/// use fat32::device::ImageFile;
+/// use fat32::tools::Inspector;
+///
+/// let image = ImageFile::open_read_only("sd.img").unwrap();
+/// let mut inspector = Inspector::new(image, io::stdout());
+/// inspector.bpb().unwrap();
+/// inspector.dir("/boot").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Inspector<D, W> {
+    device: D,
+    out: W,
+}
+
+/// The location of the file system's regions, in bytes from the start of
+/// the device.
+#[derive(Debug, Copy, Clone)]
+struct Volume {
+    start: u64,
+    bytes_per_sector: u64,
+    bytes_per_cluster: u64,
+    num_fats: u32,
+    fat_bytes: u64,
+    fat_start: u64,
+    data_start: u64,
+    root_cluster: u32,
+    fsinfo_sector: u16,
+    max_cluster: u32,
+}
+
+/// A decoded directory entry.
+struct DirRecord {
+    description: String,
+    // The long and short names, first cluster, and whether the entry is a
+    // directory, for entries naming a file or directory.
+    target: Option<(String, String, u32, bool)>,
+}
+
+impl<D: BlockDevice, W: Write> Inspector<D, W> {
+    /// Returns an inspector reading from `device` and printing to `out`.
+    pub fn new(device: D, out: W) -> Inspector<D, W> {
+        Inspector { device: device, out: out }
+    }
+
+    /// Returns the device and the writer.
+    pub fn into_inner(self) -> (D, W) {
+        (self.device, self.out)
+    }
+
+    /// Prints the master boot record and its partition table.
+    pub fn mbr(&mut self) -> io::Result<()> {
+        let buf = self.read(0, 512)?;
+        let out = &mut self.out;
+        writeln!(out, "Master boot record (sector 0)")?;
+        field(out, &buf, 0, 436, "bootstrap", format_args!(""))?;
+        field(out, &buf, 436, 10, "disk id", format_args!(""))?;
+        for i in 0..4 {
+            let base = 446 + 16 * i;
+            let part_type = buf[base + 4];
+            writeln!(out, "  partition {}", i + 1)?;
+            field(out, &buf, base, 1, "  boot indicator", format_args!("{}", match buf[base] {
+                0x00 => "inactive",
+                0x80 => "active",
+                _ => "invalid",
+            }))?;
+            field(out, &buf, base + 1, 3, "  start CHS",
+                  format_args!("{}", chs(&buf[base + 1..])))?;
+            field(out, &buf, base + 4, 1, "  type", format_args!("{:#04x} {}", part_type,
+                                                                 partition_type(part_type)))?;
+            field(out, &buf, base + 5, 3, "  end CHS", format_args!("{}", chs(&buf[base + 5..])))?;
+            field(out, &buf, base + 8, 4, "  relative sector",
+                  format_args!("{}", le_u32(&buf, base + 8)))?;
+            field(out, &buf, base + 12, 4, "  total sectors",
+                  format_args!("{}", le_u32(&buf, base + 12)))?;
+        }
+        let valid = buf[510] == 0x55 && buf[511] == 0xAA;
+        field(out, &buf, 510, 2, "signature", format_args!("{}", signature(valid)))?;
+        writeln!(out)
+    }
+
+    /// Prints the extended BIOS parameter block of the first FAT32
+    /// partition, and whether the backup boot sector matches it.
+    pub fn bpb(&mut self) -> io::Result<()> {
+        let start = self.partition_start()?;
+        let buf = self.read(start, 512)?;
+        let bytes_per_sector = le_u16(&buf, 11) as u64;
+        let backup_sector = le_u16(&buf, 50) as u64;
+        let backup = match backup_sector {
+            0 | 0xFFFF => None,
+            _ => Some(self.read(start + backup_sector * bytes_per_sector, 512)),
+        };
+
+        let out = &mut self.out;
+        writeln!(out, "BIOS parameter block (byte {:#x})", start)?;
+        field(out, &buf, 0, 3, "jump", format_args!(""))?;
+        field(out, &buf, 3, 8, "OEM id",
+              format_args!("{:?}", String::from_utf8_lossy(&buf[3..11])))?;
+        field(out, &buf, 11, 2, "bytes per sector", format_args!("{}", bytes_per_sector))?;
+        field(out, &buf, 13, 1, "sectors per cluster", format_args!("{}", buf[13]))?;
+        field(out, &buf, 14, 2, "reserved sectors", format_args!("{}", le_u16(&buf, 14)))?;
+        field(out, &buf, 16, 1, "FATs", format_args!("{}", buf[16]))?;
+        field(out, &buf, 17, 2, "root entries", format_args!("{} (0 on FAT32)", le_u16(&buf, 17)))?;
+        field(out, &buf, 19, 2, "total sectors (16)", format_args!("{}", le_u16(&buf, 19)))?;
+        field(out, &buf, 21, 1, "media", format_args!("{:#04x}", buf[21]))?;
+        field(out, &buf, 22, 2, "sectors per FAT (16)", format_args!("{}", le_u16(&buf, 22)))?;
+        field(out, &buf, 24, 2, "sectors per track", format_args!("{}", le_u16(&buf, 24)))?;
+        field(out, &buf, 26, 2, "heads", format_args!("{}", le_u16(&buf, 26)))?;
+        field(out, &buf, 28, 4, "hidden sectors", format_args!("{}", le_u32(&buf, 28)))?;
+        field(out, &buf, 32, 4, "total sectors (32)", format_args!("{}", le_u32(&buf, 32)))?;
+        field(out, &buf, 36, 4, "sectors per FAT (32)", format_args!("{}", le_u32(&buf, 36)))?;
+        let flags = le_u16(&buf, 40);
+        field(out, &buf, 40, 2, "flags", format_args!("{}", if flags & 0x80 != 0 {
+            format!("only FAT {} is active", (flags & 0xF) + 1)
+        } else {
+            "FATs are mirrored".to_string()
+        }))?;
+        field(out, &buf, 42, 2, "version", format_args!("{}.{}", buf[43], buf[42]))?;
+        field(out, &buf, 44, 4, "root cluster", format_args!("{}", le_u32(&buf, 44)))?;
+        field(out, &buf, 48, 2, "FSInfo sector", format_args!("{}", le_u16(&buf, 48)))?;
+        field(out, &buf, 50, 2, "backup boot sector", format_args!("{}", backup_sector))?;
+        field(out, &buf, 64, 1, "drive number", format_args!("{:#04x}", buf[64]))?;
+        field(out, &buf, 65, 1, "NT flags", format_args!("{:#04x}", buf[65]))?;
+        field(out, &buf, 66, 1, "signature", format_args!("{}", signature(buf[66] == 0x28
+                                                                          || buf[66] == 0x29)))?;
+        let id = le_u32(&buf, 67);
+        field(out, &buf, 67, 4, "volume id", format_args!("{:04X}-{:04X}", id >> 16, id & 0xFFFF))?;
+        field(out, &buf, 71, 11, "volume label",
+              format_args!("{:?}", String::from_utf8_lossy(&buf[71..82])))?;
+        field(out, &buf, 82, 8, "system id",
+              format_args!("{:?}", String::from_utf8_lossy(&buf[82..90])))?;
+        field(out, &buf, 90, 420, "boot code", format_args!(""))?;
+        let valid = buf[510] == 0x55 && buf[511] == 0xAA;
+        field(out, &buf, 510, 2, "boot signature", format_args!("{}", signature(valid)))?;
+        match backup {
+            None => writeln!(out, "  no backup boot sector")?,
+            Some(Ok(ref copy)) if *copy == buf => {
+                writeln!(out, "  backup boot sector {} matches", backup_sector)?
+            }
+            Some(Ok(_)) => writeln!(out, "  backup boot sector {} differs", backup_sector)?,
+            Some(Err(e)) => {
+                writeln!(out, "  backup boot sector {} is unreadable: {}", backup_sector, e)?
+            }
+        }
+        writeln!(out)
+    }
+
+    /// Prints the FSInfo sector.
+    pub fn fsinfo(&mut self) -> io::Result<()> {
+        let volume = self.volume()?;
+        let offset = volume.start + volume.fsinfo_sector as u64 * volume.bytes_per_sector;
+        let buf = self.read(offset, 512)?;
+
+        let out = &mut self.out;
+        writeln!(out, "FSInfo (sector {}, byte {:#x})", volume.fsinfo_sector, offset)?;
+        field(out, &buf, 0, 4, "lead signature", format_args!("{}", signature(le_u32(&buf, 0)
+                                                                              == 0x41615252)))?;
+        field(out, &buf, 484, 4, "struct signature", format_args!("{}", signature(le_u32(&buf, 484)
+                                                                                  == 0x61417272)))?;
+        for &(offset, name) in [(488, "free clusters"), (492, "next free cluster")].iter() {
+            let value = le_u32(&buf, offset);
+            if value == 0xFFFF_FFFF {
+                field(out, &buf, offset, 4, name, format_args!("unknown"))?;
+            } else {
+                field(out, &buf, offset, 4, name, format_args!("{}", value))?;
+            }
+        }
+        field(out, &buf, 508, 4, "trail signature", format_args!("{}", signature(le_u32(&buf, 508)
+                                                                                 == 0xAA550000)))?;
+        writeln!(out)
+    }
+
+    /// Prints the entries in the first `sectors` sectors of the first FAT,
+    /// and whether the other FATs agree with it.
+    pub fn fat(&mut self, sectors: u32) -> io::Result<()> {
+        let volume = self.volume()?;
+        let len = min(sectors as u64 * volume.bytes_per_sector, volume.fat_bytes);
+        let len = min(len / 4, volume.max_cluster as u64 + 1) as usize * 4;
+        let fat = self.read(volume.fat_start, len)?;
+        let copies = (1..volume.num_fats)
+            .map(|i| self.read(volume.fat_start + i as u64 * volume.fat_bytes, len))
+            .collect::<Vec<_>>();
+
+        let out = &mut self.out;
+        writeln!(out, "FAT 1 (byte {:#x}), first {} entries", volume.fat_start, len / 4)?;
+        for (line, chunk) in fat.chunks(4 * FAT_ENTRIES_PER_LINE).enumerate() {
+            let mut text = String::new();
+            for (i, entry) in chunk.chunks(4).enumerate() {
+                let cluster = line * FAT_ENTRIES_PER_LINE + i;
+                let value = le_u32(entry, 0);
+                text.push_str(&format!("{:>8}: {:08x} {:<10}", cluster, value,
+                                       fat_status(cluster, value, volume.max_cluster)));
+            }
+            writeln!(out, "{}", text.trim_right())?;
+        }
+
+        for (i, copy) in copies.into_iter().enumerate() {
+            let number = i + 2;
+            match copy {
+                Ok(ref copy) => match fat.chunks(4).zip(copy.chunks(4)).position(|(a, b)| a != b) {
+                    None => writeln!(out, "  FAT {} matches over these entries", number)?,
+                    Some(cluster) => {
+                        writeln!(out, "  FAT {} differs at cluster {}: {:08x}", number, cluster,
+                                 le_u32(copy, cluster * 4))?
+                    }
+                },
+                Err(e) => writeln!(out, "  FAT {} is unreadable: {}", number, e)?,
+            }
+        }
+        writeln!(out)
+    }
+
+    /// Prints the raw entries of the directory at `path`, or of the
+    /// directory holding the file at `path`. `..` components follow the
+    /// on-disk `..` entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `NotFound` if a component of `path` doesn't exist,
+    /// and of `InvalidData` if a directory's cluster chain is unreadable.
+    pub fn dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let volume = self.volume()?;
+        let mut names = vec![];
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_str().ok_or_else(|| {
+                    util::invalid_input("path contains invalid UTF-8")
+                })?),
+                Component::ParentDir => names.push(".."),
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => { }
+            }
+        }
+
+        let mut cluster = volume.root_cluster;
+        let mut file = None;
+        for (i, name) in names.iter().enumerate() {
+            let (chain, _) = self.chain(&volume, cluster);
+            let buf = self.read_clusters(&volume, &chain)?;
+            let found = dir_records(&buf).into_iter()
+                .filter_map(|record| record.target)
+                .find(|&(ref long, ref short, _, _)| {
+                    long.eq_ignore_ascii_case(name) || short.eq_ignore_ascii_case(name)
+                });
+            match found {
+                Some((_, _, next, true)) => {
+                    // `..` entries pointing at the root hold cluster 0.
+                    cluster = if next == 0 { volume.root_cluster } else { next };
+                }
+                Some((_, short, _, false)) if i + 1 == names.len() => file = Some(short),
+                Some(_) => return Err(util::invalid_input(format!("{} is not a directory", name))),
+                None => return Err(util::not_found(format!("{} not found", name))),
+            }
+        }
+
+        let (chain, problem) = self.chain(&volume, cluster);
+        let buf = self.read_clusters(&volume, &chain)?;
+        let out = &mut self.out;
+        writeln!(out, "Directory {} (clusters {})", path.as_ref().display(),
+                 chain.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" "))?;
+        if let Some(problem) = problem {
+            writeln!(out, "  cluster chain is broken: {}", problem)?;
+        }
+
+        let records = dir_records(&buf);
+        for (i, (record, raw)) in records.iter().zip(buf.chunks(DIR_ENTRY_SIZE)).enumerate() {
+            let marker = match (&file, &record.target) {
+                (&Some(ref file), &Some((_, ref short, _, _))) if file == short => '>',
+                _ => ' ',
+            };
+            writeln!(out, "{} [{:>4}] {}", marker, i, record.description)?;
+            writeln!(out, "         {}", hex_line(&raw[..16]))?;
+            writeln!(out, "         {}", hex_line(&raw[16..]))?;
+        }
+        writeln!(out)
+    }
+
+    /// Reads `len` bytes from byte `offset` of the device.
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let sector_size = self.device.sector_size();
+        let skip = (offset % sector_size) as usize;
+        let mut buf = Vec::with_capacity(skip + len + sector_size as usize);
+        let mut sector = offset / sector_size;
+        while buf.len() < skip + len {
+            if self.device.read_all_sector(sector, &mut buf)? == 0 {
+                return Err(util::eof("image ends before the structure being read"));
+            }
+            sector += 1;
+        }
+        buf.drain(..skip);
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Returns the byte offset of the first FAT32 partition, or 0 if the
+    /// device is unpartitioned and holds a FAT32 file system directly.
+    fn partition_start(&mut self) -> io::Result<u64> {
+        let mut sector = [0u8; 512];
+        sector.copy_from_slice(&self.read(0, 512)?);
+        let relative_sector = MasterBootRecord::decode(&sector).first_fat32()
+            .map(|partition| partition.relative_sector as u64);
+        match relative_sector {
+            Some(relative_sector) => Ok(relative_sector * self.device.sector_size()),
+            None if &sector[82..87] == b"FAT32" => Ok(0),
+            None => Err(util::not_found("no FAT32 partition found")),
+        }
+    }
+
+    fn volume(&mut self) -> io::Result<Volume> {
+        let start = self.partition_start()?;
+        let mut sector = [0u8; 512];
+        sector.copy_from_slice(&self.read(start, 512)?);
+        let bpb = BiosParameterBlock::decode(&sector);
+        let bytes_per_sector = bpb.bytes_per_sector as u64;
+        if bytes_per_sector < 512 || !bytes_per_sector.is_power_of_two()
+            || bpb.sectors_per_cluster == 0 {
+            return Err(util::corrupt("BPB sector or cluster size is invalid"));
+        }
+
+        let bytes_per_cluster = bpb.sectors_per_cluster as u64 * bytes_per_sector;
+        let fat_start = start + bpb.num_reserved_sectors as u64 * bytes_per_sector;
+        let fat_bytes = bpb.sectors_per_fat() as u64 * bytes_per_sector;
+        let data_start = fat_start + bpb.num_fat as u64 * fat_bytes;
+        let data_bytes = (bpb.total_logical_sectors() as u64 * bytes_per_sector)
+            .saturating_sub(data_start - start);
+        let clusters = min(data_bytes / bytes_per_cluster, (fat_bytes / 4).saturating_sub(2));
+        Ok(Volume {
+            start: start,
+            bytes_per_sector: bytes_per_sector,
+            bytes_per_cluster: bytes_per_cluster,
+            num_fats: bpb.num_fat as u32,
+            fat_bytes: fat_bytes,
+            fat_start: fat_start,
+            data_start: data_start,
+            root_cluster: bpb.root_cluster,
+            fsinfo_sector: bpb.fsinfo_sector,
+            max_cluster: min(clusters, 0x0FFF_FFEF) as u32 + 1,
+        })
+    }
+
+    /// Follows the cluster chain starting at `start` through the first FAT,
+    /// by the same rules as `VFat::resolve_chain()`. Returns the clusters
+    /// read, and why the chain ended early if it did.
+    fn chain(&mut self, volume: &Volume, start: u32) -> (Vec<u32>, Option<String>) {
+        if start < 2 || start > volume.max_cluster {
+            return (vec![], Some(format!("cluster {} is out of range", start)));
+        }
+
+        let mut chain = vec![start];
+        let mut walk = ChainWalk::new(Cluster::from(start), volume.max_cluster, false);
+        loop {
+            let cluster = walk.cluster().get_index();
+            let value = match self.read(volume.fat_start + cluster as u64 * 4, 4) {
+                Ok(buf) => le_u32(&buf, 0),
+                Err(e) => return (chain, Some(format!("FAT entry is unreadable: {}", e))),
+            };
+            match walk.step(FatEntry(value)) {
+                Ok(Some(next)) => chain.push(next.get_index()),
+                Ok(None) => return (chain, None),
+                Err(e) => return (chain, Some(e.to_string())),
+            }
+        }
+    }
+
+    fn read_clusters(&mut self, volume: &Volume, chain: &[u32]) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        for &cluster in chain {
+            let offset = volume.data_start + (cluster - 2) as u64 * volume.bytes_per_cluster;
+            buf.extend(self.read(offset, volume.bytes_per_cluster as usize)?);
+        }
+        Ok(buf)
+    }
+}
+
+/// Prints one line of a structure: its offset, raw bytes, name, and value.
+fn field<W: Write>(out: &mut W, buf: &[u8], offset: usize, len: usize, name: &str,
+                   value: fmt::Arguments) -> io::Result<()> {
+    let mut hex = buf[offset..offset + len].iter().take(FIELD_HEX_BYTES)
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if len > FIELD_HEX_BYTES {
+        hex.push_str(" ..");
+    }
+    let line = format!("  {:03x}  {:<26} {:<22} {}", offset, hex, name, value);
+    writeln!(out, "{}", line.trim_right())
+}
+
+/// Formats `bytes` as hex followed by their printable ASCII characters.
+fn hex_line(bytes: &[u8]) -> String {
+    let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    let ascii: String = bytes.iter()
+        .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+        .collect();
+    format!("{}  {}", hex, ascii)
+}
+
+fn signature(valid: bool) -> &'static str {
+    if valid { "valid" } else { "INVALID" }
+}
+
+/// Decodes a packed cylinder-head-sector address.
+fn chs(buf: &[u8]) -> String {
+    let cylinder = ((buf[1] as u16 & 0xC0) << 2) | buf[2] as u16;
+    format!("{}/{}/{}", cylinder, buf[0], buf[1] & 0x3F)
+}
+
+fn partition_type(part_type: u8) -> &'static str {
+    match part_type {
+        0x00 => "empty",
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0E => "FAT16",
+        0x05 | 0x0F => "extended",
+        0x07 => "NTFS/exFAT",
+        0x0B => "FAT32 (CHS)",
+        0x0C => "FAT32 (LBA)",
+        0x83 => "Linux",
+        0xEE => "GPT protective",
+        _ => "other",
+    }
+}
+
+/// Describes the FAT entry of `cluster` holding `value`.
+fn fat_status(cluster: usize, value: u32, max_cluster: u32) -> String {
+    match (cluster, FatEntry(value).status()) {
+        (0, _) => format!("media {:#04x}", value as u8),
+        (1, _) => "flags".to_string(),
+        (_, Status::Free) => "free".to_string(),
+        (_, Status::Reserved) => "reserved".to_string(),
+        (_, Status::Bad) => "bad".to_string(),
+        (_, Status::Eoc(_)) => "end".to_string(),
+        (_, Status::Data(next)) if next.get_index() > max_cluster => {
+            format!("-> {}!", next.get_index())
+        }
+        (_, Status::Data(next)) => format!("-> {}", next.get_index()),
+    }
+}
+
+/// Formats a FAT date and time as they are stored, even if out of range.
+fn datetime(date: u16, time: u16) -> String {
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", (date >> 9) + 1980, (date >> 5) & 0xF,
+            date & 0x1F, time >> 11, (time >> 5) & 0x3F, (time & 0x1F) * 2)
+}
+
+fn short_name(raw: &[u8]) -> String {
+    let mut name = raw[..8].to_vec();
+    // 0x05 stands in for a leading 0xE5, which marks deleted entries.
+    if name[0] == 0x05 {
+        name[0] = 0xE5;
+    }
+    let base = String::from_utf8_lossy(&name).trim_right().to_string();
+    let ext = String::from_utf8_lossy(&raw[8..11]).trim_right().to_string();
+    if ext.is_empty() { base } else { format!("{}.{}", base, ext) }
+}
+
+fn lfn_chars(raw: &[u8]) -> Vec<u16> {
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30].iter()
+        .map(|&offset| le_u16(raw, offset))
+        .collect()
+}
+
+/// Decodes the entries of a directory, up to and including the end marker.
+fn dir_records(buf: &[u8]) -> Vec<DirRecord> {
+    let mut records = vec![];
+    let mut lfn: Vec<u16> = vec![];
+    let mut lfn_checksum = None;
+    for raw in buf.chunks(DIR_ENTRY_SIZE).filter(|raw| raw.len() == DIR_ENTRY_SIZE) {
+        let attr = raw[11];
+        let (description, target) = if raw[0] == 0x00 {
+            ("end of directory".to_string(), None)
+        } else if raw[0] == 0xE5 {
+            lfn.clear();
+            (format!("deleted  {:?}", short_name(raw)), None)
+        } else if attr & 0x3F == 0x0F {
+            let chars = lfn_chars(raw);
+            let end = chars.iter().position(|&c| c == 0x0000 || c == 0xFFFF)
+                           .unwrap_or_else(|| chars.len());
+            if raw[0] & 0x40 != 0 {
+                lfn.clear();
+            }
+            lfn.splice(0..0, chars[..end].iter().cloned());
+            lfn_checksum = Some(raw[13]);
+            (format!("lfn      seq {}{}  checksum {:#04x}  {:?}", raw[0] & 0x1F,
+                     if raw[0] & 0x40 != 0 { " (last)" } else { "" }, raw[13],
+                     String::from_utf16_lossy(&chars[..end])), None)
+        } else {
+            let short = short_name(raw);
+            let checksum = raw[..11].iter()
+                .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte));
+            let long = if lfn.is_empty() {
+                None
+            } else {
+                Some(String::from_utf16_lossy(&lfn))
+            };
+            lfn.clear();
+
+            let cluster = (le_u16(raw, 20) as u32) << 16 | le_u16(raw, 26) as u32;
+            let kind = if attr & 0x08 != 0 { "label   " }
+                       else if attr & 0x10 != 0 { "dir     " }
+                       else { "file    " };
+            let mut description = format!(
+                "{} {:?}  attr {:#04x}  cluster {}  size {}  created {}  accessed {}  \
+                 modified {}",
+                kind, short, attr, cluster, le_u32(raw, 28),
+                datetime(le_u16(raw, 16), le_u16(raw, 14)),
+                &datetime(le_u16(raw, 18), 0)[..10],
+                datetime(le_u16(raw, 24), le_u16(raw, 22)));
+            if let Some(ref long) = long {
+                let valid = lfn_checksum == Some(checksum);
+                description.push_str(&format!("  long name {:?}{}", long,
+                                              if valid { "" } else { " (checksum mismatch)" }));
+            }
+
+            let target = if attr & 0x08 != 0 {
+                None
+            } else {
+                Some((long.unwrap_or_else(|| short.clone()), short, cluster, attr & 0x10 != 0))
+            };
+            (description, target)
+        };
+
+        let end = raw[0] == 0x00;
+        records.push(DirRecord { description: description, target: target });
+        if end {
+            break;
+        }
+    }
+    records
+}
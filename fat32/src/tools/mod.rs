@@ -0,0 +1,17 @@
+//! Higher-level operations on whole images, built on the `vfat` and `device`
+//! APIs, for use by host-side tooling.
+
+mod extract;
+mod build;
+mod inspect;
+mod diff;
+mod wipe;
+mod reidentify;
+pub mod fsck;
+
+pub use self::extract::{extract, Extracted};
+pub use self::build::ImageBuilder;
+pub use self::inspect::Inspector;
+pub use self::diff::{diff, diff_host, Change, ChangeKind};
+pub use self::wipe::{wipe_deleted, Wiped};
+pub use self::reidentify::{reidentify, Reidentified};
@@ -0,0 +1,51 @@
+use std::io;
+
+use mbr::MasterBootRecord;
+use traits::{BlockDevice, Entropy};
+use vfat::{MountOptions, VFat};
+
+/// The identifiers given to an image by `reidentify()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reidentified {
+    /// The new disk signature in the MBR.
+    pub disk_id: u32,
+    /// The new serial number of the first FAT32 partition.
+    pub volume_id: u32,
+}
+
+/// Gives the image on `device` a fresh MBR disk signature and volume serial
+/// number drawn from `entropy`, so that a copy of an image, such as an SD
+/// card written from the same file as another, can't be mistaken for the
+/// original. Nothing else on the image changes.
+///
+/// ```rust,no_run
+/// # use fat32::device::ImageFile;
+/// # use fat32::traits::XorShift;
+/// # use fat32::tools::reidentify;
+/// let ids = reidentify(ImageFile::open("copy.img")?, &mut XorShift::from_clock())?;
+/// println!("disk {:08X}, volume {:08X}", ids.disk_id, ids.volume_id);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if `device` doesn't hold a FAT32 partition or can't be
+/// written to. The volume serial number is written first, so on an error
+/// writing the MBR, the volume may have a new serial number but the disk its
+/// old signature.
+pub fn reidentify<D, E>(mut device: D, entropy: &mut E) -> io::Result<Reidentified>
+    where D: BlockDevice, E: Entropy
+{
+    let ids = Reidentified {
+        disk_id: entropy.next_u32(),
+        volume_id: entropy.next_u32(),
+    };
+    {
+        let vfat = VFat::from_device(&mut device, MountOptions::default())?;
+        let mut vfat = vfat.borrow_mut();
+        vfat.set_volume_id(ids.volume_id)?;
+        vfat.flush()?;
+    }
+    MasterBootRecord::write_disk_signature(&mut device, ids.disk_id)?;
+    Ok(ids)
+}
@@ -0,0 +1,101 @@
+use std::io;
+
+use traits::{self, BlockDevice, RawLock};
+use util;
+use vfat::{Cluster, Dir, Entry, EntryLocation, RawEntryKind, Shared, VFat};
+
+/// The bytes written by every pass of a wipe but the last, in turn. The last
+/// pass writes zeroes.
+const PATTERNS: [u8; 3] = [0xFF, 0x55, 0xAA];
+
+/// Totals of a wipe, as returned by `wipe_deleted()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Wiped {
+    /// Number of free clusters overwritten.
+    pub clusters: u32,
+    /// Number of deleted or unused directory entries overwritten.
+    pub entries: u64,
+}
+
+/// Erases what remains of deleted files on `vfat`, so that an image can be
+/// handed out without leaking them: every free cluster is overwritten, as
+/// are the names, sizes, and timestamps of deleted directory entries and any
+/// stale entries past the end of a directory. Live files and directories are
+/// untouched.
+///
+/// Each of `passes` passes overwrites everything, the last with zeroes and
+/// the ones before with fixed patterns, flushing between passes. Extra passes
+/// only matter on media that overwrite data in place; flash media remap
+/// writes, so a single pass is as good as any number there. A `passes` of 0
+/// is treated as 1.
+///
+/// Deleted entries keep their 0xE5 marker, and entries past the end of a
+/// directory are given the 0x00 end marker, so the directory tree reads the
+/// same afterwards.
+///
+/// # Errors
+///
+/// Errors reading the directory tree or writing to the volume are returned
+/// immediately, leaving the wipe incomplete.
+pub fn wipe_deleted<D, L>(vfat: &Shared<VFat<D, L>, L>, passes: u32) -> io::Result<Wiped>
+    where D: BlockDevice, L: RawLock
+{
+    let mut entries = Vec::new();
+    find_dead_entries(&Dir::root(vfat.clone()), &mut Vec::new(), &mut entries)?;
+
+    let mut wiped = Wiped::default();
+    let passes = ::std::cmp::max(passes, 1);
+    for pass in 0..passes {
+        let byte = if pass + 1 == passes { 0 } else { PATTERNS[pass as usize % PATTERNS.len()] };
+        let mut vfat = vfat.borrow_mut();
+        for &(location, marker) in entries.iter() {
+            let mut entry = [byte; 32];
+            entry[0] = marker;
+            vfat.write_dir_entry(location, &entry)?;
+        }
+        wiped.clusters = vfat.wipe_free_clusters(byte)?;
+        vfat.flush()?;
+    }
+    wiped.entries = entries.len() as u64;
+    Ok(wiped)
+}
+
+/// Appends the location of every deleted entry of `dir` and its descendants
+/// to `found`, with 0xE5, the deleted marker, and of every entry past the end
+/// of a directory, with 0x00, the end marker. Entries that are already blank
+/// are skipped.
+fn find_dead_entries<D, L>(dir: &Dir<D, L>, ancestors: &mut Vec<Cluster>,
+                           found: &mut Vec<(EntryLocation, u8)>) -> io::Result<()>
+    where D: BlockDevice, L: RawLock
+{
+    if ancestors.contains(&dir.first_cluster) {
+        return Err(util::corrupt("directory tree contains a cycle"));
+    }
+    ancestors.push(dir.first_cluster);
+    dir.vfat.borrow().cancel.check()?;
+
+    let mut past_end = false;
+    for raw in dir.raw_entries()? {
+        past_end = past_end || raw.kind == RawEntryKind::End;
+        let marker = match raw.kind {
+            _ if past_end => 0x00,
+            RawEntryKind::Deleted => 0xE5,
+            _ => continue,
+        };
+        if raw.bytes[0] != marker || raw.bytes[1..].iter().any(|&b| b != 0) {
+            let location = EntryLocation { cluster: Cluster::from(raw.cluster),
+                                           offset: raw.offset };
+            found.push((location, marker));
+        }
+    }
+
+    for entry in traits::Dir::entries(dir)? {
+        if let Entry::Dir(ref subdir) = entry {
+            if subdir.name() != "." && subdir.name() != ".." {
+                find_dead_entries(subdir, ancestors, found)?;
+            }
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Asynchronous counterparts of the block device and file system traits, for
+//! host tools running on an async runtime.
+//!
+//! The file system itself is synchronous. Rather than wrapping its blocking
+//! calls in futures that block whoever polls them, every operation is handed
+//! to a caller-supplied `Spawn`, such as a runtime's blocking thread pool,
+//! and a `Task` resolves once it is done. `vfat::async_fs` uses this to
+//! read volumes; `BlockOn` lets a volume be mounted on an
+//! `AsyncBlockDevice`, whose futures are then driven on the spawned thread:
+//!
+//! ```rust,ignore
+//! let spawner = |job| { tokio::task::spawn_blocking(job); };
+//! let volume = AsyncVolume::new(VFat::from(BlockOn(device))?, spawner);
+//! let config = volume.read("/config.txt").await?;
+//! ```
+
+use std::boxed::Box;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use traits::BlockDevice;
+use util;
+
+/// A heap-allocated, type-erased future.
+pub type BoxFuture<'a, T> = Pin<Box<Future<Output = T> + Send + 'a>>;
+
+/// A blocking job handed to a `Spawn`.
+pub type Job = Box<FnOnce() + Send>;
+
+/// Runs blocking jobs away from the threads that poll futures, such as on
+/// `tokio::task::spawn_blocking()`. Closures taking a `Job` implement it.
+///
+/// A spawner may drop a job instead of running it, for instance while its
+/// runtime shuts down; the job's `Task` then resolves to an error.
+pub trait Spawn: Send + Sync {
+    /// Arranges for `job` to be run.
+    fn spawn(&self, job: Job);
+}
+
+impl<F: Fn(Job) + Send + Sync> Spawn for F {
+    fn spawn(&self, job: Job) {
+        self(job)
+    }
+}
+
+/// A `Spawn` that runs every job on a new thread, for callers without a
+/// thread pool of their own.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ThreadSpawner;
+
+impl Spawn for ThreadSpawner {
+    fn spawn(&self, job: Job) {
+        thread::spawn(job);
+    }
+}
+
+/// The state shared by a `Task` and the job computing its result.
+struct TaskState<T> {
+    result: Option<io::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// A future resolving to the result of a job run by a `Spawn`.
+pub struct Task<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+/// Hands the result of a job to its `Task`. Dropped without a result, when
+/// the job panics or is never run, it resolves the task to an error.
+struct Completer<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+    done: bool,
+}
+
+impl<T> Completer<T> {
+    fn complete(&mut self, result: io::Result<T>) {
+        self.done = true;
+        let waker = {
+            let mut state = self.state.lock().expect("all okay");
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.complete(Err(util::failed("task was dropped before it finished")));
+        }
+    }
+}
+
+impl<T: Send + 'static> Task<T> {
+    /// Runs `job` with `spawner` and returns a task resolving to its result.
+    pub fn spawn<S, F>(spawner: &S, job: F) -> Task<T>
+        where S: Spawn + ?Sized, F: FnOnce() -> io::Result<T> + Send + 'static
+    {
+        let state = Arc::new(Mutex::new(TaskState { result: None, waker: None }));
+        let mut completer = Completer { state: state.clone(), done: false };
+        spawner.spawn(Box::new(move || {
+            let result = job();
+            completer.complete(result);
+        }));
+        Task { state: state }
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<T>> {
+        let mut state = self.state.lock().expect("all okay");
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Task<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Task { .. }")
+    }
+}
+
+/// Asynchronous version of `BlockDevice`.
+pub trait AsyncBlockDevice: Send {
+    /// Sector size in bytes. Must be a multiple of 512 >= 512. Defaults to 512.
+    fn sector_size(&self) -> u64 {
+        512
+    }
+
+    /// Read sector number `n` into `buf`, resolving to the number of bytes
+    /// read. See `BlockDevice::read_sector`.
+    fn read_sector<'a>(&'a mut self, n: u64, buf: &'a mut [u8])
+        -> BoxFuture<'a, io::Result<usize>>;
+
+    /// Overwrites sector `n` with the contents of `buf`, resolving to the
+    /// number of bytes written. See `BlockDevice::write_sector`.
+    fn write_sector<'a>(&'a mut self, n: u64, buf: &'a [u8])
+        -> BoxFuture<'a, io::Result<usize>>;
+}
+
+/// Adapts an `AsyncBlockDevice` to `BlockDevice` by waiting for each of its
+/// futures on the calling thread. A volume mounted on it must only be used
+/// from spawned jobs, such as through `vfat::async_fs`, never from a thread
+/// that polls futures.
+#[derive(Debug)]
+pub struct BlockOn<D>(pub D);
+
+impl<D: AsyncBlockDevice> BlockDevice for BlockOn<D> {
+    fn sector_size(&self) -> u64 {
+        self.0.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(self.0.read_sector(n, buf))
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        block_on(self.0.write_sector(n, buf))
+    }
+}
+
+/// Wakes a thread parked in `block_on()`.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// Polls `future` to completion on the calling thread, parking it while the
+/// future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use std::io;
+use std::{fmt, io};
+use std::boxed::Box;
+use std::vec::Vec;
 
 /// Trait implemented by devices that can be read/written in sector
 /// granularities.
@@ -10,12 +12,17 @@ pub trait BlockDevice: Send {
 
     /// Read sector number `n` into `buf`.
     ///
-    /// `self.sector_size()` or `buf.len()` bytes, whichever is less, are read
-    /// into `buf`. The number of bytes read is returned.
+    /// `buf` should hold at least `self.sector_size()` bytes, into the start
+    /// of which the sector is read. Given a shorter buffer, a device may
+    /// either read just the first `buf.len()` bytes of the sector, as devices
+    /// built on `io::Read` do, or refuse; callers wanting part of a sector
+    /// should read all of it. The number of bytes read is returned.
     ///
     /// # Errors
     ///
-    /// Returns an error if seeking or reading from `self` fails.
+    /// Returns an error if seeking or reading from `self` fails. May return
+    /// an error of `UnexpectedEof` if the length of `buf` is less than
+    /// `self.sector_size()`, as the sector cache of a mounted `VFat` does.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize>;
 
     /// Append sector number `n` into `vec`.
@@ -43,18 +50,103 @@ pub trait BlockDevice: Send {
 
     /// Overwrites sector `n` with the contents of `buf`.
     ///
-    /// `self.sector_size()` or `buf.len()` bytes, whichever is less, are written
-    /// to the sector. The number of byte written is returned.
+    /// `buf` should hold at least `self.sector_size()` bytes, the first
+    /// `self.sector_size()` of which are written. Given a shorter buffer, a
+    /// device may either write just the first `buf.len()` bytes of the
+    /// sector or refuse, as for `read_sector()`. The number of bytes written
+    /// is returned.
     ///
     /// # Errors
     ///
-    /// Returns an error if seeking or writing to `self` fails. Returns an
+    /// Returns an error if seeking or writing to `self` fails. May return an
     /// error of `UnexpectedEof` if the length of `buf` is less than
-    /// `self.sector_size()`.
+    /// `self.sector_size()`, as the sector cache of a mounted `VFat` does.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Tells the device that the `count` sectors starting at sector `n` no
+    /// longer hold data anyone needs, so that flash-backed devices and sparse
+    /// images can reclaim the space. Discarded sectors read back as
+    /// unspecified data.
+    ///
+    /// Discarding is a hint: the default implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device fails to discard the sectors or if they
+    /// lie past the end of the device.
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        let _ = (n, count);
+        Ok(())
+    }
+
+    /// Returns a new handle that reads the same sectors as `self` and can be
+    /// used on another thread at the same time as `self`, or `None` if the
+    /// device doesn't support that. The handle must only be used for reading.
+    ///
+    /// Reads of large runs of clusters are split across such handles with
+    /// the `read_threads` mount option. The default implementation returns
+    /// `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device supports readers but creating one
+    /// fails.
+    #[cfg(feature = "std")]
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        Ok(None)
+    }
+}
+
+/// A heap-allocated, dynamically dispatched block device. This is the default
+/// device type of `VFat`.
+pub type DynBlockDevice = Box<BlockDevice>;
+
+/// A heap-allocated, dynamically dispatched block device that can be read
+/// from several threads at once, for volumes guarded by a `BlockingRwLock`.
+#[cfg(feature = "rwlock")]
+pub type SyncBlockDevice = Box<BlockDevice + Sync>;
+
+impl fmt::Debug for BlockDevice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<block device>")
+    }
+}
+
+#[cfg(feature = "rwlock")]
+impl fmt::Debug for BlockDevice + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<block device>")
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for Box<T> {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        (**self).write_sector(n, buf)
+    }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        (**self).discard(n, count)
+    }
+
+    #[cfg(feature = "std")]
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        (**self).reader()
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -62,8 +154,18 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        (*self).discard(n, count)
+    }
+
+    #[cfg(feature = "std")]
+    fn reader(&self) -> io::Result<Option<Box<BlockDevice>>> {
+        (**self).reader()
+    }
 }
 
+#[cfg(feature = "std")]
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
     use std::io::{Read, Write, Seek};
 
@@ -86,7 +188,7 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
     }
 }
 
-impl_for_read_write_seek!(<'a> ::std::io::Cursor<&'a mut [u8]>);
-impl_for_read_write_seek!(::std::io::Cursor<Vec<u8>>);
-impl_for_read_write_seek!(::std::io::Cursor<Box<[u8]>>);
-#[cfg(test)] impl_for_read_write_seek!(::std::fs::File);
+#[cfg(feature = "std")] impl_for_read_write_seek!(<'a> ::std::io::Cursor<&'a mut [u8]>);
+#[cfg(feature = "std")] impl_for_read_write_seek!(::std::io::Cursor<Vec<u8>>);
+#[cfg(feature = "std")] impl_for_read_write_seek!(::std::io::Cursor<Box<[u8]>>);
+#[cfg(all(test, feature = "std"))] impl_for_read_write_seek!(::std::fs::File);
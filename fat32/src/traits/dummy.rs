@@ -58,6 +58,7 @@ impl Timestamp for Dummy {
     fn hour(&self) -> u8 { panic!("Dummy") }
     fn minute(&self) -> u8 { panic!("Dummy") }
     fn second(&self) -> u8 { panic!("Dummy") }
+    fn hundredths(&self) -> u8 { panic!("Dummy") }
 }
 
 impl Metadata for Dummy {
@@ -67,4 +68,5 @@ impl Metadata for Dummy {
     fn created(&self) -> Self::Timestamp { panic!("Dummy") }
     fn accessed(&self) -> Self::Timestamp { panic!("Dummy") }
     fn modified(&self) -> Self::Timestamp { panic!("Dummy") }
+    fn size(&self) -> u64 { panic!("Dummy") }
 }
@@ -0,0 +1,66 @@
+/// A source of random numbers, for identifiers that must differ between
+/// volumes, such as volume serial numbers and MBR disk IDs, so that copies
+/// of an image written to several SD cards don't collide.
+///
+/// Kernels can implement this over a hardware random number generator, such
+/// as the Raspberry Pi's. Closures returning `u32` implement it too.
+///
+/// ```rust
+/// use fat32::traits::{Entropy, XorShift};
+///
+/// let mut counter = 0;
+/// let mut next = || { counter += 1; counter };
+/// assert_eq!(next.next_u32(), 1);
+///
+/// let mut rng = XorShift::new(42);
+/// assert_ne!(rng.next_u32(), rng.next_u32());
+/// ```
+pub trait Entropy {
+    /// Returns the next random 32-bit value.
+    fn next_u32(&mut self) -> u32;
+}
+
+impl<F: FnMut() -> u32> Entropy for F {
+    fn next_u32(&mut self) -> u32 {
+        self()
+    }
+}
+
+/// A xorshift64* pseudo-random number generator: fast and small enough for a
+/// kernel, but predictable from its seed, so only as unique as the seed.
+/// Seed it from something that differs between boots, such as a free-running
+/// timer, or on hosts use `XorShift::from_clock()`.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Returns a generator seeded with `seed`. A seed of 0, which xorshift
+    /// can't leave, is replaced.
+    pub fn new(seed: u64) -> XorShift {
+        XorShift {
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+        }
+    }
+
+    /// Returns a generator seeded from the time and process ID.
+    #[cfg(feature = "std")]
+    pub fn from_clock() -> XorShift {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() << 30 ^ d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        XorShift::new(time ^ (::std::process::id() as u64) << 48)
+    }
+}
+
+impl Entropy for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+}
@@ -1,7 +1,11 @@
 use std::io;
+use std::ops::AddAssign;
 use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
 
 use traits::Metadata;
+use util;
 
 /// Trait implemented by files in the file system.
 pub trait File: io::Read + io::Write + io::Seek + Sized {
@@ -66,6 +70,34 @@ pub trait Entry: Sized {
     }
 }
 
+/// Storage consumed by a file or a directory tree.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Bytes allocated on disk, including slack space at the end of the last
+    /// allocation unit of every file and directory.
+    pub allocated: u64,
+    /// Sum of the sizes of all regular files.
+    pub apparent: u64,
+    /// Number of regular files.
+    pub files: u64,
+    /// Number of directories, including the starting directory.
+    pub dirs: u64,
+}
+
+impl AddAssign for DiskUsage {
+    fn add_assign(&mut self, other: DiskUsage) {
+        self.allocated += other.allocated;
+        self.apparent += other.apparent;
+        self.files += other.files;
+        self.dirs += other.dirs;
+    }
+}
+
+/// The largest file, in bytes, that `FileSystem::read()` and
+/// `FileSystem::read_to_string()` read, so that a corrupt size can't exhaust
+/// memory.
+pub const MAX_READ_SIZE: u64 = 16 << 20;
+
 /// Trait implemented by file systems.
 pub trait FileSystem: Sized {
     /// The type of files in this file system.
@@ -96,11 +128,12 @@ pub trait FileSystem: Sized {
     /// # Errors
     ///
     /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a regular file.
+    /// error kind of `InvalidInput` if the entry at `path` is not a regular
+    /// file.
     fn open_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
         self.open(path)?
             .into_file()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a regular file"))
+            .ok_or_else(|| util::invalid_input("not a regular file"))
     }
 
     /// Opens the directory at `path`. `path` must be absolute.
@@ -108,11 +141,41 @@ pub trait FileSystem: Sized {
     /// # Errors
     ///
     /// In addition to the error conditions for `open()`, this method returns an
-    /// error kind of `Other` if the entry at `path` is not a directory.
+    /// error kind of `InvalidInput` if the entry at `path` is not a directory.
     fn open_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
         self.open(path)?
             .into_dir()
-            .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
+            .ok_or_else(|| util::invalid_input("not a directory"))
+    }
+
+    /// Reads the whole file at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open_file()`, this method
+    /// returns an error kind of `InvalidInput` if the file is larger than
+    /// `MAX_READ_SIZE`.
+    fn read<P: AsRef<Path>>(self, path: P) -> io::Result<Vec<u8>> {
+        let mut file = self.open_file(path)?;
+        let size = file.size();
+        if size > MAX_READ_SIZE {
+            return Err(util::invalid_input(format!(
+                "file of {} bytes is over the {} byte limit", size, MAX_READ_SIZE)));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        io::Read::read_to_end(&mut file, &mut data)?;
+        Ok(data)
+    }
+
+    /// Reads the whole file at `path` as UTF-8. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `read()`, this method returns
+    /// an error kind of `InvalidData` if the file isn't valid UTF-8.
+    fn read_to_string<P: AsRef<Path>>(self, path: P) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|_| util::corrupt("file is not valid UTF-8"))
     }
 
     /// Creates a new file at `path`, opens it, and returns it.
@@ -184,4 +247,56 @@ pub trait FileSystem: Sized {
     ///
     /// All other error values are implementation defined.
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()>;
+
+    /// Computes the disk usage of the entry at `path`, recursing into
+    /// directories. `path` must be absolute.
+    ///
+    /// `progress` is invoked once for every directory after its subtree has
+    /// been summed, with the directory's path and usage. This allows callers
+    /// to report progress on large trees and to build per-directory totals.
+    ///
+    /// The default implementation walks the tree with `open()` and
+    /// `Dir::entries()`. As the traits don't expose allocation units, it
+    /// counts every file as allocating exactly its size and directories as
+    /// allocating nothing; implementations that know better should override
+    /// it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `open()` for `path`. Errors encountered
+    /// while traversing the tree are returned immediately.
+    fn disk_usage<P, F>(self, path: P, mut progress: F) -> io::Result<DiskUsage>
+        where P: AsRef<Path>, F: FnMut(&Path, &DiskUsage)
+    {
+        let entry = self.open(path.as_ref())?;
+        match entry.as_dir() {
+            Some(dir) => dir_usage(dir, path.as_ref(), &mut progress),
+            None => Ok(entry.as_file().map_or_else(DiskUsage::default, file_usage)),
+        }
+    }
+}
+
+/// The usage of a single file, as `FileSystem::disk_usage()` counts it by
+/// default.
+fn file_usage<F: File>(file: &F) -> DiskUsage {
+    DiskUsage { allocated: file.size(), apparent: file.size(), files: 1, dirs: 0 }
+}
+
+/// Totals the usage of `dir` and its descendants for the default
+/// `FileSystem::disk_usage()`.
+fn dir_usage<D, F>(dir: &D, path: &Path, progress: &mut F) -> io::Result<DiskUsage>
+    where D: Dir, D::Entry: Entry<Dir = D>, F: FnMut(&Path, &DiskUsage)
+{
+    let mut usage = DiskUsage { dirs: 1, ..DiskUsage::default() };
+    for entry in dir.entries()? {
+        if let Some(file) = entry.as_file() {
+            usage += file_usage(file);
+        } else if let Some(child) = entry.as_dir() {
+            if entry.name() != "." && entry.name() != ".." {
+                usage += dir_usage(child, &path.join(entry.name()), progress)?;
+            }
+        }
+    }
+    progress(path, &usage);
+    Ok(usage)
 }
@@ -0,0 +1,76 @@
+/// A raw mutual-exclusion primitive guarding the value inside a `Shared`.
+///
+/// Kernels can implement this for their own locks (for instance, a spinlock
+/// that masks interrupts while held) and mount file systems with
+/// `VFat::from_device_with_lock()` so that all accesses to the file system
+/// go through that lock.
+///
+/// A lock is shared by every clone of the `Shared` it guards, and so must be
+/// `Send` and `Sync`.
+///
+/// # Safety
+///
+/// `Shared` relies on the lock for memory safety: while `lock()` holds the
+/// lock, no other call to `lock()` or `lock_shared()` may return until
+/// `unlock()` is called. If `Readers` is `Concurrent`, a shared lock may
+/// coexist with other shared locks but never with an exclusive one; if it is
+/// `Exclusive`, shared locks must exclude each other too.
+pub unsafe trait RawLock: Send + Sync {
+    /// Whether `lock_shared()` lets several borrowers in at once: `Exclusive`,
+    /// the default, or `Concurrent`. A `Shared` guarded by a `Concurrent`
+    /// lock hands out `&T` on several threads at once, so it is only `Sync`
+    /// if `T` is.
+    type Readers = Exclusive;
+
+    /// Returns a new, unlocked lock.
+    fn new() -> Self;
+
+    /// Acquires the lock exclusively, waiting until it is available.
+    fn lock(&self);
+
+    /// Releases an exclusive lock acquired by `lock()`.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the current holder of the exclusive lock.
+    unsafe fn unlock(&self);
+
+    /// Acquires the lock for shared access, as `Shared::borrow()` does. By
+    /// default, the lock is acquired exclusively.
+    fn lock_shared(&self) {
+        self.lock()
+    }
+
+    /// Releases a shared lock acquired by `lock_shared()`.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by a current holder of a shared lock.
+    unsafe fn unlock_shared(&self) {
+        self.unlock()
+    }
+}
+
+/// The `Readers` of a lock whose shared locks exclude each other.
+#[derive(Debug)]
+pub enum Exclusive {}
+
+/// The `Readers` of a lock that admits several shared locks at once.
+#[derive(Debug)]
+pub enum Concurrent {}
+
+/// Implemented by the `Readers` of a lock for every `T` that a `Shared<T>`
+/// guarded by that lock may be shared between threads with: any `Send` type
+/// for `Exclusive` locks, and only those that are also `Sync` for
+/// `Concurrent` ones.
+///
+/// # Safety
+///
+/// `Shared` is `Sync` exactly when its lock's `Readers` implements this for
+/// the guarded type, so an implementation must not admit a `T` that the
+/// lock's shared locks would let several threads reach at once unless `T`
+/// is `Sync`.
+pub unsafe trait SyncFor<T: ?Sized> {}
+
+unsafe impl<T: ?Sized + Send> SyncFor<T> for Exclusive {}
+unsafe impl<T: ?Sized + Send + Sync> SyncFor<T> for Concurrent {}
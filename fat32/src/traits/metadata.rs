@@ -21,6 +21,10 @@ pub trait Timestamp: Copy + Clone + Sized {
 
     /// The second. Always in range [0, 60).
     fn second(&self) -> u8;
+
+    /// Hundredths of a second to add to `second()`. Always in range [0, 200)
+    /// as FAT stores seconds with a two second granularity.
+    fn hundredths(&self) -> u8;
 }
 
 /// Trait for directory entry metadata.
@@ -42,5 +46,8 @@ pub trait Metadata: Sized {
 
     /// The timestamp for the entry's last modification.
     fn modified(&self) -> Self::Timestamp;
+
+    /// The size of the entry in bytes as recorded in its directory entry.
+    fn size(&self) -> u64;
 }
 
@@ -1,9 +1,19 @@
 mod fs;
 mod block_device;
 mod metadata;
+mod lock;
+mod sd_card;
 mod dummy;
+mod entropy;
+#[cfg(feature = "async")]
+pub mod async_traits;
 
-pub use self::fs::{Dir, Entry, File, FileSystem};
+pub use self::fs::{Dir, Entry, File, FileSystem, DiskUsage, MAX_READ_SIZE};
 pub use self::metadata::{Metadata, Timestamp};
-pub use self::block_device::BlockDevice;
+pub use self::block_device::{BlockDevice, DynBlockDevice};
+#[cfg(feature = "rwlock")]
+pub use self::block_device::SyncBlockDevice;
+pub use self::lock::{RawLock, Exclusive, Concurrent, SyncFor};
+pub use self::sd_card::SdCard;
 pub use self::dummy::Dummy;
+pub use self::entropy::{Entropy, XorShift};
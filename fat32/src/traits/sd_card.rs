@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// The interface of an SD card driver, such as the cs140e SD driver or an
+/// `embedded-hal` SPI driver, needed to mount a file system from the card.
+///
+/// Implementations only forward to the driver; `device::SdDevice` adapts an
+/// `SdCard` into a `BlockDevice`, splitting requests into blocks and waiting
+/// while the card is busy.
+///
+/// ```rust
+/// # #[cfg(feature = "std")] {
+/// use fat32::traits::SdCard;
+/// use fat32::device::SdDevice;
+///
+/// // A driver that keeps the card's blocks in memory.
+/// struct Card(Vec<u8>);
+///
+/// impl SdCard for Card {
+///     type Error = &'static str;
+///
+///     fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+///         let start = block as usize * 512;
+///         let data = self.0.get(start..start + buf.len()).ok_or("out of range")?;
+///         buf.copy_from_slice(data);
+///         Ok(())
+///     }
+///
+///     fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+///         let start = block as usize * 512;
+///         let data = self.0.get_mut(start..start + buf.len()).ok_or("out of range")?;
+///         data.copy_from_slice(buf);
+///         Ok(())
+///     }
+/// }
+///
+/// let mut device = SdDevice::new(Card(vec![0; 4096]));
+/// device.write_sectors(2, &[0xAB; 1024]).unwrap();
+/// let mut buf = [0; 512];
+/// device.read_sectors(3, &mut buf).unwrap();
+/// assert_eq!(buf[0], 0xAB);
+/// # }
+/// ```
+pub trait SdCard {
+    /// The driver's error type.
+    type Error: fmt::Debug;
+
+    /// The size of the card's blocks in bytes. Must be a multiple of 512 >=
+    /// 512. Defaults to 512.
+    fn block_size(&self) -> u64 {
+        512
+    }
+
+    /// Reads `buf.len() / self.block_size()` consecutive blocks starting at
+    /// block `block` into `buf`. `buf.len()` is always a non-zero multiple
+    /// of the block size.
+    fn read_blocks(&mut self, block: u64, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `buf` to `buf.len() / self.block_size()` consecutive blocks
+    /// starting at block `block`. `buf.len()` is always a non-zero multiple
+    /// of the block size.
+    fn write_blocks(&mut self, block: u64, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Returns `true` while the card is busy, for instance programming a
+    /// previous write, and can't accept a command. Defaults to `false` for
+    /// drivers that wait internally.
+    fn is_busy(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
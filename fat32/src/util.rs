@@ -1,112 +1,175 @@
+use std::ffi::OsStr;
+use std::io;
 use std::mem::{size_of, align_of, forget};
-use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::ptr;
+use std::string::String;
+use std::vec::Vec;
 
-pub trait VecExt {
+// Constructors for every `io::Error` the crate creates, so that a given kind
+// of failure is always reported with the same `io::ErrorKind`.
+
+/// On-disk structures are inconsistent or malformed: `InvalidData`.
+pub fn corrupt<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The requested entry does not exist: `NotFound`.
+pub fn not_found<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, msg.into())
+}
+
+/// A device, buffer, or structure ended before the expected data: `UnexpectedEof`.
+pub fn eof<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg.into())
+}
+
+/// The caller passed an unusable argument or path: `InvalidInput`.
+pub fn invalid_input<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+/// The device or file may not be modified: `PermissionDenied`.
+pub fn permission_denied<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, msg.into())
+}
+
+/// The operation was stopped through a `CancelToken`: `Other`.
+pub fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "operation was cancelled")
+}
+
+/// The device failed the operation for another reason: `Other`.
+pub fn failed<M: Into<String>>(msg: M) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg.into())
+}
+
+/// Decodes the little-endian `u16` at `buf[offset..offset + 2]`.
+pub fn le_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+/// Decodes the little-endian `u32` at `buf[offset..offset + 4]`.
+pub fn le_u32(buf: &[u8], offset: usize) -> u32 {
+    le_u16(buf, offset) as u32 | (le_u16(buf, offset + 2) as u32) << 16
+}
+
+/// Decodes the little-endian `u64` at `buf[offset..offset + 8]`.
+pub fn le_u64(buf: &[u8], offset: usize) -> u64 {
+    le_u32(buf, offset) as u64 | (le_u32(buf, offset + 4) as u64) << 32
+}
+
+/// Encodes `value` as little-endian into `buf[offset..offset + 2]`.
+pub fn put_le_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+/// Encodes `value` as little-endian into `buf[offset..offset + 4]`.
+pub fn put_le_u32(buf: &mut [u8], offset: usize, value: u32) {
+    put_le_u16(buf, offset, value as u16);
+    put_le_u16(buf, offset + 2, (value >> 16) as u16);
+}
+
+/// Encodes `value` as little-endian into `buf[offset..offset + 8]`.
+pub fn put_le_u64(buf: &mut [u8], offset: usize, value: u64) {
+    put_le_u32(buf, offset, value as u32);
+    put_le_u32(buf, offset + 4, (value >> 32) as u32);
+}
+
+/// Returns the UTF-16 code units of `name`, unpaired surrogates included,
+/// on hosts whose OS strings are UTF-16: Windows. Elsewhere, a name that
+/// isn't UTF-8 has no UTF-16 form and `None` is returned.
+#[cfg(all(feature = "std", windows))]
+pub fn os_str_to_utf16(name: &OsStr) -> Option<Vec<u16>> {
+    use std::os::windows::ffi::OsStrExt;
+    Some(name.encode_wide().collect())
+}
+
+#[cfg(not(all(feature = "std", windows)))]
+pub fn os_str_to_utf16(name: &OsStr) -> Option<Vec<u16>> {
+    name.to_str().map(|name| name.encode_utf16().collect())
+}
+
+/// Compares UTF-16 names as `str::eq_ignore_ascii_case()` compares strings.
+pub fn utf16_eq_ignore_ascii_case(a: &[u16], b: &[u16]) -> bool {
+    fn upper(unit: u16) -> u16 {
+        if unit >= 'a' as u16 && unit <= 'z' as u16 { unit - 32 } else { unit }
+    }
+    a.len() == b.len() && a.iter().zip(b).all(|(&a, &b)| upper(a) == upper(b))
+}
+
+/// The reason a cast between element types was refused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CastError {
+    /// The data's length (or a vector's capacity) in bytes is not a multiple
+    /// of the target type's size.
+    Size,
+}
+
+pub trait VecExt<T> {
     /// Casts a `Vec<T>` into a `Vec<U>`.
     ///
     /// # Safety
     ///
-    /// The caller must ensure the following safety properties:
-    ///
-    ///   * The vector `self` contains valid elements of type `U`. In
-    ///     particular, note that `drop` will never be called for `T`s in `self`
-    ///     and instead will be called for the `U`'s in `self`.
-    ///   * The size and alignment of `T` and `U` are identical.
+    /// The caller must ensure that the vector `self` contains valid elements
+    /// of type `U`. In particular, note that `drop` will never be called for
+    /// `T`s in `self` and instead will be called for the `U`'s in `self`.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Panics if the size or alignment of `T` and `U` differ.
-    unsafe fn cast<U>(self) -> Vec<U>;
+    /// Returns `self` unchanged if the alignment of `T` and `U` differ or if
+    /// the length or capacity of `self`, in bytes, is not a multiple of the
+    /// size of `U`.
+    unsafe fn cast<U>(self) -> Result<Vec<U>, Vec<T>>;
 }
 
 pub trait SliceExt {
-    /// Casts an `&[T]` into an `&[U]`.
+    /// Copies the contents of `&[T]` into a new `Vec<U>`. `self` needn't be
+    /// aligned for `U`.
     ///
     /// # Safety
     ///
-    /// The caller must ensure the following safety properties:
+    /// The caller must ensure that the slice `self` contains valid elements of
+    /// type `U`.
     ///
-    ///   * The slice `self` contains valid elements of type `U`.
-    ///   * The size of `T` and `U` are identical.
-    ///   * The alignment of `T` is an integer multiple of the alignment of `U`.
+    /// # Errors
     ///
-    /// # Panics
-    ///
-    /// Panics if the size of `T` and `U` differ or if the alignment of `T` is
-    /// not an integer multiple of `U`.
-    unsafe fn cast<'a, U>(&'a self) -> &'a [U];
+    /// Returns `Size` if the length of `self`, in bytes, is not a multiple of
+    /// the size of `U`.
+    unsafe fn cast_copy<U: Copy>(&self) -> Result<Vec<U>, CastError>;
+}
 
-    /// Casts an `&mut [T]` into an `&mut [U]`.
-    ///
-    /// # Safety
-    ///
-    /// The caller must ensure the following safety properties:
-    ///
-    ///   * The slice `self` contains valid elements of type `U`.
-    ///   * The size of `T` and `U` are identical.
-    ///   * The alignment of `T` is an integer multiple of the alignment of `U`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the size of `T` and `U` differ or if the alignment of `T` is
-    /// not an integer multiple of `U`.
-    unsafe fn cast_mut<'a, U>(&'a mut self) -> &'a mut [U];
-}
-
-fn calc_new_len_cap<T, U>(vec: &Vec<T>) -> (usize, usize) {
-    if size_of::<T>() > size_of::<U>() {
-        assert!(size_of::<T>() % size_of::<U>() == 0);
-        let factor = size_of::<T>() / size_of::<U>();
-        (vec.len() * factor, vec.capacity() * factor)
-    } else if size_of::<U>() > size_of::<T>() {
-        assert!(size_of::<U>() % size_of::<T>() == 0);
-        let factor = size_of::<U>() / size_of::<T>();
-        (vec.len() / factor, vec.capacity() / factor)
-    } else {
-        (vec.len(), vec.capacity())
+/// Returns the number of `U`s that occupy exactly `count` `T`s.
+fn converted_len<T, U>(count: usize) -> Result<usize, CastError> {
+    let bytes = count.checked_mul(size_of::<T>()).ok_or(CastError::Size)?;
+    if size_of::<U>() == 0 || bytes % size_of::<U>() != 0 {
+        return Err(CastError::Size);
     }
+    Ok(bytes / size_of::<U>())
 }
 
-impl<T> VecExt for Vec<T> {
-    unsafe fn cast<U>(mut self) -> Vec<U> {
-        assert!(align_of::<T>() == align_of::<U>());
+impl<T> VecExt<T> for Vec<T> {
+    unsafe fn cast<U>(mut self) -> Result<Vec<U>, Vec<T>> {
+        if align_of::<T>() != align_of::<U>() {
+            return Err(self);
+        }
 
-        let (new_len, new_cap) = calc_new_len_cap::<T, U>(&self);
+        let (new_len, new_cap) = match (converted_len::<T, U>(self.len()),
+                                        converted_len::<T, U>(self.capacity())) {
+            (Ok(len), Ok(cap)) => (len, cap),
+            _ => return Err(self),
+        };
         let new_ptr = self.as_mut_ptr() as *mut U;
         forget(self);
 
-        Vec::from_raw_parts(new_ptr, new_len, new_cap)
-    }
-}
-
-fn calc_new_len<T, U>(slice: &[T]) -> usize {
-    if size_of::<T>() > size_of::<U>() {
-        assert!(size_of::<T>() % size_of::<U>() == 0);
-        let factor = size_of::<T>() / size_of::<U>();
-        slice.len() * factor
-    } else if size_of::<U>() > size_of::<T>() {
-        assert!(size_of::<U>() % size_of::<T>() == 0);
-        let factor = size_of::<U>() / size_of::<T>();
-        slice.len() / factor
-    } else {
-        slice.len()
+        Ok(Vec::from_raw_parts(new_ptr, new_len, new_cap))
     }
 }
 
 impl<T> SliceExt for [T] {
-    unsafe fn cast<'a, U>(&'a self) -> &'a [U] {
-        assert!(align_of::<T>() % align_of::<U>() == 0);
-
-        let new_len = calc_new_len::<T, U>(self);
-        let new_ptr = self.as_ptr() as *const U;
-        from_raw_parts(new_ptr, new_len)
-    }
-
-    unsafe fn cast_mut<'a, U>(&'a mut self) -> &'a mut [U] {
-        assert!(align_of::<T>() % align_of::<U>() == 0);
-
-        let new_len = calc_new_len::<T, U>(self);
-        let new_ptr = self.as_mut_ptr() as *mut U;
-        from_raw_parts_mut(new_ptr, new_len)
+    unsafe fn cast_copy<U: Copy>(&self) -> Result<Vec<U>, CastError> {
+        let new_len = converted_len::<T, U>(self.len())?;
+        let src = self.as_ptr() as *const U;
+        Ok((0..new_len).map(|i| ptr::read_unaligned(src.offset(i as isize))).collect())
     }
 }
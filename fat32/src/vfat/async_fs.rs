@@ -0,0 +1,132 @@
+//! The functions of `vfat::fs` as futures, for reading volumes from an async
+//! runtime without blocking its threads. Each operation runs on the volume's
+//! `Spawn`er, and resolves once it has finished there.
+//!
+//! ```rust,no_run
+//! # fn main() -> std::io::Result<()> {
+//! use fat32::device::ImageFile;
+//! use fat32::traits::async_traits::{block_on, ThreadSpawner};
+//! use fat32::vfat::{async_fs::AsyncVolume, VFat};
+//!
+//! let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+//! let volume = AsyncVolume::new(vfat, ThreadSpawner);
+//! let config = block_on(volume.read("/config.txt"))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use traits::async_traits::{Spawn, Task};
+use traits::{BlockDevice, DynBlockDevice, FileSystem, RawLock};
+use vfat::fs::{self, DirEntry, Metadata};
+use vfat::{DefaultLock, File, Shared, VFat};
+
+/// A mounted volume whose operations run on a `Spawn`er.
+pub struct AsyncVolume<S, D = DynBlockDevice, L: RawLock = DefaultLock> {
+    vfat: Shared<VFat<D, L>, L>,
+    spawner: Arc<S>,
+}
+
+/// An open file whose reads run on a `Spawn`er. A read or seek waits for the
+/// ones started before it.
+pub struct AsyncFile<S, D = DynBlockDevice, L: RawLock = DefaultLock> {
+    file: Arc<Mutex<File<D, L>>>,
+    size: u64,
+    spawner: Arc<S>,
+}
+
+impl<S, D, L> AsyncVolume<S, D, L>
+    where S: Spawn, D: BlockDevice + 'static, L: RawLock + 'static,
+          Shared<VFat<D, L>, L>: Send
+{
+    /// Returns `vfat` with its operations run by `spawner`.
+    pub fn new(vfat: Shared<VFat<D, L>, L>, spawner: S) -> AsyncVolume<S, D, L> {
+        AsyncVolume { vfat: vfat, spawner: Arc::new(spawner) }
+    }
+
+    /// Reads the whole file at `path`. See `fs::read()`.
+    pub fn read<P: Into<PathBuf>>(&self, path: P) -> Task<Vec<u8>> {
+        let (vfat, path) = (self.vfat.clone(), path.into());
+        Task::spawn(&*self.spawner, move || fs::read(&vfat, path))
+    }
+
+    /// Returns the entries of the directory at `path`. See `fs::read_dir()`.
+    pub fn read_dir<P: Into<PathBuf>>(&self, path: P) -> Task<Vec<DirEntry<D, L>>> {
+        let (vfat, path) = (self.vfat.clone(), path.into());
+        Task::spawn(&*self.spawner, move || fs::read_dir(&vfat, path)?.collect())
+    }
+
+    /// Returns information about the file or directory at `path`. See
+    /// `fs::metadata()`.
+    pub fn metadata<P: Into<PathBuf>>(&self, path: P) -> Task<Metadata> {
+        let (vfat, path) = (self.vfat.clone(), path.into());
+        Task::spawn(&*self.spawner, move || fs::metadata(&vfat, path))
+    }
+
+    /// Opens the file at `path`. See `FileSystem::open_file()`.
+    pub fn open_file<P: Into<PathBuf>>(&self, path: P) -> Task<AsyncFile<S, D, L>>
+        where S: 'static
+    {
+        let (vfat, path, spawner) = (self.vfat.clone(), path.into(), self.spawner.clone());
+        Task::spawn(&*self.spawner, move || {
+            let file = (&vfat).open_file(path)?;
+            Ok(AsyncFile {
+                size: file.size as u64,
+                file: Arc::new(Mutex::new(file)),
+                spawner: spawner,
+            })
+        })
+    }
+
+    /// Writes every change to the volume back to its device. See
+    /// `VFat::flush()`.
+    pub fn flush(&self) -> Task<()> {
+        let vfat = self.vfat.clone();
+        Task::spawn(&*self.spawner, move || vfat.borrow_mut().flush())
+    }
+}
+
+impl<S, D, L> AsyncFile<S, D, L>
+    where S: Spawn, D: BlockDevice + 'static, L: RawLock + 'static,
+          Shared<VFat<D, L>, L>: Send
+{
+    /// Returns the size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads up to `len` bytes from the current position, resolving to the
+    /// bytes read, which are fewer only at the end of the file.
+    pub fn read(&self, len: usize) -> Task<Vec<u8>> {
+        let file = self.file.clone();
+        Task::spawn(&*self.spawner, move || {
+            let mut file = file.lock().expect("all okay");
+            let mut data = Vec::with_capacity(len);
+            file.by_ref().take(len as u64).read_to_end(&mut data)?;
+            Ok(data)
+        })
+    }
+
+    /// Seeks to `pos`, resolving to the new position. See `io::Seek::seek`.
+    pub fn seek(&self, pos: SeekFrom) -> Task<u64> {
+        let file = self.file.clone();
+        Task::spawn(&*self.spawner, move || file.lock().expect("all okay").seek(pos))
+    }
+}
+
+// Hand-written so that `S`, `D` and `L` needn't be `Debug`.
+impl<S, D, L: RawLock> ::std::fmt::Debug for AsyncVolume<S, D, L> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str("AsyncVolume { .. }")
+    }
+}
+
+impl<S, D, L: RawLock> ::std::fmt::Debug for AsyncFile<S, D, L> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("AsyncFile").field("size", &self.size).finish()
+    }
+}
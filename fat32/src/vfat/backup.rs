@@ -0,0 +1,84 @@
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+use std::vec::Vec;
+
+#[cfg(feature = "std")]
+use util::{self, le_u32, le_u64, put_le_u32, put_le_u64};
+
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 8] = b"FAT32BAK";
+#[cfg(feature = "std")]
+const VERSION: u32 = 1;
+
+/// A copy of sectors of a volume's FAT, taken with `VFat::backup_fat()`
+/// before an operation that rewrites the FAT, so that
+/// `VFat::restore_fat()` can roll the FAT back if the operation is
+/// interrupted.
+///
+/// A backup is held in memory. With the `std` feature it can be kept in a
+/// host file with `save()` and `load()`, to survive the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FatBackup {
+    pub(crate) bytes_per_sector: u32,
+    /// Pairs of a sector's index in the FAT and its contents, in order.
+    pub(crate) sectors: Vec<(u64, Vec<u8>)>,
+}
+
+impl FatBackup {
+    /// Returns the number of FAT sectors in the backup.
+    pub fn len(&self) -> usize {
+        self.sectors.len()
+    }
+
+    /// Writes the backup to `out`.
+    ///
+    /// The backup is written as a 20-byte header, `FAT32BAK`, the version,
+    /// 1, the number of sectors, and the sector size, followed by each
+    /// sector's 8-byte index in the FAT and its contents. Integers are
+    /// little-endian.
+    #[cfg(feature = "std")]
+    pub fn save<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut header = [0; 20];
+        header[..8].copy_from_slice(MAGIC);
+        put_le_u32(&mut header, 8, VERSION);
+        put_le_u32(&mut header, 12, self.sectors.len() as u32);
+        put_le_u32(&mut header, 16, self.bytes_per_sector);
+        out.write_all(&header)?;
+        for &(index, ref data) in self.sectors.iter() {
+            let mut buf = [0; 8];
+            put_le_u64(&mut buf, 0, index);
+            out.write_all(&buf)?;
+            out.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a backup written by `save()` from `input`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if `input` doesn't hold a backup.
+    #[cfg(feature = "std")]
+    pub fn load<R: Read>(mut input: R) -> io::Result<FatBackup> {
+        let mut header = [0; 20];
+        input.read_exact(&mut header)?;
+        if &header[..8] != MAGIC || le_u32(&header, 8) != VERSION {
+            return Err(util::corrupt("not a FAT backup"));
+        }
+        let len = le_u32(&header, 12) as usize;
+        let bytes_per_sector = le_u32(&header, 16);
+        if bytes_per_sector == 0 || bytes_per_sector > 4096 {
+            return Err(util::corrupt("FAT backup has an invalid sector size"));
+        }
+
+        let mut sectors = Vec::new();
+        for _ in 0..len {
+            let mut index = [0; 8];
+            input.read_exact(&mut index)?;
+            let mut data = vec![0; bytes_per_sector as usize];
+            input.read_exact(&mut data)?;
+            sectors.push((le_u64(&index, 0), data));
+        }
+        Ok(FatBackup { bytes_per_sector: bytes_per_sector, sectors: sectors })
+    }
+}
@@ -1,8 +1,12 @@
 use std::{io, fmt};
-use std::collections::HashMap;
-use std::cmp::min;
+use std::boxed::Box;
+use std::collections::BTreeMap;
+use std::ops::{Deref, Range};
+use std::vec::Vec;
 
-use traits::BlockDevice;
+use traits::{BlockDevice, DynBlockDevice, RawLock, SyncFor};
+use util;
+use vfat::{DefaultLock, EvictionPolicy, Journal, Lru, Shared, WithContext};
 
 #[derive(Debug, Default)]
 struct CacheEntry {
@@ -10,76 +14,164 @@ struct CacheEntry {
     dirty: bool
 }
 
-#[derive(Debug)]
-pub struct Partition {
-    /// The physical sector where the partition begins.
-    pub start: u64,
-    /// The size, in bytes, of a logical sector in the partition.
-    pub sector_size: u64
+/// A write-back cache of the sectors of a device.
+///
+/// Every access to the device itself goes through the lock `L`, so a device
+/// can be shared, with `CachedDevice::shared()`, by several caches, such as
+/// those of volumes on different partitions of one SD card, or with other
+/// code of a kernel that guards the device with its own `RawLock`.
+pub struct CachedDevice<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    device: Shared<D, L>,
+    cache: BTreeMap<u64, CacheEntry>,
+    /// The most sectors held before clean ones are evicted; 0 is unbounded.
+    capacity: usize,
+    policy: Box<EvictionPolicy>,
+    /// Sectors that are never evicted.
+    pinned: Vec<Range<u64>>,
+    /// The number of sectors evicted so far.
+    evicted: u64,
 }
 
-pub struct CachedDevice {
-    device: Box<BlockDevice>,
-    cache: HashMap<u64, CacheEntry>,
-    partition: Partition
-}
-
-impl CachedDevice {
+impl<D: BlockDevice, L: RawLock> CachedDevice<D, L> {
     /// Creates a new `CachedDevice` that transparently caches sectors from
-    /// `device` and maps physical sectors to logical sectors inside of
-    /// `partition`. All reads and writes from `CacheDevice` are performed on
+    /// `device`. All reads and writes from `CacheDevice` are performed on
     /// in-memory caches.
     ///
-    /// The `partition` parameter determines the size of a logical sector and
-    /// where logical sectors begin. An access to a sector `n` _before_
-    /// `partition.start` is made to physical sector `n`. Cached sectors before
-    /// `partition.start` are the size of a physical sector. An access to a
-    /// sector `n` at or after `partition.start` is made to the _logical_ sector
-    /// `n - partition.start`. Cached sectors at or after `partition.start` are
-    /// the size of a logical sector, `partition.sector_size`.
+    /// To cache a partition of a device, wrap the device in a
+    /// `PartitionSlice` first: cached sectors are the size of `device`'s.
     ///
-    /// `partition.sector_size` must be an integer multiple of
-    /// `device.sector_size()`.
+    /// The cache is unbounded; use `set_policy()` to limit it.
+    pub fn new(device: D) -> CachedDevice<D, L> {
+        CachedDevice::shared(Shared::with_lock(device))
+    }
+
+    /// Creates a `CachedDevice` like `new()` over `device`, a device shared
+    /// with other users, each of which accesses it only while holding its
+    /// lock.
     ///
-    /// # Panics
+    /// Caches don't see each other's changes until they are flushed, so
+    /// caches sharing a device should cover disjoint sectors.
+    pub fn shared(device: Shared<D, L>) -> CachedDevice<D, L> {
+        CachedDevice {
+            device: device,
+            cache: BTreeMap::new(),
+            capacity: 0,
+            policy: Box::new(Lru::new()),
+            pinned: Vec::new(),
+            evicted: 0,
+        }
+    }
+
+    /// Limits the cache to `capacity` sectors, 0 meaning unbounded, evicting
+    /// clean sectors chosen by `policy` to make room. Dirty and pinned
+    /// sectors are never evicted, so the cache may exceed `capacity` until
+    /// they are flushed or unpinned.
     ///
-    /// Panics if the partition's sector size is < the device's sector size.
-    pub fn new<T>(device: T, partition: Partition) -> CachedDevice
-        where T: BlockDevice + 'static
-    {
-        assert!(partition.sector_size >= device.sector_size());
+    /// Sectors already cached are handed to `policy` in order, and evicted
+    /// down to `capacity` if need be.
+    pub fn set_policy(&mut self, capacity: usize, mut policy: Box<EvictionPolicy>) {
+        for &sector in self.cache.keys() {
+            policy.insert(sector);
+        }
+        self.capacity = capacity;
+        self.policy = policy;
+        self.evict(0);
+    }
 
-        CachedDevice {
-            device: Box::new(device),
-            cache: HashMap::new(),
-            partition: partition
+    /// Keeps `sectors` in the cache once they are read, whatever the
+    /// eviction policy.
+    pub fn pin(&mut self, sectors: Range<u64>) {
+        self.pinned.push(sectors);
+    }
+
+    /// Undoes a `pin()` of exactly `sectors`.
+    pub fn unpin(&mut self, sectors: Range<u64>) {
+        if let Some(i) = self.pinned.iter().position(|pinned| *pinned == sectors) {
+            self.pinned.remove(i);
         }
     }
 
-    /// Maps a user's request for a sector `virt` to the physical sector and
-    /// number of physical sectors required to access `virt`.
-    fn virtual_to_physical(&self, virt: u64) -> (u64, u64) {
-        if self.device.sector_size() == self.partition.sector_size {
-            (virt, 1)
-        } else if virt < self.partition.start {
-            (virt, 1)
-        } else {
-            let factor = self.partition.sector_size / self.device.sector_size();
-            let logical_offset = virt - self.partition.start;
-            let physical_offset = logical_offset * factor;
-            let physical_sector = self.partition.start + physical_offset;
-            (physical_sector, factor)
+    /// Returns the number of sectors evicted to make room so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Evicts clean, unpinned sectors until `room` more fit within the
+    /// capacity or none can be evicted.
+    fn evict(&mut self, room: usize) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.cache.len() + room > self.capacity {
+            let victim = {
+                let (cache, pinned) = (&self.cache, &self.pinned);
+                self.policy.victim(&mut |sector| {
+                    !cache[&sector].dirty && !pinned.iter().any(|r| r.contains(&sector))
+                })
+            };
+            match victim {
+                Some(sector) => {
+                    self.cache.remove(&sector);
+                    self.policy.remove(sector);
+                    self.evicted += 1;
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Caches `entry` as sector `sector`, first making room for it.
+    fn insert(&mut self, sector: u64, entry: CacheEntry) {
+        self.evict(1);
+        self.cache.insert(sector, entry);
+        self.policy.insert(sector);
+    }
+
+    /// Drops the cached copy of `sector`, if any.
+    fn remove(&mut self, sector: u64) {
+        if self.cache.remove(&sector).is_some() {
+            self.policy.remove(sector);
         }
     }
 
+    /// Returns a borrow of the cached device, holding its lock until
+    /// dropped.
+    pub fn inner<'a>(&'a self) -> impl Deref<Target = D> + 'a {
+        self.device.borrow()
+    }
+
+    /// Returns the shared device, to share it with another cache.
+    pub fn shared_device(&self) -> Shared<D, L> {
+        self.device.clone()
+    }
+
+    fn device_sector_size(&self) -> u64 {
+        self.device.borrow().sector_size()
+    }
+
+    /// Returns the number of cached sectors.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the number of cached sectors not yet written back.
+    pub fn dirty_len(&self) -> usize {
+        self.cache.values().filter(|entry| entry.dirty).count()
+    }
+
+    /// Returns `true` if any of `sectors` is cached.
+    pub fn is_cached(&self, sectors: Range<u64>) -> bool {
+        self.cache.range(sectors).next().is_some()
+    }
+
     fn read_entry_from_dev(&mut self, sector: u64)
         -> io::Result<CacheEntry> {
-        let (phy_sec, factor) = self.virtual_to_physical(sector);
-        let mut data = Vec::with_capacity((self.device.sector_size() * factor) as usize);
-//        println!("virt {} phys {} factor {}", sector, phy_sec, factor);
-        for i in 0..factor {
-//            println!("reading physical sector {}", phy_sec + i);
-            self.device.read_all_sector(phy_sec + i, &mut data)?;
+        let sector_size = self.device_sector_size();
+        let mut data = Vec::with_capacity(sector_size as usize);
+        let read = self.device.borrow_mut().read_all_sector(sector, &mut data)
+            .at_sector(sector)?;
+        if read as u64 != sector_size {
+            return Err(util::eof("short read from device")).at_sector(sector);
         }
         let entry = CacheEntry {
             data : data,
@@ -99,11 +191,15 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        if !self.cache.contains_key(&sector) {
+        if self.cache.contains_key(&sector) {
+            self.policy.touch(sector);
+        } else {
             let entry = self.read_entry_from_dev(sector)?;
-            self.cache.insert(sector, entry);
+            self.insert(sector, entry);
         }
-        Ok(&mut self.cache.get_mut(&sector).unwrap().data)
+        let entry = self.cache.get_mut(&sector).unwrap();
+        entry.dirty = true;
+        Ok(&mut entry.data)
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -114,42 +210,207 @@ impl CachedDevice {
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
 //        println!("getting sector {}", sector);
-        if !self.cache.contains_key(&sector) {
+        if self.cache.contains_key(&sector) {
+            self.policy.touch(sector);
+        } else {
             let entry = self.read_entry_from_dev(sector)?;
-            self.cache.insert(sector, entry);
+            self.insert(sector, entry);
         }
         Ok(&self.cache.get(&sector).unwrap().data)
     }
+
+    /// Copies `buf.len()` bytes starting at byte `offset` of the cached
+    /// sector `sector` into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the range extends past the end of
+    /// the sector, or an error if the sector can't be read from the disk.
+    pub fn read_at(&mut self, sector: u64, offset: usize, buf: &mut [u8])
+        -> io::Result<()>
+    {
+        let sec = self.get(sector)?;
+        let data = sector_range(sec.len(), offset, buf.len()).map(|r| &sec[r])?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Copies bytes from sector `sector` into `buf` like `read_at()`, but
+    /// reads a sector that isn't cached from the device without caching it,
+    /// so that reading a whole device doesn't fill the cache.
+    pub fn read_uncached_at(&mut self, sector: u64, offset: usize, buf: &mut [u8])
+        -> io::Result<()>
+    {
+        if let Some(entry) = self.cache.get(&sector) {
+            let range = sector_range(entry.data.len(), offset, buf.len())?;
+            buf.copy_from_slice(&entry.data[range]);
+            return Ok(());
+        }
+        let entry = self.read_entry_from_dev(sector)?;
+        let range = sector_range(entry.data.len(), offset, buf.len())?;
+        buf.copy_from_slice(&entry.data[range]);
+        Ok(())
+    }
+
+    /// Writes the whole sector `sector` from `buf` straight to the device,
+    /// dropping any cached copy, so that overwriting a large area doesn't
+    /// fill the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `UnexpectedEof` if the device writes less than a
+    /// sector.
+    pub fn write_uncached(&mut self, sector: u64, buf: &[u8]) -> io::Result<()> {
+        self.remove(sector);
+        let written = self.device.borrow_mut().write_sector(sector, buf).at_sector(sector)?;
+        if written != self.device_sector_size() as usize {
+            return Err(util::eof("short write to device")).at_sector(sector);
+        }
+        Ok(())
+    }
+
+    /// Copies `buf` into the cached sector `sector` starting at byte
+    /// `offset`, marking the sector dirty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if the range extends past the end of
+    /// the sector, or an error if the sector can't be read from the disk.
+    pub fn write_at(&mut self, sector: u64, offset: usize, buf: &[u8]) -> io::Result<()> {
+        let range = sector_range(self.get(sector)?.len(), offset, buf.len())?;
+        self.get_mut(sector)?[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    /// Drops the discarded sectors from the cache, dirty or not, and passes
+    /// the discard on to the device.
+    pub fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        let end = n.checked_add(count)
+                   .ok_or_else(|| util::invalid_input("discarded sectors overflow"))?;
+        let cached: Vec<u64> = self.cache.range(n..end).map(|(&sector, _)| sector).collect();
+        for sector in cached {
+            self.remove(sector);
+        }
+        self.device.borrow_mut().discard(n, count)
+    }
+
+    /// Writes every dirty cached sector back to the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the device fails. Sectors not
+    /// yet written back stay dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut device = self.device.borrow_mut();
+        for (&sector, entry) in self.cache.iter_mut().filter(|&(_, ref entry)| entry.dirty) {
+            let written = device.write_sector(sector, &entry.data).at_sector(sector)?;
+            if written != entry.data.len() {
+                return Err(util::eof("short write to device")).at_sector(sector);
+            }
+            entry.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Writes every dirty cached sector back to the device through
+    /// `journal`: the sectors are committed to the journal, as one
+    /// transaction, before being written in place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput`, without writing anything, if there
+    /// are more dirty sectors than the journal holds, since splitting them
+    /// over several transactions would let a crash leave the flush half done.
+    /// Returns an error if writing to the device fails. Sectors not yet
+    /// written back stay dirty.
+    pub(crate) fn flush_journaled(&mut self, journal: &Journal) -> io::Result<()> {
+        let dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        if dirty.len() > journal.capacity() {
+            return Err(util::invalid_input(format!(
+                "{} dirty sectors don't fit in a journal of {}; flush more often",
+                dirty.len(), journal.capacity())));
+        }
+
+        let mut device = self.device.borrow_mut();
+        {
+            let cache = &self.cache;
+            let sectors: Vec<(u64, &[u8])> = dirty.iter()
+                .map(|sector| (*sector, &cache[sector].data[..]))
+                .collect();
+            journal.commit(&mut *device, &sectors)?;
+        }
+        for &sector in &dirty {
+            let entry = self.cache.get_mut(&sector).unwrap();
+            let written = device.write_sector(sector, &entry.data).at_sector(sector)?;
+            if written != entry.data.len() {
+                return Err(util::eof("short write to device")).at_sector(sector);
+            }
+            entry.dirty = false;
+        }
+        journal.clear(&mut *device)
+    }
 }
 
-// FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
-// `write_sector` methods should only read/write from/to cached sectors.
-impl BlockDevice for CachedDevice {
+/// Returns the range of `len` bytes at `offset` in a sector of `sector_len`
+/// bytes, or an `InvalidInput` error if the range doesn't fit.
+fn sector_range(sector_len: usize, offset: usize, len: usize) -> io::Result<Range<usize>> {
+    match offset.checked_add(len) {
+        Some(end) if end <= sector_len => Ok(offset..end),
+        _ => Err(util::invalid_input(format!(
+            "{} bytes at offset {} don't fit in a {} byte sector", len, offset, sector_len))),
+    }
+}
+
+/// Sectors are read and written whole: a buffer shorter than the sector is an
+/// error of `UnexpectedEof` rather than a partial transfer. Use `read_at()`
+/// and `write_at()` to access part of a sector.
+///
+/// With a `Concurrent` lock, the shared device must also be `Sync`.
+impl<D: BlockDevice, L: RawLock> BlockDevice for CachedDevice<D, L>
+    where L::Readers: SyncFor<D>
+{
+    fn sector_size(&self) -> u64 {
+        self.device_sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         let sec = self.get(n)?;
-        let len = min(sec.len(), buf.len());
-        buf[..len].copy_from_slice(&sec[..len]);
-        Ok(len)
+        if buf.len() < sec.len() {
+            return Err(util::eof("buffer is smaller than the sector"));
+        }
+        buf[..sec.len()].copy_from_slice(sec);
+        Ok(sec.len())
     }
 
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
-        if buf.len() < self.sector_size() as usize {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                      "buffer too small"));
+        let len = self.get(n)?.len();
+        if buf.len() < len {
+            return Err(util::eof("buffer is smaller than the sector"));
         }
-        let sec = self.get_mut(n)?;
-        let len = min(sec.len(), buf.len());
-        sec[..len].copy_from_slice(&buf[..len]);
+        self.get_mut(n)?.copy_from_slice(&buf[..len]);
         Ok(len)
     }
+
+    fn discard(&mut self, n: u64, count: u64) -> io::Result<()> {
+        CachedDevice::discard(self, n, count)
+    }
 }
 
-impl fmt::Debug for CachedDevice {
+impl<D, L: RawLock> fmt::Debug for CachedDevice<D, L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CachedDevice")
 //            .field("device", &"<block device>")
             .field("cache", &self.cache)
-            .field("partition", &self.partition)
+            .field("capacity", &self.capacity)
+            .field("pinned", &self.pinned)
+            .field("evicted", &self.evicted)
             .finish()
     }
 }
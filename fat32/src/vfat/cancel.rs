@@ -0,0 +1,48 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use util;
+
+/// A request to stop the long operations on a volume, such as walking its
+/// tree, scanning its FAT, or extracting it, obtained with
+/// `VFat::cancel_token()`.
+///
+/// Clones share the request, so a host tool can keep one to cancel from a
+/// signal handler or another thread, and a kernel can cancel on shutdown.
+/// Operations check for it only where stopping leaves the sector cache and
+/// any journal transaction consistent, and then fail with an error of kind
+/// `Other`. Every later operation fails the same way until the token is
+/// `reset()`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Returns a token that hasn't been cancelled.
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Asks the operations checking this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Withdraws the request to stop, so that operations can run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the token has been cancelled and not reset since.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns an error if the token has been cancelled.
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            return Err(util::cancelled());
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,18 @@
+/// The first cluster of the data region. Clusters 0 and 1 are reserved: their
+/// FAT entries hold the media descriptor and volume flags.
+pub const FIRST_DATA_CLUSTER: u32 = 2;
+
+/// The last cluster number FAT32 can address; the values above it are
+/// reserved, mark bad clusters, or end a chain.
+pub const MAX_DATA_CLUSTER: u32 = 0x0FFFFFEF;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
 pub struct Cluster(u32);
 
 impl From<u32> for Cluster {
+    /// Converts a raw FAT entry or directory entry value, dropping the 4
+    /// reserved high bits. The result may not be a data cluster; see
+    /// `Cluster::data()` for a checked conversion.
     fn from(raw_num: u32) -> Cluster {
         Cluster(raw_num & !(0xF << 28))
     }
@@ -10,10 +20,62 @@ impl From<u32> for Cluster {
 
 // TODO: Implement any useful helper methods on `Cluster`.
 impl Cluster {
+    /// Returns the data cluster numbered `raw`, or `None` if `raw` is a
+    /// reserved value: 0, 1, or above `MAX_DATA_CLUSTER`, including values
+    /// with any of the 4 high bits set.
+    pub fn data(raw: u32) -> Option<Cluster> {
+        let cluster = Cluster(raw);
+        if cluster.is_valid_data_cluster() {
+            Some(cluster)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the data cluster numbered `raw` on a volume whose last
+    /// cluster is `max_cluster`, or `None` if `raw` lies outside of its data
+    /// region.
+    pub fn data_within(raw: u32, max_cluster: u32) -> Option<Cluster> {
+        Cluster::data(raw).filter(|_| raw <= max_cluster)
+    }
+
     pub fn get_index(&self) -> u32 { self.0 }
 
-    pub fn get_offset(&self) -> Option<u32> { 
-        self.0.checked_sub(2)
+    /// Returns the position of `self` in the data region, counting from 0,
+    /// or `None` for clusters 0 and 1.
+    pub fn get_offset(&self) -> Option<u32> {
+        self.0.checked_sub(FIRST_DATA_CLUSTER)
+    }
+
+    /// Whether `self` could number a cluster of the data region of some
+    /// FAT32 volume.
+    pub fn is_valid_data_cluster(&self) -> bool {
+        self.0 >= FIRST_DATA_CLUSTER && self.0 <= MAX_DATA_CLUSTER
+    }
+
+    /// Returns the first sector of `self`, on a volume whose data region
+    /// starts at sector `data_start_sector` with `sectors_per_cluster`
+    /// sectors to a cluster, or `None` for clusters 0 and 1.
+    pub fn first_sector(&self, data_start_sector: u64, sectors_per_cluster: u8) -> Option<u64> {
+        self.get_offset()
+            .map(|offset| data_start_sector + offset as u64 * sectors_per_cluster as u64)
     }
-}
 
+    /// Returns the cluster holding `sector`, the inverse of
+    /// `first_sector()`, or `None` if `sector` precedes the data region.
+    pub fn from_sector(sector: u64, data_start_sector: u64, sectors_per_cluster: u8)
+        -> Option<Cluster>
+    {
+        let offset = sector.checked_sub(data_start_sector)? / sectors_per_cluster as u64;
+        if offset > (MAX_DATA_CLUSTER - FIRST_DATA_CLUSTER) as u64 {
+            return None;
+        }
+        Some(Cluster(offset as u32 + FIRST_DATA_CLUSTER))
+    }
+
+    /// Returns the cluster `n` clusters after `self`, or `None` if it would
+    /// lie past `MAX_DATA_CLUSTER`.
+    pub fn checked_add(&self, n: u32) -> Option<Cluster> {
+        self.0.checked_add(n).and_then(Cluster::data)
+    }
+}
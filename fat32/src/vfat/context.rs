@@ -0,0 +1,125 @@
+use std::{fmt, io};
+use std::path::{Path, PathBuf};
+
+use vfat::Cluster;
+
+/// Where in the file system an I/O error occurred. Each field is filled in by
+/// the layer that knows it as the error is returned.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The path, or name, of the file or directory being accessed.
+    pub path: Option<PathBuf>,
+    /// The index of the cluster being accessed.
+    pub cluster: Option<u32>,
+    /// The physical sector being read from the device.
+    pub sector: Option<u64>,
+}
+
+/// An I/O error together with the context in which it occurred.
+///
+/// Errors returned by the file system carry a `ContextError` as their inner
+/// error, with the `io::ErrorKind` of the original error. Use
+/// `ContextError::of()` to retrieve the context.
+#[derive(Debug)]
+pub struct ContextError {
+    context: ErrorContext,
+    error: io::Error,
+}
+
+impl ContextError {
+    /// The context of the error.
+    pub fn context(&self) -> &ErrorContext {
+        &self.context
+    }
+
+    /// The original error.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// Returns the context attached to `error`, if any.
+    #[cfg(feature = "std")]
+    pub fn of(error: &io::Error) -> Option<&ErrorContext> {
+        error.get_ref()
+             .and_then(|e| e.downcast_ref::<ContextError>())
+             .map(|e| &e.context)
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+
+        let mut sep = " (";
+        if let Some(ref path) = self.context.path {
+            write!(f, "{}path {}", sep, path.display())?;
+            sep = ", ";
+        }
+        if let Some(cluster) = self.context.cluster {
+            write!(f, "{}cluster {}", sep, cluster)?;
+            sep = ", ";
+        }
+        if let Some(sector) = self.context.sector {
+            write!(f, "{}sector {}", sep, sector)?;
+            sep = ", ";
+        }
+        if sep == ", " {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for ContextError {
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Attaches context to the error of an `io::Result`. The cluster and sector
+/// closest to the failure are kept, while outer layers replace the path with
+/// a more complete one.
+pub(crate) trait WithContext: Sized {
+    fn with_context<F: FnOnce(&mut ErrorContext)>(self, f: F) -> Self;
+
+    fn at_sector(self, sector: u64) -> Self {
+        self.with_context(|c| { c.sector.get_or_insert(sector); })
+    }
+
+    fn in_cluster(self, cluster: Cluster) -> Self {
+        self.with_context(|c| { c.cluster.get_or_insert(cluster.get_index()); })
+    }
+
+    fn at_path<P: AsRef<Path>>(self, path: P) -> Self {
+        self.with_context(|c| c.path = Some(path.as_ref().to_path_buf()))
+    }
+}
+
+impl<T> WithContext for io::Result<T> {
+    fn with_context<F: FnOnce(&mut ErrorContext)>(self, f: F) -> Self {
+        self.map_err(|error| add_context(error, f))
+    }
+}
+
+#[cfg(feature = "std")]
+fn add_context<F: FnOnce(&mut ErrorContext)>(mut error: io::Error, f: F) -> io::Error {
+    if let Some(inner) = error.get_mut().and_then(|e| e.downcast_mut::<ContextError>()) {
+        f(&mut inner.context);
+        return error;
+    }
+
+    let mut context = ErrorContext::default();
+    f(&mut context);
+    io::Error::new(error.kind(), ContextError { context: context, error: error })
+}
+
+/// Without `std`, errors can only carry a message, so the context is rendered
+/// into it as the error passes through each layer.
+#[cfg(not(feature = "std"))]
+fn add_context<F: FnOnce(&mut ErrorContext)>(error: io::Error, f: F) -> io::Error {
+    let mut context = ErrorContext::default();
+    f(&mut context);
+    let kind = error.kind();
+    io::Error::new(kind, format!("{}", ContextError { context: context, error: error }))
+}
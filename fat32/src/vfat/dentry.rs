@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+use std::string::String;
+use std::vec::Vec;
+
+use traits::{BlockDevice, RawLock};
+use vfat::{Cluster, Dir, Entry, EntryLocation, File, Metadata, Shared, VFat};
+
+/// What a path resolved to: enough of its directory entry to rebuild the
+/// `Entry` without reading its parent directory again.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedEntry {
+    name: String,
+    short_name: String,
+    path: PathBuf,
+    first_cluster: Cluster,
+    metadata: Metadata,
+    size: u32,
+    is_dir: bool,
+    location: Option<EntryLocation>,
+}
+
+impl CachedEntry {
+    pub fn from_entry<D: BlockDevice, L: RawLock>(entry: &Entry<D, L>) -> CachedEntry {
+        match *entry {
+            Entry::File(ref file) => CachedEntry {
+                name: file.name.clone(),
+                short_name: file.short_name.clone(),
+                path: file.path.clone(),
+                first_cluster: file.first_cluster,
+                metadata: file.metadata.clone(),
+                size: file.size,
+                is_dir: false,
+                location: file.location,
+            },
+            Entry::Dir(ref dir) => CachedEntry {
+                name: dir.name.clone(),
+                short_name: dir.short_name.clone(),
+                path: dir.path.clone(),
+                first_cluster: dir.first_cluster,
+                metadata: dir.metadata.clone(),
+                size: 0,
+                is_dir: true,
+                location: dir.location,
+            },
+        }
+    }
+
+    pub fn to_entry<D: BlockDevice, L: RawLock>(&self, vfat: &Shared<VFat<D, L>, L>)
+        -> Entry<D, L>
+    {
+        if self.is_dir {
+            Entry::Dir(Dir {
+                name: self.name.clone(),
+                short_name: self.short_name.clone(),
+                path: self.path.clone(),
+                first_cluster: self.first_cluster,
+                vfat: vfat.clone(),
+                metadata: self.metadata.clone(),
+                deleted: false,
+                location: self.location,
+            })
+        } else {
+            let mut file = File::new(self.name.clone(), self.short_name.clone(), vfat.clone(),
+                                     self.first_cluster, self.metadata.clone(), self.size);
+            file.path = self.path.clone();
+            if let Some(location) = self.location {
+                file.open_at(location);
+            }
+            Entry::File(file)
+        }
+    }
+}
+
+/// A least recently used cache of path lookups, keyed by the path with its
+/// components lower-cased, as names are compared case-insensitively.
+///
+/// Entries are kept in order of use, most recent last. Caches are small, so
+/// finding an entry is a linear scan.
+#[derive(Debug, Default)]
+pub(crate) struct DentryCache {
+    entries: Vec<(String, CachedEntry)>,
+    capacity: usize,
+}
+
+impl DentryCache {
+    /// Returns an empty cache holding up to `capacity` lookups. A capacity of
+    /// 0 disables caching.
+    pub fn new(capacity: usize) -> DentryCache {
+        DentryCache {
+            entries: Vec::new(),
+            capacity: capacity,
+        }
+    }
+
+    /// Appends the component `name` to the cache key `key`, lower-cased
+    /// unless names are compared case-sensitively.
+    pub fn push_component(key: &mut String, name: &str, case_sensitive: bool) {
+        key.push('/');
+        if case_sensitive {
+            key.push_str(name);
+        } else {
+            key.extend(name.chars().map(|c| c.to_ascii_lowercase()));
+        }
+    }
+
+    /// Returns the lookup of `key`, marking it most recently used.
+    pub fn get(&mut self, key: &str) -> Option<CachedEntry> {
+        let i = self.entries.iter().position(|&(ref k, _)| k == key)?;
+        let entry = self.entries.remove(i);
+        let cached = entry.1.clone();
+        self.entries.push(entry);
+        Some(cached)
+    }
+
+    /// Records that `key` resolved to `entry`, evicting the least recently
+    /// used lookup if the cache is full.
+    pub fn insert<D: BlockDevice, L: RawLock>(&mut self, key: &str, entry: &Entry<D, L>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(i) = self.entries.iter().position(|&(ref k, _)| k == key) {
+            self.entries.remove(i);
+        } else if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push((String::from(key), CachedEntry::from_entry(entry)));
+    }
+
+    /// Forgets every lookup, as after the directory tree changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
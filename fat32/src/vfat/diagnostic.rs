@@ -0,0 +1,74 @@
+use std::fmt;
+use std::vec::Vec;
+
+use vfat::EntryLocation;
+
+/// The most diagnostics a volume keeps; later ones are dropped, so that a
+/// badly damaged image can't exhaust memory.
+const MAX_DIAGNOSTICS: usize = 256;
+
+/// A minor violation of the FAT specification in a directory entry, of the
+/// kind real-world tools leave behind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The case flags in byte 12 of a short entry have bits set other than
+    /// the lower-case base (0x08) and extension (0x10) bits. The flags are
+    /// ignored.
+    NtFlags(u8),
+    /// A long file name entry holds characters after the name's terminating
+    /// NUL other than the 0xFFFF padding. The name ends at the NUL.
+    LfnPadding,
+    /// A file of size 0 has a first cluster. The file reads as empty.
+    EmptyFileWithCluster(u32),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::NtFlags(flags) => write!(f, "invalid case flags {:#04x}", flags),
+            Violation::LfnPadding => write!(f, "long file name has data past its end"),
+            Violation::EmptyFileWithCluster(cluster) =>
+                write!(f, "empty file has first cluster {}", cluster),
+        }
+    }
+}
+
+/// A violation found while reading a volume mounted with
+/// `ParsePolicy::Lenient`, and where it was found.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Where the offending directory entry is stored.
+    pub location: EntryLocation,
+    pub violation: Violation,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entry at cluster {}, offset {}: {}",
+               self.location.cluster.get_index(), self.location.offset, self.violation)
+    }
+}
+
+/// The diagnostics collected on a volume, each recorded once however often
+/// its directory is read.
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    found: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Records `diagnostic` unless it was already recorded.
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        if self.found.len() < MAX_DIAGNOSTICS && !self.found.contains(&diagnostic) {
+            self.found.push(diagnostic);
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Diagnostic] {
+        &self.found
+    }
+
+    pub fn clear(&mut self) {
+        self.found.clear();
+    }
+}
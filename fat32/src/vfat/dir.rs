@@ -1,45 +1,123 @@
 use std::ffi::OsStr;
 //use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
 //use std::borrow::Cow;
+use std::cmp::min;
 use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::string::String;
-use std::str;
-use std::vec::IntoIter;
+use std::iter::Enumerate;
+use std::vec::{Vec, IntoIter};
 
-use traits;
-use util::VecExt;
-use vfat::{VFat, Shared, File, Cluster, Entry};
-use vfat::{Metadata, Attributes, Timestamp, Time, Date};
+use traits::{self, BlockDevice, DynBlockDevice, RawLock};
+use util::{self, VecExt, SliceExt};
+use vfat::{VFat, Shared, DefaultLock, File, Cluster, Entry, WithContext};
+use vfat::{Metadata, Attributes, Timestamp, Time, Date, TimestampPolicy, ParsePolicy, UtcOffset};
+use vfat::{Diagnostic, Violation};
 
 #[derive(Debug)]
-pub struct Dir {
+pub struct Dir<D = DynBlockDevice, L: RawLock = DefaultLock> {
     pub name: String,
+    /// The 8.3 name stored in the directory entry.
+    pub short_name: String,
+    /// The absolute path the directory was reached by, attached to its
+    /// errors and joined with the names of its entries to give theirs.
+    pub path: PathBuf,
     pub first_cluster: Cluster,
-    pub vfat: Shared<VFat>,
+    pub vfat: Shared<VFat<D, L>, L>,
     pub metadata: Metadata,
+    /// Whether the entry was recovered from a deleted directory entry by
+    /// `entries_all()`.
+    pub deleted: bool,
+    /// Where the directory's short entry is stored, or `None` for the root
+    /// directory, which has none.
+    pub location: Option<EntryLocation>,
     // FIXME: Fill me in.
 }
 
-impl Dir {
+impl<D: BlockDevice, L: RawLock> Dir<D, L> {
     pub fn name(&self) -> &String {
         &self.name
     }
 
+    pub fn short_name(&self) -> &String {
+        &self.short_name
+    }
+
+    /// Returns the absolute path the directory was reached by.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
 
-    pub fn root(vfat: Shared<VFat>) -> Dir {
+    pub fn root(vfat: Shared<VFat<D, L>, L>) -> Dir<D, L> {
+        let (first_cluster, utc_offset) = {
+            let vfat = vfat.borrow();
+            (vfat.root_dir_cluster, vfat.options.utc_offset)
+        };
         Dir{
             name: String::from("/"),
-            first_cluster: vfat.borrow().root_dir_cluster,
+            short_name: String::from("/"),
+            path: PathBuf::from("/"),
+            first_cluster: first_cluster,
             vfat: vfat.clone(),
-            metadata: Metadata::default(),
+            metadata: Metadata { utc_offset: utc_offset, ..Metadata::default() },
+            deleted: false,
+            location: None,
         }
     }
 
 }
 
+/// Where a directory entry is stored on disk: the position of its 32-byte
+/// short entry, the one holding its metadata, size, and first cluster.
+/// `VFat::read_dir_entry()` and `VFat::write_dir_entry()` access the entry
+/// in place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EntryLocation {
+    /// The cluster of the parent directory holding the entry.
+    pub cluster: Cluster,
+    /// The byte offset of the entry in its cluster.
+    pub offset: usize,
+}
+
+/// A position in a directory listing, from which `Dir::entries_from()`
+/// resumes it: the cluster holding the next entry and the entry's index in
+/// the cluster.
+///
+/// The default position, whose token is 0, is the start of any directory.
+/// Tokens fit in the offsets a `readdir()` or `getdents()` implementation
+/// hands back to its callers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct DirPosition {
+    pub cluster: Cluster,
+    pub index: usize,
+}
+
+impl Default for DirPosition {
+    fn default() -> DirPosition {
+        DirPosition::from_token(0)
+    }
+}
+
+impl DirPosition {
+    /// Returns the position encoded as an integer.
+    pub fn to_token(&self) -> u64 {
+        (self.cluster.get_index() as u64) << 32 | self.index as u64
+    }
+
+    /// Returns the position encoded in `token` by `to_token()`.
+    pub fn from_token(token: u64) -> DirPosition {
+        DirPosition {
+            cluster: Cluster::from((token >> 32) as u32),
+            index: token as u32 as usize,
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug)]
 pub struct VFatRegularDirEntry {
@@ -59,24 +137,51 @@ pub struct VFatRegularDirEntry {
 }
 
 impl VFatRegularDirEntry {
+    /// Returns the entry's metadata, its timestamps taken to be in UTC.
     pub fn metadata(&self) -> Metadata {
         Metadata {
             attr: self.attr,
             ctime: Timestamp{
                 time: self.ctime,
                 date: self.cdate,
+                hundredths: self.ctime_tenth_sec,
             },
             atime: Timestamp{
                 time: Time(0),
                 date: self.adate,
+                hundredths: 0,
             },
             mtime: Timestamp{
                 time: self.mtime,
                 date: self.mdate,
+                hundredths: 0,
             },
+            size: self.file_sz,
+            utc_offset: UtcOffset::UTC,
         }
     }
 
+    /// Returns the 8.3 name of the entry, with the extension separated by a
+    /// dot. Short names are in an OEM code page; anything outside of ASCII is
+    /// replaced rather than dropping the entry.
+    pub fn short_name(&self) -> String {
+        let name = String::from_utf8_lossy(&self.name);
+        let ext = String::from_utf8_lossy(&self.ext);
+
+        let mut name_str = String::from(name.trim_right());
+        if ext.trim_right().len() > 0 {
+            name_str.push_str(".");
+            name_str.push_str(ext.trim_right());
+        }
+        name_str
+    }
+
+    /// Returns the checksum of the short name stored in the long file name
+    /// entries that belong to this entry.
+    pub fn checksum(&self) -> u8 {
+        self.name.iter().chain(self.ext.iter())
+            .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+    }
 }
 
 #[repr(C, packed)]
@@ -101,122 +206,627 @@ pub struct VFatUnknownDirEntry {
     reserved2: [u8; 20],
 }
 
+#[derive(Copy, Clone)]
 pub union VFatDirEntry {
     unknown: VFatUnknownDirEntry,
     regular: VFatRegularDirEntry,
     long_filename: VFatLfnDirEntry,
 }
 
-impl Dir {
+impl VFatDirEntry {
+    /// Returns the first minor violation of the specification in `self`, an
+    /// entry that is neither deleted nor the end of its directory.
+    fn violation(&self) -> Option<Violation> {
+        if unsafe { self.unknown.attr }.lfn() {
+            let entry = unsafe { self.long_filename };
+            let mut chars = [0u16; 13];
+            chars[..5].copy_from_slice(&{entry.chars1});
+            chars[5..11].copy_from_slice(&{entry.chars2});
+            chars[11..].copy_from_slice(&{entry.chars3});
+            // The name ends at a NUL, or runs straight into the padding if it
+            // exactly fills its entries.
+            if let Some(end) = chars.iter().position(|&c| c == 0x0000 || c == 0xFFFF) {
+                if chars[end + 1..].iter().any(|&c| c != 0xFFFF) {
+                    return Some(Violation::LfnPadding);
+                }
+            }
+            return None;
+        }
+
+        let entry = unsafe { self.regular };
+        if entry.win_nt_reserved & !0x18 != 0 {
+            return Some(Violation::NtFlags(entry.win_nt_reserved));
+        }
+        let cluster = (entry.cluster_num_hi as u32) << 16 | entry.cluster_num_lo as u32;
+        if !entry.attr.directory() && !entry.attr.volume_id()
+            && entry.file_sz == 0 && cluster != 0 {
+            return Some(Violation::EmptyFileWithCluster(cluster));
+        }
+        None
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> Dir<D, L> {
     /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// case-insensitive, unless the volume was mounted with the
+    /// `case_sensitive` option.
+    ///
+    /// On Windows, `name` may hold unpaired surrogates, as long file names
+    /// can; such a name is compared as UTF-16 with `find_utf16()`.
     ///
     /// # Errors
     ///
     /// If no entry with name `name` exists in `self`, an error of `NotFound` is
     /// returned.
     ///
-    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
-    /// is returned.
-    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
+    /// If `name` contains invalid UTF-8 characters and isn't a Windows
+    /// string, an error of `InvalidInput` is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<D, L>> {
         use traits::Dir;
         use traits::Entry;
 
-        let name_str = name.as_ref()
-                           .to_str()
-                           .ok_or(io::Error::new(io::ErrorKind::InvalidInput,
-                                       "input contains invalid UTF-8 char")
-                                  )?;
+        let name_str = match name.as_ref().to_str() {
+            Some(name_str) => name_str,
+            None => {
+                let units = util::os_str_to_utf16(name.as_ref())
+                    .ok_or_else(|| util::invalid_input("input contains invalid UTF-8 char"))?;
+                return self.find_utf16(&units);
+            }
+        };
+        let case_sensitive = self.vfat.borrow().options.case_sensitive;
         self.entries()?
-            .find(|entry| entry.name().eq_ignore_ascii_case(name_str))
-            .ok_or(io::Error::new(io::ErrorKind::NotFound, "name not found"))
+            .find(|entry| if case_sensitive {
+                entry.name() == name_str
+            } else {
+                entry.name().eq_ignore_ascii_case(name_str)
+            })
+            .ok_or_else(|| util::not_found("name not found"))
+    }
+
+    /// Finds the entry whose name is the UTF-16 string `name` and returns it.
+    /// Names are compared a code unit at a time, as `find()` compares them, before
+    /// unpaired surrogates in long file names are replaced with U+FFFD, so
+    /// that such names can be found.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    pub fn find_utf16(&self, name: &[u16]) -> io::Result<Entry<D, L>> {
+        let case_sensitive = self.vfat.borrow().options.case_sensitive;
+        let mut entries = self.entries_all(false)?;
+        while let Some(entry) = entries.next() {
+            let found = if case_sensitive {
+                &entries.units[..] == name
+            } else {
+                util::utf16_eq_ignore_ascii_case(&entries.units, name)
+            };
+            if found {
+                return Ok(entry);
+            }
+        }
+        Err(util::not_found("name not found"))
+    }
+}
+
+/// Long file names are at most 255 UTF-16 code units, 13 to an entry.
+const MAX_LFN_CHARS: usize = 255;
+const MAX_LFN_ENTRIES: usize = 20;
+
+/// The run of LFN entries preceding a regular entry.
+///
+/// A run starts with the entry holding the last part of the name, flagged
+/// with 0x40, and counts down to sequence number 1. A run that is broken,
+/// because an entry is missing, out of order, or carries a different
+/// checksum, is discarded and the regular entry keeps its short name.
+struct LfnRun {
+    chars: [u16; 13 * MAX_LFN_ENTRIES],
+    // The number of entries in the run, or 0 if no valid run is in progress.
+    len: usize,
+    // The sequence number of the next entry expected in the run.
+    next: usize,
+    checksum: u8,
+}
+
+impl LfnRun {
+    fn new() -> LfnRun {
+        LfnRun {
+            chars: [0; 13 * MAX_LFN_ENTRIES],
+            len: 0,
+            next: 0,
+            checksum: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, entry: &VFatLfnDirEntry) {
+        let seq = (entry.seq & 0x1F) as usize;
+        if entry.seq & 0x40 != 0 {
+            // Runs too long for a 255 character name are ignored.
+            self.len = if seq <= MAX_LFN_ENTRIES { seq } else { 0 };
+            self.checksum = entry.checksum;
+        } else if seq != self.next || entry.checksum != self.checksum {
+            self.len = 0;
+        }
+        if self.len == 0 || seq == 0 {
+            self.len = 0;
+            return;
+        }
+
+        self.next = seq - 1;
+        let start = self.next * 13;
+        self.chars[start      ..start + 5 ].copy_from_slice(&{entry.chars1});
+        self.chars[start + 5  ..start + 11].copy_from_slice(&{entry.chars2});
+        self.chars[start + 11 ..start + 13].copy_from_slice(&{entry.chars3});
+    }
+
+    /// Ends the run, returning the long name if the run is complete and
+    /// belongs to `entry`. LFN entries left behind when a tool unaware of long
+    /// names replaced the short entry no longer match its checksum.
+    ///
+    /// The name is returned as UTF-16 code units, which may include unpaired
+    /// surrogates.
+    fn take(&mut self, entry: &VFatRegularDirEntry) -> Option<&[u16]> {
+        let len = self.len;
+        self.reset();
+        if len == 0 || self.next != 0 || self.checksum != entry.checksum() {
+            return None;
+        }
+
+        // Only the run's own entries are considered, so a longer name read
+        // earlier can't leak into this one.
+        let chars = &self.chars[..len * 13];
+        let end = chars.iter().position(|&c| c == 0x0000 || c == 0xFFFF)
+                       .unwrap_or_else(|| chars.len());
+        Some(&chars[..min(end, MAX_LFN_CHARS)])
     }
 }
 
-pub struct VFatDirEntryIter {
-    entries: IntoIter<VFatDirEntry>,
-    vfat: Shared<VFat>,
+pub struct VFatDirEntryIter<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    entries: Enumerate<IntoIter<VFatDirEntry>>,
+    /// The index in the directory of the first of `entries`.
+    first: usize,
+    /// The index in the directory of the next entry to read.
+    next: usize,
+    /// The directory's clusters, to locate its entries.
+    clusters: Vec<Cluster>,
+    bytes_per_cluster: usize,
+    vfat: Shared<VFat<D, L>, L>,
+    /// The path of the directory, the parent of the entries.
+    path: PathBuf,
+    policy: TimestampPolicy,
+    utc_offset: UtcOffset,
+    include_deleted: bool,
+    /// The name of the entry last returned as UTF-16, before unpaired
+    /// surrogates were replaced.
+    units: Vec<u16>,
 }
 
-impl Iterator for VFatDirEntryIter {
-    type Item = Entry;
+impl<D: BlockDevice, L: RawLock> Iterator for VFatDirEntryIter<D, L> {
+    type Item = Entry<D, L>;
     fn next(&mut self) -> Option<Self::Item> {
-        let mut lfn_vec = [0u16; 13 * 31]; // Max lfn length = 13 u16 * 31 entries
-        let mut has_lfn = false;
+        let mut lfn = LfnRun::new();
+        let mut deleted_lfn = Vec::new();
 
-        for ref entry in self.entries.by_ref() {
+        while let Some((index, ref entry)) = self.entries.next() {
+            let index = self.first + index;
             let unknown_entry = unsafe { entry.unknown };
             if unknown_entry.seq == 0x00 {
                 return None; 
-            } else if unknown_entry.seq == 0xE5 {
-                continue
             }
+            self.next = index + 1;
+            if unknown_entry.seq == 0xE5 {
+                // A deleted entry ends any run in progress.
+                lfn.reset();
+                if !self.include_deleted {
+                    continue
+                } else if unknown_entry.attr.lfn() {
+                    deleted_lfn.push(unsafe { entry.long_filename });
+                    continue
+                } else if unknown_entry.attr.volume_id() {
+                    deleted_lfn.clear();
+                    continue
+                }
+
+                let mut entry = unsafe { entry.regular };
+                let name = recover_names(&deleted_lfn, &mut entry);
+                let short_name = entry.short_name();
+                let name = name.unwrap_or_else(|| short_name.clone());
+                self.units = name.encode_utf16().collect();
+                return Some(self.entry(name, short_name, &entry, index, true));
+            }
+            deleted_lfn.clear();
 
             if unknown_entry.attr.lfn() {
-                let entry = unsafe { entry.long_filename };
-                has_lfn = true;
-                let seq = (entry.seq & 0x1F) as usize - 1;
-                lfn_vec[seq * 13      ..seq * 13 + 5 ].copy_from_slice(&entry.chars1);
-                lfn_vec[seq * 13 + 5  ..seq * 13 + 11].copy_from_slice(&entry.chars2);
-                lfn_vec[seq * 13 + 11 ..seq * 13 + 13].copy_from_slice(&entry.chars3);
+                lfn.push(unsafe { &entry.long_filename });
             } else {
                 let entry = unsafe { entry.regular };
-                let name = if !has_lfn {
-                    let mut name = entry.name.clone();
-                    let name = str::from_utf8(&name).ok()?.trim_right();
-                    let ext = str::from_utf8(&entry.ext).ok()?.trim_right();
-
-                    let mut name_str = String::from(name);
-                    if ext.len() > 0 {
-                        name_str.push_str(&".");
-                        name_str.push_str(&ext);
+                let short_name = entry.short_name();
+                let name = match lfn.take(&entry) {
+                    Some(units) => {
+                        self.units.clear();
+                        self.units.extend_from_slice(units);
+                        // Unpaired surrogates become U+FFFD so that one
+                        // damaged name doesn't end iteration over the
+                        // directory.
+                        String::from_utf16_lossy(units)
+                    }
+                    None => {
+                        self.units = short_name.encode_utf16().collect();
+                        short_name.clone()
                     }
-//                    println!("shortname {}", &name_str);
-                    name_str
-                } else {
-                    let len = lfn_vec.iter().position(|&c| c == 0x0000 || c == 0xFFFF)
-                                     .unwrap_or_else(||lfn_vec.len());
-                    String::from_utf16(&lfn_vec[..len]).ok()?
                 };
+//                println!("name {}", &name);
+                return Some(self.entry(name, short_name, &entry, index, false));
+            }
+        }
+        None
+    }
+}
 
-                let first_cluster = Cluster::from((entry.cluster_num_hi as u32) << 16 
-                                                 | entry.cluster_num_lo as u32);
+impl<D: BlockDevice, L: RawLock> VFatDirEntryIter<D, L> {
+    /// Returns the position of the next entry, from which
+    /// `Dir::entries_from()` resumes the listing. At the end of the
+    /// directory, resuming lists nothing.
+    pub fn position(&self) -> DirPosition {
+        let entries_per_cluster = self.bytes_per_cluster / mem::size_of::<VFatDirEntry>();
+        let cluster = match self.next / entries_per_cluster {
+            i if i < self.clusters.len() => i,
+            // Past the last entry of the last cluster.
+            _ => return DirPosition {
+                cluster: self.clusters[self.clusters.len() - 1],
+                index: entries_per_cluster,
+            },
+        };
+        DirPosition {
+            cluster: self.clusters[cluster],
+            index: self.next % entries_per_cluster,
+        }
+    }
 
-//                println!("name {}", &name);
-                return Some(if entry.attr.directory() {
-                    Entry::Dir(Dir{
-                        name: name,
-                        first_cluster: first_cluster,
-                        vfat: self.vfat.clone(),
-                        metadata: entry.metadata(),
-                    })
-                } else {
-                    Entry::File(File::new(name, self.vfat.clone(), first_cluster, entry.metadata(), entry.file_sz))
+    /// Returns the `Entry` for the short entry `entry`, the `index`th of the
+    /// directory, with the timestamp policy applied to its metadata.
+    fn entry(&self, name: String, short_name: String, entry: &VFatRegularDirEntry,
+             index: usize, deleted: bool) -> Entry<D, L> {
+        let first_cluster = Cluster::from((entry.cluster_num_hi as u32) << 16 
+                                         | entry.cluster_num_lo as u32);
+
+        // Deleted entries are listed on request only, so a bad timestamp in
+        // one doesn't end the listing.
+        let policy = match self.policy {
+            TimestampPolicy::Error if deleted => TimestampPolicy::EpochDefault,
+            policy => policy,
+        };
+        // Under `TimestampPolicy::Error`, `Dir::entries_from()` already
+        // failed on any live entry with an invalid timestamp, so this never
+        // falls back; iteration doesn't stop here either way.
+        let metadata = Metadata {
+            utc_offset: self.utc_offset,
+            ..entry.metadata().sanitize(policy).unwrap_or_else(|| entry.metadata())
+        };
+        let start = index * mem::size_of::<VFatDirEntry>();
+        let location = EntryLocation {
+            cluster: self.clusters[start / self.bytes_per_cluster],
+            offset: start % self.bytes_per_cluster,
+        };
+        let path = self.path.join(&name);
+        if entry.attr.directory() {
+            Entry::Dir(Dir{
+                name: name,
+                short_name: short_name,
+                path: path,
+                first_cluster: first_cluster,
+                vfat: self.vfat.clone(),
+                metadata: metadata,
+                deleted: deleted,
+                location: Some(location),
+            })
+        } else {
+            let mut file = File::new(name, short_name, self.vfat.clone(), first_cluster,
+                                     metadata, entry.file_sz);
+            file.path = path;
+            file.deleted = deleted;
+            file.open_at(location);
+            Entry::File(file)
+        }
+    }
+}
+
+/// Recovers what it can of the names of the deleted short entry `entry` from
+/// the deleted LFN entries `parts` preceding it.
+///
+/// Deleting an entry overwrites its first byte, and the sequence numbers of
+/// its LFN entries, with 0xE5. The LFN entries are stored last part first, so
+/// the long name can still be put together, and its first character is
+/// usually the one the short name lost. If the LFN checksum confirms that
+/// guess, the short name is restored and the long name returned. Otherwise
+/// the short name's first character is left as `?` and `None` is returned.
+fn recover_names(parts: &[VFatLfnDirEntry], entry: &mut VFatRegularDirEntry) -> Option<String> {
+    entry.name[0] = b'?';
+    if parts.is_empty() || parts.len() > MAX_LFN_ENTRIES
+        || parts.iter().any(|part| part.checksum != parts[0].checksum) {
+        return None;
+    }
+
+    let mut chars = Vec::with_capacity(parts.len() * 13);
+    for part in parts.iter().rev() {
+        chars.extend_from_slice(&{part.chars1});
+        chars.extend_from_slice(&{part.chars2});
+        chars.extend_from_slice(&{part.chars3});
+    }
+    let end = chars.iter().position(|&c| c == 0x0000 || c == 0xFFFF)
+                   .unwrap_or_else(|| chars.len());
+    let name = String::from_utf16_lossy(&chars[..min(end, MAX_LFN_CHARS)]);
+
+    let first = name.chars().next()?.to_ascii_uppercase();
+    if !first.is_ascii() {
+        return None;
+    }
+    entry.name[0] = first as u8;
+    if entry.checksum() != parts[0].checksum {
+        entry.name[0] = b'?';
+        return None;
+    }
+    Some(name)
+}
+
+/// What a raw directory entry is, going by its first byte and attributes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawEntryKind {
+    /// The short entry of a file or directory.
+    Regular,
+    /// Part of a long file name.
+    Lfn,
+    /// An entry freed by deleting a file or directory, first byte 0xE5.
+    Deleted,
+    /// The volume label.
+    Volume,
+    /// The end of the directory, first byte 0x00. Entries after it are
+    /// unused, but may hold stale data.
+    End,
+}
+
+/// A 32-byte directory entry exactly as stored on disk, along with where it
+/// is stored.
+#[derive(Debug, Copy, Clone)]
+pub struct RawDirEntry {
+    /// The position of the entry in its directory, counting from 0.
+    pub index: usize,
+    /// The cluster holding the entry.
+    pub cluster: u32,
+    /// The byte offset of the entry in its cluster.
+    pub offset: usize,
+    pub kind: RawEntryKind,
+    pub bytes: [u8; 32],
+}
+
+impl RawDirEntry {
+    fn new(index: usize, cluster: Cluster, offset: usize, bytes: &[u8]) -> RawDirEntry {
+        let mut raw = [0; 32];
+        raw.copy_from_slice(bytes);
+        let attr = Attributes(raw[11]);
+        let kind = match raw[0] {
+            0x00 => RawEntryKind::End,
+            0xE5 => RawEntryKind::Deleted,
+            _ if attr.lfn() => RawEntryKind::Lfn,
+            _ if attr.volume_id() => RawEntryKind::Volume,
+            _ => RawEntryKind::Regular,
+        };
+        RawDirEntry {
+            index: index,
+            cluster: cluster.get_index(),
+            offset: offset,
+            kind: kind,
+            bytes: raw,
+        }
+    }
+
+    /// Returns the entry's attributes. Meaningless for an `End` entry.
+    pub fn attributes(&self) -> Attributes {
+        Attributes(self.bytes[11])
+    }
+}
+
+/// An iterator over every 32-byte entry of a directory, returned by
+/// `Dir::raw_entries()`.
+#[derive(Debug)]
+pub struct RawDirEntries {
+    buf: Vec<u8>,
+    clusters: Vec<Cluster>,
+    bytes_per_cluster: usize,
+    index: usize,
+}
+
+impl Iterator for RawDirEntries {
+    type Item = RawDirEntry;
+
+    fn next(&mut self) -> Option<RawDirEntry> {
+        let start = self.index * 32;
+        if start + 32 > self.buf.len() {
+            return None;
+        }
+        let cluster = self.clusters[start / self.bytes_per_cluster];
+        let entry = RawDirEntry::new(self.index, cluster, start % self.bytes_per_cluster,
+                                     &self.buf[start..start + 32]);
+        self.index += 1;
+        Some(entry)
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> Dir<D, L> {
+    /// Returns an iterator over every 32-byte entry in the directory's
+    /// clusters, as stored on disk: long file name parts, deleted entries,
+    /// the volume label, and the entries past the end of the directory are
+    /// all included. Meant for forensic and repair tools; `entries()` is the
+    /// way to list a directory.
+    ///
+    /// On a lenient volume, clusters that fail to read are returned as
+    /// zeroes.
+    pub fn raw_entries(&self) -> io::Result<RawDirEntries> {
+        let mut buf = Vec::new();
+        let mut vfat = self.vfat.borrow_mut();
+        let lenient = vfat.options.lenient;
+        let start = self.start_cluster(&vfat);
+        let clusters = vfat.resolve_chain(start, lenient).at_path(&self.path)?;
+        if lenient {
+            vfat.read_clusters_lenient(&clusters, &mut buf, &mut Vec::new())
+        } else {
+            vfat.read_clusters(&clusters, &mut buf)
+        }.at_path(&self.path)?;
+
+        Ok(RawDirEntries {
+            buf: buf,
+            clusters: clusters,
+            bytes_per_cluster: vfat.bytes_per_cluster(),
+            index: 0,
+        })
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> Dir<D, L> {
+    /// Returns the cluster the directory's contents start at. The `..`
+    /// entries of the root's subdirectories hold cluster 0, which stands
+    /// for the root directory.
+    fn start_cluster(&self, vfat: &VFat<D, L>) -> Cluster {
+        match self.first_cluster.get_index() {
+            0 => vfat.root_dir_cluster,
+            _ => self.first_cluster,
+        }
+    }
+
+    /// Returns an iterator over the entries in this directory like
+    /// `entries()`, also including deleted entries if `include_deleted`.
+    ///
+    /// Deleted entries are marked with `deleted` and keep the first cluster
+    /// and size they had. Their first cluster may since have been reused, and
+    /// as their chain was freed, only data in contiguous clusters can
+    /// usually be recovered. A deleted entry's name is recovered from its
+    /// deleted long file name entries if possible; otherwise the short name
+    /// is used, with its lost first character replaced by `?`.
+    pub fn entries_all(&self, include_deleted: bool) -> io::Result<VFatDirEntryIter<D, L>> {
+        self.entries_from(DirPosition::default(), include_deleted)
+    }
+
+    /// Returns an iterator over the entries in this directory like
+    /// `entries_all()`, starting at `position`, as returned by
+    /// `VFatDirEntryIter::position()`. Only the clusters from the one
+    /// holding `position` on are read, so a listing can be resumed across
+    /// calls cheaply.
+    ///
+    /// A position is only meaningful for the directory it came from, as long
+    /// as the directory is unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `position` isn't in the
+    /// directory.
+    pub fn entries_from(&self, position: DirPosition, include_deleted: bool)
+        -> io::Result<VFatDirEntryIter<D, L>>
+    {
+//        println!("{:?}", self.vfat.clone());
+//        println!("entries per sector: {}", self.vfat.borrow().bytes_per_sector / mem::size_of::<VFatUnknownDirEntry>() as u16);
+        let mut buf = Vec::new();
+        let (clusters, bytes_per_cluster, first) = {
+            let mut vfat = self.vfat.borrow_mut();
+            let lenient = vfat.options.lenient;
+            let start = self.start_cluster(&vfat);
+            let clusters = vfat.resolve_chain(start, lenient).at_path(&self.path)?;
+            let bytes_per_cluster = vfat.bytes_per_cluster();
+            let entries_per_cluster = bytes_per_cluster / mem::size_of::<VFatDirEntry>();
+            let first = if position == DirPosition::default() {
+                0
+            } else {
+                clusters.iter().position(|&cluster| cluster == position.cluster)
+                    .filter(|_| position.index <= entries_per_cluster)
+                    .ok_or_else(|| util::invalid_input("position is not in the directory"))
+                    .at_path(&self.path)?
+            };
+            if lenient {
+                // Zeroed clusters read as the end of the directory.
+                vfat.read_clusters_lenient(&clusters[first..], &mut buf, &mut Vec::new())
+            } else {
+                vfat.read_clusters(&clusters[first..], &mut buf)
+            }.at_path(&self.path)?;
+            (clusters, bytes_per_cluster, first * entries_per_cluster)
+        };
+        // Every field of an entry is plain data, so any 32 bytes are valid.
+        let mut entries: Vec<VFatDirEntry> = match unsafe { buf.cast() } {
+            Ok(entries) => entries,
+            Err(buf) => unsafe { buf.cast_copy() }
+                .map_err(|_| util::corrupt("directory is not a whole number of entries"))
+                .at_path(&self.path)?,
+        };
+        let skipped = min(position.index, entries.len());
+        entries.drain(..skipped);
+        let first = first + skipped;
+
+        let (policy, parse_policy, utc_offset) = {
+            let vfat = self.vfat.borrow();
+            (vfat.options.timestamp_policy, vfat.options.parse_policy, vfat.options.utc_offset)
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            let unknown_entry = unsafe { entry.unknown };
+            if unknown_entry.seq == 0x00 {
+                break;
+            } else if unknown_entry.seq == 0xE5 {
+                continue;
+            }
+
+            if let Some(violation) = entry.violation() {
+                if parse_policy == ParsePolicy::Strict {
+                    return Err(util::corrupt(format!("directory entry {}: {}", first + i,
+                                                     violation)))
+                        .at_path(&self.path);
+                }
+                let start = (first + i) * mem::size_of::<VFatDirEntry>();
+                let location = EntryLocation {
+                    cluster: clusters[start / bytes_per_cluster],
+                    offset: start % bytes_per_cluster,
+                };
+                self.vfat.borrow_mut().diagnostics.push(Diagnostic {
+                    location: location,
+                    violation: violation,
                 });
             }
+
+            if policy == TimestampPolicy::Error && !unknown_entry.attr.lfn() {
+                let entry = unsafe { entry.regular };
+                if entry.metadata().sanitize(policy).is_none() {
+                    return Err(util::corrupt("directory entry has an invalid timestamp"))
+                        .at_path(&self.path);
+                }
+            }
         }
-        None
+
+        Ok(VFatDirEntryIter {
+            entries: entries.into_iter().enumerate(),
+            first: first,
+            next: first,
+            clusters: clusters,
+            bytes_per_cluster: bytes_per_cluster,
+            vfat: self.vfat.clone(),
+            path: self.path.clone(),
+            policy: policy,
+            utc_offset: utc_offset,
+            include_deleted: include_deleted,
+            units: Vec::new(),
+        })
     }
 }
 
 // FIXME: Implement `trait::Dir` for `Dir`.
-impl traits::Dir for Dir {
+impl<D: BlockDevice, L: RawLock> traits::Dir for Dir<D, L> {
     /// The type of entry stored in this directory.
-    type Entry = Entry;
+    type Entry = Entry<D, L>;
 
     /// An type that is an iterator over the entries in this directory.
-    type Iter = VFatDirEntryIter;
+    type Iter = VFatDirEntryIter<D, L>;
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter> {
-//        println!("{:?}", self.vfat.clone());
-//        println!("entries per sector: {}", self.vfat.borrow().bytes_per_sector / mem::size_of::<VFatUnknownDirEntry>() as u16);
-        let mut buf = Vec::new();
-        self.vfat.borrow_mut()
-            .read_chain(self.first_cluster, &mut buf)
-            .and_then(|_read|
-                Ok(VFatDirEntryIter{entries: unsafe { buf.cast() }.into_iter(),
-                                    vfat: self.vfat.clone()})
-            )
+        self.entries_all(false)
     }
 }
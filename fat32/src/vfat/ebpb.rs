@@ -1,7 +1,16 @@
 use std::fmt;
-use std::{mem};
+use std::io;
+use std::string::String;
 
 use traits::BlockDevice;
+use util::{self, le_u16, le_u32, put_le_u16, put_le_u32};
+
+/// The size of the boot code area of a FAT32 boot sector, in bytes.
+pub const BOOT_CODE_SIZE: usize = 420;
+/// The offset of the boot code area in a FAT32 boot sector.
+pub const BOOT_CODE_OFFSET: usize = 90;
+/// The offset of the volume serial number in a FAT32 boot sector.
+pub const VOLUME_ID_OFFSET: usize = 67;
 use vfat::Error;
 
 #[repr(C, packed)]
@@ -37,31 +46,119 @@ pub struct BiosParameterBlock {
     pub sys_id_str: [u8; 8],
     /* boot code separate into 3 parts to
      * make derive(Default) available*/
-    pub boot_code: [u8; 420],
+    pub boot_code: [u8; BOOT_CODE_SIZE],
     pub bootable_signature: u16, 
 }
 
 impl BiosParameterBlock {
-    fn modify_byte_order(mut bpb: BiosParameterBlock) -> BiosParameterBlock {
-        bpb.bytes_per_sector = u16::from_le(bpb.bytes_per_sector);
-        bpb.num_reserved_sectors = u16::from_le(bpb.num_reserved_sectors);
-        bpb.max_dir_entries = u16::from_le(bpb.max_dir_entries);
-        bpb.total_logical_sectors = u16::from_le(bpb.total_logical_sectors);
-        bpb.sectors_per_fat = u16::from_le(bpb.sectors_per_fat);
-        bpb.sectors_per_track = u16::from_le(bpb.sectors_per_track);
-        bpb.num_heads = u16::from_le(bpb.num_heads);
-        bpb.num_hidden_sectors = u32::from_le(bpb.num_hidden_sectors);
-        bpb.total_logical_sectors_32 = u32::from_le(bpb.total_logical_sectors_32);
-        bpb.sectors_per_fat_32 = u32::from_le(bpb.sectors_per_fat_32);
-        bpb.flags = u16::from_le(bpb.flags);
-        bpb.root_cluster = u32::from_le(bpb.root_cluster);
-        bpb.fsinfo_sector = u16::from_le(bpb.fsinfo_sector);
-        bpb.backup_boot_sector = u16::from_le(bpb.backup_boot_sector);
-        bpb.volumn_id = u32::from_le(bpb.volumn_id);
-        bpb.bootable_signature = u16::from_le(bpb.bootable_signature);
+    /// Decodes a BPB from the bytes of a sector. No validation is performed.
+    pub fn decode(buf: &[u8; 512]) -> BiosParameterBlock {
+        let mut bpb = BiosParameterBlock {
+            jump_short_nop: [buf[0], buf[1], buf[2]],
+            oem_id: [0; 8],
+            bytes_per_sector: le_u16(buf, 11),
+            sectors_per_cluster: buf[13],
+            num_reserved_sectors: le_u16(buf, 14),
+            num_fat: buf[16],
+            max_dir_entries: le_u16(buf, 17),
+            total_logical_sectors: le_u16(buf, 19),
+            media_desc_type: buf[21],
+            sectors_per_fat: le_u16(buf, 22),
+            sectors_per_track: le_u16(buf, 24),
+            num_heads: le_u16(buf, 26),
+            num_hidden_sectors: le_u32(buf, 28),
+            total_logical_sectors_32: le_u32(buf, 32),
+            sectors_per_fat_32: le_u32(buf, 36),
+            flags: le_u16(buf, 40),
+            fat_version: [buf[42], buf[43]],
+            root_cluster: le_u32(buf, 44),
+            fsinfo_sector: le_u16(buf, 48),
+            backup_boot_sector: le_u16(buf, 50),
+            reserved: [0; 12],
+            drive_num: buf[64],
+            win_nt_flag: buf[65],
+            signature: buf[66],
+            volumn_id: le_u32(buf, VOLUME_ID_OFFSET),
+            volumn_label: [0; 11],
+            sys_id_str: [0; 8],
+            boot_code: [0; BOOT_CODE_SIZE],
+            bootable_signature: le_u16(buf, 510),
+        };
+        bpb.oem_id.copy_from_slice(&buf[3..11]);
+        bpb.reserved.copy_from_slice(&buf[52..64]);
+        bpb.volumn_label.copy_from_slice(&buf[71..82]);
+        bpb.sys_id_str.copy_from_slice(&buf[82..90]);
+        bpb.boot_code.copy_from_slice(&buf[BOOT_CODE_OFFSET..510]);
         bpb
     }
 
+    /// Encodes `self` into the bytes of a sector.
+    pub fn encode(&self) -> [u8; 512] {
+        let mut buf = [0u8; 512];
+        buf[..3].copy_from_slice(&self.jump_short_nop);
+        buf[3..11].copy_from_slice(&self.oem_id);
+        put_le_u16(&mut buf, 11, self.bytes_per_sector);
+        buf[13] = self.sectors_per_cluster;
+        put_le_u16(&mut buf, 14, self.num_reserved_sectors);
+        buf[16] = self.num_fat;
+        put_le_u16(&mut buf, 17, self.max_dir_entries);
+        put_le_u16(&mut buf, 19, self.total_logical_sectors);
+        buf[21] = self.media_desc_type;
+        put_le_u16(&mut buf, 22, self.sectors_per_fat);
+        put_le_u16(&mut buf, 24, self.sectors_per_track);
+        put_le_u16(&mut buf, 26, self.num_heads);
+        put_le_u32(&mut buf, 28, self.num_hidden_sectors);
+        put_le_u32(&mut buf, 32, self.total_logical_sectors_32);
+        put_le_u32(&mut buf, 36, self.sectors_per_fat_32);
+        put_le_u16(&mut buf, 40, self.flags);
+        buf[42..44].copy_from_slice(&self.fat_version);
+        put_le_u32(&mut buf, 44, self.root_cluster);
+        put_le_u16(&mut buf, 48, self.fsinfo_sector);
+        put_le_u16(&mut buf, 50, self.backup_boot_sector);
+        buf[52..64].copy_from_slice(&self.reserved);
+        buf[64] = self.drive_num;
+        buf[65] = self.win_nt_flag;
+        buf[66] = self.signature;
+        put_le_u32(&mut buf, VOLUME_ID_OFFSET, self.volumn_id);
+        buf[71..82].copy_from_slice(&self.volumn_label);
+        buf[82..90].copy_from_slice(&self.sys_id_str);
+        buf[BOOT_CODE_OFFSET..510].copy_from_slice(&self.boot_code);
+        put_le_u16(&mut buf, 510, self.bootable_signature);
+        buf
+    }
+
+    /// Replaces the boot code with `code`, padded with zeroes. The BPB and
+    /// the boot signature are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `code` is longer than
+    /// `BOOT_CODE_SIZE` bytes.
+    pub fn set_boot_code(&mut self, code: &[u8]) -> io::Result<()> {
+        if code.len() > BOOT_CODE_SIZE {
+            return Err(util::invalid_input(format!("boot code is longer than {} bytes",
+                                                   BOOT_CODE_SIZE)));
+        }
+        self.boot_code = [0; BOOT_CODE_SIZE];
+        self.boot_code[..code.len()].copy_from_slice(code);
+        Ok(())
+    }
+
+    /// Installs the volume boot record `vbr`, a whole boot sector as built
+    /// for FAT32, by taking its jump instruction and boot code. The BPB and
+    /// the boot signature are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `vbr` isn't 512 bytes long.
+    pub fn set_vbr(&mut self, vbr: &[u8]) -> io::Result<()> {
+        if vbr.len() != 512 {
+            return Err(util::invalid_input("volume boot record must be 512 bytes"));
+        }
+        self.jump_short_nop.copy_from_slice(&vbr[..3]);
+        self.set_boot_code(&vbr[BOOT_CODE_OFFSET..BOOT_CODE_OFFSET + BOOT_CODE_SIZE])
+    }
+
     pub fn sectors_per_fat(&self) -> u32 {
         if self.sectors_per_fat != 0 {
             self.sectors_per_fat as u32
@@ -79,6 +176,44 @@ impl BiosParameterBlock {
     
     }
 
+    /// Checks that the fields describing the layout of the volume are sane
+    /// for a FAT32 volume in a partition of `partition_bytes` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UnsupportedFatType` if the volume is FAT12 or FAT16, and
+    /// `InvalidBpb` naming the first field found to be invalid otherwise.
+    pub fn validate(&self, partition_bytes: u64) -> Result<(), Error> {
+        if self.sectors_per_fat != 0 {
+            return Err(Error::UnsupportedFatType);
+        }
+        match self.bytes_per_sector {
+            512 | 1024 | 2048 | 4096 => {}
+            _ => return Err(Error::InvalidBpb("bytes per sector")),
+        }
+        if !self.sectors_per_cluster.is_power_of_two() {
+            return Err(Error::InvalidBpb("sectors per cluster"));
+        }
+        if self.num_reserved_sectors == 0 {
+            return Err(Error::InvalidBpb("reserved sectors"));
+        }
+        if self.num_fat == 0 {
+            return Err(Error::InvalidBpb("number of FATs"));
+        }
+        if self.sectors_per_fat_32 == 0 {
+            return Err(Error::InvalidBpb("sectors per FAT"));
+        }
+
+        let total_sectors = self.total_logical_sectors() as u64;
+        let metadata_sectors = self.num_reserved_sectors as u64
+            + self.num_fat as u64 * self.sectors_per_fat_32 as u64;
+        if total_sectors <= metadata_sectors
+            || total_sectors * self.bytes_per_sector as u64 > partition_bytes {
+            return Err(Error::InvalidBpb("total sectors"));
+        }
+        Ok(())
+    }
+
     /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
     /// device `device`.
     ///
@@ -89,12 +224,9 @@ impl BiosParameterBlock {
         mut device: T,
         sector: u64
     ) -> Result<BiosParameterBlock, Error> {
-        let mut bpb_buf = [0u8; mem::size_of::<BiosParameterBlock>()];
-        if let Err(e) = device.read_sector(sector, &mut bpb_buf) {
-            return Err(Error::Io(e));
-        }
-        let bpb : BiosParameterBlock = unsafe{ mem::transmute(bpb_buf) };
-        let bpb = Self::modify_byte_order(bpb);
+        let mut bpb_buf = [0u8; 512];
+        device.read_sector(sector, &mut bpb_buf)?;
+        let bpb = BiosParameterBlock::decode(&bpb_buf);
 
         if bpb.bootable_signature != 0xAA55 {
             return Err(Error::BadSignature);
@@ -1,19 +1,111 @@
-use traits;
-use vfat::{File, Dir, Metadata};
+use std::fmt;
+
+use traits::{self, BlockDevice, DynBlockDevice, RawLock};
+use vfat::{File, Dir, EntryLocation, Metadata, DefaultLock, Timestamp};
 
 // TODO: You may need to change this definition.
 #[derive(Debug)]
-pub enum Entry {
-    File(File),
-    Dir(Dir)
+pub enum Entry<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    File(File<D, L>),
+    Dir(Dir<D, L>)
+}
+
+/// A borrowed summary of an `Entry`, suitable for directory listings.
+#[derive(Debug, Copy, Clone)]
+pub struct EntryInfo<'a> {
+    pub name: &'a str,
+    pub is_dir: bool,
+    pub metadata: &'a Metadata,
 }
 
 // TODO: Implement any useful helper methods on `Entry`.
+impl<D: BlockDevice, L: RawLock> Entry<D, L> {
+    /// Returns a borrowed summary of this entry.
+    pub fn info(&self) -> EntryInfo {
+        use traits::Entry;
+
+        EntryInfo {
+            name: self.name(),
+            is_dir: self.is_dir(),
+            metadata: self.metadata(),
+        }
+    }
+
+    /// Returns the 8.3 name stored in the entry's directory entry. It is the
+    /// same as `name()` unless the entry has a long file name.
+    pub fn short_name(&self) -> &str {
+        match self {
+            &Entry::File(ref f) => f.short_name(),
+            &Entry::Dir(ref d) => d.short_name(),
+        }
+    }
+
+    /// Returns `true` if this entry is a file.
+    pub fn is_file(&self) -> bool {
+        match self {
+            &Entry::File(_) => true,
+            &Entry::Dir(_) => false,
+        }
+    }
+
+    /// Returns `true` if this entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        !self.is_file()
+    }
+
+    /// Returns `true` if this entry was recovered from a deleted directory
+    /// entry by `Dir::entries_all()`.
+    pub fn is_deleted(&self) -> bool {
+        match self {
+            &Entry::File(ref f) => f.deleted,
+            &Entry::Dir(ref d) => d.deleted,
+        }
+    }
+
+    /// Returns where this entry's short entry is stored, or `None` for the
+    /// root directory.
+    pub fn location(&self) -> Option<EntryLocation> {
+        match self {
+            &Entry::File(ref f) => f.location,
+            &Entry::Dir(ref d) => d.location,
+        }
+    }
+
+    /// Returns the size of the file in bytes, or 0 for a directory.
+    pub fn len(&self) -> u64 {
+        match self {
+            &Entry::File(ref f) => f.size as u64,
+            &Entry::Dir(_) => 0,
+        }
+    }
+
+    /// Returns when the entry was created.
+    pub fn created(&self) -> Timestamp {
+        traits::Entry::metadata(self).ctime
+    }
+
+    /// Returns when the entry was last modified.
+    pub fn modified(&self) -> Timestamp {
+        traits::Entry::metadata(self).mtime
+    }
+}
+
+impl<D, L: RawLock> From<File<D, L>> for Entry<D, L> {
+    fn from(file: File<D, L>) -> Entry<D, L> {
+        Entry::File(file)
+    }
+}
+
+impl<D, L: RawLock> From<Dir<D, L>> for Entry<D, L> {
+    fn from(dir: Dir<D, L>) -> Entry<D, L> {
+        Entry::Dir(dir)
+    }
+}
 
 // FIXME: Implement `traits::Entry` for `Entry`.
-impl traits::Entry for Entry {
-    type File = File;
-    type Dir = Dir;
+impl<D: BlockDevice, L: RawLock> traits::Entry for Entry<D, L> {
+    type File = File<D, L>;
+    type Dir = Dir<D, L>;
     type Metadata = Metadata;
 
     /// The name of the file or directory corresponding to this entry.
@@ -72,3 +164,10 @@ impl traits::Entry for Entry {
         }
     }
 }
+
+/// Formats the entry `ls -l` style: its metadata followed by its name.
+impl<D: BlockDevice, L: RawLock> fmt::Display for Entry<D, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", traits::Entry::metadata(self), traits::Entry::name(self))
+    }
+}
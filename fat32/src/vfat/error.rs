@@ -1,13 +1,69 @@
-use std::io;
+use std::{fmt, io};
+#[cfg(not(feature = "std"))]
+use std::string::ToString;
 
 use mbr;
+use vfat::Cluster;
 
 #[derive(Debug)]
 pub enum Error {
+    /// The master boot record could not be read or is invalid.
     Mbr(mbr::Error),
+    /// There was an I/O error while reading the file system.
     Io(io::Error),
+    /// The EBPB magic signature was invalid.
     BadSignature,
-    NotFound
+    /// No FAT32 partition was found.
+    NotFound,
+    /// A field of the BIOS parameter block holds an invalid value. `.0` names
+    /// the field.
+    InvalidBpb(&'static str),
+    /// The cluster chain running through cluster `.0` is corrupt: it links
+    /// to a free cluster or never ends.
+    CorruptChain(Cluster),
+    /// Cluster `.0` of a cluster chain is marked bad in the FAT.
+    BadCluster(Cluster),
+    /// Cluster `.0` of a cluster chain is marked reserved in the FAT.
+    ReservedCluster(Cluster),
+    /// The partition holds a FAT12 or FAT16 file system.
+    UnsupportedFatType,
+    /// The volume can't be mounted because of the state of its journal. `.0`
+    /// describes the problem.
+    Journal(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Mbr(ref e) => write!(f, "invalid master boot record: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::BadSignature => write!(f, "invalid EBPB signature"),
+            Error::NotFound => write!(f, "no FAT32 partition found"),
+            Error::InvalidBpb(field) => write!(f, "invalid BPB field: {}", field),
+            Error::CorruptChain(cluster) => {
+                write!(f, "corrupt cluster chain at cluster {}", cluster.get_index())
+            }
+            Error::BadCluster(cluster) => {
+                write!(f, "cluster {} is marked bad", cluster.get_index())
+            }
+            Error::ReservedCluster(cluster) => {
+                write!(f, "cluster {} is marked reserved", cluster.get_index())
+            }
+            Error::UnsupportedFatType => write!(f, "FAT12 and FAT16 are not supported"),
+            Error::Journal(problem) => write!(f, "journal {}", problem),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for Error {
+    fn source(&self) -> Option<&(::std::error::Error + 'static)> {
+        match *self {
+            Error::Mbr(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<mbr::Error> for Error {
@@ -21,3 +77,22 @@ impl From<io::Error> for Error {
         Error::Io(error)
     }
 }
+
+/// I/O errors are passed through unchanged. A missing partition maps to
+/// `NotFound` and everything else is corrupt on-disk data, `InvalidData`.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        let kind = match error {
+            Error::Io(e) => return e,
+            Error::Mbr(mbr::Error::Io(e)) => return e,
+            Error::NotFound => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        #[cfg(feature = "std")]
+        let message = error;
+        #[cfg(not(feature = "std"))]
+        let message = error.to_string();
+        io::Error::new(kind, message)
+    }
+}
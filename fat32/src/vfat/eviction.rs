@@ -0,0 +1,212 @@
+use std::boxed::Box;
+use std::collections::{BTreeMap, BTreeSet};
+use std::vec::Vec;
+
+/// A strategy for choosing which sector a full sector cache evicts.
+///
+/// The cache tells the policy which sectors it holds and when each is used,
+/// and asks it for a victim when it needs room. Dirty sectors and pinned
+/// sectors, such as the FAT with `MountOptions::pin_fat`, can't be evicted:
+/// a policy must only choose a sector for which `evictable` returns `true`,
+/// and returns `None` if there is none, in which case the cache grows past
+/// its capacity until the next flush.
+///
+/// Every method is called with a sector the cache does or, for `insert()`,
+/// is about to hold, so policies don't need to handle unknown sectors.
+pub trait EvictionPolicy: Send + Sync {
+    /// Called when `sector` is added to the cache.
+    fn insert(&mut self, sector: u64);
+
+    /// Called when the cached `sector` is read or written.
+    fn touch(&mut self, sector: u64);
+
+    /// Called when `sector` leaves the cache, whether it was chosen by
+    /// `victim()` or dropped for another reason.
+    fn remove(&mut self, sector: u64);
+
+    /// Returns the sector to evict next among those `evictable` accepts. The
+    /// sector stays in the policy until `remove()` is called for it.
+    fn victim(&mut self, evictable: &mut FnMut(u64) -> bool) -> Option<u64>;
+}
+
+/// The eviction policies a volume can be mounted with, as chosen by
+/// `MountOptions::cache_policy`. Custom policies are installed with
+/// `VFat::set_eviction_policy()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Evict the least recently used sector. See `Lru`.
+    Lru,
+    /// Evict the least frequently used sector. See `Lfu`.
+    Lfu,
+    /// Evict with the clock algorithm. See `Clock`.
+    Clock,
+}
+
+impl CachePolicy {
+    /// Returns a new policy of this kind.
+    pub fn build(self) -> Box<EvictionPolicy> {
+        match self {
+            CachePolicy::Lru => Box::new(Lru::new()),
+            CachePolicy::Lfu => Box::new(Lfu::new()),
+            CachePolicy::Clock => Box::new(Clock::new()),
+        }
+    }
+}
+
+impl Default for CachePolicy {
+    fn default() -> CachePolicy {
+        CachePolicy::Lru
+    }
+}
+
+/// Evicts the least recently used sector. Every use costs O(log n).
+#[derive(Debug, Default, Clone)]
+pub struct Lru {
+    /// The time of each sector's last use.
+    used: BTreeMap<u64, u64>,
+    /// Every sector, keyed by the time of its last use.
+    by_time: BTreeMap<u64, u64>,
+    now: u64,
+}
+
+impl Lru {
+    pub fn new() -> Lru {
+        Lru::default()
+    }
+}
+
+impl EvictionPolicy for Lru {
+    fn insert(&mut self, sector: u64) {
+        self.touch(sector);
+    }
+
+    fn touch(&mut self, sector: u64) {
+        if let Some(time) = self.used.insert(sector, self.now) {
+            self.by_time.remove(&time);
+        }
+        self.by_time.insert(self.now, sector);
+        self.now += 1;
+    }
+
+    fn remove(&mut self, sector: u64) {
+        if let Some(time) = self.used.remove(&sector) {
+            self.by_time.remove(&time);
+        }
+    }
+
+    fn victim(&mut self, evictable: &mut FnMut(u64) -> bool) -> Option<u64> {
+        self.by_time.values().cloned().find(|&sector| evictable(sector))
+    }
+}
+
+/// Evicts the least frequently used sector, and of those the least recently
+/// used. Counts start over when a sector is evicted. Every use costs
+/// O(log n).
+#[derive(Debug, Default, Clone)]
+pub struct Lfu {
+    /// The number of uses of each sector and the time of its last use.
+    used: BTreeMap<u64, (u64, u64)>,
+    /// Every sector with its number of uses and time of last use, ordered
+    /// for eviction.
+    order: BTreeSet<(u64, u64, u64)>,
+    now: u64,
+}
+
+impl Lfu {
+    pub fn new() -> Lfu {
+        Lfu::default()
+    }
+}
+
+impl EvictionPolicy for Lfu {
+    fn insert(&mut self, sector: u64) {
+        self.touch(sector);
+    }
+
+    fn touch(&mut self, sector: u64) {
+        let count = match self.used.get(&sector) {
+            Some(&(count, time)) => {
+                self.order.remove(&(count, time, sector));
+                count + 1
+            }
+            None => 1,
+        };
+        self.used.insert(sector, (count, self.now));
+        self.order.insert((count, self.now, sector));
+        self.now += 1;
+    }
+
+    fn remove(&mut self, sector: u64) {
+        if let Some((count, time)) = self.used.remove(&sector) {
+            self.order.remove(&(count, time, sector));
+        }
+    }
+
+    fn victim(&mut self, evictable: &mut FnMut(u64) -> bool) -> Option<u64> {
+        self.order.iter().map(|&(_, _, sector)| sector).find(|&sector| evictable(sector))
+    }
+}
+
+/// Evicts with the clock, or second chance, algorithm: sectors sit on a ring
+/// with a bit set whenever they are used again, and a hand sweeping the ring
+/// evicts the first sector whose bit is clear, clearing bits as it passes.
+/// Approximates LRU with a use costing O(1) beyond finding the sector.
+#[derive(Debug, Default, Clone)]
+pub struct Clock {
+    /// The ring of sectors and their used bits.
+    ring: Vec<(u64, bool)>,
+    /// The position of each sector on the ring.
+    positions: BTreeMap<u64, usize>,
+    hand: usize,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+}
+
+impl EvictionPolicy for Clock {
+    fn insert(&mut self, sector: u64) {
+        self.positions.insert(sector, self.ring.len());
+        self.ring.push((sector, false));
+    }
+
+    fn touch(&mut self, sector: u64) {
+        if let Some(&i) = self.positions.get(&sector) {
+            self.ring[i].1 = true;
+        }
+    }
+
+    fn remove(&mut self, sector: u64) {
+        let i = match self.positions.remove(&sector) {
+            Some(i) => i,
+            None => return,
+        };
+        self.ring.swap_remove(i);
+        if let Some(&(moved, _)) = self.ring.get(i) {
+            self.positions.insert(moved, i);
+        }
+        if self.hand >= self.ring.len() {
+            self.hand = 0;
+        }
+    }
+
+    fn victim(&mut self, evictable: &mut FnMut(u64) -> bool) -> Option<u64> {
+        // Two sweeps clear every bit, so a sector that can be evicted is
+        // found by then.
+        for _ in 0..2 * self.ring.len() {
+            let i = self.hand;
+            self.hand = (i + 1) % self.ring.len();
+            let (sector, used) = self.ring[i];
+            if !evictable(sector) {
+                continue;
+            }
+            if !used {
+                return Some(sector);
+            }
+            self.ring[i].1 = false;
+        }
+        None
+    }
+}
@@ -1,13 +1,14 @@
-use std::fmt;
+use std::{fmt, io};
 use vfat::*;
 
 use self::Status::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Status {
     /// The FAT entry corresponds to an unused (free) cluster.
     Free,
-    /// The FAT entry/cluster is reserved.
+    /// The FAT entry/cluster is reserved: the value 1, or one in range
+    /// [0xFFFFFF0, 0xFFFFFF6].
     Reserved,
     /// The FAT entry corresponds to a valid data cluster. The next cluster in
     /// the chain is `Cluster`.
@@ -15,18 +16,57 @@ pub enum Status {
     /// The FAT entry corresponds to a bad (disk failed) cluster.
     Bad,
     /// The FAT entry corresponds to a valid data cluster. The corresponding
-    /// cluster is the last in its chain.
+    /// cluster is the last in its chain. Any value in range [0xFFFFFF8,
+    /// 0xFFFFFFF] marks the end of a chain; the one stored is kept.
     Eoc(u32)
 }
 
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Free => write!(f, "free"),
+            Reserved => write!(f, "reserved"),
+            Data(next) => write!(f, "next {}", next.get_index()),
+            Bad => write!(f, "bad"),
+            Eoc(last) => write!(f, "end of chain {:#09X}", last),
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
 pub struct FatEntry(pub u32);
 
 impl FatEntry {
-    /// Returns the `Status` of the FAT entry `self`.
+    /// The bits of an entry holding its value. The high 4 bits are reserved,
+    /// and must be kept when the entry is rewritten.
+    pub const VALUE_MASK: u32 = 0x0FFFFFFF;
+
+    /// Returns the entry as stored, including its reserved high 4 bits.
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the 28-bit value of the entry, without its reserved bits.
+    pub fn value(&self) -> u32 {
+        self.0 & FatEntry::VALUE_MASK
+    }
+
+    /// Returns the reserved high 4 bits of the entry, in place.
+    pub fn reserved_bits(&self) -> u32 {
+        self.0 & !FatEntry::VALUE_MASK
+    }
+
+    /// Returns `self` with its value replaced by the low 28 bits of `value`,
+    /// keeping its reserved high 4 bits.
+    pub fn with_value(&self, value: u32) -> FatEntry {
+        FatEntry(self.reserved_bits() | (value & FatEntry::VALUE_MASK))
+    }
+
+    /// Returns the `Status` of the FAT entry `self`. Every 28-bit value has
+    /// one; the reserved high 4 bits are ignored.
     pub fn status(&self) -> Status {
-        match self.0 & 0xFFFFFFF {
+        match self.value() {
             0x0000000 => Free,
             0x0000001 => Reserved,
             next @ 0x0000002 ... 0xFFFFFEF => Data(Cluster::from(next)),
@@ -41,8 +81,151 @@ impl FatEntry {
 impl fmt::Debug for FatEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FatEntry")
-//            .field("value", &self.0)
+            .field("raw", &format_args!("{:#010X}", self.raw()))
             .field("status", &self.status())
             .finish()
     }
 }
+
+/// Formats the entry as its raw value and status, as in a dump of the FAT:
+/// `0x00000005 (next 5)`.
+impl fmt::Display for FatEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010X} ({})", self.raw(), self.status())
+    }
+}
+
+/// Follows a cluster chain one FAT entry at a time, judging each link as
+/// `VFat::walk_chain()` and `VFat::resolve_chain()` do. Reading the entries
+/// is left to the caller, so tools that read the FAT of an unmountable image
+/// directly follow chains by the same rules.
+///
+/// Chains that loop back on themselves are detected with Brent's algorithm.
+#[derive(Debug, Clone)]
+pub(crate) struct ChainWalk {
+    hare: Cluster,
+    tortoise: Cluster,
+    power: u32,
+    lambda: u32,
+    len: u32,
+    max_cluster: u32,
+    lenient: bool,
+}
+
+impl ChainWalk {
+    /// Starts a walk of the chain starting at `start` on a volume whose last
+    /// data cluster is `max_cluster`. When `lenient`, a bad cluster ends the
+    /// chain instead of failing.
+    pub fn new(start: Cluster, max_cluster: u32, lenient: bool) -> ChainWalk {
+        ChainWalk {
+            hare: start,
+            tortoise: start,
+            power: 1,
+            lambda: 0,
+            len: 1,
+            max_cluster: max_cluster,
+            lenient: lenient,
+        }
+    }
+
+    /// Returns the cluster the walk has reached.
+    pub fn cluster(&self) -> Cluster {
+        self.hare
+    }
+
+    /// Returns the number of clusters walked, including `cluster()`.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Moves on from `cluster()`, whose FAT entry is `entry`, and returns
+    /// the cluster it links to, or `None` if `cluster()` ends the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::BadCluster` or `Error::ReservedCluster` if `entry`
+    /// marks the cluster so, and `Error::CorruptChain` if it is free, links
+    /// outside the data region, or closes a loop.
+    pub fn step(&mut self, entry: FatEntry) -> io::Result<Option<Cluster>> {
+        let cluster = self.hare;
+        let next = match successor(cluster, entry, self.max_cluster, self.lenient)? {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        // No chain is longer than the data region.
+        if next == self.tortoise || self.len >= self.max_cluster - 1 {
+            return Err(Error::CorruptChain(cluster).into());
+        }
+        self.hare = next;
+        self.len += 1;
+        self.lambda += 1;
+        if self.lambda == self.power {
+            self.tortoise = next;
+            self.power *= 2;
+            self.lambda = 0;
+        }
+        Ok(Some(next))
+    }
+}
+
+/// Returns the cluster that `entry`, the FAT entry for `cluster`, links to,
+/// or `None` if `cluster` is the last of its chain. When `lenient`, a bad
+/// cluster ends the chain instead of failing.
+pub(crate) fn successor(cluster: Cluster, entry: FatEntry, max_cluster: u32, lenient: bool)
+    -> io::Result<Option<Cluster>>
+{
+    match entry.status() {
+        Status::Data(next_cluster) => {
+            if next_cluster.get_index() > max_cluster {
+                return Err(Error::CorruptChain(cluster).into());
+            }
+            Ok(Some(next_cluster))
+        }
+        Status::Eoc(_) => Ok(None),
+        Status::Bad if lenient => Ok(None),
+        Status::Bad => Err(Error::BadCluster(cluster).into()),
+        Status::Reserved => Err(Error::ReservedCluster(cluster).into()),
+        Status::Free => Err(Error::CorruptChain(cluster).into()),
+    }
+}
+
+/// Entry 1 of a FAT32 FAT borrows bit 27 as a clean-shutdown flag and bit 26
+/// as a no-I/O-errors flag; with both cleared it no longer reads as an end
+/// of chain.
+pub(crate) const VOLUME_FLAGS: u32 = 0x0C000000;
+
+/// FAT entries 0 and 1 holding other than the signature they should, found
+/// by `VFat::check_fat_signature()`: entry 0 repeats the media descriptor
+/// as `0x0FFFFF00 | media`, and entry 1 marks an end of chain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FatSignatureMismatch {
+    /// The media descriptor in the BPB.
+    pub media: u8,
+    /// Entry 0 as stored, if it doesn't match `media`.
+    pub entry0: Option<u32>,
+    /// Entry 1 as stored, if it isn't an end of chain.
+    pub entry1: Option<u32>,
+}
+
+impl FatSignatureMismatch {
+    /// Compares `entry0` and `entry1`, FAT entries 0 and 1, with what they
+    /// should hold on a volume with media descriptor `media`, returning the
+    /// difference if there is any.
+    pub(crate) fn check(media: u8, entry0: FatEntry, entry1: FatEntry)
+        -> Option<FatSignatureMismatch>
+    {
+        let entry0 = if entry0.value() == 0x0FFFFF00 | media as u32 {
+            None
+        } else {
+            Some(entry0.raw())
+        };
+        let entry1 = match FatEntry(entry1.raw() | VOLUME_FLAGS).status() {
+            Eoc(_) => None,
+            _ => Some(entry1.raw()),
+        };
+        if entry0.is_none() && entry1.is_none() {
+            return None;
+        }
+        Some(FatSignatureMismatch { media: media, entry0: entry0, entry1: entry1 })
+    }
+}
@@ -1,90 +1,380 @@
 use std::cmp::{min};
 use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
 
-use traits;
-use vfat::{VFat, Shared, Cluster, Metadata};
+use traits::{self, BlockDevice, DynBlockDevice, RawLock};
+use digest::Digest;
+use util;
+use vfat::{VFat, Shared, DefaultLock, Cluster, Metadata, Status, WithContext, SeekPolicy};
+use vfat::{Time, Timestamp};
+use vfat::EntryLocation;
 
 #[derive(Debug)]
-pub struct File {
+pub struct File<D = DynBlockDevice, L: RawLock = DefaultLock> {
     pub name: String,
-    pub vfat: Shared<VFat>,
+    /// The 8.3 name stored in the directory entry.
+    pub short_name: String,
+    /// The absolute path the file was reached by, attached to its errors.
+    pub path: PathBuf,
+    pub vfat: Shared<VFat<D, L>, L>,
     pub first_cluster: Cluster,
     pub metadata: Metadata,
     pub size: u32,
-    file_ptr: u32,
+    /// Whether the entry was recovered from a deleted directory entry by
+    /// `Dir::entries_all()`.
+    pub deleted: bool,
+    /// Where the file's short entry is stored, if it was read from a
+    /// directory.
+    pub location: Option<EntryLocation>,
+    file_ptr: u64,
+    unreliable: Vec<Range<u64>>,
+    // The length of the cluster chain, once it has been validated.
+    chain_len: Option<u64>,
+    // The index within the chain and number of the cluster last read from.
+    cursor: Option<(u64, Cluster)>,
+    // Overrides the volume's seek policy.
+    seek_policy: Option<SeekPolicy>,
+    // Whether the file is in the volume's open-file table.
+    registered: bool,
 
     // FIXME: Fill me in.
 }
 
-impl File {
-    pub fn new(name: String, vfat: Shared<VFat>, first_cluster: Cluster,
-               metadata: Metadata, file_sz: u32) -> Self {
+impl<D: BlockDevice, L: RawLock> File<D, L> {
+    pub fn new(name: String, short_name: String, vfat: Shared<VFat<D, L>, L>,
+               first_cluster: Cluster, metadata: Metadata, file_sz: u32) -> Self {
         File {
+            path: PathBuf::from(name.as_str()),
             name: name,
+            short_name: short_name,
             vfat: vfat,
             first_cluster: first_cluster,
             metadata: metadata,
             file_ptr: 0,
-            size: file_sz
+            size: file_sz,
+            deleted: false,
+            location: None,
+            unreliable: Vec::new(),
+            chain_len: None,
+            cursor: None,
+            seek_policy: None,
+            registered: false,
+        }
+    }
+
+    /// Sets the location of the file's directory entry and enters the file
+    /// in the volume's open-file table, adopting the size of the handles
+    /// already open to it.
+    pub(crate) fn open_at(&mut self, location: EntryLocation) {
+        self.location = Some(location);
+        if self.deleted {
+            return;
+        }
+        let (size, first_cluster) = self.vfat.borrow_mut().open_files
+            .open(location, self.size, self.first_cluster);
+        self.registered = true;
+        self.adopt(size, first_cluster);
+    }
+
+    /// Picks up a change to the file's size made through another handle.
+    pub(crate) fn refresh(&mut self) {
+        let vfat = self.vfat.clone();
+        self.refresh_from(&vfat.borrow());
+    }
+
+    fn refresh_from(&mut self, vfat: &VFat<D, L>) {
+        if let (true, Some(location)) = (self.registered, self.location) {
+            if let Some((size, first_cluster)) = vfat.open_files.get(location) {
+                self.adopt(size, first_cluster);
+            }
+        }
+    }
+
+    fn adopt(&mut self, size: u32, first_cluster: Cluster) {
+        if size == self.size && first_cluster == self.first_cluster {
+            return;
+        }
+        self.size = size;
+        self.first_cluster = first_cluster;
+        // The chain may have been cut short.
+        self.chain_len = None;
+        self.cursor = None;
+        self.unreliable.retain(|r| r.start < size as u64);
+        for r in self.unreliable.iter_mut() {
+            r.end = min(r.end, size as u64);
         }
     }
     pub fn name(&self) -> &String {
         &self.name
     }
 
+    pub fn short_name(&self) -> &String {
+        &self.short_name
+    }
+
+    /// Returns the absolute path the file was reached by.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
+
+    /// Returns the byte ranges of the file that were replaced with zeroes
+    /// because they lie in damaged clusters. Only a volume mounted with
+    /// `MountOptions::lenient` reads past damage; the ranges are known once
+    /// the file has been read.
+    pub fn unreliable_ranges(&self) -> &[Range<u64>] {
+        &self.unreliable
+    }
+
+    /// Sets whether this file may be seeked past its end, overriding the
+    /// volume's `MountOptions::seek_policy`.
+    pub fn set_seek_policy(&mut self, policy: SeekPolicy) {
+        self.seek_policy = Some(policy);
+    }
+
+    /// Shrinks the file to `size` bytes, rewriting its directory entry and
+    /// then freeing the clusters past its new end, so that an interrupted
+    /// shrink leaks clusters rather than leaving the entry pointing at freed
+    /// ones. Every handle open to the file
+    /// sees the new size; a handle positioned past it reads end of file. If
+    /// the volume was mounted with a `clock`, the file's modification and
+    /// access times are set from it. The change stays in the sector cache
+    /// until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `size` is larger than the file
+    /// or the file wasn't read from a directory entry.
+    pub fn set_len(&mut self, size: u64) -> io::Result<()> {
+        let location = match self.location {
+            Some(location) if !self.deleted => location,
+            _ => return Err(util::invalid_input("file has no directory entry"))
+                .at_path(&self.path),
+        };
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        self.refresh_from(&vfat);
+        if size > self.size as u64 {
+            return Err(util::invalid_input("files can only be shrunk")).at_path(&self.path);
+        }
+
+        let bytes_per_cluster = vfat.bytes_per_cluster() as u64;
+        let len = ((size + bytes_per_cluster - 1) / bytes_per_cluster) as u32;
+        let old_first_cluster = self.first_cluster;
+        let first_cluster = if len == 0 { Cluster::from(0) } else { self.first_cluster };
+
+        let mut entry = vfat.read_dir_entry(location).at_path(&self.path)?;
+        util::put_le_u16(&mut entry, 20, (first_cluster.get_index() >> 16) as u16);
+        util::put_le_u16(&mut entry, 26, first_cluster.get_index() as u16);
+        util::put_le_u32(&mut entry, 28, size as u32);
+        let now = vfat.options.clock.map(|clock| clock());
+        if let Some(now) = now {
+            util::put_le_u16(&mut entry, 18, now.date.to_raw());
+            util::put_le_u16(&mut entry, 22, now.time.0);
+            util::put_le_u16(&mut entry, 24, now.date.to_raw());
+        }
+        vfat.write_dir_entry(location, &entry).at_path(&self.path)?;
+        if let Some(now) = now {
+            self.metadata.mtime = Timestamp { hundredths: 0, ..now };
+            self.metadata.atime = Timestamp { time: Time(0), hundredths: 0, ..now };
+        }
+
+        vfat.open_files.resize(location, size as u32, first_cluster);
+        self.adopt(size as u32, first_cluster);
+        vfat.truncate_chain(old_first_cluster, len).at_path(&self.path)?;
+        Ok(())
+    }
+
+    /// Feeds the whole file to `digest` and returns the digest, reading a
+    /// cluster at a time rather than buffering the file. Reading starts from
+    /// the beginning of the file and leaves it positioned at its end.
+    ///
+    /// ```rust,no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// use fat32::device::ImageFile;
+    /// use fat32::digest::Sha256;
+    /// use fat32::traits::FileSystem;
+    /// use fat32::vfat::VFat;
+    ///
+    /// let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+    /// let digest = (&vfat).open_file("/kernel8.img")?.hash(Sha256::new())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn hash<H: Digest>(&mut self, mut digest: H) -> io::Result<H::Output> {
+        io::Seek::seek(self, SeekFrom::Start(0))?;
+        let mut buf = vec![0; self.vfat.borrow().bytes_per_cluster() as usize];
+        loop {
+            match io::Read::read(self, &mut buf) {
+                Ok(0) => return Ok(digest.finish()),
+                Ok(n) => digest.update(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
-impl traits::File for File {
-    /// Writes any buffered data to disk.
+impl<D: BlockDevice, L: RawLock> traits::File for File<D, L> {
+    /// Writes every change to the volume, including those made through this
+    /// file, back to its device. See `VFat::flush()`.
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!()
+        self.vfat.borrow_mut().flush().at_path(&self.path)
     }
 
-    /// Returns the size of the file in bytes.
+    /// Returns the size of the file in bytes, as last set through any handle
+    /// open to it.
     fn size(&self) -> u64 {
+        if let (true, Some(location)) = (self.registered, self.location) {
+            if let Some((size, _)) = self.vfat.borrow().open_files.get(location) {
+                return size as u64;
+            }
+        }
         self.size as u64
     }
 
 }
 
-impl io::Read for File {
+impl<D, L: RawLock> Drop for File<D, L> {
+    fn drop(&mut self) {
+        if let (true, Some(location)) = (self.registered, self.location) {
+            self.vfat.borrow_mut().open_files.close(location);
+        }
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> File<D, L> {
+    /// Records that `range` of the file was replaced with zeroes.
+    fn mark_unreliable(&mut self, range: Range<u64>) {
+        let range = range.start..min(range.end, self.size as u64);
+        if range.start >= range.end
+            || self.unreliable.iter().any(|r| r.start <= range.start && range.end <= r.end)
+        {
+            return;
+        }
+        self.unreliable.push(range);
+        self.unreliable.sort_by_key(|r| r.start);
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> io::Read for File<D, L> {
+    /// Reads from the file's current position, a cluster at a time, so that
+    /// reading needs no more memory than `buf` however large the file.
+    ///
+    /// On a volume mounted with `MountOptions::lenient`, damaged clusters,
+    /// and the part of the file past a chain cut short by a bad cluster, read
+    /// as zeroes and are recorded in `unreliable_ranges()`.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.size == 0 {
+        // A handle of its own leaves `self` free to update while borrowed.
+        let vfat = self.vfat.clone();
+        let mut vfat = vfat.borrow_mut();
+        self.refresh_from(&vfat);
+        if self.file_ptr >= self.size as u64 {
             return Ok(0);
         }
 
-        let mut v = Vec::new();
-        let _read = self.vfat.borrow_mut().read_chain(self.first_cluster, &mut v)?;
+        let lenient = vfat.options.lenient;
+        let bytes_per_cluster = vfat.bytes_per_cluster() as u64;
 
-        let file_left = self.size - self.file_ptr;
-        let can_read = min(file_left, buf.len() as u32);
-        buf[..can_read as usize]
-            .copy_from_slice(&v[self.file_ptr as usize..(self.file_ptr+can_read) as usize]);
-        self.file_ptr += can_read;
-        Ok(can_read as usize)
-    }
+        // The whole chain is checked on the first read so that a corrupt
+        // chain is reported no matter which part of the file is read.
+        if self.chain_len.is_none() {
+            let chain_len = vfat.walk_chain(self.first_cluster, lenient).at_path(&self.path)?
+                as u64;
+            if (self.size as u64 - 1) / bytes_per_cluster >= chain_len {
+                if !lenient {
+                    return Err(util::corrupt("file size exceeds its cluster chain"))
+                        .at_path(&self.path);
+                }
+                // A chain cut short by a bad cluster is padded out to the
+                // size of the file.
+                let end = chain_len * bytes_per_cluster;
+                self.mark_unreliable(end..self.size as u64);
+            }
+            self.chain_len = Some(chain_len);
+        }
+        let chain_len = self.chain_len.unwrap_or(0);
+
+        let can_read = min(self.size as u64 - self.file_ptr, buf.len() as u64) as usize;
+        let mut read = 0;
+        while read < can_read {
+            let index = self.file_ptr / bytes_per_cluster;
+            let offset = (self.file_ptr % bytes_per_cluster) as usize;
+            if index >= chain_len {
+                for byte in buf[read..can_read].iter_mut() {
+                    *byte = 0;
+                }
+                self.file_ptr += (can_read - read) as u64;
+                read = can_read;
+                break;
+            }
 
+            let (mut at, mut cluster) = match self.cursor {
+                Some((at, cluster)) if at <= index => (at, cluster),
+                _ => (0, self.first_cluster),
+            };
+            while at < index {
+                cluster = vfat.next_cluster(cluster, lenient)?
+                    .ok_or_else(|| util::corrupt("file size exceeds its cluster chain"))
+                    .at_path(&self.path)?;
+                at += 1;
+            }
+            self.cursor = Some((at, cluster));
+
+            let len = min(can_read - read, bytes_per_cluster as usize - offset);
+            let chunk = &mut buf[read..read + len];
+            let n = if !lenient {
+                vfat.read_cluster(cluster, offset, chunk).at_path(&self.path)?
+            } else {
+                // A bad cluster ends the chain, so only the last can be bad.
+                let is_bad = index + 1 == chain_len
+                    && vfat.fat_entry(cluster)?.status() == Status::Bad;
+                if is_bad || vfat.read_cluster(cluster, offset, chunk).is_err() {
+                    for byte in chunk.iter_mut() {
+                        *byte = 0;
+                    }
+                    let start = index * bytes_per_cluster;
+                    self.mark_unreliable(start..start + bytes_per_cluster);
+                }
+                len
+            };
+            if n == 0 {
+                return Err(util::eof("cluster read returned no data")).at_path(&self.path);
+            }
+            read += n;
+            self.file_ptr += n as u64;
+        }
+        Ok(read)
+    }
 }
 
-impl io::Write for File {
+impl<D: BlockDevice, L: RawLock> io::Write for File<D, L> {
+    /// Writing file contents isn't supported; this always returns an error of
+    /// `PermissionDenied`. Use `set_len()` to shrink a file.
     fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+        Err(util::permission_denied("writing file contents is not supported"))
+            .at_path(&self.path)
     }
 
+    /// Same as `traits::File::sync()`.
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!() 
+        traits::File::sync(self)
     }
 }
 
-impl io::Seek for File {
+impl<D: BlockDevice, L: RawLock> io::Seek for File<D, L> {
     /// Seek to offset `pos` in the file.
     ///
     /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
-    /// file returns an `InvalidInput` error.
+    /// file returns an `InvalidInput` error, unless the file's seek policy is
+    /// `SeekPolicy::Sparse`, in which case reads there return end of file.
     ///
     /// If the seek operation completes successfully, this method returns the
     /// new position from the start of the stream. That position can be used
@@ -92,53 +382,31 @@ impl io::Seek for File {
     ///
     /// # Errors
     ///
-    /// Seeking before the start of a file or beyond the end of the file results
-    /// in an `InvalidInput` error.
+    /// Seeking before the start of a file, beyond the end of the file under
+    /// `SeekPolicy::Strict`, or beyond 4 GiB - 1 results in an `InvalidInput`
+    /// error.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         use traits::File;
-        match pos {
-            SeekFrom::Start(offset) => {
-                if offset > self.size() {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput,
-                        format!("invalid position {}", offset)))
-                } else {
-                    self.file_ptr = offset as u32;
-                    Ok(self.file_ptr as u64)
-                }
-            }
-            SeekFrom::End(offset) => {
-                let new_ptr = if offset.is_negative() {
-                    self.size().checked_sub((-offset) as u64)
-                } else {
-                    self.size().checked_add(offset as u64)
-                
-                }.ok_or(io::Error::new(io::ErrorKind::InvalidInput,
-                        format!("invalid position {}", offset)))? as u32;
-                        
-                if new_ptr > self.size() as u32 {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput,
-                        format!("invalid position {}", offset)))
-                } else {
-                    self.file_ptr = new_ptr as u32;
-                    Ok(self.file_ptr as u64)
-                }
-            },
-            SeekFrom::Current(offset) => {
-                let new_ptr = if offset.is_negative() {
-                    self.file_ptr.checked_sub((-offset) as u32)
-                } else {
-                    self.file_ptr.checked_add(offset as u32)
-                }.ok_or(io::Error::new(io::ErrorKind::InvalidInput,
-                        format!("invalid position {}", offset)))?;
-                if new_ptr > self.size() as u32 {
-                    Err(io::Error::new(io::ErrorKind::InvalidInput,
-                        format!("invalid position {}", offset)))
-                } else {
-                    self.file_ptr = new_ptr as u32;
-                    Ok(self.file_ptr as u64)
-                }
-            }
-        
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i128),
+            SeekFrom::End(offset) => (self.size(), offset as i128),
+            SeekFrom::Current(offset) => (self.file_ptr, offset as i128),
+        };
+        let invalid = || util::invalid_input(format!("invalid position {}", offset));
+
+        let policy = match self.seek_policy {
+            Some(policy) => policy,
+            None => self.vfat.borrow().options.seek_policy,
+        };
+        let limit = match policy {
+            SeekPolicy::Strict => self.size(),
+            SeekPolicy::Sparse => ::std::u32::MAX as u64,
+        };
+        let new_ptr = base as i128 + offset;
+        if new_ptr < 0 || new_ptr > limit as i128 {
+            return Err(invalid());
         }
+        self.file_ptr = new_ptr as u64;
+        Ok(self.file_ptr)
     }
 }
@@ -0,0 +1,187 @@
+//! Functions mirroring `std::fs` over a mounted volume, to ease porting code
+//! written against `std`. Each takes the volume first, so that
+//! `std::fs::read("config.txt")` becomes `fs::read(&vfat, "/config.txt")`.
+//! Paths must be absolute.
+//!
+//! ```rust,no_run
+//! # fn main() -> std::io::Result<()> {
+//! use fat32::device::ImageFile;
+//! use fat32::vfat::{fs, VFat};
+//!
+//! let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+//! for entry in fs::read_dir(&vfat, "/")? {
+//!     let entry = entry?;
+//!     println!("{} {}", entry.path().display(), entry.metadata().len());
+//! }
+//! let config = fs::read_to_string(&vfat, "/config.txt")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+
+use traits::{self, BlockDevice, DynBlockDevice, FileSystem, RawLock};
+use util;
+use vfat::{Attributes, DefaultLock, Entry, Shared, Timestamp, VFat};
+use vfat::dir::VFatDirEntryIter;
+
+/// Information about a file or directory, as returned by `metadata()`.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    inner: ::vfat::Metadata,
+    is_dir: bool,
+}
+
+impl Metadata {
+    fn of<D: BlockDevice, L: RawLock>(entry: &Entry<D, L>) -> Metadata {
+        Metadata {
+            inner: traits::Entry::metadata(entry).clone(),
+            is_dir: entry.is_dir(),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+
+    /// Returns the size of the file in bytes, or 0 for a directory.
+    pub fn len(&self) -> u64 {
+        if self.is_dir { 0 } else { self.inner.size as u64 }
+    }
+
+    pub fn created(&self) -> Timestamp {
+        self.inner.ctime
+    }
+
+    pub fn modified(&self) -> Timestamp {
+        self.inner.mtime
+    }
+
+    pub fn accessed(&self) -> Timestamp {
+        self.inner.atime
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.inner.attr.read_only()
+    }
+
+    /// Returns the entry's FAT attributes.
+    pub fn attributes(&self) -> Attributes {
+        self.inner.attr
+    }
+}
+
+/// An entry of a directory, as returned by `ReadDir`.
+#[derive(Debug)]
+pub struct DirEntry<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    path: PathBuf,
+    entry: Entry<D, L>,
+}
+
+impl<D: BlockDevice, L: RawLock> DirEntry<D, L> {
+    /// Returns the absolute path of the entry.
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn file_name(&self) -> &str {
+        traits::Entry::name(&self.entry)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        Metadata::of(&self.entry)
+    }
+
+    /// Returns the entry itself, to open it.
+    pub fn into_entry(self) -> Entry<D, L> {
+        self.entry
+    }
+}
+
+/// An iterator over the entries of a directory, as returned by `read_dir()`.
+/// `.` and `..`, and the volume label, are skipped.
+pub struct ReadDir<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    path: PathBuf,
+    entries: VFatDirEntryIter<D, L>,
+}
+
+impl<D: BlockDevice, L: RawLock> Iterator for ReadDir<D, L> {
+    type Item = io::Result<DirEntry<D, L>>;
+
+    fn next(&mut self) -> Option<io::Result<DirEntry<D, L>>> {
+        for entry in self.entries.by_ref() {
+            let skip = {
+                let name = traits::Entry::name(&entry);
+                name == "." || name == ".."
+                    || traits::Entry::metadata(&entry).attr.volume_id()
+            };
+            if !skip {
+                return Some(Ok(DirEntry {
+                    path: self.path.join(traits::Entry::name(&entry)),
+                    entry: entry,
+                }));
+            }
+        }
+        None
+    }
+}
+
+/// Reads the whole file at `path`.
+pub fn read<D, L, P>(vfat: &Shared<VFat<D, L>, L>, path: P) -> io::Result<Vec<u8>>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
+    let mut file = vfat.open_file(path)?;
+    let mut data = Vec::with_capacity(file.size as usize);
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads the whole file at `path` as UTF-8.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidData` if the file isn't valid UTF-8.
+pub fn read_to_string<D, L, P>(vfat: &Shared<VFat<D, L>, L>, path: P) -> io::Result<String>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
+    String::from_utf8(read(vfat, path)?)
+        .map_err(|_| util::corrupt("file is not valid UTF-8"))
+}
+
+/// Returns an iterator over the entries of the directory at `path`.
+pub fn read_dir<D, L, P>(vfat: &Shared<VFat<D, L>, L>, path: P) -> io::Result<ReadDir<D, L>>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
+    let dir = vfat.open_dir(path.as_ref())?;
+    Ok(ReadDir {
+        path: path.as_ref().to_path_buf(),
+        entries: traits::Dir::entries(&dir)?,
+    })
+}
+
+/// Returns information about the file or directory at `path`.
+pub fn metadata<D, L, P>(vfat: &Shared<VFat<D, L>, L>, path: P) -> io::Result<Metadata>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>
+{
+    Ok(Metadata::of(&vfat.open(path)?))
+}
+
+/// Copies the file at `from` on the volume to the host file `to`, replacing
+/// it if it exists. Returns the number of bytes copied.
+#[cfg(feature = "std")]
+pub fn copy<D, L, P, Q>(vfat: &Shared<VFat<D, L>, L>, from: P, to: Q) -> io::Result<u64>
+    where D: BlockDevice, L: RawLock, P: AsRef<Path>, Q: AsRef<Path>
+{
+    let mut file = vfat.open_file(from)?;
+    let mut out = fs::File::create(to)?;
+    io::copy(&mut file, &mut out)
+}
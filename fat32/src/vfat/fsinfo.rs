@@ -0,0 +1,77 @@
+use std::vec::Vec;
+
+use util::{le_u32, put_le_u32};
+use vfat::BiosParameterBlock;
+
+const LEAD_SIGNATURE: u32 = 0x4161_5252;
+const STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+/// Stored in place of a count or hint that isn't known.
+const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The hints kept in a volume's FSInfo sector, so that the free space of a
+/// volume can be reported without scanning the FAT. Nothing guarantees the
+/// hints are right: a driver that doesn't update them leaves them stale.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FsInfo {
+    /// The number of free clusters, or `None` if unknown.
+    pub free_count: Option<u32>,
+    /// The cluster to start looking for a free cluster from, or `None` if
+    /// unknown.
+    pub next_free: Option<u32>,
+}
+
+/// The FSInfo free-cluster count disagreeing with the FAT, as found by
+/// `VFat::check_free_count()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FreeCountMismatch {
+    /// The count in the FSInfo sector, or `None` if it was marked unknown.
+    pub recorded: Option<u32>,
+    /// The number of clusters the FAT marks free.
+    pub actual: u32,
+}
+
+impl FsInfo {
+    /// Returns the sectors, relative to the start of the partition, holding
+    /// the FSInfo sector of the volume described by `ebpb` and its backup
+    /// after the backup boot sector, primary first. Sectors outside of the
+    /// reserved region are ignored.
+    pub(crate) fn locate(ebpb: &BiosParameterBlock) -> Vec<u64> {
+        let reserved = ebpb.num_reserved_sectors as u64;
+        let fsinfo = ebpb.fsinfo_sector as u64;
+        if fsinfo == 0 || fsinfo >= reserved {
+            return Vec::new();
+        }
+        let mut sectors = vec![fsinfo];
+        let backup = ebpb.backup_boot_sector as u64;
+        if backup != 0 && backup + fsinfo < reserved {
+            sectors.push(backup + fsinfo);
+        }
+        sectors
+    }
+
+    /// Parses an FSInfo sector, returning `None` if its signatures are wrong.
+    pub(crate) fn parse(sector: &[u8]) -> Option<FsInfo> {
+        if sector.len() < 512
+            || le_u32(sector, 0) != LEAD_SIGNATURE
+            || le_u32(sector, 484) != STRUCT_SIGNATURE
+            || le_u32(sector, 508) != TRAIL_SIGNATURE
+        {
+            return None;
+        }
+        let hint = |offset| match le_u32(sector, offset) {
+            UNKNOWN => None,
+            value => Some(value),
+        };
+        Some(FsInfo {
+            free_count: hint(488),
+            next_free: hint(492),
+        })
+    }
+
+    /// Writes the hints into `sector`, an FSInfo sector.
+    pub(crate) fn write(&self, sector: &mut [u8]) {
+        put_le_u32(sector, 488, self.free_count.unwrap_or(UNKNOWN));
+        put_le_u32(sector, 492, self.next_free.unwrap_or(UNKNOWN));
+    }
+}
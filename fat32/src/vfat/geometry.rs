@@ -0,0 +1,42 @@
+/// The layout of a mounted volume, as returned by `VFat::geometry()`.
+///
+/// Sector numbers are relative to the start of the partition.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Geometry {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub bytes_per_cluster: u32,
+    /// The number of sectors in the partition.
+    pub total_sectors: u64,
+    /// The number of data clusters, numbered from 2.
+    pub num_clusters: u32,
+    pub sectors_per_fat: u32,
+    /// The number of copies of the FAT.
+    pub num_fats: u8,
+    /// The first sector of the first copy of the FAT.
+    pub fat_start_sector: u64,
+    /// The first sector of the data region, where cluster 2 begins.
+    pub data_start_sector: u64,
+    /// The first cluster of the root directory.
+    pub root_cluster: u32,
+}
+
+impl Geometry {
+    /// Returns the number of bytes in the data region.
+    pub fn data_bytes(&self) -> u64 {
+        self.num_clusters as u64 * self.bytes_per_cluster as u64
+    }
+}
+
+/// The state of a mounted volume's caches, as returned by `VFat::stats()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of sectors held in the sector cache.
+    pub cached_sectors: usize,
+    /// The number of cached sectors changed since they were last written
+    /// to the device.
+    pub dirty_sectors: usize,
+    /// The number of sectors evicted from the sector cache to make room,
+    /// with `MountOptions::cache_sectors`.
+    pub evicted_sectors: u64,
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::vec::Vec;
+
+use traits::{BlockDevice, DynBlockDevice, FileSystem, RawLock};
+use util;
+use vfat::{VFat, Shared, DefaultLock, File, Dir, Entry};
+use vfat::dir::VFatDirEntryIter;
+
+/// A file descriptor: the index of an open file or directory in a
+/// `HandleTable`.
+pub type Fd = usize;
+
+enum Handle<D, L: RawLock> {
+    File(File<D, L>),
+    /// A directory and, once it is being listed, the listing.
+    Dir(Dir<D, L>, Option<VFatDirEntryIter<D, L>>),
+}
+
+/// The files and directories a process has open on a volume, each known by a
+/// small integer file descriptor, so that a kernel's `open()`, `read()`,
+/// `lseek()`, and `close()` system calls can pass their arguments straight
+/// through.
+///
+/// Like POSIX, `open()` returns the lowest descriptor not in use.
+///
+/// ```rust,no_run
+/// use std::io;
+/// use fat32::vfat::{HandleTable, VFat};
+/// use fat32::device::ImageFile;
+///
+/// let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+/// let mut handles = HandleTable::new(vfat);
+/// let fd = handles.open("/config.txt")?;
+/// let mut buf = [0; 512];
+/// let n = handles.read(fd, &mut buf)?;
+/// handles.close(fd)?;
+/// # Ok::<(), io::Error>(())
+/// ```
+pub struct HandleTable<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    vfat: Shared<VFat<D, L>, L>,
+    handles: Vec<Option<Handle<D, L>>>,
+    limit: usize,
+}
+
+impl<D: BlockDevice, L: RawLock> HandleTable<D, L> {
+    /// Returns an empty table for `vfat` with no limit on the number of open
+    /// descriptors.
+    pub fn new(vfat: Shared<VFat<D, L>, L>) -> HandleTable<D, L> {
+        HandleTable::with_limit(vfat, ::std::usize::MAX)
+    }
+
+    /// Returns an empty table for `vfat` holding at most `limit` open
+    /// descriptors.
+    pub fn with_limit(vfat: Shared<VFat<D, L>, L>, limit: usize) -> HandleTable<D, L> {
+        HandleTable {
+            vfat: vfat,
+            handles: Vec::new(),
+            limit: limit,
+        }
+    }
+
+    /// Opens the file or directory at the absolute path `path` and returns
+    /// its descriptor. Files are positioned at their start.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `FileSystem::open()` does, or with an error of kind `Other`
+    /// if `limit` descriptors are already open.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Fd> {
+        let fd = match self.handles.iter().position(|handle| handle.is_none()) {
+            Some(fd) => fd,
+            None if self.handles.len() < self.limit => self.handles.len(),
+            None => return Err(util::failed("too many open files")),
+        };
+        let handle = match (&self.vfat).open(path)? {
+            Entry::File(file) => Handle::File(file),
+            Entry::Dir(dir) => Handle::Dir(dir, None),
+        };
+        if fd == self.handles.len() {
+            self.handles.push(Some(handle));
+        } else {
+            self.handles[fd] = Some(handle);
+        }
+        Ok(fd)
+    }
+
+    /// Closes `fd`, making it available to later calls to `open()`.
+    pub fn close(&mut self, fd: Fd) -> io::Result<()> {
+        self.handle(fd)?;
+        self.handles[fd] = None;
+        while let Some(&None) = self.handles.last() {
+            self.handles.pop();
+        }
+        Ok(())
+    }
+
+    /// Reads from the file `fd` at its current position, as `io::Read`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `fd` isn't open or is a
+    /// directory.
+    pub fn read(&mut self, fd: Fd, buf: &mut [u8]) -> io::Result<usize> {
+        self.file(fd)?.read(buf)
+    }
+
+    /// Writing isn't supported: returns an error of `PermissionDenied` for
+    /// any open `fd`.
+    pub fn write(&mut self, fd: Fd, _buf: &[u8]) -> io::Result<usize> {
+        self.handle(fd)?;
+        Err(util::permission_denied("read-only file system"))
+    }
+
+    /// Moves the position of the file `fd`, as `io::Seek`, and returns the
+    /// new position. For a directory, seeking to the start restarts its
+    /// listing and returns 0.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `fd` isn't open, or is a
+    /// directory and `pos` isn't its start.
+    pub fn seek(&mut self, fd: Fd, pos: SeekFrom) -> io::Result<u64> {
+        match *self.handle(fd)? {
+            Handle::File(ref mut file) => file.seek(pos),
+            Handle::Dir(_, ref mut listing) if pos == SeekFrom::Start(0) => {
+                *listing = None;
+                Ok(0)
+            }
+            Handle::Dir(..) => Err(util::invalid_input("directories can only be rewound")),
+        }
+    }
+
+    /// Returns the next entry of the directory `fd`, or `None` once every
+    /// entry has been returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `fd` isn't open or is a file.
+    pub fn read_dir(&mut self, fd: Fd) -> io::Result<Option<Entry<D, L>>> {
+        match *self.handle(fd)? {
+            Handle::Dir(ref dir, ref mut listing) => {
+                if listing.is_none() {
+                    *listing = Some(dir.entries_all(false)?);
+                }
+                Ok(listing.as_mut().and_then(|listing| listing.next()))
+            }
+            Handle::File(_) => Err(util::invalid_input("not a directory")),
+        }
+    }
+
+    /// Returns the open file `fd`, for operations the table doesn't provide.
+    pub fn file(&mut self, fd: Fd) -> io::Result<&mut File<D, L>> {
+        match *self.handle(fd)? {
+            Handle::File(ref mut file) => Ok(file),
+            Handle::Dir(..) => Err(util::invalid_input("is a directory")),
+        }
+    }
+
+    /// Returns the open directory `fd`.
+    pub fn dir(&mut self, fd: Fd) -> io::Result<&Dir<D, L>> {
+        match *self.handle(fd)? {
+            Handle::Dir(ref dir, _) => Ok(dir),
+            Handle::File(_) => Err(util::invalid_input("not a directory")),
+        }
+    }
+
+    /// Returns the number of open descriptors.
+    pub fn len(&self) -> usize {
+        self.handles.iter().filter(|handle| handle.is_some()).count()
+    }
+
+    fn handle(&mut self, fd: Fd) -> io::Result<&mut Handle<D, L>> {
+        self.handles.get_mut(fd)
+            .and_then(|handle| handle.as_mut())
+            .ok_or_else(|| util::invalid_input(format!("bad file descriptor {}", fd)))
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> fmt::Debug for HandleTable<D, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let open: Vec<Fd> = (0..self.handles.len())
+            .filter(|&fd| self.handles[fd].is_some())
+            .collect();
+        f.debug_struct("HandleTable")
+            .field("open", &open)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
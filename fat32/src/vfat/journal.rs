@@ -0,0 +1,166 @@
+use std::cmp::{max, min};
+use std::io;
+use std::vec::Vec;
+
+use traits::BlockDevice;
+use util::{self, le_u32, le_u64, put_le_u32, put_le_u64};
+use vfat::{BiosParameterBlock, WithContext};
+
+const MAGIC: &[u8; 8] = b"FAT32JNL";
+const VERSION: u32 = 1;
+/// The length of the header before its list of sectors.
+const HEADER_LEN: usize = 24;
+/// Windows keeps boot code in reserved sectors 0, 2, and 12, so the journal
+/// starts after them.
+const FIRST_FREE_SECTOR: u64 = 13;
+
+/// A redo log for metadata updates, kept in otherwise unused reserved sectors
+/// of the volume.
+///
+/// A transaction is committed by writing copies of the sectors it changes to
+/// the journal, followed by a header sector listing where they belong and a
+/// checksum of the header and the copies. Only then are the sectors written
+/// in place, after which the header is cleared. A crash before the header is
+/// complete leaves the volume as it was, and a crash after it leaves a valid
+/// header behind, so the transaction is replayed on the next mount.
+///
+/// The header sector is followed by the copies:
+///
+/// | offset | size | field                                     |
+/// |--------|------|-------------------------------------------|
+/// | 0      | 8    | magic, `FAT32JNL`                         |
+/// | 8      | 4    | version, 1                                |
+/// | 12     | 4    | number of sectors in the transaction, `n` |
+/// | 16     | 4    | FNV-1a hash of the header and the copies  |
+/// | 20     | 4    | reserved, 0                               |
+/// | 24     | 8 n  | the sectors the copies belong at          |
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Journal {
+    /// The header sector, relative to the start of the partition.
+    start: u64,
+    /// The most sectors a transaction may change.
+    capacity: usize,
+}
+
+/// A transaction read back from the journal: sectors and their contents.
+type Transaction = Vec<(u64, Vec<u8>)>;
+
+impl Journal {
+    /// Finds room for a journal in the reserved sectors of the volume
+    /// described by `ebpb`, after the boot sectors, the FSInfo sector, and
+    /// the backup boot sectors. Returns `None` if there isn't room for a
+    /// header and at least one copy.
+    pub fn locate(ebpb: &BiosParameterBlock) -> Option<Journal> {
+        let reserved = ebpb.num_reserved_sectors as u64;
+        let mut start = FIRST_FREE_SECTOR;
+        // The backup boot sector is followed by a copy of the FSInfo sector
+        // and of the third boot sector.
+        for &(sector, len) in &[(ebpb.fsinfo_sector, 1), (ebpb.backup_boot_sector, 3)] {
+            if sector != 0 && (sector as u64) < reserved {
+                start = max(start, sector as u64 + len);
+            }
+        }
+
+        let header_capacity = (ebpb.bytes_per_sector as usize).saturating_sub(HEADER_LEN) / 8;
+        match reserved.checked_sub(start + 1) {
+            Some(copies) if copies > 0 && header_capacity > 0 => Some(Journal {
+                start: start,
+                capacity: min(copies as usize, header_capacity),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the most sectors a single transaction may change.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Writes `sectors`, pairs of a sector and its new contents, to the
+    /// journal as one committed transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if there are more sectors than the
+    /// journal holds, and an error if writing to `device` fails.
+    pub fn commit<D: BlockDevice>(&self, device: &mut D, sectors: &[(u64, &[u8])])
+        -> io::Result<()>
+    {
+        if sectors.len() > self.capacity {
+            return Err(util::invalid_input(format!(
+                "{} sectors don't fit in a journal of {}", sectors.len(), self.capacity)));
+        }
+
+        let mut header = vec![0; device.sector_size() as usize];
+        header[..8].copy_from_slice(MAGIC);
+        put_le_u32(&mut header, 8, VERSION);
+        put_le_u32(&mut header, 12, sectors.len() as u32);
+        for (i, &(sector, data)) in sectors.iter().enumerate() {
+            put_le_u64(&mut header, HEADER_LEN + i * 8, sector);
+            let copy = self.start + 1 + i as u64;
+            device.write_sector(copy, data).at_sector(copy)?;
+        }
+        let hash = checksum(&header, sectors.iter().map(|&(_, data)| data));
+        put_le_u32(&mut header, 16, hash);
+        device.write_sector(self.start, &header).at_sector(self.start)?;
+        Ok(())
+    }
+
+    /// Marks the last transaction as written in place.
+    pub fn clear<D: BlockDevice>(&self, device: &mut D) -> io::Result<()> {
+        let header = vec![0; device.sector_size() as usize];
+        device.write_sector(self.start, &header).at_sector(self.start)?;
+        Ok(())
+    }
+
+    /// Returns the committed transaction in the journal, if there is one. A
+    /// transaction whose header or copies fail the checksum was torn while
+    /// being committed and is ignored.
+    pub fn pending<D: BlockDevice>(&self, device: &mut D) -> io::Result<Option<Transaction>> {
+        let sector_size = device.sector_size() as usize;
+        let mut header = vec![0; sector_size];
+        device.read_sector(self.start, &mut header).at_sector(self.start)?;
+        let len = le_u32(&header, 12) as usize;
+        if &header[..8] != MAGIC || le_u32(&header, 8) != VERSION || len > self.capacity {
+            return Ok(None);
+        }
+
+        let mut transaction = Vec::with_capacity(len);
+        for i in 0..len {
+            let copy = self.start + 1 + i as u64;
+            let mut data = vec![0; sector_size];
+            device.read_sector(copy, &mut data).at_sector(copy)?;
+            transaction.push((le_u64(&header, HEADER_LEN + i * 8), data));
+        }
+
+        let hash = le_u32(&header, 16);
+        put_le_u32(&mut header, 16, 0);
+        if checksum(&header, transaction.iter().map(|&(_, ref data)| &data[..])) != hash {
+            return Ok(None);
+        }
+        Ok(Some(transaction))
+    }
+
+    /// Writes the committed transaction in the journal, if there is one, in
+    /// place and clears it. Returns the number of sectors written.
+    pub fn replay<D: BlockDevice>(&self, device: &mut D) -> io::Result<usize> {
+        let transaction = match self.pending(device)? {
+            Some(transaction) => transaction,
+            None => return Ok(0),
+        };
+        for &(sector, ref data) in transaction.iter() {
+            device.write_sector(sector, data).at_sector(sector)?;
+        }
+        self.clear(device)?;
+        Ok(transaction.len())
+    }
+}
+
+/// Returns the 32-bit FNV-1a hash of `header`, with its hash field zeroed,
+/// followed by `copies`.
+fn checksum<'a, I: Iterator<Item = &'a [u8]>>(header: &[u8], copies: I) -> u32 {
+    let hash = |hash: u32, bytes: &[u8]| {
+        bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+    };
+    copies.fold(hash(0x811c9dc5, header), |h, data| hash(h, data))
+}
@@ -1,6 +1,11 @@
+use std::cmp::{min, max, Ordering};
 use std::fmt;
+use std::string::String;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use traits;
+use vfat::TimestampPolicy;
 
 /// A date as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
@@ -8,11 +13,63 @@ use traits;
 pub struct Date(u16);
 
 impl Date {
+    /// Packs a calendar date into its on-disk representation. `year` must be
+    /// in range [1980, 2107].
+    pub fn from_ymd(year: usize, month: u8, day: u8) -> Date {
+        Date((((year - 1980) as u16) << 9) | ((month as u16 & 0xF) << 5) | (day as u16 & 0x1F))
+    }
+
     pub fn year(&self) -> usize { (self.0 >> 9) as usize + 1980 }
 
     pub fn month(&self) -> u8 { ((self.0 & 0x1E0) >> 5) as u8 }
 
     pub fn day(&self) -> u8 { self.0 as u8 & 0x1F }
+
+    /// Returns the on-disk representation of `self`.
+    pub(crate) fn to_raw(&self) -> u16 { self.0 }
+
+    /// Returns the month if it is in range [1, 12].
+    pub fn checked_month(&self) -> Option<u8> {
+        match self.month() {
+            month @ 1...12 => Some(month),
+            _ => None
+        }
+    }
+
+    /// Returns the day if it exists in the month and year of `self`.
+    pub fn checked_day(&self) -> Option<u8> {
+        let month = self.checked_month()?;
+        match self.day() {
+            0 => None,
+            day if day > days_in_month(self.year(), month) => None,
+            day => Some(day)
+        }
+    }
+
+    /// Whether `self` denotes an existing calendar date.
+    pub fn is_valid(&self) -> bool {
+        self.checked_day().is_some()
+    }
+
+    /// Returns `self` with the month and day clamped into their valid ranges.
+    pub fn clamped(&self) -> Date {
+        let month = min(max(self.month(), 1), 12);
+        let day = min(max(self.day(), 1), days_in_month(self.year(), month));
+        Date::from_ymd(self.year(), month, day)
+    }
+}
+
+fn is_leap_year(year: usize) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: usize, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
 }
 
 /// Time as represented in FAT32 on-disk structures.
@@ -21,11 +78,44 @@ impl Date {
 pub struct Time(pub u16);
 
 impl Time {
+    /// Packs a time of day into its on-disk representation. Seconds are
+    /// truncated to a two second granularity.
+    pub fn from_hms(hour: u8, minute: u8, second: u8) -> Time {
+        Time(((hour as u16 & 0x1F) << 11) | ((minute as u16 & 0x3F) << 5) | (second as u16 / 2 & 0x1F))
+    }
+
     pub fn hour(&self) -> u8 { (self.0 >> 11) as u8 }
 
     pub fn minute(&self) -> u8 { ((self.0 & 0x7E0) >> 5) as u8 }
 
     pub fn second(&self) -> u8 { (self.0 as u8 & 0x1F) * 2 }
+
+    /// Returns the hour if it is in range [0, 24).
+    pub fn checked_hour(&self) -> Option<u8> {
+        if self.hour() < 24 { Some(self.hour()) } else { None }
+    }
+
+    /// Returns the minute if it is in range [0, 60).
+    pub fn checked_minute(&self) -> Option<u8> {
+        if self.minute() < 60 { Some(self.minute()) } else { None }
+    }
+
+    /// Returns the second if it is in range [0, 60).
+    pub fn checked_second(&self) -> Option<u8> {
+        if self.second() < 60 { Some(self.second()) } else { None }
+    }
+
+    /// Whether every field of `self` is in range.
+    pub fn is_valid(&self) -> bool {
+        self.checked_hour().is_some()
+            && self.checked_minute().is_some()
+            && self.checked_second().is_some()
+    }
+
+    /// Returns `self` with every field clamped into its valid range.
+    pub fn clamped(&self) -> Time {
+        Time::from_hms(min(self.hour(), 23), min(self.minute(), 59), min(self.second(), 58))
+    }
 }
 
 /// File attributes as represented in FAT32 on-disk structures.
@@ -69,14 +159,313 @@ impl Attributes {
     pub fn lfn(&self) -> bool {
         self.0 == Self::LFN
     }
+
+    /// Builds attributes from a Unix `st_mode`. The entry is a directory if
+    /// `mode` has the directory file type and read-only if the owner write
+    /// bit is clear. Other attributes can be set with the `with_*` methods.
+    pub fn from_unix_mode(mode: u32) -> Attributes {
+        Attributes(0)
+            .with_directory(mode & S_IFMT == S_IFDIR)
+            .with_read_only(mode & 0o200 == 0)
+    }
+
+    fn with(self, flag: u8, set: bool) -> Attributes {
+        if set { Attributes(self.0 | flag) } else { Attributes(self.0 & !flag) }
+    }
+
+    pub fn with_read_only(self, set: bool) -> Attributes {
+        self.with(Self::READ_ONLY, set)
+    }
+
+    pub fn with_hidden(self, set: bool) -> Attributes {
+        self.with(Self::HIDDEN, set)
+    }
+
+    pub fn with_system(self, set: bool) -> Attributes {
+        self.with(Self::SYSTEM, set)
+    }
+
+    pub fn with_directory(self, set: bool) -> Attributes {
+        self.with(Self::DIRECTORY, set)
+    }
+
+    pub fn with_archive(self, set: bool) -> Attributes {
+        self.with(Self::ARCHIVE, set)
+    }
 }
 
+/// Bit mask for the file type bits of a Unix `st_mode`.
+pub const S_IFMT: u32 = 0o170000;
+/// Unix `st_mode` file type of a directory.
+pub const S_IFDIR: u32 = 0o040000;
+/// Unix `st_mode` file type of a regular file.
+pub const S_IFREG: u32 = 0o100000;
+
 /// A structure containing a date and time.
 #[repr(C, packed)]
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Timestamp {
     pub time: Time,
     pub date: Date,
+    /// Fine resolution in units of 10 ms, in range [0, 200). Only creation
+    /// times carry this on disk; it is 0 for all other timestamps.
+    pub hundredths: u8,
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Seconds between the Unix epoch and 1980-01-01 00:00:00, the FAT epoch.
+const FAT_EPOCH_SECS: u64 = 315_532_800;
+
+/// Seconds between the Unix epoch and 2107-12-31 23:59:58, the latest
+/// timestamp representable on disk.
+const FAT_MAX_SECS: u64 = 4_354_819_198;
+
+/// Returns the number of days between 1970-01-01 and the given civil date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the civil date `(year, month, day)` that is `days` days after
+/// 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+                       - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u8, day as u8)
+}
+
+impl Timestamp {
+    /// Whether `self` is all zeroes, which FAT uses for a timestamp that was
+    /// never set, such as the creation and access times of entries written
+    /// by drivers that only keep modification times.
+    pub fn is_unset(&self) -> bool {
+        self.date.0 == 0 && self.time.0 == 0 && self.hundredths == 0
+    }
+
+    /// Whether the date, time, and fine resolution of `self` are in range,
+    /// or `self` is unset.
+    pub fn is_valid(&self) -> bool {
+        self.is_unset()
+            || (self.date.is_valid() && self.time.is_valid() && self.hundredths < 200)
+    }
+
+    /// Applies `policy` to `self`. Returns `None` if `self` is invalid and
+    /// `policy` is `TimestampPolicy::Error`. Unset timestamps are valid and
+    /// returned as they are.
+    pub fn sanitize(&self, policy: TimestampPolicy) -> Option<Timestamp> {
+        if self.is_valid() || policy == TimestampPolicy::Unchecked {
+            return Some(*self);
+        }
+
+        match policy {
+            TimestampPolicy::Clamp => Some(Timestamp {
+                time: self.time.clamped(),
+                date: self.date.clamped(),
+                hundredths: min(self.hundredths, 199),
+            }),
+            TimestampPolicy::EpochDefault => Some(Timestamp {
+                time: Time(0),
+                date: Date::from_ymd(1980, 1, 1),
+                hundredths: 0,
+            }),
+            _ => None
+        }
+    }
+
+    /// Returns the number of whole seconds between the Unix epoch and `self`,
+    /// interpreting `self` as UTC. Timestamps read from a volume are in the
+    /// volume's local time; convert those with `Metadata::to_unix_seconds()`.
+    pub fn to_unix_seconds(&self) -> i64 {
+        let days = days_from_civil(self.date.year() as i64,
+                                   self.date.month() as i64,
+                                   self.date.day() as i64);
+        days * SECS_PER_DAY as i64
+            + self.time.hour() as i64 * 3600
+            + self.time.minute() as i64 * 60
+            + self.time.second() as i64
+            + self.hundredths as i64 / 100
+    }
+
+    /// Builds a timestamp `secs` seconds after the Unix epoch, interpreted as
+    /// UTC. Times outside of the range FAT can represent are clamped to it.
+    pub fn from_unix_seconds(secs: i64) -> Timestamp {
+        let secs = if secs < 0 { 0 } else { secs as u64 };
+        Timestamp::from_unix_parts(secs, 0)
+    }
+
+    /// Builds a timestamp `secs` seconds and `nanos` nanoseconds after the
+    /// Unix epoch, interpreted as UTC. Times outside of the range FAT can
+    /// represent are clamped to it.
+    fn from_unix_parts(secs: u64, nanos: u32) -> Timestamp {
+        let (secs, nanos) = if secs < FAT_EPOCH_SECS {
+            (FAT_EPOCH_SECS, 0)
+        } else if secs > FAT_MAX_SECS {
+            (FAT_MAX_SECS, 0)
+        } else {
+            (secs, nanos)
+        };
+
+        let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+        let secs_of_day = secs % SECS_PER_DAY;
+        let second = (secs_of_day % 60) as u8;
+        Timestamp {
+            time: Time::from_hms((secs_of_day / 3600) as u8,
+                                 (secs_of_day / 60 % 60) as u8,
+                                 second),
+            date: Date::from_ymd(year as usize, month, day),
+            hundredths: (second % 2) * 100 + (nanos / 10_000_000) as u8,
+        }
+    }
+}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Timestamp) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Timestamps are ordered chronologically. The on-disk date and time
+/// encodings place the most significant unit in the highest bits, so their
+/// raw values compare in calendar order.
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Timestamp) -> Ordering {
+        let key = |ts: &Timestamp| (ts.date.0, ts.time.0, ts.hundredths);
+        key(self).cmp(&key(other))
+    }
+}
+
+/// Converts the timestamp to a `SystemTime`, interpreting it as UTC.
+#[cfg(feature = "std")]
+impl From<Timestamp> for SystemTime {
+    fn from(ts: Timestamp) -> SystemTime {
+        let secs = ts.to_unix_seconds();
+        let nanos = (ts.hundredths % 100) as u32 * 10_000_000;
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::new(secs as u64, nanos)
+        } else {
+            UNIX_EPOCH - Duration::new((-secs) as u64, 0) + Duration::new(0, nanos)
+        }
+    }
+}
+
+/// Converts a `SystemTime` to a UTC timestamp, e.g. to stamp "now" on an
+/// entry. Times outside of [1980, 2107] are clamped to that range.
+#[cfg(feature = "std")]
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Timestamp {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => Timestamp::from_unix_parts(d.as_secs(), d.subsec_nanos()),
+            Err(_) => Timestamp::from_unix_parts(0, 0),
+        }
+    }
+}
+
+/// The offset of the local time FAT timestamps are recorded in from UTC.
+///
+/// FAT stores timestamps in an unspecified local time. A `UtcOffset` makes
+/// the zone explicit so conversions to absolute times are consistent.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct UtcOffset(i32);
+
+impl UtcOffset {
+    /// Timestamps are recorded in UTC.
+    pub const UTC: UtcOffset = UtcOffset(0);
+
+    /// An offset of `seconds` seconds east of UTC.
+    pub fn from_seconds(seconds: i32) -> UtcOffset {
+        UtcOffset(seconds)
+    }
+
+    /// An offset of `hours` hours and `minutes` minutes east of UTC. For
+    /// offsets west of UTC, both values should be negative.
+    pub fn from_hm(hours: i32, minutes: i32) -> UtcOffset {
+        UtcOffset(hours * 3600 + minutes * 60)
+    }
+
+    /// The offset in seconds east of UTC.
+    pub fn seconds(&self) -> i32 {
+        self.0
+    }
+
+    /// Returns the number of whole seconds between the Unix epoch and the
+    /// local timestamp `ts`.
+    pub fn to_unix_seconds(&self, ts: Timestamp) -> i64 {
+        ts.to_unix_seconds() - self.0 as i64
+    }
+
+    /// Converts the local timestamp `ts` to an absolute `SystemTime`.
+    #[cfg(feature = "std")]
+    pub fn to_system_time(&self, ts: Timestamp) -> SystemTime {
+        let local = SystemTime::from(ts);
+        if self.0 >= 0 {
+            local - Duration::new(self.0 as u64, 0)
+        } else {
+            local + Duration::new((-self.0) as u64, 0)
+        }
+    }
+
+    /// Converts the absolute time `time` to a local timestamp.
+    #[cfg(feature = "std")]
+    pub fn to_timestamp(&self, time: SystemTime) -> Timestamp {
+        let local = if self.0 >= 0 {
+            time + Duration::new(self.0 as u64, 0)
+        } else {
+            time - Duration::new((-self.0) as u64, 0)
+        };
+        Timestamp::from(local)
+    }
+
+    /// Formats the local timestamp `ts` as an ISO 8601 date and time with
+    /// this offset, e.g. `2018-03-02T12:34:56.25+01:00`.
+    pub fn format_iso8601(&self, ts: Timestamp) -> String {
+        let mut out = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:02}",
+                              ts.date.year(), ts.date.month(), ts.date.day(),
+                              ts.time.hour(), ts.time.minute(),
+                              ts.time.second() + ts.hundredths / 100,
+                              ts.hundredths % 100);
+        if self.0 == 0 {
+            out.push('Z');
+        } else {
+            let sign = if self.0 < 0 { '-' } else { '+' };
+            let minutes = self.0.abs() / 60;
+            out.push_str(&format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60));
+        }
+        out
+    }
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use chrono::{NaiveDateTime, Timelike};
+    use super::Timestamp;
+
+    impl From<Timestamp> for NaiveDateTime {
+        fn from(ts: Timestamp) -> NaiveDateTime {
+            let nanos = (ts.hundredths % 100) as u32 * 10_000_000;
+            NaiveDateTime::from_timestamp(ts.to_unix_seconds(), nanos)
+        }
+    }
+
+    impl From<NaiveDateTime> for Timestamp {
+        fn from(time: NaiveDateTime) -> Timestamp {
+            let secs = time.timestamp();
+            let secs = if secs < 0 { 0 } else { secs as u64 };
+            Timestamp::from_unix_parts(secs, time.nanosecond() % 1_000_000_000)
+        }
+    }
 }
 
 /// Metadata for a directory entry.
@@ -86,6 +475,57 @@ pub struct Metadata {
     pub ctime: Timestamp,
     pub atime: Timestamp,
     pub mtime: Timestamp,
+    /// The size, in bytes, recorded in the directory entry. Always 0 for
+    /// directories on conforming volumes.
+    pub size: u32,
+    /// The offset from UTC of the local time the timestamps are in: the
+    /// `utc_offset` the volume was mounted with.
+    pub utc_offset: UtcOffset,
+}
+
+impl Metadata {
+    /// Applies `policy` to every timestamp in `self`. Returns `None` if any
+    /// timestamp is invalid and `policy` is `TimestampPolicy::Error`.
+    pub fn sanitize(&self, policy: TimestampPolicy) -> Option<Metadata> {
+        Some(Metadata {
+            ctime: self.ctime.sanitize(policy)?,
+            atime: self.atime.sanitize(policy)?,
+            mtime: self.mtime.sanitize(policy)?,
+            ..self.clone()
+        })
+    }
+
+    /// Returns the number of whole seconds between the Unix epoch and `ts`,
+    /// one of the timestamps of `self`, taking `utc_offset` into account.
+    pub fn to_unix_seconds(&self, ts: Timestamp) -> i64 {
+        self.utc_offset.to_unix_seconds(ts)
+    }
+
+    /// Converts `ts`, one of the timestamps of `self`, to a `SystemTime`,
+    /// taking `utc_offset` into account.
+    #[cfg(feature = "std")]
+    pub fn to_system_time(&self, ts: Timestamp) -> SystemTime {
+        self.utc_offset.to_system_time(ts)
+    }
+
+    /// Maps the FAT attributes to a Unix `st_mode`.
+    ///
+    /// Directories are `0o755` and files `0o644`; read-only entries have all
+    /// write bits cleared. FAT has no notion of ownership, so the same
+    /// permissions are reported for user, group, and other where sensible.
+    pub fn unix_mode(&self) -> u32 {
+        let (file_type, perms) = if self.attr.directory() {
+            (S_IFDIR, 0o755)
+        } else {
+            (S_IFREG, 0o644)
+        };
+
+        if self.attr.read_only() {
+            file_type | (perms & !0o222)
+        } else {
+            file_type | perms
+        }
+    }
 }
 
 impl traits::Timestamp for Timestamp {
@@ -100,6 +540,8 @@ impl traits::Timestamp for Timestamp {
     fn minute(&self) -> u8 { self.time.minute() }
 
     fn second(&self) -> u8 { self.time.second()  }
+
+    fn hundredths(&self) -> u8 { self.hundredths }
 }
 
 impl traits::Metadata for Metadata {
@@ -120,18 +562,34 @@ impl traits::Metadata for Metadata {
     /// The timestamp for the entry's last modification.
     fn modified(&self) -> Self::Timestamp { self.mtime }
 
+    /// The size of the entry in bytes.
+    fn size(&self) -> u64 { self.size as u64 }
 }
 
-// FIXME: Implement `fmt::Display` (to your liking) for `Metadata`.
-impl fmt::Display for Metadata {
+/// Formats the timestamp as `YYYY-MM-DD HH:MM`.
+impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Metadata")
-         .field("attr", &format!("{:?}", &self.attr))
-//         .field("ctime_tenth_sec", &self.ctime_tenth_sec)
-         .field("ctime", &self.ctime)
-         .field("atime", &self.atime)
-         .field("mtime", &self.mtime)
-         .finish()
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}",
+               self.date.year(), self.date.month(), self.date.day(),
+               self.time.hour(), self.time.minute())
     }
+}
 
+/// Formats the metadata `ls -l` style: attribute flags (`d`irectory,
+/// `a`rchive, `r`ead-only, `h`idden, `s`ystem), modification time, and size.
+///
+/// For example, `d-rh- 2024-01-02 13:37          0`.
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn flag(f: &mut fmt::Formatter, set: bool, c: char) -> fmt::Result {
+            write!(f, "{}", if set { c } else { '-' })
+        }
+
+        flag(f, self.attr.directory(), 'd')?;
+        flag(f, self.attr.archive(), 'a')?;
+        flag(f, self.attr.read_only(), 'r')?;
+        flag(f, self.attr.hidden(), 'h')?;
+        flag(f, self.attr.system(), 's')?;
+        write!(f, " {} {:>10}", self.mtime, self.size)
+    }
 }
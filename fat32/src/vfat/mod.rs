@@ -4,22 +4,65 @@ pub(crate) mod dir;
 pub(crate) mod vfat;
 pub(crate) mod ebpb;
 pub(crate) mod error;
+pub(crate) mod context;
 pub(crate) mod cluster;
 pub(crate) mod fat;
 pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
+pub(crate) mod eviction;
+pub(crate) mod notify;
+pub(crate) mod open;
+pub(crate) mod handle;
+pub(crate) mod journal;
+pub(crate) mod fsinfo;
+pub(crate) mod diagnostic;
+pub(crate) mod backup;
+pub(crate) mod dentry;
+pub(crate) mod cancel;
+#[cfg(feature = "std")]
+pub(crate) mod parallel;
 pub(crate) mod shared;
+pub(crate) mod usage;
+pub(crate) mod geometry;
+pub(crate) mod partition;
+pub(crate) mod options;
+#[cfg(feature = "serde")]
+pub(crate) mod serialize;
+pub mod fs;
+#[cfg(feature = "async")]
+pub mod async_fs;
 
-pub use self::ebpb::BiosParameterBlock;
+pub use self::ebpb::{BiosParameterBlock, BOOT_CODE_SIZE};
 pub use self::file::File;
-pub use self::dir::Dir;
+pub use self::dir::{Dir, DirPosition, EntryLocation, RawDirEntry, RawEntryKind, RawDirEntries};
 pub use self::error::Error;
+pub use self::context::{ErrorContext, ContextError};
 pub use self::vfat::VFat;
-pub use self::entry::Entry;
-pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
-pub use self::shared::Shared;
+pub use self::entry::{Entry, EntryInfo};
+pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp, UtcOffset};
+pub use self::metadata::{S_IFMT, S_IFDIR, S_IFREG};
+pub use self::shared::{Shared, DefaultLock};
+#[cfg(feature = "rwlock")]
+pub use self::shared::BlockingRwLock;
+pub use self::options::{MountOptions, TimestampPolicy, SeekPolicy, ParsePolicy};
+pub use self::usage::{Usage, ClusterState, Run};
+pub use self::geometry::{Geometry, Stats};
+pub use self::cancel::CancelToken;
+pub use self::fsinfo::{FsInfo, FreeCountMismatch};
+pub use self::fat::FatSignatureMismatch;
+pub use self::diagnostic::{Diagnostic, Violation};
+pub use self::backup::FatBackup;
+pub use self::partition::PartitionReader;
+pub use self::handle::{HandleTable, Fd};
 
-pub(crate) use self::cache::{CachedDevice, Partition};
-pub(crate) use self::fat::{Status, FatEntry};
+pub(crate) use self::cache::CachedDevice;
+pub use self::eviction::{EvictionPolicy, CachePolicy, Lru, Lfu, Clock};
+pub use self::notify::{ChangeEvent, Notification, SubscriptionId};
+pub(crate) use self::journal::Journal;
+pub(crate) use self::dentry::DentryCache;
+pub(crate) use self::open::OpenFiles;
+pub(crate) use self::diagnostic::Diagnostics;
+pub(crate) use self::fat::{Status, FatEntry, ChainWalk};
 pub(crate) use self::cluster::Cluster;
+pub(crate) use self::context::WithContext;
@@ -0,0 +1,77 @@
+use std::boxed::Box;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+/// What happened to a file or directory, as reported to subscribers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ChangeEvent {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change to a file or directory, delivered to the callbacks registered
+/// with `VFat::subscribe()` once it reaches the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub event: ChangeEvent,
+    /// The absolute path of the file or directory.
+    pub path: PathBuf,
+}
+
+/// Identifies a subscription, to cancel it with `VFat::unsubscribe()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// The subscribers of a volume and the changes not yet delivered to them.
+#[derive(Default)]
+pub(crate) struct Notifier {
+    subscribers: Vec<(SubscriptionId, Box<FnMut(&Notification) + Send + Sync>)>,
+    pending: Vec<Notification>,
+    next_id: u64,
+}
+
+impl Notifier {
+    pub fn subscribe(&mut self, callback: Box<FnMut(&Notification) + Send + Sync>)
+        -> SubscriptionId
+    {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, callback));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|&(subscriber, _)| subscriber != id);
+        self.subscribers.len() != before
+    }
+
+    /// Queues a change for delivery by `deliver()`. Changes are only queued
+    /// while someone is subscribed.
+    pub fn record(&mut self, event: ChangeEvent, path: &Path) {
+        if !self.subscribers.is_empty() {
+            self.pending.push(Notification { event: event, path: path.to_path_buf() });
+        }
+    }
+
+    /// Passes every queued change to every subscriber, in the order the
+    /// changes were made.
+    pub fn deliver(&mut self) {
+        for notification in self.pending.drain(..) {
+            for &mut (_, ref mut callback) in self.subscribers.iter_mut() {
+                callback(&notification);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("subscribers", &self.subscribers.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
@@ -0,0 +1,78 @@
+use std::vec::Vec;
+
+use vfat::{Cluster, EntryLocation};
+
+/// A file that one or more `File` handles have open, keyed by the location
+/// of its directory entry.
+#[derive(Debug)]
+struct OpenFile {
+    location: EntryLocation,
+    size: u32,
+    first_cluster: Cluster,
+    handles: usize,
+}
+
+/// The files open on a volume, shared by every handle to the same file so
+/// that a change to its size made through one handle is seen by the others.
+///
+/// Few files are open at once, so finding one is a linear scan.
+#[derive(Debug, Default)]
+pub(crate) struct OpenFiles {
+    files: Vec<OpenFile>,
+}
+
+impl OpenFiles {
+    /// Records another handle to the file whose entry is at `location`, read
+    /// with `size` and `first_cluster`, and returns the size and first
+    /// cluster the handle should use: those of the handles already open, if
+    /// any, as they may have changed since the entry was read.
+    pub fn open(&mut self, location: EntryLocation, size: u32, first_cluster: Cluster)
+        -> (u32, Cluster)
+    {
+        if let Some(file) = self.files.iter_mut().find(|file| file.location == location) {
+            file.handles += 1;
+            return (file.size, file.first_cluster);
+        }
+        self.files.push(OpenFile {
+            location: location,
+            size: size,
+            first_cluster: first_cluster,
+            handles: 1,
+        });
+        (size, first_cluster)
+    }
+
+    /// Forgets a handle to the file at `location`, and the file once its
+    /// last handle is closed.
+    pub fn close(&mut self, location: EntryLocation) {
+        if let Some(i) = self.files.iter().position(|file| file.location == location) {
+            self.files[i].handles -= 1;
+            if self.files[i].handles == 0 {
+                self.files.swap_remove(i);
+            }
+        }
+    }
+
+    /// Returns the size and first cluster of the open file at `location`.
+    pub fn get(&self, location: EntryLocation) -> Option<(u32, Cluster)> {
+        self.files.iter()
+            .find(|file| file.location == location)
+            .map(|file| (file.size, file.first_cluster))
+    }
+
+    /// Returns the number of handles open to the file at `location`.
+    pub fn handles(&self, location: EntryLocation) -> usize {
+        self.files.iter()
+            .find(|file| file.location == location)
+            .map_or(0, |file| file.handles)
+    }
+
+    /// Records that the open file at `location` is now `size` bytes long,
+    /// starting at `first_cluster`.
+    pub fn resize(&mut self, location: EntryLocation, size: u32, first_cluster: Cluster) {
+        if let Some(file) = self.files.iter_mut().find(|file| file.location == location) {
+            file.size = size;
+            file.first_cluster = first_cluster;
+        }
+    }
+}
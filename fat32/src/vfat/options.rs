@@ -0,0 +1,299 @@
+use vfat::{CachePolicy, Timestamp, UtcOffset};
+
+/// How timestamps that fail validation are presented to callers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimestampPolicy {
+    /// Return the on-disk values as-is, even if they are out of range.
+    Unchecked,
+    /// Clamp every out-of-range field into its valid range.
+    Clamp,
+    /// Replace invalid timestamps with the FAT epoch, 1980-01-01 00:00:00.
+    EpochDefault,
+    /// Fail reading a directory containing an invalid timestamp with an error
+    /// kind of `InvalidData`.
+    Error,
+}
+
+impl Default for TimestampPolicy {
+    fn default() -> TimestampPolicy {
+        TimestampPolicy::Unchecked
+    }
+}
+
+/// How a `File` treats seeking past its end.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekPolicy {
+    /// Seeking past the end of a file fails with an error of
+    /// `InvalidInput`, as the file can't be extended.
+    Strict,
+    /// Seeking past the end of a file succeeds, as with POSIX `lseek()`, and
+    /// reads there return end of file. Positions past the largest possible
+    /// FAT file, 4 GiB - 1, still fail.
+    Sparse,
+}
+
+impl Default for SeekPolicy {
+    fn default() -> SeekPolicy {
+        SeekPolicy::Strict
+    }
+}
+
+/// How directory entries that break the FAT specification in minor ways,
+/// such as invalid case flags or an empty file with a first cluster, are
+/// treated. Damage that makes an entry unreadable is an error either way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParsePolicy {
+    /// Fail reading a directory containing such an entry with an error of
+    /// `InvalidData`, as for grading or fuzzing.
+    Strict,
+    /// Read the entry as best as possible, recording a `Diagnostic` for
+    /// `VFat::diagnostics()`.
+    Lenient,
+}
+
+impl Default for ParsePolicy {
+    fn default() -> ParsePolicy {
+        ParsePolicy::Lenient
+    }
+}
+
+/// Options controlling how a volume is mounted with `VFat::from_with_options`.
+///
+/// Options can be set as fields, or with the builder methods of the same
+/// names starting from `MountOptions::new()`:
+///
+/// ```rust
+/// use fat32::vfat::{CachePolicy, MountOptions, UtcOffset};
+///
+/// let options = MountOptions::new()
+///     .read_only(true)
+///     .cache(1024, CachePolicy::Lru)
+///     .utc_offset(UtcOffset::from_hm(1, 0));
+/// assert!(options.read_only);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct MountOptions {
+    /// Policy applied to timestamps read from directory entries.
+    pub timestamp_policy: TimestampPolicy,
+    /// Policy applied to directory entries that break the specification.
+    /// Defaults to `ParsePolicy::Lenient`.
+    pub parse_policy: ParsePolicy,
+    /// Offset from UTC of the local time the volume's timestamps are in.
+    /// Defaults to UTC.
+    pub utc_offset: UtcOffset,
+    /// Read past damaged clusters for data recovery. Clusters marked bad in
+    /// the FAT, and clusters the device fails to read, are returned as zeroes
+    /// instead of failing the read, and a chain ending in a bad cluster is
+    /// treated as ending there. `File::unreliable_ranges()` reports which
+    /// bytes were substituted.
+    pub lenient: bool,
+    /// Discard the sectors of clusters as they are freed, like the `discard`
+    /// mount option on Linux, so that the device can reclaim them.
+    pub discard: bool,
+    /// Commit changes through a journal in the volume's reserved sectors, so
+    /// that a crash can't leave them half written, and replay a journal left
+    /// behind by a crash when mounting. Mounting fails if the reserved
+    /// sectors have no room for a journal.
+    ///
+    /// Without this option, mounting a volume whose journal holds a
+    /// transaction that was never written in place fails.
+    pub journal: bool,
+    /// The number of path lookups to remember, so that opening a path again
+    /// doesn't read every directory along it. 0 disables the cache. Defaults
+    /// to 64.
+    pub dentry_cache: usize,
+    /// The number of threads large runs of clusters are read on at once, by
+    /// `VFat::read_chain()` and `tools::extract()`. Only devices that hand
+    /// out readers with `BlockDevice::reader()`, such as `ImageFile`, are
+    /// read in parallel, and only with the `std` feature. Defaults to 1,
+    /// reading on the calling thread.
+    pub read_threads: usize,
+    /// The most bytes a single read of a whole cluster chain, such as
+    /// `VFat::read_chain()` or listing a directory, may read into memory.
+    /// Longer chains fail with an error of `InvalidInput` before anything is
+    /// read, so that a hostile image can't exhaust memory. Reading a `File`
+    /// streams and isn't limited. Defaults to 16 MiB.
+    pub max_chain_bytes: usize,
+    /// Whether files may be seeked past their end. Individual files can
+    /// override this with `File::set_seek_policy()`. Defaults to
+    /// `SeekPolicy::Strict`.
+    pub seek_policy: SeekPolicy,
+    /// The most sectors the sector cache holds before evicting clean ones. 0
+    /// leaves the cache unbounded, so that every sector read stays cached
+    /// until the volume is dropped. Defaults to 0.
+    pub cache_sectors: usize,
+    /// How the sector cache chooses which sector to evict when it holds
+    /// `cache_sectors`. Defaults to `CachePolicy::Lru`.
+    pub cache_policy: CachePolicy,
+    /// Never evict sectors of the FAT from the sector cache, so that walking
+    /// cluster chains never rereads them. Defaults to `false`.
+    pub pin_fat: bool,
+    /// Count the free clusters in the FAT when mounting and compare the count
+    /// with the one in the FSInfo sector, reporting a difference through
+    /// `VFat::free_count_mismatch()`. Reads the whole FAT, so defaults to
+    /// `false`.
+    pub check_free_count: bool,
+    /// Check that FAT entry 0 repeats the media descriptor and that entry 1
+    /// marks an end of chain when mounting, reporting a mismatch through
+    /// `VFat::fat_signature_mismatch()`. Defaults to `false`.
+    pub check_fat_signature: bool,
+    /// Fix what the `check_free_count` and `check_fat_signature` checks find
+    /// when mounting, in the sector cache until the next `flush()`. What was
+    /// found is still reported. Ignored with `read_only`. Defaults to
+    /// `false`.
+    pub repair: bool,
+    /// Refuse every change to the volume with an error of
+    /// `PermissionDenied`, and fail mounting a volume whose journal needs
+    /// replaying. Defaults to `false`.
+    pub read_only: bool,
+    /// Compare names exactly when looking them up, rather than ignoring ASCII
+    /// case as FAT does. Names are matched as `Entry::name()` returns them.
+    /// Defaults to `false`.
+    pub case_sensitive: bool,
+    /// Returns the current local time, in `utc_offset`, to stamp entries
+    /// with when they change, such as by `File::set_len()`. Without a clock,
+    /// timestamps are left as they are. Defaults to `None`.
+    pub clock: Option<fn() -> Timestamp>,
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions {
+            timestamp_policy: TimestampPolicy::default(),
+            parse_policy: ParsePolicy::default(),
+            utc_offset: UtcOffset::default(),
+            lenient: false,
+            discard: false,
+            journal: false,
+            dentry_cache: 64,
+            read_threads: 1,
+            max_chain_bytes: 16 << 20,
+            seek_policy: SeekPolicy::default(),
+            cache_sectors: 0,
+            cache_policy: CachePolicy::default(),
+            pin_fat: false,
+            check_free_count: false,
+            check_fat_signature: false,
+            repair: false,
+            read_only: false,
+            case_sensitive: false,
+            clock: None,
+        }
+    }
+}
+
+impl MountOptions {
+    /// Returns the default options.
+    pub fn new() -> MountOptions {
+        MountOptions::default()
+    }
+
+    /// Sets `read_only`.
+    pub fn read_only(mut self, read_only: bool) -> MountOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets `cache_sectors` and `cache_policy`.
+    pub fn cache(mut self, sectors: usize, policy: CachePolicy) -> MountOptions {
+        self.cache_sectors = sectors;
+        self.cache_policy = policy;
+        self
+    }
+
+    /// Sets `pin_fat`.
+    pub fn pin_fat(mut self, pin_fat: bool) -> MountOptions {
+        self.pin_fat = pin_fat;
+        self
+    }
+
+    /// Sets `case_sensitive`.
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> MountOptions {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Sets `lenient`.
+    pub fn lenient(mut self, lenient: bool) -> MountOptions {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Sets `timestamp_policy`.
+    pub fn timestamp_policy(mut self, policy: TimestampPolicy) -> MountOptions {
+        self.timestamp_policy = policy;
+        self
+    }
+
+    /// Sets `parse_policy`.
+    pub fn parse_policy(mut self, policy: ParsePolicy) -> MountOptions {
+        self.parse_policy = policy;
+        self
+    }
+
+    /// Sets `utc_offset`.
+    pub fn utc_offset(mut self, offset: UtcOffset) -> MountOptions {
+        self.utc_offset = offset;
+        self
+    }
+
+    /// Sets `clock`.
+    pub fn clock(mut self, clock: fn() -> Timestamp) -> MountOptions {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets `seek_policy`.
+    pub fn seek_policy(mut self, policy: SeekPolicy) -> MountOptions {
+        self.seek_policy = policy;
+        self
+    }
+
+    /// Sets `journal`.
+    pub fn journal(mut self, journal: bool) -> MountOptions {
+        self.journal = journal;
+        self
+    }
+
+    /// Sets `discard`.
+    pub fn discard(mut self, discard: bool) -> MountOptions {
+        self.discard = discard;
+        self
+    }
+
+    /// Sets `dentry_cache`.
+    pub fn dentry_cache(mut self, entries: usize) -> MountOptions {
+        self.dentry_cache = entries;
+        self
+    }
+
+    /// Sets `read_threads`.
+    pub fn read_threads(mut self, threads: usize) -> MountOptions {
+        self.read_threads = threads;
+        self
+    }
+
+    /// Sets `max_chain_bytes`.
+    pub fn max_chain_bytes(mut self, bytes: usize) -> MountOptions {
+        self.max_chain_bytes = bytes;
+        self
+    }
+
+    /// Sets `check_free_count`.
+    pub fn check_free_count(mut self, check: bool) -> MountOptions {
+        self.check_free_count = check;
+        self
+    }
+
+    /// Sets `check_fat_signature`.
+    pub fn check_fat_signature(mut self, check: bool) -> MountOptions {
+        self.check_fat_signature = check;
+        self
+    }
+
+    /// Sets `repair`.
+    pub fn repair(mut self, repair: bool) -> MountOptions {
+        self.repair = repair;
+        self
+    }
+}
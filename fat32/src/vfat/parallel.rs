@@ -0,0 +1,114 @@
+use std::cmp::max;
+use std::io;
+use std::thread;
+use std::vec::Vec;
+
+use traits::{BlockDevice, RawLock};
+use util;
+use vfat::{Cluster, VFat, WithContext};
+
+/// Reads shorter than this are done on the calling thread.
+const MIN_PARALLEL_BYTES: usize = 256 << 10;
+/// The most bytes read by a thread in one piece.
+const PIECE_BYTES: usize = 64 << 10;
+
+/// Consecutive sectors, starting at `sector`, to be read into `buf`.
+struct Piece<'a> {
+    sector: u64,
+    buf: &'a mut [u8],
+}
+
+impl<D: BlockDevice, L: RawLock> VFat<D, L> {
+    /// Reads `clusters` into `buf`, which is exactly as long as they are, on
+    /// `options.read_threads` threads. Returns `false`, having read nothing,
+    /// if the read should be done on the calling thread instead: because
+    /// it's small, only one thread is allowed, or the device doesn't hand
+    /// out readers.
+    ///
+    /// The clusters are split into pieces of consecutive clusters that are
+    /// dealt out to the threads, each reading through its own reader and
+    /// bypassing the sector cache. Pieces with a sector in the cache are
+    /// instead copied from the cache, as it may hold changes the device
+    /// doesn't have yet.
+    pub(crate) fn read_clusters_parallel(&mut self, clusters: &[Cluster], buf: &mut [u8])
+        -> io::Result<bool>
+    {
+        let threads = self.options.read_threads;
+        if threads < 2 || buf.len() < MIN_PARALLEL_BYTES {
+            return Ok(false);
+        }
+        let mut readers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            match self.device.inner().reader()? {
+                Some(reader) => readers.push(reader),
+                None => return Ok(false),
+            }
+        }
+
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let sectors_per_cluster = self.sectors_per_cluster as u64;
+        let piece_clusters = max(1, PIECE_BYTES / bytes_per_cluster);
+
+        let mut work: Vec<Vec<Piece>> = (0..threads).map(|_| Vec::new()).collect();
+        let mut dealt = 0;
+        let mut rest = buf;
+        let mut i = 0;
+        while i < clusters.len() {
+            let first = clusters[i];
+            let mut len = 1;
+            while i + len < clusters.len() && len < piece_clusters
+                && clusters[i + len].get_index() == first.get_index() + len as u32
+            {
+                len += 1;
+            }
+
+            let sector = self.cluster_sector(first)?;
+            let sectors = len as u64 * sectors_per_cluster;
+            let (piece, tail) = { rest }.split_at_mut(len * bytes_per_cluster);
+            rest = tail;
+            if self.device.is_cached(sector..sector + sectors) {
+                for (j, chunk) in piece.chunks_mut(bytes_per_sector).enumerate() {
+                    self.device.read_at(sector + j as u64, 0, chunk).in_cluster(first)?;
+                }
+            } else {
+                work[dealt % threads].push(Piece { sector: sector, buf: piece });
+                dealt += 1;
+            }
+            i += len;
+        }
+
+        let results: Vec<io::Result<()>> = thread::scope(|scope| {
+            let handles: Vec<_> = readers.into_iter().zip(work)
+                .map(|(mut reader, pieces)| {
+                    scope.spawn(move || read_pieces(&mut reader, bytes_per_sector, pieces))
+                })
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| {
+                    Err(util::failed("a thread reading clusters panicked"))
+                }))
+                .collect()
+        });
+        for result in results {
+            result?;
+        }
+        Ok(true)
+    }
+}
+
+/// Reads every one of `pieces` from `reader`, a sector of `sector_size`
+/// bytes at a time.
+fn read_pieces<R: BlockDevice + ?Sized>(reader: &mut R, sector_size: usize, pieces: Vec<Piece>)
+    -> io::Result<()>
+{
+    for piece in pieces {
+        for (i, chunk) in piece.buf.chunks_mut(sector_size).enumerate() {
+            let sector = piece.sector + i as u64;
+            if reader.read_sector(sector, chunk).at_sector(sector)? != chunk.len() {
+                return Err(util::eof("short read from device")).at_sector(sector);
+            }
+        }
+    }
+    Ok(())
+}
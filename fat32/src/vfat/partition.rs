@@ -0,0 +1,96 @@
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+
+use traits::{BlockDevice, DynBlockDevice, RawLock};
+use util;
+use vfat::{VFat, Shared, DefaultLock};
+
+/// Reads the raw bytes of a mounted volume's partition, from its boot sector
+/// to its last sector, as one stream. Useful for copying or hashing a whole
+/// partition.
+///
+/// Reads go through the volume's sector cache, so changes not yet flushed
+/// are included, but sectors that aren't cached are read without caching
+/// them.
+///
+/// ```rust,no_run
+/// use std::io;
+/// use fat32::vfat::{PartitionReader, VFat};
+/// use fat32::device::ImageFile;
+///
+/// let vfat = VFat::from(ImageFile::open_read_only("sd.img")?)?;
+/// let mut out = std::fs::File::create("partition.img")?;
+/// io::copy(&mut PartitionReader::new(vfat), &mut out)?;
+/// # Ok::<(), io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct PartitionReader<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    vfat: Shared<VFat<D, L>, L>,
+    pos: u64,
+    len: u64,
+    sector_size: u64,
+}
+
+impl<D: BlockDevice, L: RawLock> PartitionReader<D, L> {
+    /// Returns a reader over the partition of `vfat`, positioned at its
+    /// start.
+    pub fn new(vfat: Shared<VFat<D, L>, L>) -> PartitionReader<D, L> {
+        let (len, sector_size) = {
+            let vfat = vfat.borrow();
+            let geometry = vfat.geometry();
+            let sector_size = geometry.bytes_per_sector as u64;
+            (geometry.total_sectors * sector_size, sector_size)
+        };
+        PartitionReader {
+            vfat: vfat,
+            pos: 0,
+            len: len,
+            sector_size: sector_size,
+        }
+    }
+
+    /// Returns the size of the partition in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> io::Read for PartitionReader<D, L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let can_read = min(self.len.saturating_sub(self.pos), buf.len() as u64) as usize;
+        let mut vfat = self.vfat.borrow_mut();
+        let mut read = 0;
+        while read < can_read {
+            let sector = self.pos / self.sector_size;
+            let offset = (self.pos % self.sector_size) as usize;
+            let len = min(can_read - read, self.sector_size as usize - offset);
+            vfat.device.read_uncached_at(sector, offset, &mut buf[read..read + len])?;
+            read += len;
+            self.pos += len as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> io::Seek for PartitionReader<D, L> {
+    /// Seeks to offset `pos` in the partition. Seeking past the end is
+    /// allowed, and reads there return end of file.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of the partition results in an
+    /// `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i128),
+            SeekFrom::End(offset) => (self.len, offset as i128),
+            SeekFrom::Current(offset) => (self.pos, offset as i128),
+        };
+        let new_pos = base as i128 + offset;
+        if new_pos < 0 || new_pos > ::std::u64::MAX as i128 {
+            return Err(util::invalid_input(format!("invalid position {}", offset)));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,55 @@
+use serde::ser::{Serialize, Serializer, SerializeStruct};
+
+use traits::Timestamp as TimestampTrait;
+use vfat::{Attributes, EntryInfo, Metadata, Timestamp};
+
+/// Serializes as the calendar fields of the timestamp.
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Timestamp", 7)?;
+        s.serialize_field("year", &self.year())?;
+        s.serialize_field("month", &self.month())?;
+        s.serialize_field("day", &self.day())?;
+        s.serialize_field("hour", &self.hour())?;
+        s.serialize_field("minute", &self.minute())?;
+        s.serialize_field("second", &self.second())?;
+        s.serialize_field("hundredths", &self.hundredths())?;
+        s.end()
+    }
+}
+
+/// Serializes as one boolean per attribute flag.
+impl Serialize for Attributes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Attributes", 6)?;
+        s.serialize_field("read_only", &self.read_only())?;
+        s.serialize_field("hidden", &self.hidden())?;
+        s.serialize_field("system", &self.system())?;
+        s.serialize_field("volume_id", &self.volume_id())?;
+        s.serialize_field("directory", &self.directory())?;
+        s.serialize_field("archive", &self.archive())?;
+        s.end()
+    }
+}
+
+impl Serialize for Metadata {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Metadata", 5)?;
+        s.serialize_field("attributes", &self.attr)?;
+        s.serialize_field("created", &self.ctime)?;
+        s.serialize_field("accessed", &self.atime)?;
+        s.serialize_field("modified", &self.mtime)?;
+        s.serialize_field("size", &self.size)?;
+        s.end()
+    }
+}
+
+impl<'a> Serialize for EntryInfo<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("EntryInfo", 3)?;
+        s.serialize_field("name", self.name)?;
+        s.serialize_field("is_dir", &self.is_dir)?;
+        s.serialize_field("metadata", self.metadata)?;
+        s.end()
+    }
+}
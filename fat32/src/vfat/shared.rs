@@ -1,55 +1,268 @@
+use std::cell::UnsafeCell;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 
+use traits::RawLock;
+#[cfg(feature = "rwlock")]
+use traits::Concurrent;
+
+pub use self::imp::DefaultLock;
+
 /// A smart pointer to a shared instance of type `T`.
 ///
 /// The inner `T` can be borrowed immutably with `.borrow()` and mutably with
 /// `.borrow_mut()`. The implementation guarantees the usual reference
 /// guarantees.
+///
+/// Mutual exclusion is provided by the lock `L`. The default lock blocks
+/// the calling thread on hosted targets, so a `Shared` can be cloned into and
+/// used from multiple threads. With the `rwlock` feature, a `BlockingRwLock`
+/// lets any number of immutable borrows be held concurrently. Without
+/// `std`, the default lock panics on conflicting borrows instead of
+/// blocking. Bare-metal users can supply their own `RawLock` with
+/// `Shared::with_lock()`.
+pub struct Shared<T, L: RawLock = DefaultLock>(imp::Ptr<Inner<T, L>>);
+
+struct Inner<T, L> {
+    lock: L,
+    value: UnsafeCell<T>,
+}
+
+/// A lock state built on `Mutex` and `Condvar`, shared by `BlockingLock`
+/// and `BlockingRwLock`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+struct Blocking {
+    // 0 when unlocked, -1 when locked exclusively, and the number of readers
+    // when locked for shared access.
+    state: ::std::sync::Mutex<isize>,
+    cond: ::std::sync::Condvar,
+}
+
+#[cfg(feature = "std")]
+impl Blocking {
+    fn new() -> Blocking {
+        Blocking {
+            state: ::std::sync::Mutex::new(0),
+            cond: ::std::sync::Condvar::new(),
+        }
+    }
+
+    fn lock(&self) {
+        let mut state = self.state.lock().expect("all okay");
+        while *state != 0 {
+            state = self.cond.wait(state).expect("all okay");
+        }
+        *state = -1;
+    }
+
+    fn unlock(&self) {
+        *self.state.lock().expect("all okay") = 0;
+        self.cond.notify_all();
+    }
+
+    #[cfg_attr(not(feature = "rwlock"), allow(dead_code))]
+    fn lock_shared(&self) {
+        let mut state = self.state.lock().expect("all okay");
+        while *state < 0 {
+            state = self.cond.wait(state).expect("all okay");
+        }
+        *state += 1;
+    }
+
+    #[cfg_attr(not(feature = "rwlock"), allow(dead_code))]
+    fn unlock_shared(&self) {
+        let mut state = self.state.lock().expect("all okay");
+        *state -= 1;
+        if *state == 0 {
+            self.cond.notify_all();
+        }
+    }
+}
+
+/// A blocking lock: every borrow, mutable or not, waits for the others to
+/// be returned. This is the default lock on hosted targets.
+#[cfg(feature = "std")]
 #[derive(Debug)]
-pub struct Shared<T>(imp::Inner<T>);
+pub struct BlockingLock(Blocking);
 
-#[cfg(target_os = "ros")]
+#[cfg(feature = "std")]
+unsafe impl RawLock for BlockingLock {
+    fn new() -> BlockingLock {
+        BlockingLock(Blocking::new())
+    }
+
+    fn lock(&self) {
+        self.0.lock()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.unlock()
+    }
+}
+
+/// A blocking readers-writer lock: any number of immutable borrows may be
+/// held at once, on any threads, so a `Shared` guarded by it is only `Sync`
+/// if its value is. Mount a volume on a `Sync` device with it, such as a
+/// `SyncBlockDevice`, for concurrent readers.
+#[cfg(feature = "rwlock")]
+#[derive(Debug)]
+pub struct BlockingRwLock(Blocking);
+
+#[cfg(feature = "rwlock")]
+unsafe impl RawLock for BlockingRwLock {
+    type Readers = Concurrent;
+
+    fn new() -> BlockingRwLock {
+        BlockingRwLock(Blocking::new())
+    }
+
+    fn lock(&self) {
+        self.0.lock()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.unlock()
+    }
+
+    fn lock_shared(&self) {
+        self.0.lock_shared()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.0.unlock_shared()
+    }
+}
+
+#[cfg(all(feature = "std", target_os = "ros"))]
 mod imp {
     use std::rc::Rc;
-    use std::sync::Mutex;
+    use traits::{RawLock, SyncFor};
     use super::Shared;
 
-    pub type Inner<T> = Rc<Mutex<T>>;
-
-    pub fn new<T>(val: T) -> Inner<T> {
-        Rc::new(Mutex::new(val))
-    }
+    pub type Ptr<T> = Rc<T>;
+    pub type DefaultLock = super::BlockingLock;
 
     // Without an enabled MMU/cache, the processor faults on atomic accesses.
     // As such, use an `Rc` instead of an `Arc` when running on ROS until
     // multithreading, the MMU, and caches are enabled.
-    unsafe impl<T> Sync for Shared<T> {}
-    unsafe impl<T> Send for Shared<T> {}
+    unsafe impl<T: Send, L: RawLock> Sync for Shared<T, L> where L::Readers: SyncFor<T> {}
+    unsafe impl<T: Send, L: RawLock> Send for Shared<T, L> where L::Readers: SyncFor<T> {}
+}
+
+#[cfg(all(feature = "std", not(target_os = "ros")))]
+mod imp {
+    use std::sync::Arc;
+    use traits::{RawLock, SyncFor};
+    use super::Inner;
+
+    pub type Ptr<T> = Arc<T>;
+    pub type DefaultLock = super::BlockingLock;
+
+    unsafe impl<T: Send, L: RawLock> Send for Inner<T, L> {}
+    // With a `Concurrent` lock, shared borrows may be held on several
+    // threads at once, so `T` must be `Sync` too.
+    unsafe impl<T: Send, L: RawLock> Sync for Inner<T, L> where L::Readers: SyncFor<T> {}
 }
 
-#[cfg(not(target_os = "ros"))]
+#[cfg(not(feature = "std"))]
 mod imp {
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use traits::{RawLock, SyncFor};
+    use super::Inner;
+
+    pub type Ptr<T> = Arc<T>;
+
+    /// Without `std` there is no blocking lock to wait on, so a borrow made
+    /// while another is held panics instead, on whichever thread or core
+    /// makes it.
+    #[derive(Debug)]
+    pub struct DefaultLock(AtomicBool);
+
+    unsafe impl RawLock for DefaultLock {
+        fn new() -> DefaultLock {
+            DefaultLock(AtomicBool::new(false))
+        }
+
+        fn lock(&self) {
+            let acquired = self.0.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed);
+            assert!(acquired.is_ok(), "already borrowed");
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    unsafe impl<T: Send, L: RawLock> Send for Inner<T, L> {}
+    unsafe impl<T: Send, L: RawLock> Sync for Inner<T, L> where L::Readers: SyncFor<T> {}
+}
+
+struct SharedRef<'a, T: 'a, L: RawLock + 'a>(&'a Inner<T, L>);
+
+impl<'a, T, L: RawLock> Deref for SharedRef<'a, T, L> {
+    type Target = T;
 
-    pub type Inner<T> = ::std::sync::Arc<::std::sync::Mutex<T>>;
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<'a, T, L: RawLock> Drop for SharedRef<'a, T, L> {
+    fn drop(&mut self) {
+        unsafe { self.0.lock.unlock_shared() }
+    }
+}
+
+struct SharedRefMut<'a, T: 'a, L: RawLock + 'a>(&'a Inner<T, L>);
+
+impl<'a, T, L: RawLock> Deref for SharedRefMut<'a, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
 
-    pub fn new<T>(val: T) -> Inner<T> {
-        Arc::new(Mutex::new(val))
+impl<'a, T, L: RawLock> DerefMut for SharedRefMut<'a, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<'a, T, L: RawLock> Drop for SharedRefMut<'a, T, L> {
+    fn drop(&mut self) {
+        unsafe { self.0.lock.unlock() }
     }
 }
 
 impl<T> Shared<T> {
-    /// Wraps `val` into a `Shared<T>` and returns it.
+    /// Wraps `val` into a `Shared<T>` guarded by the default lock and
+    /// returns it.
     pub fn new(val: T) -> Shared<T> {
-        Shared(imp::new(val))
+        Shared::with_lock(val)
+    }
+}
+
+impl<T, L: RawLock> Shared<T, L> {
+    /// Wraps `val` into a `Shared<T, L>` guarded by a new lock of type `L`
+    /// and returns it.
+    pub fn with_lock(val: T) -> Shared<T, L> {
+        Shared(imp::Ptr::new(Inner {
+            lock: L::new(),
+            value: UnsafeCell::new(val),
+        }))
     }
 
     /// Returns an immutable borrow to the inner value.
     ///
     /// If the inner value is presently mutably borrowed, this function blocks
-    /// until that borrow is returned.
+    /// until that borrow is returned. Unless the lock's `Readers` are
+    /// `Concurrent`, it also waits for immutable borrows.
     pub fn borrow<'a>(&'a self) -> impl Deref<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        self.0.lock.lock_shared();
+        SharedRef(&self.0)
     }
 
     /// Returns an mutable borrow to the inner value.
@@ -57,16 +270,25 @@ impl<T> Shared<T> {
     /// If the inner value is presently borrowed, mutably or immutably, this
     /// function blocks until all borrows are returned.
     pub fn borrow_mut<'a>(&'a self) -> impl DerefMut<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        self.0.lock.lock();
+        SharedRefMut(&self.0)
     }
 }
 
-impl<T> Clone for Shared<T> {
+impl<T, L: RawLock> Clone for Shared<T, L> {
     /// Returns a copy of the shared pointer.
     ///
     /// The value `T` itself is not copied; only the metadata associated with
     /// the smart pointer required for accurate book-keeping is copied.
-    fn clone(&self) -> Shared<T> {
+    fn clone(&self) -> Shared<T, L> {
         Shared(self.0.clone())
     }
 }
+
+impl<T, L: RawLock> fmt::Debug for Shared<T, L> {
+    // The inner value isn't printed: doing so would need to take the lock,
+    // which may already be held by the caller.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Shared { .. }")
+    }
+}
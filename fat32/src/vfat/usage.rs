@@ -0,0 +1,115 @@
+use std::fmt;
+use std::vec::Vec;
+
+use vfat::Cluster;
+
+/// The allocation state of a single data cluster.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClusterState {
+    /// The cluster is not allocated to any chain.
+    Free,
+    /// The cluster belongs to a chain (or is otherwise unavailable).
+    Used,
+    /// The cluster is marked as bad in the FAT.
+    Bad,
+}
+
+/// A run of consecutive clusters sharing the same `ClusterState`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub state: ClusterState,
+    pub start: Cluster,
+    pub len: u32,
+}
+
+/// Cluster allocation map of a volume's data region, as returned by
+/// `VFat::usage()`.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    /// State of every data cluster; index 0 corresponds to cluster 2.
+    states: Vec<ClusterState>,
+    pub bytes_per_cluster: u64,
+    pub free: u32,
+    pub used: u32,
+    pub bad: u32,
+}
+
+impl Usage {
+    pub(crate) fn new(states: Vec<ClusterState>, bytes_per_cluster: u64) -> Usage {
+        let count = |s| states.iter().filter(|&&state| state == s).count() as u32;
+        let (free, used, bad) = (count(ClusterState::Free),
+                                 count(ClusterState::Used),
+                                 count(ClusterState::Bad));
+        Usage {
+            states: states,
+            bytes_per_cluster: bytes_per_cluster,
+            free: free,
+            used: used,
+            bad: bad,
+        }
+    }
+
+    /// The total number of clusters in the data region.
+    pub fn total(&self) -> u32 {
+        self.states.len() as u32
+    }
+
+    /// Returns the state of `cluster`, or `None` if `cluster` is outside of
+    /// the data region.
+    pub fn state(&self, cluster: Cluster) -> Option<ClusterState> {
+        cluster.get_offset().and_then(|i| self.states.get(i as usize).cloned())
+    }
+
+    /// Returns the per-cluster allocation map. Index 0 corresponds to cluster 2.
+    pub fn bitmap(&self) -> &[ClusterState] {
+        &self.states
+    }
+
+    /// Returns a run-length encoded view of the allocation map.
+    pub fn runs(&self) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+        for (i, &state) in self.states.iter().enumerate() {
+            if let Some(run) = runs.last_mut() {
+                if run.state == state {
+                    run.len += 1;
+                    continue;
+                }
+            }
+            runs.push(Run { state: state, start: Cluster::from(i as u32 + 2), len: 1 });
+        }
+        runs
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total() as u64 * self.bytes_per_cluster
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free as u64 * self.bytes_per_cluster
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used as u64 * self.bytes_per_cluster
+    }
+
+    pub fn bad_bytes(&self) -> u64 {
+        self.bad as u64 * self.bytes_per_cluster
+    }
+}
+
+/// Formats the usage as a `df`-style summary.
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.total() == 0 {
+            0
+        } else {
+            (self.used as u64 * 100 + self.total() as u64 - 1) / self.total() as u64
+        };
+
+        writeln!(f, "{:>12} {:>12} {:>12} {:>12} {:>5}",
+                 "Size", "Used", "Avail", "Bad", "Use%")?;
+        write!(f, "{:>12} {:>12} {:>12} {:>12} {:>4}%",
+               self.total_bytes(), self.used_bytes(), self.free_bytes(),
+               self.bad_bytes(), percent)
+    }
+}
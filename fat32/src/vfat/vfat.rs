@@ -1,162 +1,1217 @@
 use std::io;
 //use std::slice;
 use std::path::{Path, Component};
-use std::cmp::min;
+use std::ops::Range;
+use std::cmp::{min, max};
 use std::mem;
+use std::boxed::Box;
+use std::string::String;
+use std::vec::Vec;
 
-use util::SliceExt;
+use util;
 use mbr::{MasterBootRecord};
-use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
-use traits::{FileSystem, BlockDevice};
+use vfat::{DefaultLock, Shared, CancelToken, Cluster, File, Dir, Entry, FatEntry, Error, Status};
+use vfat::{fat, ChainWalk};
+use vfat::{BiosParameterBlock, CachedDevice, DentryCache, Journal, Usage, ClusterState};
+use vfat::{Geometry, Stats, EntryLocation, EvictionPolicy, OpenFiles};
+use vfat::{ChangeEvent, Notification, SubscriptionId, FsInfo, FreeCountMismatch};
+use vfat::{FatBackup, Diagnostic, Diagnostics, FatSignatureMismatch};
+use vfat::fat::VOLUME_FLAGS;
+use vfat::ebpb::{BOOT_CODE_OFFSET, BOOT_CODE_SIZE, VOLUME_ID_OFFSET};
+use vfat::cluster::{FIRST_DATA_CLUSTER, MAX_DATA_CLUSTER};
+use vfat::notify::Notifier;
+use device::PartitionSlice;
+use vfat::{MountOptions, WithContext};
+use traits::{self, FileSystem, BlockDevice, DynBlockDevice, DiskUsage, RawLock};
 
+/// The number of clusters addressable by FAT32: data clusters are numbered
+/// from 2 up to 0x0FFFFFEF.
+const MAX_DATA_CLUSTERS: u64 = (MAX_DATA_CLUSTER - FIRST_DATA_CLUSTER + 1) as u64;
+
+/// How many clusters a walk of a chain or of the FAT visits between checks
+/// for cancellation.
+const CANCEL_INTERVAL: u32 = 4096;
+
+/// A mounted FAT32 file system on a device of type `D`.
+///
+/// `D` defaults to a boxed, dynamically dispatched `BlockDevice`, which is
+/// what `VFat::from()` produces. Use `VFat::from_device()` to keep the
+/// concrete device type and have device calls statically dispatched.
 #[derive(Debug)]
-pub struct VFat {
-    pub device: CachedDevice,
+pub struct VFat<D = DynBlockDevice, L: RawLock = DefaultLock> {
+    /// The sector cache, which takes a lock of type `L`, the lock of the
+    /// `Shared` the volume is mounted in, around every access to the device.
+    pub device: CachedDevice<PartitionSlice<D>, L>,
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
     pub sectors_per_fat: u32,
+    /// The number of copies of the FAT, all of which are kept up to date.
+    pub num_fats: u8,
+    /// The media descriptor in the BPB, which FAT entry 0 repeats.
+    pub media_descriptor: u8,
+    /// The first sector of the FAT, relative to the start of the partition.
     pub fat_start_sector: u64,
+    /// The first sector of the data region, relative to the start of the
+    /// partition.
     pub data_start_sector: u64,
     pub root_dir_cluster: Cluster,
+    pub num_data_clusters: u32,
+    /// The highest valid cluster index. Data clusters are numbered from 2 to
+    /// `max_cluster` inclusive.
+    pub max_cluster: u32,
+    pub options: MountOptions,
+    /// The journal changes are committed through, with the `journal` mount
+    /// option.
+    pub(crate) journal: Option<Journal>,
+    /// Recent path lookups made by `open()`.
+    pub(crate) dentries: DentryCache,
+    /// Checked by long operations on the volume.
+    pub(crate) cancel: CancelToken,
+    /// Subscribers to changes and the changes not yet flushed.
+    pub(crate) notifier: Notifier,
+    /// The files with open `File` handles.
+    pub(crate) open_files: OpenFiles,
+    /// The FSInfo sector and its backup, relative to the start of the
+    /// partition.
+    pub(crate) fsinfo_sectors: Vec<u64>,
+    /// What the `check_free_count` mount option found.
+    pub(crate) free_count_mismatch: Option<FreeCountMismatch>,
+    /// What the `check_fat_signature` mount option found.
+    pub(crate) fat_signature_mismatch: Option<FatSignatureMismatch>,
+    /// Violations found reading directories under `ParsePolicy::Lenient`.
+    pub(crate) diagnostics: Diagnostics,
 }
 
 impl VFat {
-    pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
+    pub fn from<T>(device: T) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        VFat::from_with_options(device, MountOptions::default())
+    }
+
+    /// Mounts the first FAT32 partition of `device` with `options`.
+    pub fn from_with_options<T>(device: T, options: MountOptions)
+        -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
+    {
+        VFat::from_device(Box::new(device) as DynBlockDevice, options)
+    }
+}
+
+impl<D: BlockDevice> VFat<D> {
+    /// Mounts the first FAT32 partition of `device` with `options` without
+    /// boxing `device`.
+    pub fn from_device(device: D, options: MountOptions)
+        -> Result<Shared<VFat<D>>, Error>
+    {
+        VFat::from_device_with_lock(device, options)
+    }
+}
+
+impl<D: BlockDevice, L: RawLock> VFat<D, L> {
+    /// Mounts the first FAT32 partition of `device` with `options`, guarding
+    /// the mounted file system, and accesses to `device`, with locks of type
+    /// `L`.
+    pub fn from_device_with_lock(mut device: D, options: MountOptions)
+        -> Result<Shared<VFat<D, L>, L>, Error>
     {
         let mbr = MasterBootRecord::from(&mut device)?;
-        let bpb_start = mbr.first_fat32().ok_or(Error::NotFound)?
-                           .relative_sector as u64;
+        let partition = mbr.first_fat32().ok_or(Error::NotFound)?;
+        let bpb_start = partition.relative_sector as u64;
         let ebpb = BiosParameterBlock::from(&mut device, bpb_start)?;
+        ebpb.validate(partition.total_sectors as u64 * device.sector_size())?;
+        // Logical sectors may be larger or smaller than the device's, such
+        // as 512 byte sectors on a 4096 byte native device, but one must
+        // divide the other.
+        let bytes_per_sector = ebpb.bytes_per_sector as u64;
+        if max(bytes_per_sector, device.sector_size())
+            % min(bytes_per_sector, device.sector_size()) != 0 {
+            return Err(Error::InvalidBpb("bytes per sector"));
+        }
 //        println!("{:?}", mbr);
 //        println!("{:?}", ebpb);
-        let fat_start_sector = bpb_start + ebpb.num_reserved_sectors as u64;
+        let fat_start_sector = ebpb.num_reserved_sectors as u64;
         let data_start_sector = fat_start_sector +
             (ebpb.num_fat as u64) * ebpb.sectors_per_fat() as u64;
-        let dev = CachedDevice::new(device, 
-                                    Partition{
-                                        start: bpb_start,
-                                        sector_size: ebpb.bytes_per_sector as u64,
-                                    });
+        let data_sectors = (ebpb.total_logical_sectors() as u64)
+            .saturating_sub(data_start_sector);
+        let fat_entries = ebpb.sectors_per_fat() as u64 * ebpb.bytes_per_sector as u64
+            / mem::size_of::<FatEntry>() as u64;
+        let num_data_clusters = min(data_sectors / ebpb.sectors_per_cluster as u64,
+                                    fat_entries.saturating_sub(2));
+        let num_data_clusters = min(num_data_clusters, MAX_DATA_CLUSTERS) as u32;
+        let max_cluster = num_data_clusters + 1;
+        let root_dir_cluster = Cluster::data_within(Cluster::from(ebpb.root_cluster).get_index(),
+                                                    max_cluster)
+            .ok_or(Error::InvalidBpb("root cluster"))?;
+        let mut slice = PartitionSlice::new(device, bpb_start, partition.total_sectors as u64,
+                                            bytes_per_sector);
+        let journal = match Journal::locate(&ebpb) {
+            Some(journal) if options.journal && !options.read_only => {
+                journal.replay(&mut slice)?;
+                Some(journal)
+            }
+            Some(journal) => {
+                if journal.pending(&mut slice)?.is_some() {
+                    return Err(Error::Journal("holds changes that were never written; \
+                                               mount read-write with the journal option \
+                                               to replay it"));
+                }
+                None
+            }
+            None if options.journal => return Err(Error::Journal("doesn't fit the volume")),
+            None => None,
+        };
+        let mut dev = CachedDevice::new(slice);
+        dev.set_policy(options.cache_sectors, options.cache_policy.build());
+        if options.pin_fat {
+            dev.pin(fat_start_sector..data_start_sector);
+        }
 
-        Ok(Shared::new(VFat {
+        let mut vfat = VFat {
             device: dev,
             bytes_per_sector: ebpb.bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster,
             sectors_per_fat: ebpb.sectors_per_fat(),
-            fat_start_sector: bpb_start + ebpb.num_reserved_sectors as u64,
+            num_fats: ebpb.num_fat,
+            media_descriptor: ebpb.media_desc_type,
+            fat_start_sector: fat_start_sector,
             data_start_sector: data_start_sector,
-            root_dir_cluster: Cluster::from(ebpb.root_cluster)
-        }))
+            root_dir_cluster: root_dir_cluster,
+            num_data_clusters: num_data_clusters,
+            max_cluster: max_cluster,
+            options: options,
+            journal: journal,
+            dentries: DentryCache::new(options.dentry_cache),
+            cancel: CancelToken::new(),
+            notifier: Notifier::default(),
+            open_files: OpenFiles::default(),
+            fsinfo_sectors: FsInfo::locate(&ebpb),
+            free_count_mismatch: None,
+            fat_signature_mismatch: None,
+            diagnostics: Diagnostics::default(),
+        };
+        let repair = options.repair && !options.read_only;
+        if options.check_free_count {
+            let mismatch = vfat.check_free_count()?;
+            if mismatch.is_some() && repair {
+                vfat.recompute_free_count()?;
+            }
+            vfat.free_count_mismatch = mismatch;
+        }
+        if options.check_fat_signature {
+            let mismatch = vfat.check_fat_signature()?;
+            if mismatch.is_some() && repair {
+                vfat.repair_fat_signature()?;
+            }
+            vfat.fat_signature_mismatch = mismatch;
+        }
+        Ok(Shared::with_lock(vfat))
+    }
+
+    /// Returns the layout of the volume.
+    pub fn geometry(&self) -> Geometry {
+        Geometry {
+            bytes_per_sector: self.bytes_per_sector,
+            sectors_per_cluster: self.sectors_per_cluster,
+            bytes_per_cluster: self.bytes_per_cluster() as u32,
+            total_sectors: self.device.inner().num_sectors(),
+            num_clusters: self.num_data_clusters,
+            sectors_per_fat: self.sectors_per_fat,
+            num_fats: self.num_fats,
+            fat_start_sector: self.fat_start_sector,
+            data_start_sector: self.data_start_sector,
+            root_cluster: self.root_dir_cluster.get_index(),
+        }
+    }
+
+    /// Returns the boot sector as a `BiosParameterBlock`.
+    pub fn boot_sector(&mut self) -> io::Result<BiosParameterBlock> {
+        let mut buf = [0u8; 512];
+        buf.copy_from_slice(&self.device.get(0)?[..512]);
+        Ok(BiosParameterBlock::decode(&buf))
+    }
+
+    /// Returns the boot code in the boot sector, `BOOT_CODE_SIZE` bytes.
+    pub fn boot_code(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.boot_sector()?.boot_code.to_vec())
+    }
+
+    /// Replaces the boot code in the boot sector and its backup with
+    /// `code`, padded with zeroes, as `BiosParameterBlock::set_boot_code()`
+    /// does. The change stays in the sector cache until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `code` is longer than
+    /// `BOOT_CODE_SIZE` bytes.
+    pub fn set_boot_code(&mut self, code: &[u8]) -> io::Result<()> {
+        self.update_boot_code(|bpb| bpb.set_boot_code(code))
+    }
+
+    /// Installs the volume boot record `vbr` in the boot sector and its
+    /// backup, as `BiosParameterBlock::set_vbr()` does: only its jump
+    /// instruction and boot code are taken. The change stays in the sector
+    /// cache until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `vbr` isn't 512 bytes long.
+    pub fn install_vbr(&mut self, vbr: &[u8]) -> io::Result<()> {
+        self.update_boot_code(|bpb| bpb.set_vbr(vbr))
+    }
+
+    /// Returns the volume serial number from the boot sector.
+    pub fn volume_id(&mut self) -> io::Result<u32> {
+        Ok(self.boot_sector()?.volumn_id)
+    }
+
+    /// Sets the volume serial number in the boot sector and its backup, as
+    /// when a copy of an image needs an identity of its own. The change
+    /// stays in the sector cache until the next `flush()`.
+    pub fn set_volume_id(&mut self, id: u32) -> io::Result<()> {
+        self.check_writable()?;
+        let bpb = self.boot_sector()?;
+        let mut buf = [0; 4];
+        util::put_le_u32(&mut buf, 0, id);
+        for sector in VFat::<D, L>::boot_sector_copies(&bpb) {
+            self.device.write_at(sector, VOLUME_ID_OFFSET, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the sectors holding the boot sector `bpb` and its backup.
+    fn boot_sector_copies(bpb: &BiosParameterBlock) -> Vec<u64> {
+        let mut sectors = vec![0];
+        let backup = bpb.backup_boot_sector as u64;
+        if backup != 0 && backup < bpb.num_reserved_sectors as u64 {
+            sectors.push(backup);
+        }
+        sectors
+    }
+
+    /// Applies `update` to the boot sector and writes the jump instruction
+    /// and boot code it leaves to the boot sector and its backup. Nothing
+    /// else in either sector is rewritten.
+    fn update_boot_code<F>(&mut self, update: F) -> io::Result<()>
+        where F: FnOnce(&mut BiosParameterBlock) -> io::Result<()>
+    {
+        self.check_writable()?;
+        let mut bpb = self.boot_sector()?;
+        update(&mut bpb)?;
+        let buf = bpb.encode();
+        for sector in VFat::<D, L>::boot_sector_copies(&bpb) {
+            self.device.write_at(sector, 0, &buf[..3])?;
+            self.device.write_at(sector, BOOT_CODE_OFFSET,
+                                 &buf[BOOT_CODE_OFFSET..BOOT_CODE_OFFSET + BOOT_CODE_SIZE])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current state of the volume's caches.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            cached_sectors: self.device.len(),
+            dirty_sectors: self.device.dirty_len(),
+            evicted_sectors: self.device.evicted(),
+        }
+    }
+
+    /// Limits the sector cache to `capacity` sectors, 0 meaning unbounded,
+    /// evicting with `policy`, in place of `MountOptions::cache_sectors` and
+    /// `MountOptions::cache_policy`. Use this to install a custom policy.
+    pub fn set_eviction_policy(&mut self, capacity: usize, policy: Box<EvictionPolicy>) {
+        self.device.set_policy(capacity, policy);
+    }
+
+    /// Returns the volume's cancellation token. Cancelling it stops walking
+    /// cluster chains and directory trees, scanning the FAT, freeing chains,
+    /// and extracting files at the next point where stopping is safe.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
     }
 
     //  * A method to read from an offset of a cluster into a buffer.
+    /// Reads from byte `offset` of `cluster` into `buf`, stopping at the end
+    /// of the cluster, and returns the number of bytes read.
     pub fn read_cluster(&mut self, cluster: Cluster, offset: usize, buf: &mut [u8])
         -> io::Result<usize> {
 //        println!("vfat {:?}", self);
 //        println!("cluster {}, self.bytes_per_sector {}, self.device.sector_size {}", cluster.get_index(), self.bytes_per_sector, self.device.sector_size());
-        let cluster_start = self.data_start_sector
-            + cluster.get_offset()
-                     .ok_or(io::Error::new(io::ErrorKind::InvalidInput, 
-                            format!("invalid cluster number {:?}", cluster)))? as u64
-            * self.sectors_per_cluster as u64;
-        let start_sector = cluster_start + offset as u64;
-        let end_sector = cluster_start + self.sectors_per_cluster as u64;
-        let can_read = buf.len() as u64 / self.bytes_per_sector as u64;
-        let can_read_end = min(end_sector, start_sector + can_read);
+        let cluster_start = self.cluster_sector(cluster)?;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let end = min(self.bytes_per_cluster(), offset.saturating_add(buf.len()));
 
+        let mut pos = offset;
         let mut read = 0;
-        for i in start_sector..can_read_end {
-            read += self.device.read_sector(i, &mut buf[read..])?;
+        while pos < end {
+            let sector = cluster_start + (pos / bytes_per_sector) as u64;
+            let start = pos % bytes_per_sector;
+            let len = min(end - pos, bytes_per_sector - start);
+            self.device.read_at(sector, start, &mut buf[read..read + len])
+                       .in_cluster(cluster)?;
+            pos += len;
+            read += len;
         }
         Ok(read)
     }
 
+    /// Reads the 32-byte directory entry at `location`, as found by
+    /// `Entry::location()`.
+    pub fn read_dir_entry(&mut self, location: EntryLocation) -> io::Result<[u8; 32]> {
+        let mut entry = [0u8; 32];
+        self.check_dir_entry(location)?;
+        self.read_cluster(location.cluster, location.offset, &mut entry)?;
+        Ok(entry)
+    }
+
+    /// Rewrites the 32-byte directory entry at `location` with `entry`, as
+    /// when updating a file's metadata or size. The change stays in the
+    /// sector cache until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `location` doesn't lie within
+    /// its cluster on a 32-byte boundary.
+    pub fn write_dir_entry(&mut self, location: EntryLocation, entry: &[u8; 32])
+        -> io::Result<()>
+    {
+        self.check_writable()?;
+        self.check_dir_entry(location)?;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let sector = self.cluster_sector(location.cluster)?
+            + (location.offset / bytes_per_sector) as u64;
+        self.device.write_at(sector, location.offset % bytes_per_sector, entry)
+                   .in_cluster(location.cluster)?;
+        // Cached lookups hold copies of entries.
+        self.dentries.clear();
+        Ok(())
+    }
+
+    /// Returns an error of `PermissionDenied` if the volume was mounted
+    /// with the `read_only` option.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.options.read_only {
+            return Err(util::permission_denied("read-only file system"));
+        }
+        Ok(())
+    }
+
+    fn check_dir_entry(&self, location: EntryLocation) -> io::Result<()> {
+        if location.offset % 32 != 0 || location.offset + 32 > self.bytes_per_cluster() {
+            return Err(util::invalid_input(format!(
+                "no directory entry at offset {} of a cluster", location.offset)));
+        }
+        Ok(())
+    }
+
     //  * A method to read all of the clusters chained from a starting cluster
     //    into a vector.
+    /// Chains longer than the `max_chain_bytes` mount option fail with an
+    /// error of `InvalidInput`.
     pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
-        let mut cur_cluster = start;
-        let mut read = 0;
-//        println!("read chain from {:?}", start);
-        loop {
-            let buflen = buf.len();
-            buf.resize(buflen + self.bytes_per_sector as usize * self.sectors_per_cluster as usize, 0);
-            read += self.read_cluster(cur_cluster, 0, &mut buf[read..])?;
-            match self.fat_entry(cur_cluster)?.status() {
-                Status::Data(next_cluster) => {
-                    cur_cluster = next_cluster; }
-                Status::Eoc(_) => {
-                    return Ok(read);
-                },
-                _ => return Err(io::Error::new(io::ErrorKind::Other, "sector unreadable"))
+        // Resolve the chain up front so that a looping or overlong chain is
+        // rejected before any data is read into `buf`.
+        let max_len = self.max_chain_clusters();
+        let chain = self.resolve_chain_within(start, false, max_len)?;
+        self.read_clusters(&chain, buf)
+    }
+
+    /// Reads each of `clusters` in turn, appending them to `buf`, and
+    /// returns the number of bytes read. More clusters than the
+    /// `max_chain_bytes` mount option allows fail with an error of
+    /// `InvalidInput`.
+    ///
+    /// With the `read_threads` mount option, large reads from a device that
+    /// hands out readers are split across that many threads.
+    pub fn read_clusters(&mut self, clusters: &[Cluster], buf: &mut Vec<u8>)
+        -> io::Result<usize>
+    {
+        if clusters.len() > self.max_chain_clusters() {
+            return Err(self.over_limit());
+        }
+        let len = clusters.len() * self.bytes_per_cluster();
+        read_into_spare(buf, len, |buf| {
+            #[cfg(feature = "std")]
+            {
+                if self.read_clusters_parallel(clusters, buf)? {
+                    return Ok(buf.len());
+                }
+            }
+
+            let mut read = 0;
+            for &cluster in clusters.iter() {
+                read += self.read_cluster(cluster, 0, &mut buf[read..])?;
+            }
+            Ok(read)
+        })
+    }
+
+    /// Reads the chain starting at `start` into `buf` like `read_chain()`,
+    /// but substitutes zeroes for clusters that are marked bad or that fail
+    /// to read, and ends the chain at a bad cluster. The byte ranges of `buf`
+    /// that were substituted, relative to its length on entry, are appended to
+    /// `unreliable`.
+    pub fn read_chain_lenient(&mut self, start: Cluster, buf: &mut Vec<u8>,
+                              unreliable: &mut Vec<Range<u64>>) -> io::Result<usize> {
+        let max_len = self.max_chain_clusters();
+        let chain = self.resolve_chain_within(start, true, max_len)?;
+        self.read_clusters_lenient(&chain, buf, unreliable)
+    }
+
+    /// Reads each of `clusters` in turn into `buf` like `read_clusters()`,
+    /// but substitutes zeroes as `read_chain_lenient()` does. `clusters` is
+    /// taken to end its chain, so its last cluster may be marked bad.
+    pub fn read_clusters_lenient(&mut self, clusters: &[Cluster], buf: &mut Vec<u8>,
+                                 unreliable: &mut Vec<Range<u64>>) -> io::Result<usize> {
+        if clusters.len() > self.max_chain_clusters() {
+            return Err(self.over_limit());
+        }
+        let bytes_per_cluster = self.bytes_per_cluster();
+        read_into_spare(buf, clusters.len() * bytes_per_cluster, |buf| {
+            for (i, &cluster) in clusters.iter().enumerate() {
+                let chunk = &mut buf[i * bytes_per_cluster..(i + 1) * bytes_per_cluster];
+                // A bad cluster ends the chain, so only the last one can be bad.
+                let is_bad = i + 1 == clusters.len()
+                    && self.fat_entry(cluster)?.status() == Status::Bad;
+                if is_bad || self.read_cluster(cluster, 0, chunk).is_err() {
+                    for byte in chunk.iter_mut() {
+                        *byte = 0;
+                    }
+                    let start = (i * bytes_per_cluster) as u64;
+                    unreliable.push(start..start + bytes_per_cluster as u64);
+                }
             }
+            Ok(buf.len())
+        })
+    }
+
+    /// Returns the offset of `cluster` into the data region, or an error of
+    /// kind `InvalidData` if `cluster` doesn't lie in the data region.
+    pub(crate) fn check_cluster(&self, cluster: Cluster) -> io::Result<u32> {
+        match cluster.get_offset() {
+            Some(offset) if cluster.get_index() <= self.max_cluster => Ok(offset),
+            _ => Err(util::corrupt(format!("cluster {} is out of range",
+                                           cluster.get_index()))),
         }
     }
 
-    //  * A method to return a reference to a `FatEntry` for a cluster where the
-    //    reference points directly into a cached sector.
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
-        let entries_per_sector = self.bytes_per_sector as usize / mem::size_of::<FatEntry>();
+    /// Returns the first sector of `cluster`, or an error of kind
+    /// `InvalidData` if `cluster` doesn't lie in the data region.
+    pub(crate) fn cluster_sector(&self, cluster: Cluster) -> io::Result<u64> {
+        match cluster.first_sector(self.data_start_sector, self.sectors_per_cluster) {
+            Some(sector) if cluster.get_index() <= self.max_cluster => Ok(sector),
+            _ => Err(util::corrupt(format!("cluster {} is out of range",
+                                           cluster.get_index()))),
+        }
+    }
+
+    pub(crate) fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one. When `lenient`, a bad cluster ends the
+    /// chain instead of failing.
+    pub(crate) fn next_cluster(&mut self, cluster: Cluster, lenient: bool)
+        -> io::Result<Option<Cluster>>
+    {
+        let entry = self.fat_entry(cluster)?;
+        fat::successor(cluster, entry, self.max_cluster, lenient)
+    }
+
+    /// Returns the `FatEntry` for `cluster`, decoded from the cached FAT
+    /// sector holding it.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        self.check_cluster(cluster)?;
+        let entry_size = mem::size_of::<FatEntry>();
+        let entries_per_sector = self.bytes_per_sector as usize / entry_size;
         let cluster_idx = cluster.get_index() as usize;
         let nth_sec_in_fat = cluster_idx / entries_per_sector;
-        let index_in_sector = cluster_idx % entries_per_sector;
-        let sec = self.device.get(self.fat_start_sector as u64 + nth_sec_in_fat as u64)?;
-        let entries: &[FatEntry] = unsafe { sec.cast() };
-//        println!("cluster: {:?} entries_per_sector {} nth_sec_in_fat {} entries.len {}, index_in_sector {}, entries {:?}",
-//                 cluster, entries_per_sector, nth_sec_in_fat, entries.len(), index_in_sector, entries);
-//        println!("{:?}", entries);
-//        let entry = entries[index_in_sector];
-        Ok(&entries[index_in_sector])
+        let offset = cluster_idx % entries_per_sector * entry_size;
+        let sec = self.device.get(self.fat_start_sector as u64 + nth_sec_in_fat as u64)
+                             .in_cluster(cluster)?;
+        if sec.len() < offset + entry_size {
+            return Err(util::eof("FAT sector is truncated")).in_cluster(cluster);
+        }
+        Ok(FatEntry(util::le_u32(sec, offset)))
+    }
+
+    /// Sets the FAT entry for `cluster` to `entry` in every copy of the FAT,
+    /// keeping the entry's reserved high 4 bits. The change stays in the
+    /// sector cache until the next `flush()`.
+    fn set_fat_entry(&mut self, cluster: Cluster, entry: FatEntry) -> io::Result<()> {
+        self.check_writable()?;
+        let value = self.fat_entry(cluster)?.with_value(entry.value()).raw();
+        self.write_fat_value(cluster, value)
+    }
+
+    /// Writes `value`, high bits included, as the FAT entry of `cluster` in
+    /// every copy of the FAT. `cluster` may be 0 or 1.
+    fn write_fat_value(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let bytes = [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8];
+        let offset = cluster.get_index() as u64 * mem::size_of::<FatEntry>() as u64;
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        for copy in 0..self.num_fats as u64 {
+            let sector = self.fat_start_sector + copy * self.sectors_per_fat as u64
+                + offset / bytes_per_sector;
+            self.device.write_at(sector, (offset % bytes_per_sector) as usize, &bytes)
+                       .in_cluster(cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the sectors of the FAT holding the entries of `clusters`, so
+    /// that `restore_fat()` can roll them back should an operation that
+    /// rewrites those entries, such as a repair, be interrupted. Pass
+    /// `2..max_cluster + 1` to copy the whole FAT. Changes not yet flushed
+    /// are included in the copy.
+    pub fn backup_fat(&mut self, clusters: Range<u32>) -> io::Result<FatBackup> {
+        let entries_per_sector = self.bytes_per_sector as u64 / mem::size_of::<FatEntry>() as u64;
+        let first = clusters.start as u64 / entries_per_sector;
+        let end = min((clusters.end as u64 + entries_per_sector - 1) / entries_per_sector,
+                      self.sectors_per_fat as u64);
+        let mut sectors = Vec::new();
+        for index in first..end {
+            let data = self.device.get(self.fat_start_sector + index)?.to_vec();
+            sectors.push((index, data));
+        }
+        Ok(FatBackup {
+            bytes_per_sector: self.bytes_per_sector as u32,
+            sectors: sectors,
+        })
+    }
+
+    /// Writes the FAT sectors copied in `backup` back to every copy of the
+    /// FAT, undoing the changes made to them since. The change stays in the
+    /// sector cache until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `backup` wasn't taken from a
+    /// volume with the same sector size and a FAT at least as large.
+    pub fn restore_fat(&mut self, backup: &FatBackup) -> io::Result<()> {
+        self.check_writable()?;
+        let fits = backup.sectors.iter()
+            .all(|&(index, ref data)| index < self.sectors_per_fat as u64
+                                      && data.len() == self.bytes_per_sector as usize);
+        if backup.bytes_per_sector != self.bytes_per_sector as u32 || !fits {
+            return Err(util::invalid_input("FAT backup doesn't match the volume"));
+        }
+        for &(index, ref data) in backup.sectors.iter() {
+            for copy in 0..self.num_fats as u64 {
+                let sector = self.fat_start_sector + copy * self.sectors_per_fat as u64 + index;
+                self.device.write_at(sector, 0, data)?;
+            }
+        }
+        // Chains may have changed under cached lookups.
+        self.dentries.clear();
+        Ok(())
+    }
+
+    /// Runs `op`, which rewrites the entries of `clusters` in the FAT, after
+    /// backing them up with `backup_fat()`, and restores them if `op` fails,
+    /// such as when it is cancelled through the volume's `CancelToken`.
+    ///
+    /// Only the FAT is rolled back: data `op` wrote to clusters, or discarded
+    /// with the `discard` mount option, stays as it is.
+    pub fn with_fat_rollback<T, F>(&mut self, clusters: Range<u32>, op: F) -> io::Result<T>
+        where F: FnOnce(&mut VFat<D, L>) -> io::Result<T>
+    {
+        let backup = self.backup_fat(clusters)?;
+        match op(self) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                self.restore_fat(&backup)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Marks every cluster of the chain starting at `start` free and returns
+    /// the number of clusters freed. A `start` of cluster 0 frees nothing.
+    ///
+    /// With the `discard` mount option, the FAT is flushed to the device and
+    /// the sectors of the freed clusters are then discarded; flushing first
+    /// ensures no chain on the device still points at discarded data.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<u32> {
+        self.check_writable()?;
+        // Once the FAT is being changed, the chain is freed in full.
+        self.cancel.check()?;
+        let mut clusters: Vec<u32> = self.resolve_chain(start, false)?
+            .iter().map(|cluster| cluster.get_index()).collect();
+        let len = clusters.len() as u32;
+        for &cluster in clusters.iter() {
+            self.set_fat_entry(Cluster::from(cluster), FatEntry(0))?;
+        }
+        // Whatever referred to the chain is gone or about to be.
+        self.dentries.clear();
+
+        if self.options.discard && !clusters.is_empty() {
+            self.flush()?;
+            clusters.sort();
+            let sectors_per_cluster = self.sectors_per_cluster as u64;
+            let mut run = clusters[0]..clusters[0] + 1;
+            // The trailing 0 can't extend a run, so it ends the last one.
+            for &cluster in clusters[1..].iter().chain(Some(&0)) {
+                if cluster == run.end {
+                    run.end += 1;
+                    continue;
+                }
+                let first = self.cluster_sector(Cluster::from(run.start))?;
+                self.device.discard(first, (run.end - run.start) as u64 * sectors_per_cluster)
+                           .in_cluster(Cluster::from(run.start))?;
+                run = cluster..cluster + 1;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Cuts the chain starting at `start` down to its first `len` clusters,
+    /// marking the last one kept as the end of the chain, and returns the
+    /// number of clusters freed. Cutting a chain to 0 clusters frees it all.
+    pub fn truncate_chain(&mut self, start: Cluster, len: u32) -> io::Result<u32> {
+        if len == 0 {
+            return self.free_chain(start);
+        }
+        let chain = self.resolve_chain(start, false)?;
+        if chain.len() <= len as usize {
+            return Ok(0);
+        }
+        self.set_fat_entry(chain[len as usize - 1], FatEntry(0x0FFFFFFF))?;
+        self.free_chain(chain[len as usize])
+    }
+
+    /// Returns the number of `File` handles open to the file whose directory
+    /// entry is at `location`.
+    pub fn open_handles(&self, location: EntryLocation) -> usize {
+        self.open_files.handles(location)
+    }
+
+    /// Overwrites every free cluster with `byte`, writing straight to the
+    /// device, and returns the number of clusters overwritten. Clusters freed
+    /// by deleting files keep their data until reused; this erases it.
+    pub fn wipe_free_clusters(&mut self, byte: u8) -> io::Result<u32> {
+        self.check_writable()?;
+        let sector = vec![byte; self.bytes_per_sector as usize];
+        let sectors_per_cluster = self.sectors_per_cluster as u64;
+        let mut wiped = 0;
+        for raw in 2..self.max_cluster + 1 {
+            if (raw - 2) % CANCEL_INTERVAL == 0 {
+                self.cancel.check()?;
+            }
+            let cluster = Cluster::from(raw);
+            if self.fat_entry(cluster)?.status() != Status::Free {
+                continue;
+            }
+            let first = self.cluster_sector(cluster)?;
+            for n in first..first + sectors_per_cluster {
+                self.device.write_uncached(n, &sector).in_cluster(cluster)?;
+            }
+            wiped += 1;
+        }
+        Ok(wiped)
+    }
+
+    /// Writes every change made to the file system's cached sectors back to
+    /// the device, through the journal with the `journal` mount option, and
+    /// then tells subscribers about the changes recorded with `notify()`.
+    ///
+    /// With the journal, all changes since the last flush are committed as
+    /// one transaction, so a flush fails with `InvalidInput`, writing
+    /// nothing, if they change more sectors than the journal holds.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.journal {
+            Some(ref journal) => self.device.flush_journaled(journal),
+            None => self.device.flush(),
+        }?;
+        self.notifier.deliver();
+        Ok(())
+    }
+
+    /// Registers `callback` to be called with every change to a file or
+    /// directory once `flush()` has written it to the device, so that a sync
+    /// tool or a VFS layer can invalidate its own caches.
+    ///
+    /// Callbacks run with the volume locked, and must not access it.
+    pub fn subscribe<F>(&mut self, callback: F) -> SubscriptionId
+        where F: FnMut(&Notification) + Send + Sync + 'static
+    {
+        self.notifier.subscribe(Box::new(callback))
+    }
+
+    /// Cancels the subscription `id`. Returns `false` if it was already
+    /// cancelled.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.notifier.unsubscribe(id)
+    }
+
+    /// Records that the file or directory at `path` was created, modified, or
+    /// removed by a change that is still in the sector cache, such as one
+    /// made with `write_dir_entry()`. Subscribers are told once `flush()`
+    /// writes it to the device. Nothing is recorded without subscribers.
+    pub fn notify<P: AsRef<Path>>(&mut self, event: ChangeEvent, path: P) {
+        self.notifier.record(event, path.as_ref());
+    }
+
+    /// Reads the hints in the volume's FSInfo sector. Returns `None` if the
+    /// volume has no FSInfo sector or its signatures are wrong.
+    pub fn fsinfo(&mut self) -> io::Result<Option<FsInfo>> {
+        match self.fsinfo_sectors.first() {
+            Some(&sector) => Ok(FsInfo::parse(self.device.get(sector)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Scans the FAT and returns the number of free data clusters.
+    pub fn count_free_clusters(&mut self) -> io::Result<u32> {
+        let mut free = 0;
+        for raw in 2..self.num_data_clusters + 2 {
+            if (raw - 2) % CANCEL_INTERVAL == 0 {
+                self.cancel.check()?;
+            }
+            if self.fat_entry(Cluster::from(raw))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+
+    /// Returns FAT entries 0 and 1, which hold no cluster and so can't be
+    /// read with `fat_entry()`.
+    fn reserved_fat_entries(&mut self) -> io::Result<(FatEntry, FatEntry)> {
+        let sector = self.device.get(self.fat_start_sector)?;
+        if sector.len() < 8 {
+            return Err(util::eof("FAT sector is truncated"));
+        }
+        Ok((FatEntry(util::le_u32(sector, 0)), FatEntry(util::le_u32(sector, 4))))
+    }
+
+    /// Checks that FAT entry 0 repeats the media descriptor and that entry 1
+    /// marks an end of chain, as `dosfsck` does, returning what is wrong if
+    /// either doesn't. The volume flags in entry 1 are ignored.
+    pub fn check_fat_signature(&mut self) -> io::Result<Option<FatSignatureMismatch>> {
+        let (entry0, entry1) = self.reserved_fat_entries()?;
+        Ok(FatSignatureMismatch::check(self.media_descriptor, entry0, entry1))
+    }
+
+    /// Returns what was wrong with FAT entries 0 and 1 when mounting with
+    /// the `check_fat_signature` option, unless `repair_fat_signature()` has
+    /// since fixed it.
+    pub fn fat_signature_mismatch(&self) -> Option<FatSignatureMismatch> {
+        self.fat_signature_mismatch
+    }
+
+    /// Rewrites FAT entries 0 and 1 in every copy of the FAT if
+    /// `check_fat_signature()` finds them wrong, keeping their reserved high
+    /// bits and the volume flags of entry 1, and returns whether anything
+    /// was rewritten. The change stays in the sector cache until the next
+    /// `flush()`.
+    pub fn repair_fat_signature(&mut self) -> io::Result<bool> {
+        self.check_writable()?;
+        let mismatch = match self.check_fat_signature()? {
+            Some(mismatch) => mismatch,
+            None => return Ok(false),
+        };
+        let (entry0, entry1) = self.reserved_fat_entries()?;
+        if mismatch.entry0.is_some() {
+            let value = entry0.with_value(0x0FFFFF00 | self.media_descriptor as u32).raw();
+            self.write_fat_value(Cluster::from(0), value)?;
+        }
+        if mismatch.entry1.is_some() {
+            let flags = entry1.value() & VOLUME_FLAGS;
+            let value = entry1.with_value((0x0FFFFFFF & !VOLUME_FLAGS) | flags).raw();
+            self.write_fat_value(Cluster::from(1), value)?;
+        }
+        self.fat_signature_mismatch = None;
+        Ok(true)
+    }
+
+    /// Compares the free-cluster count in the FSInfo sector with a scan of
+    /// the FAT, returning the difference if they disagree. A count marked
+    /// unknown disagrees with any scan; a volume without a valid FSInfo
+    /// sector has nothing to disagree with.
+    pub fn check_free_count(&mut self) -> io::Result<Option<FreeCountMismatch>> {
+        let recorded = match self.fsinfo()? {
+            Some(fsinfo) => fsinfo.free_count,
+            None => return Ok(None),
+        };
+        let actual = self.count_free_clusters()?;
+        if recorded == Some(actual) {
+            return Ok(None);
+        }
+        Ok(Some(FreeCountMismatch { recorded: recorded, actual: actual }))
+    }
+
+    /// Returns the disagreement between the FSInfo free-cluster count and the
+    /// FAT found when mounting with the `check_free_count` option, unless
+    /// `recompute_free_count()` has since corrected it.
+    pub fn free_count_mismatch(&self) -> Option<FreeCountMismatch> {
+        self.free_count_mismatch
+    }
+
+    /// Returns the violations of the specification found in the directories
+    /// read so far, each listed once, on a volume mounted with
+    /// `ParsePolicy::Lenient`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.diagnostics.as_slice()
+    }
+
+    /// Forgets the diagnostics collected so far.
+    pub fn clear_diagnostics(&mut self) {
+        self.diagnostics.clear();
+    }
+
+    /// Counts the free clusters in the FAT and stores the count in the
+    /// FSInfo sector and its backup, returning the count. The change stays
+    /// in the sector cache until the next `flush()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the volume has no valid FSInfo
+    /// sector.
+    pub fn recompute_free_count(&mut self) -> io::Result<u32> {
+        self.check_writable()?;
+        let mut fsinfo = self.fsinfo()?
+            .ok_or_else(|| util::corrupt("volume has no valid FSInfo sector"))?;
+        let free = self.count_free_clusters()?;
+        fsinfo.free_count = Some(free);
+        for &sector in self.fsinfo_sectors.clone().iter() {
+            let mut buf = self.device.get(sector)?.to_vec();
+            if FsInfo::parse(&buf).is_some() {
+                fsinfo.write(&mut buf);
+                self.device.write_at(sector, 0, &buf)?;
+            }
+        }
+        self.free_count_mismatch = None;
+        Ok(free)
+    }
+
+    /// Returns the number of clusters in the chain starting at `start`. A
+    /// `start` of cluster 0 denotes an empty chain.
+    ///
+    /// Chains that loop back on themselves are detected with Brent's
+    /// algorithm and reported as `Error::CorruptChain`.
+    pub fn chain_len(&mut self, start: Cluster) -> io::Result<u32> {
+        self.walk_chain(start, false)
+    }
+
+    /// Returns the number of clusters in the chain starting at `start`, like
+    /// `chain_len()`. When `lenient`, a bad cluster ends the chain instead of
+    /// failing.
+    pub(crate) fn walk_chain(&mut self, start: Cluster, lenient: bool) -> io::Result<u32> {
+        if start.get_index() == 0 {
+            return Ok(0);
+        }
+
+        let mut walk = ChainWalk::new(start, self.max_cluster, lenient);
+        loop {
+            let entry = self.fat_entry(walk.cluster())?;
+            if walk.step(entry)?.is_none() {
+                return Ok(walk.len());
+            }
+            if walk.len() % CANCEL_INTERVAL == 0 {
+                self.cancel.check()?;
+            }
+        }
+    }
+
+    /// Returns the clusters of the chain starting at `start`, in order. A
+    /// `start` of cluster 0 denotes an empty chain. When `lenient`, a bad
+    /// cluster ends the chain instead of failing.
+    ///
+    /// Rather than looking up each cluster's FAT entry in the sector cache,
+    /// the entries of a FAT sector are decoded together the first time the
+    /// chain reaches it, so a chain laid out in order costs one lookup per
+    /// FAT sector. Looping chains are detected as in `chain_len()`.
+    pub fn resolve_chain(&mut self, start: Cluster, lenient: bool) -> io::Result<Vec<Cluster>> {
+        self.resolve_chain_within(start, lenient, usize::max_value())
+    }
+
+    /// Returns the number of clusters in `max_chain_bytes`, the most one
+    /// read of a whole chain may read.
+    fn max_chain_clusters(&self) -> usize {
+        self.options.max_chain_bytes / self.bytes_per_cluster()
+    }
+
+    fn over_limit(&self) -> io::Error {
+        util::invalid_input(format!("cluster chain is longer than the {} byte limit",
+                                    self.options.max_chain_bytes))
+    }
+
+    /// Resolves the chain starting at `start` like `resolve_chain()`, but
+    /// fails with an error of `InvalidInput` as soon as it's longer than
+    /// `max_len` clusters.
+    fn resolve_chain_within(&mut self, start: Cluster, lenient: bool, max_len: usize)
+        -> io::Result<Vec<Cluster>>
+    {
+        if start.get_index() == 0 {
+            return Ok(Vec::new());
+        }
+        self.check_cluster(start)?;
+        if max_len == 0 {
+            return Err(self.over_limit());
+        }
+
+        let entry_size = mem::size_of::<FatEntry>();
+        let entries_per_sector = self.bytes_per_sector as usize / entry_size;
+        let mut entries: Vec<u32> = Vec::with_capacity(entries_per_sector);
+        let mut decoded = None;
+
+        let mut chain = vec![start];
+        let mut walk = ChainWalk::new(start, self.max_cluster, lenient);
+        loop {
+            let hare = walk.cluster();
+            let index = hare.get_index() as usize;
+            let sector = (index / entries_per_sector) as u64;
+            if decoded != Some(sector) {
+                let sec = self.device.get(self.fat_start_sector + sector).in_cluster(hare)?;
+                entries.clear();
+                entries.extend(sec.chunks(entry_size).filter(|bytes| bytes.len() == entry_size)
+                                  .map(|bytes| util::le_u32(bytes, 0)));
+                decoded = Some(sector);
+            }
+            let entry = *entries.get(index % entries_per_sector)
+                                .ok_or_else(|| util::eof("FAT sector is truncated"))?;
+
+            let next = match walk.step(FatEntry(entry))? {
+                Some(next) => next,
+                None => return Ok(chain),
+            };
+            if chain.len() >= max_len {
+                return Err(self.over_limit());
+            }
+            if chain.len() as u32 % CANCEL_INTERVAL == 0 {
+                self.cancel.check()?;
+            }
+            chain.push(next);
+        }
+    }
+
+    /// Returns the number of bytes allocated to the chain starting at `start`.
+    pub fn allocated_bytes(&mut self, start: Cluster) -> io::Result<u64> {
+        Ok(self.chain_len(start)? as u64 * self.bytes_per_cluster() as u64)
+    }
+
+    /// Scans the FAT and returns the allocation state of every cluster in the
+    /// data region along with free/used/bad totals.
+    pub fn usage(&mut self) -> io::Result<Usage> {
+        let mut states = Vec::with_capacity(self.num_data_clusters as usize);
+        for raw in 2..self.num_data_clusters + 2 {
+            if (raw - 2) % CANCEL_INTERVAL == 0 {
+                self.cancel.check()?;
+            }
+            let state = match self.fat_entry(Cluster::from(raw))?.status() {
+                Status::Free => ClusterState::Free,
+                Status::Bad => ClusterState::Bad,
+                // Reserved entries inside the data region can't be handed out,
+                // so they are accounted for as used.
+                Status::Data(_) | Status::Eoc(_) | Status::Reserved => ClusterState::Used,
+            };
+            states.push(state);
+        }
+
+        Ok(Usage::new(states, self.bytes_per_cluster() as u64))
+    }
+}
+
+/// Extends `buf` by `len` zeroed bytes and has `read` fill them, returning
+/// the number of bytes it read. `buf` is then cut back to the bytes actually
+/// read, or to its original length if `read` fails.
+fn read_into_spare<F>(buf: &mut Vec<u8>, len: usize, read: F) -> io::Result<usize>
+    where F: FnOnce(&mut [u8]) -> io::Result<usize>
+{
+    let start = buf.len();
+    buf.reserve_exact(len);
+    buf.resize(start + len, 0);
+    match read(&mut buf[start..]) {
+        Ok(read) => {
+            buf.truncate(start + min(read, len));
+            Ok(min(read, len))
+        }
+        Err(e) => {
+            buf.truncate(start);
+            Err(e)
+        }
     }
 }
 
-impl<'a> FileSystem for &'a Shared<VFat> {
-    type File = File;
-    type Dir = Dir;
-    type Entry = Entry;
+/// Totals the usage of `dir` and its descendants. `ancestors` holds the first
+/// cluster of every directory on the path to `dir` and is used to reject
+/// corrupt directory trees that loop back on themselves.
+fn dir_usage<D, L, F>(vfat: &Shared<VFat<D, L>, L>, dir: &Dir<D, L>, path: &Path,
+                      ancestors: &mut Vec<Cluster>, progress: &mut F)
+    -> io::Result<DiskUsage> where D: BlockDevice, L: RawLock, F: FnMut(&Path, &DiskUsage)
+{
+    if ancestors.contains(&dir.first_cluster) {
+        return Err(util::corrupt("directory tree contains a cycle"));
+    }
+    ancestors.push(dir.first_cluster);
+    vfat.borrow().cancel.check()?;
+
+    let mut usage = DiskUsage {
+        allocated: vfat.borrow_mut().allocated_bytes(dir.first_cluster)?,
+        dirs: 1,
+        ..DiskUsage::default()
+    };
+
+    for entry in traits::Dir::entries(dir)? {
+        match entry {
+            Entry::File(ref file) => {
+                usage.allocated += vfat.borrow_mut().allocated_bytes(file.first_cluster)?;
+                usage.apparent += file.size as u64;
+                usage.files += 1;
+            }
+            Entry::Dir(ref dir) => {
+                if dir.name() != "." && dir.name() != ".." {
+                    usage += dir_usage(vfat, dir, &path.join(dir.name()), ancestors, progress)?;
+                }
+            }
+        }
+    }
+
+    ancestors.pop();
+    progress(path, &usage);
+    Ok(usage)
+}
+
+/// Opens `path`, which isn't valid UTF-8, comparing its names as UTF-16 with
+/// `Dir::find_utf16()`, so that on Windows hosts names holding unpaired
+/// surrogates can be opened. The dentry cache, keyed by `str`, isn't used.
+fn open_utf16<D, L>(vfat: &Shared<VFat<D, L>, L>, path: &Path) -> io::Result<Entry<D, L>>
+    where D: BlockDevice, L: RawLock
+{
+    let mut components = path.components();
+    if components.next() != Some(Component::RootDir) {
+        return Err(util::invalid_input("path is not absolute"));
+    }
+    // The entries walked through from the root, the last being the current
+    // one. `..` steps back to the one before, like `open()`.
+    let mut walked = vec![Entry::Dir(Dir::root(vfat.clone()))];
+    for component in components {
+        let name = match component {
+            Component::Normal(name) => util::os_str_to_utf16(name)
+                .ok_or_else(|| util::invalid_input("input contains invalid UTF-8 char"))
+                .at_path(path)?,
+            Component::ParentDir => {
+                if let Some(&Entry::File(_)) = walked.last() {
+                    return Err(util::invalid_input("not a directory"));
+                }
+                if walked.len() > 1 {
+                    walked.pop();
+                }
+                continue;
+            }
+            Component::CurDir => continue,
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(util::invalid_input("unsupported path component"));
+            }
+        };
+        let entry = match walked.last() {
+            Some(&Entry::Dir(ref dir)) => dir.find_utf16(&name).at_path(path)?,
+            _ => return Err(util::invalid_input("not a directory")),
+        };
+        walked.push(entry);
+    }
+    Ok(walked.pop().expect("the root is never popped"))
+}
+
+impl<'a, D: BlockDevice, L: RawLock> FileSystem for &'a Shared<VFat<D, L>, L> {
+    type File = File<D, L>;
+    type Dir = Dir<D, L>;
+    type Entry = Entry<D, L>;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
         use vfat::Entry as vfatEntry;
         use traits::Entry;
 
-        let mut cur_dir = vfatEntry::Dir(Dir::root(self.clone()));
-
-        for comp in path.as_ref().components() {
-//            println!("comp: {:?}", comp);
-            match comp {
-                Component::RootDir => { },
-                Component::Normal(name) => {
-                    cur_dir = cur_dir.as_dir()
-                                     .ok_or(io::Error::new(io::ErrorKind::NotFound, 
-                                                           "File not found"))?
-                                     .find(name)?
+        let path = path.as_ref();
+        let path_str = match path.to_str() {
+            Some(path_str) => path_str,
+            None => return open_utf16(self, path),
+        };
+        if !::path::is_absolute(path_str) {
+            return Err(util::invalid_input("path is not absolute"));
+        }
+
+        // The entries walked through from the root, the last being the
+        // current one, each with the length of its key into the dentry
+        // cache. `..` steps back to the one before, once the current entry
+        // is known to be a directory, so `/file/..` and `/missing/..` fail
+        // as they would on a host.
+        let mut walked = vec![(vfatEntry::Dir(Dir::root(self.clone())), 0)];
+        // The path resolved so far, normalized as a key into the dentry cache.
+        let mut key = String::new();
+        let case_sensitive = self.borrow().options.case_sensitive;
+
+        for component in ::path::components(path_str) {
+            let name = match component {
+                ::path::Component::RootDir => continue,
+                ::path::Component::Normal(name) => name,
+                ::path::Component::ParentDir => {
+                    if walked.last().and_then(|&(ref entry, _)| entry.as_dir()).is_none() {
+                        return Err(util::invalid_input("not a directory"));
+                    }
+                    if walked.len() > 1 {
+                        walked.pop();
+                    }
+                    key.truncate(walked.last().map_or(0, |&(_, len)| len));
+                    continue;
                 }
-                Component::CurDir => unimplemented!("CurDir"),
-                Component::ParentDir => unimplemented!("ParentDir"),
-                Component::Prefix(_) => unimplemented!("Prefix"),
-            }
+            };
+
+            let entry = {
+                let dir = walked.last().and_then(|&(ref entry, _)| entry.as_dir())
+                                .ok_or_else(|| util::invalid_input("not a directory"))?;
+                DentryCache::push_component(&mut key, name, case_sensitive);
+                let cached = self.borrow_mut().dentries.get(&key);
+                match cached {
+                    Some(cached) => cached.to_entry(self),
+                    None => {
+                        let entry = dir.find(name).at_path(path)?;
+                        self.borrow_mut().dentries.insert(&key, &entry);
+                        entry
+                    }
+                }
+            };
+            walked.push((entry, key.len()));
         }
-        Ok(cur_dir)
+        Ok(walked.pop().expect("the root is never popped").0)
     }
 
     fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+        Err(util::permission_denied("read-only file system"))
     }
 
     fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        Err(util::permission_denied("read-only file system"))
     }
 
     fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        Err(util::permission_denied("read-only file system"))
     }
 
     fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+        Err(util::permission_denied("read-only file system"))
+    }
+
+    fn disk_usage<P, F>(self, path: P, mut progress: F) -> io::Result<DiskUsage>
+        where P: AsRef<Path>, F: FnMut(&Path, &DiskUsage)
+    {
+        match self.open(path.as_ref())? {
+            Entry::Dir(dir) => {
+                dir_usage(self, &dir, path.as_ref(), &mut Vec::new(), &mut progress)
+            }
+            Entry::File(file) => Ok(DiskUsage {
+                allocated: self.borrow_mut().allocated_bytes(file.first_cluster)?,
+                apparent: file.size as u64,
+                files: 1,
+                ..DiskUsage::default()
+            }),
+        }
     }
 }